@@ -1,8 +1,7 @@
 use darling::FromAttributes;
 use proc_macro2::TokenStream;
 use quote::quote;
-use syn::spanned::Spanned;
-use syn::Type;
+use syn::{spanned::Spanned, Type};
 
 #[derive(FromAttributes)]
 #[darling(attributes(kafka))]
@@ -12,13 +11,14 @@ pub struct FieldOptions {
     #[darling(default)]
     pub max_version: Option<i16>,
     #[darling(default)]
-    pub tag: Option<i16>,
-    #[darling(default)]
     pub default: Option<syn::ExprPath>,
     #[darling(default)]
     pub serialize_with: Option<syn::ExprPath>,
     #[darling(default)]
     pub deserialize_with: Option<syn::ExprPath>,
+    /// The field is an `Option` that is encoded as a nullable value instead of being skipped.
+    #[darling(default)]
+    pub nullable: bool,
 }
 
 pub fn darling_to_syn(e: darling::Error) -> Vec<syn::Error> {
@@ -32,7 +32,27 @@ pub fn to_compile_errors(errors: Vec<syn::Error>) -> TokenStream {
     quote!(#(#compile_errors)*)
 }
 
+pub fn version_condition(field_attrs: &FieldOptions) -> Option<TokenStream> {
+    match (field_attrs.min_version, field_attrs.max_version) {
+        (Some(min_version), Some(max_version)) => Some(quote! {
+            (#min_version..=#max_version).contains(&version)
+        }),
+        (Some(min_version), None) => Some(quote! {
+            version >= #min_version
+        }),
+        (None, Some(max_version)) => Some(quote! {
+            version <= #max_version
+        }),
+        (None, None) => None,
+    }
+}
 
+pub fn flexible_condition(tag_version: Option<i16>) -> TokenStream {
+    match tag_version {
+        Some(tag_version) => quote! { version >= #tag_version },
+        None => quote! { false },
+    }
+}
 
 pub fn option_type(ty: &Type) -> Option<&Type> {
     let Type::Path(ty) = ty else { return None };
@@ -42,14 +62,14 @@ pub fn option_type(ty: &Type) -> Option<&Type> {
 
     let ty = &ty.path;
 
-    if ty.segments.is_empty() || ty.segments.last().unwrap().ident.to_string() != "Option" {
+    if ty.segments.is_empty() || ty.segments.last().unwrap().ident != "Option" {
         return None;
     }
 
     if !(ty.segments.len() == 1
         || (ty.segments.len() == 3
         && ["core", "std"].contains(&ty.segments[0].ident.to_string().as_str())
-        && ty.segments[1].ident.to_string() == "option"))
+        && ty.segments[1].ident == "option"))
     {
         return None;
     }
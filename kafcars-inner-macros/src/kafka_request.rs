@@ -2,7 +2,7 @@ use quote::quote;
 use darling::FromAttributes;
 use proc_macro2::{Ident, TokenStream};
 use syn::{Data, DeriveInput, Fields, Type};
-use crate::common::{darling_to_syn, FieldOptions};
+use crate::common::{darling_to_syn, option_type, FieldOptions};
 use crate::versioned_serialize::{derive_versioned_serialize_with_options, VersionedSerializeOptions};
 
 #[derive(FromAttributes)]
@@ -45,9 +45,9 @@ pub fn derive_kafka_request(input: &DeriveInput) -> Result<TokenStream, Vec<syn:
         )])?;
     let min_version = named_type_options.min_version.unwrap_or(0);
     let max_version = named_type_options.max_version;
-    let tagged_field = find_tagged_field_version(&input);
-    let tagged_field_version = tagged_field
-        .map(|f| f.0)
+    let tagged_field_version = named_type_options
+        .tag_version
+        .or_else(|| find_tagged_field_version(input).map(|f| f.0))
         .map(|v| quote! {Some(#v)})
         .unwrap_or(quote! {None});
     Ok(quote! {
@@ -64,7 +64,7 @@ pub fn derive_kafka_request(input: &DeriveInput) -> Result<TokenStream, Vec<syn:
     })
 }
 
-fn find_tagged_field_version(input: &DeriveInput) -> Option<(i16, &Ident)> {
+pub fn find_tagged_field_version(input: &DeriveInput) -> Option<(i16, &Ident)> {
     match &input.data {
         Data::Struct(s) => {
             match s.fields {
@@ -74,8 +74,9 @@ fn find_tagged_field_version(input: &DeriveInput) -> Option<(i16, &Ident)> {
                             FieldOptions::from_attributes(&field.attrs[..]) else { continue; };
                         let Some(min_version) = field_attrs.min_version else { continue; };
                         let Some(name) = &field.ident else { continue; };
-                        let Type::Path(path) = &field.ty else { continue; };
-                        if path.path.segments.last().unwrap().ident.to_string() == "TaggedFields" {
+                        let field_type = option_type(&field.ty).unwrap_or(&field.ty);
+                        let Type::Path(path) = field_type else { continue; };
+                        if path.path.segments.last().unwrap().ident == "TaggedFields" {
                             return Some((min_version, name));
                         }
                     }
@@ -0,0 +1,46 @@
+use crate::{
+    common::darling_to_syn,
+    kafka_request::find_tagged_field_version,
+    versioned_deserialize::{
+        derive_versioned_deserialize_with_options, VersionedDeserializeOptions,
+    },
+};
+use darling::FromAttributes;
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::DeriveInput;
+
+#[derive(FromAttributes)]
+#[darling(attributes(kafka))]
+struct KafkaResponseOptions {
+    #[darling(default)]
+    max_version: i16,
+    #[darling(default)]
+    tag_version: Option<i16>,
+}
+
+pub fn derive_kafka_response(input: &DeriveInput) -> Result<TokenStream, Vec<syn::Error>> {
+    let named_type_options =
+        KafkaResponseOptions::from_attributes(&input.attrs[..]).map_err(darling_to_syn)?;
+    let derive_deserialize_opts = VersionedDeserializeOptions {
+        max_version: named_type_options.max_version,
+        tag_version: named_type_options.tag_version,
+    };
+    let derive_deserialize =
+        derive_versioned_deserialize_with_options(derive_deserialize_opts, input)?;
+
+    let ident = &input.ident;
+    let generics = &input.generics;
+    let (_, ty_generics, where_clause) = generics.split_for_impl();
+    let tagged_field_version = named_type_options
+        .tag_version
+        .or_else(|| find_tagged_field_version(input).map(|f| f.0))
+        .map(|v| quote! {Some(#v)})
+        .unwrap_or(quote! {None});
+    Ok(quote! {
+        #derive_deserialize
+        impl crate::protocol::messages::KafkaResponse for #ident #ty_generics #where_clause {
+            const TAGGED_FIELDS_MIN_VERSION: Option<crate::protocol::messages::ApiVersion> = #tagged_field_version;
+        }
+    })
+}
@@ -1,23 +1,24 @@
-use std::error::Error;
+use crate::common::{
+    darling_to_syn, flexible_condition, option_type, version_condition, FieldOptions,
+};
 use darling::FromAttributes;
 use proc_macro2::{Span, TokenStream};
 use quote::quote;
-use syn::{Data, DataStruct, DeriveInput, Fields, Type};
-use syn::spanned::Spanned;
-use crate::common::{darling_to_syn, option_type, FieldOptions};
+use syn::{Data, DataStruct, DeriveInput, Fields};
 
 #[derive(FromAttributes)]
 #[darling(attributes(kafka))]
 pub struct VersionedDeserializeOptions {
     #[darling(default)]
     pub max_version: i16,
+    /// First version using the flexible (compact) encoding
     #[darling(default)]
     pub tag_version: Option<i16>,
 }
 
 pub fn derive_versioned_deserialize_with_options(options: VersionedDeserializeOptions, input: &DeriveInput) -> Result<TokenStream, Vec<syn::Error>> {
     let schema_def = match &input.data {
-        Data::Struct(s) => get_schema_def(s, input.ident.span())?,
+        Data::Struct(s) => get_schema_def(s, options.tag_version, input.ident.span())?,
         _ => {
             return Err(vec![syn::Error::new(
                 input.ident.span(),
@@ -30,6 +31,7 @@ pub fn derive_versioned_deserialize_with_options(options: VersionedDeserializeOp
     let generics = &input.generics;
     let (_, ty_generics, where_clause) = generics.split_for_impl();
     let max_version = options.max_version;
+    let flexible = flexible_condition(options.tag_version);
     Ok(quote! {
         impl <R: std::io::Read> crate::protocol::deserializer::DeserializeVersioned<R> for #ident #ty_generics #where_clause {
             fn deserialize_versioned(data: &mut R, version: i16) -> Result<Self, crate::protocol::error::SerializationError> {
@@ -39,9 +41,17 @@ pub fn derive_versioned_deserialize_with_options(options: VersionedDeserializeOp
                         given_version: version,
                     });
                 }
+                #[allow(unused_variables)]
+                let flexible = #flexible;
                 #schema_def
             }
         }
+
+        impl <R: std::io::Read> crate::protocol::deserializer::DeserializeCompact<R> for #ident #ty_generics #where_clause {
+            fn deserialize_compact(data: &mut R, version: i16) -> Result<Self, crate::protocol::error::SerializationError> {
+                <Self as crate::protocol::deserializer::DeserializeVersioned<R>>::deserialize_versioned(data, version)
+            }
+        }
     })
 }
 
@@ -51,7 +61,7 @@ pub fn derive_versioned_deserialize(input: &DeriveInput) -> Result<TokenStream,
     derive_versioned_deserialize_with_options(named_type_options, input)
 }
 
-fn get_schema_def(s: &DataStruct, error_span: Span) -> Result<TokenStream, Vec<syn::Error>> {
+fn get_schema_def(s: &DataStruct, tag_version: Option<i16>, error_span: Span) -> Result<TokenStream, Vec<syn::Error>> {
     let mut field_exprs = vec![];
     let mut names = vec![];
     match s.fields {
@@ -61,35 +71,52 @@ fn get_schema_def(s: &DataStruct, error_span: Span) -> Result<TokenStream, Vec<s
                 let field_attrs =
                     FieldOptions::from_attributes(&field.attrs[..]).map_err(darling_to_syn)?;
                 let mut field_type = &field.ty;
-                if let Some(option_type) = option_type(field_type) {
-                    field_type = option_type;
+                let is_option = option_type(field_type).is_some();
+                let skip_if_none = is_option && !field_attrs.nullable;
+                if skip_if_none {
+                    field_type = option_type(field_type).unwrap();
                 }
-                let condition = if let (Some(min_version), Some(max_version)) = (field_attrs.min_version, field_attrs.max_version) {
-                    Some(quote! {
-                        version >= #min_version && version <= #max_version
-                    })
-                } else if let Some(min_version) = field_attrs.min_version {
-                    Some(quote! {
-                        version >= #min_version
-                    })
-                } else if let Some(max_version) = field_attrs.max_version {
-                    Some(quote! {
-                        version <= #max_version
-                    })
+                let read = if let Some(deserialize) = &field_attrs.deserialize_with {
+                    quote! {
+                        #deserialize(data)?
+                    }
+                } else if tag_version.is_some() {
+                    quote! {
+                        if flexible {
+                            <#field_type as crate::protocol::deserializer::DeserializeCompact<R>>::deserialize_compact(data, version)?
+                        } else {
+                            <#field_type as crate::protocol::deserializer::DeserializeVersioned<R>>::deserialize_versioned(data, version)?
+                        }
+                    }
                 } else {
-                    None
+                    quote! {
+                        <#field_type as crate::protocol::deserializer::DeserializeVersioned<R>>::deserialize_versioned(data, version)?
+                    }
                 };
-                field_exprs.push(if let Some(condition) = condition {
+                field_exprs.push(if let Some(condition) = version_condition(&field_attrs) {
+                    let (read, fallback) = if skip_if_none {
+                        (quote! { Some(#read) }, quote! { None })
+                    } else if is_option {
+                        (read, quote! { None })
+                    } else if let Some(default) = &field_attrs.default {
+                        (read, quote! { #default() })
+                    } else {
+                        (read, quote! { Default::default() })
+                    };
                     quote! {
                         let #name = if #condition {
-                            Some(<#field_type as crate::protocol::deserializer::DeserializeVersioned<R>>::deserialize_versioned(data, version)?)
+                            #read
                         } else {
-                            None
+                            #fallback
                         };
                     }
+                } else if skip_if_none {
+                    quote! {
+                        let #name = Some(#read);
+                    }
                 } else {
                     quote! {
-                        let #name = <#field_type as crate::protocol::deserializer::DeserializeVersioned<R>>::deserialize_versioned(data, version)?;
+                        let #name = #read;
                     }
                 });
                 names.push(quote! {
@@ -1,23 +1,24 @@
-use std::error::Error;
+use crate::common::{
+    darling_to_syn, flexible_condition, option_type, version_condition, FieldOptions,
+};
 use darling::FromAttributes;
 use proc_macro2::{Span, TokenStream};
 use quote::quote;
-use syn::{Data, DataStruct, DeriveInput, Fields, Type};
-use syn::spanned::Spanned;
-use crate::common::{darling_to_syn, option_type, FieldOptions};
+use syn::{Data, DataStruct, DeriveInput, Fields};
 
 #[derive(FromAttributes)]
 #[darling(attributes(kafka))]
 pub struct VersionedSerializeOptions {
     #[darling(default)]
     pub max_version: i16,
+    /// First version using the flexible (compact) encoding
     #[darling(default)]
     pub tag_version: Option<i16>,
 }
 
 pub fn derive_versioned_serialize_with_options(options: VersionedSerializeOptions, input: &DeriveInput) -> Result<TokenStream, Vec<syn::Error>> {
     let schema_def = match &input.data {
-        Data::Struct(s) => get_schema_def(s, input.ident.span())?,
+        Data::Struct(s) => get_schema_def(s, options.tag_version, input.ident.span())?,
         _ => {
             return Err(vec![syn::Error::new(
                 input.ident.span(),
@@ -30,6 +31,7 @@ pub fn derive_versioned_serialize_with_options(options: VersionedSerializeOption
     let generics = &input.generics;
     let (_, ty_generics, where_clause) = generics.split_for_impl();
     let max_version = options.max_version;
+    let flexible = flexible_condition(options.tag_version);
     Ok(quote! {
         impl <W: std::io::Write> crate::protocol::serializer::SerializeVersioned<W> for #ident #ty_generics #where_clause {
             fn serialize_versioned(&self, writer: &mut W, version: i16) -> Result<(), crate::protocol::error::SerializationError> {
@@ -39,9 +41,17 @@ pub fn derive_versioned_serialize_with_options(options: VersionedSerializeOption
                         given_version: version,
                     });
                 }
+                #[allow(unused_variables)]
+                let flexible = #flexible;
                 #schema_def
             }
         }
+
+        impl <W: std::io::Write> crate::protocol::serializer::SerializeCompact<W> for #ident #ty_generics #where_clause {
+            fn serialize_compact(&self, writer: &mut W, version: i16) -> Result<(), crate::protocol::error::SerializationError> {
+                crate::protocol::serializer::SerializeVersioned::serialize_versioned(self, writer, version)
+            }
+        }
     })
 }
 
@@ -51,7 +61,7 @@ pub fn derive_versioned_serialize(input: &DeriveInput) -> Result<TokenStream, Ve
     derive_versioned_serialize_with_options(named_type_options, input)
 }
 
-fn get_schema_def(s: &DataStruct, error_span: Span) -> Result<TokenStream, Vec<syn::Error>> {
+fn get_schema_def(s: &DataStruct, tag_version: Option<i16>, error_span: Span) -> Result<TokenStream, Vec<syn::Error>> {
     let mut field_exprs = vec![];
     match s.fields {
         Fields::Named(ref a) => {
@@ -60,49 +70,40 @@ fn get_schema_def(s: &DataStruct, error_span: Span) -> Result<TokenStream, Vec<s
                 let field_attrs =
                     FieldOptions::from_attributes(&field.attrs[..]).map_err(darling_to_syn)?;
                 let field_type = &field.ty;
-                let call_serialize = if option_type(field_type).is_some() {
-                    let call_serialize = if let Some(serialize) = field_attrs.serialize_with {
-                        quote! {
-                            #serialize(#name, writer)?;
-                        }
-                    } else {
-                        quote! {
-                            #name.serialize_versioned(writer, version)?;
-                        }
-                    };
+                let skip_if_none = option_type(field_type).is_some() && !field_attrs.nullable;
+                let value = if skip_if_none {
+                    quote! { #name }
+                } else {
+                    quote! { &self.#name }
+                };
+                let write = if let Some(serialize) = &field_attrs.serialize_with {
                     quote! {
-                        if let Some(#name) = &self.#name {
-                            #call_serialize
+                        #serialize(#value, writer)?;
+                    }
+                } else if tag_version.is_some() {
+                    quote! {
+                        if flexible {
+                            crate::protocol::serializer::SerializeCompact::serialize_compact(#value, writer, version)?;
+                        } else {
+                            crate::protocol::serializer::SerializeVersioned::serialize_versioned(#value, writer, version)?;
                         }
                     }
                 } else {
-                    if let Some(serialize) = field_attrs.serialize_with {
-                        quote! {
-                            #serialize(#name, writer)?;
-                        }
-                    } else {
-                        quote! {
-                            self.#name.serialize_versioned(writer, version)?;
-                        }
+                    quote! {
+                        crate::protocol::serializer::SerializeVersioned::serialize_versioned(#value, writer, version)?;
                     }
                 };
-                let condition = if let (Some(min_version), Some(max_version)) = (field_attrs.min_version, field_attrs.max_version) {
-                    Some(quote! {
-                        version >= #min_version && version <= #max_version
-                    })
-                } else if let Some(min_version) = field_attrs.min_version {
-                    Some(quote! {
-                        version >= #min_version
-                    })
-                } else if let Some(max_version) = field_attrs.max_version {
-                    Some(quote! {
-                        version <= #max_version
-                    })
+                let call_serialize = if skip_if_none {
+                    quote! {
+                        if let Some(#name) = &self.#name {
+                            #write
+                        }
+                    }
                 } else {
-                    None
+                    write
                 };
-                
-                field_exprs.push(if let Some(condition) = condition {
+
+                field_exprs.push(if let Some(condition) = version_condition(&field_attrs) {
                     quote! {
                         if #condition {
                             #call_serialize
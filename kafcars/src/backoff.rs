@@ -0,0 +1,180 @@
+use log::info;
+use rand::Rng;
+use snafu::Snafu;
+use std::{future::Future, ops::ControlFlow, time::Duration};
+use tokio::time::Instant;
+
+/// Exponential backoff with jitter used when retrying failed operations.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackoffConfig {
+    /// Delay before the first retry
+    pub init_backoff: Duration,
+    /// Upper bound for the delay between two retries
+    pub max_backoff: Duration,
+    /// Factor the delay grows by after each retry
+    pub multiplier: f64,
+    /// Fraction of the delay that is randomized, `0.0` disables jitter
+    pub jitter: f64,
+    /// Give up once retrying took longer than this, retry forever if `None`
+    pub deadline: Option<Duration>,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        Self {
+            init_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2.,
+            jitter: 0.5,
+            deadline: None,
+        }
+    }
+}
+
+#[derive(Debug, Snafu)]
+pub enum BackoffError {
+    #[snafu(display("Retry exceeded deadline. Source: {source}"))]
+    DeadlineExceeded {
+        deadline: Duration,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+/// State of a single retry loop, create a new one for every operation.
+#[derive(Debug)]
+pub struct Backoff {
+    config: BackoffConfig,
+    next_backoff: Duration,
+    start: Instant,
+}
+
+impl Backoff {
+    pub fn new(config: &BackoffConfig) -> Self {
+        Self {
+            config: config.clone(),
+            next_backoff: config.init_backoff,
+            start: Instant::now(),
+        }
+    }
+
+    /// The delay before the next retry, `None` if it would end after the deadline.
+    fn next(&mut self) -> Option<Duration> {
+        let backoff = self.next_backoff.min(self.config.max_backoff);
+        self.next_backoff = backoff.mul_f64(self.config.multiplier.max(1.));
+
+        let jitter = self.config.jitter.clamp(0., 1.);
+        let backoff = if jitter > 0. {
+            backoff.mul_f64(1. - jitter * rand::thread_rng().gen::<f64>())
+        } else {
+            backoff
+        };
+
+        match self.config.deadline {
+            Some(deadline) if self.start.elapsed() + backoff > deadline => None,
+            _ => Some(backoff),
+        }
+    }
+
+    /// Runs `do_stuff` until it breaks, sleeping between the attempts. Gives up with the last
+    /// error once the deadline is exceeded.
+    pub async fn retry_with_backoff<F, F1, B, E>(
+        &mut self,
+        request_name: &str,
+        mut do_stuff: F,
+    ) -> Result<B, BackoffError>
+    where
+        F: FnMut() -> F1,
+        F1: Future<Output = ControlFlow<B, E>>,
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        loop {
+            let error = match do_stuff().await {
+                ControlFlow::Break(result) => return Ok(result),
+                ControlFlow::Continue(error) => error,
+            };
+            let backoff = self.backoff(request_name, error)?;
+            tokio::time::sleep(backoff).await;
+        }
+    }
+
+    /// The delay before retrying a request that failed with `error`, or the error to give up
+    /// with once the deadline is exceeded.
+    pub(crate) fn backoff<E>(
+        &mut self,
+        request_name: &str,
+        error: E,
+    ) -> Result<Duration, BackoffError>
+    where
+        E: std::error::Error + Send + Sync + 'static,
+    {
+        let Some(backoff) = self.next() else {
+            return Err(BackoffError::DeadlineExceeded {
+                deadline: self.config.deadline.unwrap_or_default(),
+                source: Box::new(error),
+            });
+        };
+        info!(
+            "Request '{}' failed, retrying in {:?}: {}",
+            request_name, backoff, error
+        );
+        Ok(backoff)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn test_backoff_grows_until_max() {
+        let mut backoff = Backoff::new(&BackoffConfig {
+            init_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(5),
+            multiplier: 2.,
+            jitter: 0.,
+            deadline: None,
+        });
+
+        let delays: Vec<_> = (0..5).map(|_| backoff.next().unwrap().as_secs()).collect();
+        assert_eq!(delays, vec![1, 2, 4, 5, 5]);
+    }
+
+    #[test]
+    fn test_backoff_jitter_stays_in_bounds() {
+        let mut backoff = Backoff::new(&BackoffConfig {
+            init_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(1),
+            jitter: 0.5,
+            ..Default::default()
+        });
+
+        for _ in 0..100 {
+            let delay = backoff.next().unwrap();
+            assert!(delay >= Duration::from_millis(500) && delay <= Duration::from_secs(1));
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retry_stops_at_deadline() {
+        let mut backoff = Backoff::new(&BackoffConfig {
+            deadline: Some(Duration::from_secs(1)),
+            ..Default::default()
+        });
+
+        let mut attempts = 0;
+        let err = backoff
+            .retry_with_backoff("test", || {
+                attempts += 1;
+                async { ControlFlow::<(), _>::Continue(io::Error::other("unreachable")) }
+            })
+            .await
+            .unwrap_err();
+
+        assert!(attempts > 1);
+        assert_eq!(
+            err.to_string(),
+            "Retry exceeded deadline. Source: unreachable"
+        );
+    }
+}
@@ -1,23 +1,458 @@
 use crate::{
-    error::Result,
-    protocol::messages::metadata::{MetadataRequest, MetadataRequestTopic},
+    backoff::{Backoff, BackoffConfig},
+    client::{
+        stream::{ConnectionConfig, ConnectionStream},
+        transport::{Connector, TcpConnector},
+    },
+    error::{
+        ClientClosedSnafu, ClusterIdMismatchSnafu, ConnectSnafu, ConnectionSnafu, Error, Result,
+    },
+    protocol::messages::metadata::MetadataRequest,
+};
+use log::info;
+use snafu::{ensure, ResultExt};
+use std::{
+    future::Future,
+    ops::ControlFlow,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Weak,
+    },
+};
+use tokio::{
+    spawn,
+    sync::watch,
+    time::{sleep, timeout_at, Instant},
 };
-use tokio::net::TcpStream;
 
-pub struct BrokerConnection {
+/// Connection to a single broker that is (re-)established lazily when it is needed, e.g. after
+/// the previous connection got poisoned.
+pub struct BrokerConnection<C: Connector = TcpConnector> {
     broker: String,
-    client_id: Option<String>,
+    connector: C,
+    config: ConnectionConfig,
+    backoff_config: BackoffConfig,
+    /// Never locked across an `.await`, callers wait for changes instead
+    current: watch::Sender<Current<C::Transport>>,
+    closed: AtomicBool,
+}
+
+enum Current<T> {
+    Disconnected,
+    /// One caller connects, the others wait until it succeeded or failed
+    Connecting,
+    Connected(Arc<ConnectionStream<T>>),
+}
+
+impl<T> Clone for Current<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Disconnected => Self::Disconnected,
+            Self::Connecting => Self::Connecting,
+            Self::Connected(stream) => Self::Connected(stream.clone()),
+        }
+    }
+}
+
+/// What the connection to a broker is doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Disconnected,
+    Connecting,
+    Connected { in_flight_requests: usize },
+}
+
+/// Gives up the connection attempt of a caller when it fails or is dropped, so that the next
+/// caller connects.
+struct ConnectAttempt<'a, T> {
+    current: &'a watch::Sender<Current<T>>,
+}
+
+impl<T> Drop for ConnectAttempt<'_, T> {
+    fn drop(&mut self) {
+        self.current.send_if_modified(|current| match current {
+            Current::Connecting => {
+                *current = Current::Disconnected;
+                true
+            }
+            _ => false,
+        });
+    }
 }
 
-impl BrokerConnection {
-    pub async fn new(broker: String, client_id: Option<String>) -> Result<Self> {
-        let connection = TcpStream::connect(broker.clone()).await?;
-        Ok(Self { broker, client_id })
+impl<C: Connector> BrokerConnection<C> {
+    pub fn new(
+        broker: String,
+        connector: C,
+        config: ConnectionConfig,
+        backoff_config: BackoffConfig,
+    ) -> Self {
+        Self {
+            broker,
+            connector,
+            config,
+            backoff_config,
+            current: watch::Sender::new(Current::Disconnected),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the current connection, or connects with backoff if there is no usable one.
+    pub async fn get(&self) -> Result<Arc<ConnectionStream<C::Transport>>> {
+        self.get_or_connect(|| async {
+            let mut backoff = Backoff::new(&self.backoff_config);
+            backoff
+                .retry_with_backoff(&format!("connect to {}", self.broker), || async {
+                    match self.connect().await {
+                        Ok(stream) => ControlFlow::Break(Ok(stream)),
+                        // connecting again will not change the cluster of the broker
+                        Err(e @ Error::ClusterIdMismatch { .. }) => ControlFlow::Break(Err(e)),
+                        Err(e) => ControlFlow::Continue(e),
+                    }
+                })
+                .await
+                .context(ConnectionSnafu)?
+        })
+        .await
     }
 
-    pub async fn request_metadata(&self, topics: Vec<String>) -> Result<()> {
-        Ok(())
+    /// Like [`get`](Self::get), but tries to connect only once.
+    pub async fn try_get(&self) -> Result<Arc<ConnectionStream<C::Transport>>> {
+        self.get_or_connect(|| self.connect()).await
+    }
+
+    /// Returns the current connection if it is usable. Otherwise the first caller connects with
+    /// `connect`, and concurrent callers wait for its connection or try themselves if it failed.
+    async fn get_or_connect<F, Fut>(
+        &self,
+        connect: F,
+    ) -> Result<Arc<ConnectionStream<C::Transport>>>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<Arc<ConnectionStream<C::Transport>>>>,
+    {
+        let mut changes = self.current.subscribe();
+        loop {
+            ensure!(!self.closed.load(Ordering::SeqCst), ClientClosedSnafu);
+            let current = changes.borrow_and_update().clone();
+            let claimed = match current {
+                Current::Connecting => {
+                    // the sender lives as long as `self`
+                    changes.changed().await.ok();
+                    continue;
+                }
+                Current::Connected(stream) => match stream.poisoned().await {
+                    None => return Ok(stream),
+                    Some(cause) => {
+                        let claimed = self.claim(|current| {
+                            matches!(current, Current::Connected(s) if Arc::ptr_eq(s, &stream))
+                        });
+                        if claimed {
+                            info!("Reconnecting to broker {}: {}", self.broker, cause);
+                        }
+                        claimed
+                    }
+                },
+                Current::Disconnected => {
+                    self.claim(|current| matches!(current, Current::Disconnected))
+                }
+            };
+            if !claimed {
+                continue;
+            }
+
+            let attempt = ConnectAttempt {
+                current: &self.current,
+            };
+            // a close that started before the claim does not wait for this attempt
+            ensure!(!self.closed.load(Ordering::SeqCst), ClientClosedSnafu);
+            let stream = connect().await?;
+            self.current
+                .send_replace(Current::Connected(stream.clone()));
+            drop(attempt);
+            return Ok(stream);
+        }
+    }
+
+    /// Marks the connection as connecting if it is still in the state `seen`, so that only one
+    /// caller connects.
+    fn claim(&self, seen: impl Fn(&Current<C::Transport>) -> bool) -> bool {
+        self.current.send_if_modified(|current| {
+            if seen(current) {
+                *current = Current::Connecting;
+                true
+            } else {
+                false
+            }
+        })
+    }
+
+    /// Closes the current connection after draining it until `deadline`, and refuses to
+    /// reconnect afterwards.
+    pub async fn close(&self, deadline: Instant) {
+        self.closed.store(true, Ordering::SeqCst);
+        // waits for a connection attempt in progress, so its stream is closed as well
+        let mut changes = self.current.subscribe();
+        let connecting = changes.wait_for(|current| !matches!(current, Current::Connecting));
+        if timeout_at(deadline, connecting).await.is_err() {
+            return;
+        }
+        if let Current::Connected(stream) = self.current.send_replace(Current::Disconnected) {
+            stream.close(deadline).await;
+        }
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        match &*self.current.borrow() {
+            Current::Disconnected => ConnectionState::Disconnected,
+            Current::Connecting => ConnectionState::Connecting,
+            Current::Connected(stream) => ConnectionState::Connected {
+                in_flight_requests: stream.in_flight_requests(),
+            },
+        }
+    }
+
+    async fn connect(&self) -> Result<Arc<ConnectionStream<C::Transport>>> {
+        let transport = self
+            .connector
+            .connect(&self.broker)
+            .await
+            .context(ConnectSnafu {
+                broker: self.broker.clone(),
+            })?;
+        let mut stream = ConnectionStream::new(transport, self.config.clone());
+        stream.sync_versions().await?;
+        if self.config.expected_cluster_id.is_some() {
+            let metadata = stream
+                .send_request(MetadataRequest::new(Some(vec![])))
+                .await?;
+            verify_cluster_id(&self.config, &self.broker, metadata.cluster_id.as_deref())?;
+        }
+
+        let stream = Arc::new(stream);
+        spawn(Self::health_check(
+            Arc::downgrade(&stream),
+            self.broker.clone(),
+            self.config.clone(),
+        ));
+        Ok(stream)
+    }
+
+    /// Closes the connection once it is idle for too long and probes it while it is idle, until
+    /// it is poisoned or dropped. It is reopened by the next [`get`](Self::get).
+    async fn health_check(
+        stream: Weak<ConnectionStream<C::Transport>>,
+        broker: String,
+        config: ConnectionConfig,
+    ) {
+        let Some(period) = [config.connections_max_idle, config.keepalive_interval]
+            .into_iter()
+            .flatten()
+            .min()
+        else {
+            return;
+        };
+        let mut last_probe = Instant::now();
+
+        loop {
+            sleep(period / 2).await;
+            let Some(stream) = stream.upgrade() else {
+                return;
+            };
+            if stream.poisoned().await.is_some() || stream.close_if_idle().await {
+                return;
+            }
+
+            if let Some(interval) = config.keepalive_interval {
+                if stream.idle_for() >= interval && last_probe.elapsed() >= interval {
+                    last_probe = Instant::now();
+                    if let Err(e) = stream.keepalive().await {
+                        info!("Keepalive to broker {} failed: {}", broker, e);
+                        return;
+                    }
+                }
+            }
+        }
     }
 }
 
-async fn metadata_request(request: &MetadataRequest, connection: &BrokerConnection) {}
+/// Fails unless the cluster id a broker reported is the expected one, if there is any.
+pub(crate) fn verify_cluster_id(
+    config: &ConnectionConfig,
+    broker: &str,
+    actual: Option<&str>,
+) -> Result<()> {
+    match &config.expected_cluster_id {
+        Some(expected) if actual != Some(expected.as_str()) => ClusterIdMismatchSnafu {
+            broker,
+            expected,
+            actual: actual.map(str::to_string),
+        }
+        .fail(),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{mock::MockCluster, stream::PoisonCause};
+    use assert_matches::assert_matches;
+    use futures::{
+        future::{join_all, BoxFuture},
+        FutureExt,
+    };
+    use std::{
+        io,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+    use tokio::io::DuplexStream;
+
+    #[derive(Debug, Default)]
+    struct RefusingConnector {
+        attempts: AtomicUsize,
+    }
+
+    impl Connector for RefusingConnector {
+        type Transport = DuplexStream;
+
+        fn connect<'a>(&'a self, _: &'a str) -> BoxFuture<'a, io::Result<Self::Transport>> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            async { Err(io::Error::from(io::ErrorKind::ConnectionRefused)) }.boxed()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_gives_up_with_last_cause() {
+        let broker = BrokerConnection::new(
+            "localhost:9000".to_string(),
+            RefusingConnector::default(),
+            ConnectionConfig::default(),
+            BackoffConfig {
+                deadline: Some(Duration::from_secs(1)),
+                ..Default::default()
+            },
+        );
+
+        let err = broker.get().await.err().unwrap();
+        assert_matches!(err, Error::Connection { .. });
+        assert!(err.to_string().starts_with(concat!(
+            "All retries failed: Retry exceeded deadline. ",
+            "Source: Error connecting to broker \"localhost:9000\""
+        )));
+        assert!(broker.connector.attempts.load(Ordering::SeqCst) > 1);
+    }
+
+    fn mock_broker(mock: &MockCluster, config: ConnectionConfig) -> BrokerConnection<MockCluster> {
+        BrokerConnection::new(
+            "broker-0:9092".to_string(),
+            mock.clone(),
+            config,
+            BackoffConfig::default(),
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_connection_is_closed_and_reopened() {
+        let mock = MockCluster::new(1);
+        let broker = mock_broker(
+            &mock,
+            ConnectionConfig {
+                connections_max_idle: Some(Duration::from_secs(60)),
+                ..Default::default()
+            },
+        );
+
+        let stream = broker.get().await.unwrap();
+        tokio::time::sleep(Duration::from_secs(100)).await;
+        assert_matches!(
+            stream.poisoned().await.as_deref(),
+            Some(PoisonCause::Idle { .. })
+        );
+
+        broker.get().await.unwrap();
+        assert_eq!(mock.state.lock().connections.len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_keepalive_detects_dead_connection() {
+        let mock = MockCluster::new(1);
+        let broker = mock_broker(
+            &mock,
+            ConnectionConfig {
+                request_timeout: Duration::from_secs(5),
+                connections_max_idle: None,
+                keepalive_interval: Some(Duration::from_secs(10)),
+                ..Default::default()
+            },
+        );
+
+        let stream = broker.get().await.unwrap();
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert!(stream.poisoned().await.is_none());
+
+        mock.state.lock().silent = true;
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        assert_matches!(
+            stream.poisoned().await.as_deref(),
+            Some(PoisonCause::KeepaliveFailed { .. })
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_one_caller_connects_while_others_wait() {
+        let mock = MockCluster::new(1);
+        mock.state.lock().down.insert("broker-0:9092".to_string());
+        let broker = Arc::new(mock_broker(&mock, ConnectionConfig::default()));
+
+        // a dropped caller gives up its attempt
+        tokio::time::timeout(Duration::from_millis(10), broker.get())
+            .await
+            .unwrap_err();
+        assert_eq!(broker.state(), ConnectionState::Disconnected);
+
+        let gets: Vec<_> = (0..10)
+            .map(|_| {
+                let broker = broker.clone();
+                spawn(async move { broker.get().await })
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(broker.state(), ConnectionState::Connecting);
+
+        mock.state.lock().down.clear();
+        for get in join_all(gets).await {
+            get.unwrap().unwrap();
+        }
+        assert_eq!(mock.state.lock().connections.len(), 1);
+        assert_eq!(
+            broker.state(),
+            ConnectionState::Connected {
+                in_flight_requests: 0
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_refuses_broker_of_other_cluster() {
+        let mock = MockCluster::new(1);
+        let broker = mock_broker(
+            &mock,
+            ConnectionConfig {
+                expected_cluster_id: Some("production".to_string()),
+                ..Default::default()
+            },
+        );
+
+        assert_matches!(
+            broker.get().await,
+            Err(Error::ClusterIdMismatch { actual: Some(actual), .. }) if actual == "mock-cluster"
+        );
+        // not retried
+        assert_eq!(mock.state.lock().connections.len(), 1);
+
+        mock.state.lock().cluster_id = Some("production".to_string());
+        broker.get().await.unwrap();
+    }
+}
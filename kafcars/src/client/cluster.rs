@@ -0,0 +1,536 @@
+use crate::{
+    backoff::{Backoff, BackoffConfig},
+    client::{
+        broker::verify_cluster_id,
+        interceptor::Interceptors,
+        metadata::{MetadataCache, MetadataResponse, MetadataResponseBroker, TopologyDiff},
+        pool::{BrokerPool, ConnectionPoolConfig, RequestClass},
+        producer::memory::BufferMemory,
+        resolver::{split_address, BrokerAddressResolver},
+        stream::ConnectionConfig,
+        transport::BoxedConnector,
+    },
+    error::{ClientClosedSnafu, ConnectionSnafu, Error, NoBrokersSnafu, Result, ServerSnafu},
+    protocol::{
+        api_key::ApiKey,
+        deserializer::DeserializeVersioned,
+        error::Error as ProtocolError,
+        messages::{
+            find_coordinator::{CoordinatorType, FindCoordinatorRequest},
+            metadata::MetadataRequest,
+            KafkaRequest, KafkaResponse, TaggedFields,
+        },
+        serializer::SerializeVersioned,
+    },
+};
+use futures::future::{join_all, BoxFuture};
+use log::warn;
+use parking_lot::{Mutex, RwLock};
+use rand::{prelude::SliceRandom, thread_rng};
+use snafu::{ensure, ResultExt};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io::Cursor,
+    ops::ControlFlow,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Weak,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::broadcast,
+    time::{timeout_at, Instant},
+};
+
+pub(crate) type Broker = Arc<BrokerPool<BoxedConnector>>;
+
+/// Work that has to finish before the connections of a closing client are shut down, e.g.
+/// flushing buffered records.
+pub(crate) trait CloseHook: Send + Sync {
+    fn close(&self, deadline: Instant) -> BoxFuture<'_, ()>;
+}
+
+/// The brokers of a cluster and its metadata, shared by a client and everything created from it.
+pub(crate) struct Cluster {
+    /// Brokers to discover the cluster through, kept for when no known broker answers
+    bootstrap_brokers: Vec<Broker>,
+    /// Brokers of the cluster by node id, connected lazily when they are first used
+    brokers: RwLock<HashMap<i32, Broker>>,
+    connector: BoxedConnector,
+    address_resolver: Arc<dyn BrokerAddressResolver>,
+    config: ConnectionConfig,
+    pool_config: ConnectionPoolConfig,
+    backoff_config: BackoffConfig,
+    metadata_cache: MetadataCache,
+    close_hooks: Mutex<Vec<Weak<dyn CloseHook>>>,
+    closed: AtomicBool,
+    /// Shared by all producers of the client
+    producer_memory: Arc<BufferMemory>,
+    interceptors: Interceptors,
+}
+
+impl Cluster {
+    pub fn new(
+        bootstrap_brokers: Vec<String>,
+        connector: BoxedConnector,
+        address_resolver: Arc<dyn BrokerAddressResolver>,
+        config: ConnectionConfig,
+        pool_config: ConnectionPoolConfig,
+        backoff_config: BackoffConfig,
+        metadata_max_age: Duration,
+    ) -> Self {
+        let bootstrap_brokers = bootstrap_brokers
+            .iter()
+            .map(|broker| {
+                let address = match split_address(broker) {
+                    Some((host, port)) => address_resolver.resolve(None, host, port),
+                    None => broker.clone(),
+                };
+                Arc::new(BrokerPool::new(
+                    address,
+                    connector.clone(),
+                    config.clone(),
+                    &pool_config,
+                    backoff_config.clone(),
+                ))
+            })
+            .collect();
+        Self {
+            bootstrap_brokers,
+            brokers: RwLock::new(HashMap::new()),
+            connector,
+            address_resolver,
+            config,
+            pool_config,
+            backoff_config,
+            metadata_cache: MetadataCache::new(metadata_max_age),
+            close_hooks: Mutex::new(vec![]),
+            closed: AtomicBool::new(false),
+            producer_memory: Arc::new(BufferMemory::default()),
+            interceptors: Interceptors::default(),
+        }
+    }
+
+    pub fn with_producer_memory(mut self, producer_memory: BufferMemory) -> Self {
+        self.producer_memory = Arc::new(producer_memory);
+        self
+    }
+
+    pub fn with_interceptors(mut self, interceptors: Interceptors) -> Self {
+        self.interceptors = interceptors;
+        self
+    }
+
+    /// Requests metadata from the first broker that answers, trying the known brokers and then
+    /// the bootstrap brokers in random order. `None` requests all topics.
+    pub async fn request_metadata(&self, topics: Option<Vec<String>>) -> Result<MetadataResponse> {
+        let (broker, response) = self
+            .request_any(|| MetadataRequest::new(topics.clone()))
+            .await?;
+        verify_cluster_id(&self.config, &broker, response.cluster_id.as_deref())?;
+        self.update_brokers(&response.brokers);
+        Ok(response)
+    }
+
+    /// Sends the request built by `request` to the first broker that answers, trying the known
+    /// brokers and then the bootstrap brokers in random order. Returns the address of the broker
+    /// that answered with its response.
+    pub async fn request_any<R, F>(&self, request: F) -> Result<(String, R::KafkaResponse)>
+    where
+        F: Fn() -> R,
+        R: KafkaRequest + SerializeVersioned<Vec<u8>>,
+        R::KafkaResponse: KafkaResponse + DeserializeVersioned<Cursor<Vec<u8>>>,
+    {
+        ensure!(!self.is_closed(), ClientClosedSnafu);
+        let mut last_error = None;
+        for broker in self.metadata_candidates() {
+            let result = async {
+                Ok(broker
+                    .try_get(RequestClass::Admin)
+                    .await?
+                    .send_request(request())
+                    .await?)
+            }
+            .await;
+            match result {
+                Ok(response) => return Ok((broker.broker().to_string(), response)),
+                Err(e @ Error::ClusterIdMismatch { .. }) => return Err(e),
+                Err(e) => {
+                    warn!("{} request to {} failed: {}", R::API_KEY, broker.broker(), e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| NoBrokersSnafu.build()))
+    }
+
+    /// Metadata of all topics, served from the cache while it is fresh.
+    pub async fn metadata(&self) -> Result<Arc<MetadataResponse>> {
+        self.metadata_cache
+            .get(|| self.request_metadata(None))
+            .await
+    }
+
+    /// Fetches the metadata of all topics into the cache, regardless of its age.
+    pub async fn refresh_metadata(&self) -> Result<Arc<MetadataResponse>> {
+        self.metadata_cache
+            .refresh(|| self.request_metadata(None))
+            .await
+    }
+
+    /// Receives the topology changes found by every later metadata refresh.
+    pub fn subscribe_topology(&self) -> broadcast::Receiver<TopologyDiff> {
+        self.metadata_cache.subscribe()
+    }
+
+    pub fn backoff_config(&self) -> &BackoffConfig {
+        &self.backoff_config
+    }
+
+    pub fn producer_memory(&self) -> &Arc<BufferMemory> {
+        &self.producer_memory
+    }
+
+    pub fn interceptors(&self) -> &Interceptors {
+        &self.interceptors
+    }
+
+    pub fn metadata_max_age(&self) -> Duration {
+        self.metadata_cache.max_age()
+    }
+
+    /// Invalidates the cached metadata if a broker responded with an error caused by it being
+    /// outdated.
+    pub fn invalidate_metadata_on(&self, error: ProtocolError) {
+        if error.invalidates_metadata() {
+            self.metadata_cache.invalidate(&error.to_string());
+        }
+    }
+
+    /// The node id of the current leader of a partition.
+    pub async fn partition_leader(&self, topic: &str, partition: i32) -> Result<i32> {
+        let metadata = self.metadata().await?;
+        let leader = match metadata.topics.iter().find(|t| t.name == topic) {
+            None => Err(ProtocolError::UnknownTopicOrPartition),
+            Some(topic) => match (
+                topic.error,
+                topic
+                    .partitions
+                    .iter()
+                    .find(|p| p.partition_index == partition),
+            ) {
+                (Some(error), _) => Err(error),
+                (None, None) => Err(ProtocolError::UnknownTopicOrPartition),
+                (None, Some(partition)) => match partition.error {
+                    Some(error) => Err(error),
+                    None if partition.leader_id < 0 => Err(ProtocolError::LeaderNotAvailable),
+                    None => Ok(partition.leader_id),
+                },
+            },
+        };
+
+        leader.or_else(|error| {
+            self.invalidate_metadata_on(error);
+            ServerSnafu {
+                api_key: ApiKey::Metadata,
+                error,
+            }
+            .fail()
+        })
+    }
+
+    /// The number of partitions of a topic.
+    pub async fn partition_count(&self, topic: &str) -> Result<i32> {
+        let metadata = self.metadata().await?;
+        let count = match metadata.topics.iter().find(|t| t.name == topic) {
+            None => Err(ProtocolError::UnknownTopicOrPartition),
+            Some(topic) => match topic.error {
+                Some(error) => Err(error),
+                None if topic.partitions.is_empty() => Err(ProtocolError::LeaderNotAvailable),
+                None => Ok(topic.partitions.len() as i32),
+            },
+        };
+
+        count.or_else(|error| {
+            self.invalidate_metadata_on(error);
+            ServerSnafu {
+                api_key: ApiKey::Metadata,
+                error,
+            }
+            .fail()
+        })
+    }
+
+    /// The broker currently leading a partition.
+    pub async fn leader_broker(&self, topic: &str, partition: i32) -> Result<Broker> {
+        let leader = self.partition_leader(topic, partition).await?;
+        let broker = self.brokers.read().get(&leader).cloned();
+        broker.ok_or_else(|| {
+            // the metadata names a leader that is not among its brokers
+            let error = ProtocolError::LeaderNotAvailable;
+            self.invalidate_metadata_on(error);
+            ServerSnafu {
+                api_key: ApiKey::Metadata,
+                error,
+            }
+            .build()
+        })
+    }
+
+    /// The controller of the cluster, which creates and deletes topics.
+    pub async fn controller(&self) -> Result<Broker> {
+        let metadata = self.metadata().await?;
+        let broker = metadata
+            .controller_id
+            .and_then(|controller| self.brokers.read().get(&controller).cloned());
+        broker.ok_or_else(|| {
+            let error = ProtocolError::NotController;
+            self.invalidate_metadata_on(error);
+            ServerSnafu {
+                api_key: ApiKey::Metadata,
+                error,
+            }
+            .build()
+        })
+    }
+
+    /// The broker coordinating a consumer group or transaction. Fails with a retriable error if
+    /// the coordinator is not known yet.
+    pub async fn coordinator(&self, key: &str, key_type: CoordinatorType) -> Result<Broker> {
+        let (_, response) = self
+            .request_any(|| FindCoordinatorRequest {
+                key: key.to_string(),
+                key_type: key_type.into(),
+                tagged_fields: Some(TaggedFields::default()),
+            })
+            .await?;
+        if let Some(error) = response.error {
+            return ServerSnafu {
+                api_key: ApiKey::FindCoordinator,
+                error,
+            }
+            .fail();
+        }
+
+        if !self.brokers.read().contains_key(&response.node_id) {
+            // a broker that joined after the last metadata refresh
+            self.refresh_metadata().await?;
+        }
+        let broker = self.brokers.read().get(&response.node_id).cloned();
+        broker.ok_or_else(|| {
+            ServerSnafu {
+                api_key: ApiKey::FindCoordinator,
+                error: ProtocolError::CoordinatorNotAvailable,
+            }
+            .build()
+        })
+    }
+
+    fn metadata_candidates(&self) -> Vec<Broker> {
+        let mut brokers: Vec<Broker> = self.brokers.read().values().cloned().collect();
+        brokers.shuffle(&mut thread_rng());
+
+        let mut bootstrap_brokers = self.bootstrap_brokers.clone();
+        bootstrap_brokers.shuffle(&mut thread_rng());
+
+        brokers.extend(bootstrap_brokers);
+        brokers
+    }
+
+    /// Replaces the known brokers, keeping the connections of brokers whose resolved address is
+    /// unchanged.
+    fn update_brokers(&self, response_brokers: &[MetadataResponseBroker]) {
+        let mut brokers = self.brokers.write();
+        *brokers = response_brokers
+            .iter()
+            .map(|broker| {
+                let address =
+                    self.address_resolver
+                        .resolve(Some(broker.node_id), &broker.host, broker.port);
+                let connection = match brokers.get(&broker.node_id) {
+                    Some(connection) if connection.broker() == address => connection.clone(),
+                    _ => Arc::new(self.new_broker(address)),
+                };
+                (broker.node_id, connection)
+            })
+            .collect();
+    }
+
+    fn new_broker(&self, address: String) -> BrokerPool<BoxedConnector> {
+        BrokerPool::new(
+            address,
+            self.connector.clone(),
+            self.config.clone(),
+            &self.pool_config,
+            self.backoff_config.clone(),
+        )
+    }
+
+    pub fn in_flight_requests(&self) -> BTreeMap<i32, usize> {
+        self.brokers
+            .read()
+            .iter()
+            .map(|(node_id, broker)| (*node_id, broker.in_flight_requests()))
+            .collect()
+    }
+
+    pub fn connecting_brokers(&self) -> BTreeSet<i32> {
+        self.brokers
+            .read()
+            .iter()
+            .filter(|(_, broker)| broker.is_connecting())
+            .map(|(node_id, _)| *node_id)
+            .collect()
+    }
+
+    /// Runs `hook` when the client is closed, as long as it is alive.
+    pub fn register_close_hook(&self, hook: Weak<dyn CloseHook>) {
+        self.close_hooks.lock().push(hook);
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Refuses new work, runs the close hooks and then drains and closes every broker
+    /// connection, giving up at `deadline`.
+    pub async fn close(&self, deadline: Instant) {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        let hooks: Vec<_> = self
+            .close_hooks
+            .lock()
+            .drain(..)
+            .filter_map(|hook| hook.upgrade())
+            .collect();
+        let hooks = join_all(hooks.iter().map(|hook| hook.close(deadline)));
+        if timeout_at(deadline, hooks).await.is_err() {
+            warn!("Close hooks did not finish before the deadline");
+        }
+
+        let mut brokers: Vec<_> = self.brokers.read().values().cloned().collect();
+        brokers.extend(self.bootstrap_brokers.iter().cloned());
+        join_all(brokers.iter().map(|broker| broker.close(deadline))).await;
+    }
+
+    /// Discovers the cluster through the first bootstrap broker that answers a metadata request.
+    pub async fn bootstrap(&self) -> Result<()> {
+        let mut backoff = Backoff::new(&self.backoff_config);
+        backoff
+            .retry_with_backoff("bootstrap", || async {
+                match self.request_metadata(Some(vec![])).await {
+                    Ok(_) => ControlFlow::Break(Ok(())),
+                    Err(e @ Error::ClusterIdMismatch { .. }) => ControlFlow::Break(Err(e)),
+                    Err(e) => ControlFlow::Continue(e),
+                }
+            })
+            .await
+            .context(ConnectionSnafu)?
+    }
+
+    #[cfg(test)]
+    pub fn brokers(&self) -> Vec<(i32, String)> {
+        let mut brokers: Vec<_> = self
+            .brokers
+            .read()
+            .iter()
+            .map(|(node_id, broker)| (*node_id, broker.broker().to_string()))
+            .collect();
+        brokers.sort();
+        brokers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{mock::MockCluster, resolver::AdvertisedAddresses};
+    use futures::future::try_join_all;
+
+    fn cluster(mock: &MockCluster, metadata_max_age: Duration) -> Arc<Cluster> {
+        Arc::new(Cluster::new(
+            vec!["broker-0:9092".to_string()],
+            BoxedConnector::new(mock.clone()),
+            Arc::new(AdvertisedAddresses),
+            ConnectionConfig::default(),
+            ConnectionPoolConfig::default(),
+            BackoffConfig::default(),
+            metadata_max_age,
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_lookups_share_one_request() {
+        let mock = MockCluster::new(3);
+        mock.add_topic("test", 6);
+        let cluster = cluster(&mock, Duration::from_secs(60));
+
+        let leaders = try_join_all((0..1000).map(|i| {
+            let cluster = cluster.clone();
+            async move { cluster.partition_leader("test", i % 6).await }
+        }))
+        .await
+        .unwrap();
+
+        assert_eq!(leaders[4], 1);
+        assert_eq!(mock.state.lock().metadata_requests, 1);
+    }
+
+    #[tokio::test]
+    async fn test_invalidated_by_leader_errors() {
+        let mock = MockCluster::new(2);
+        mock.add_topic("test", 1);
+        let cluster = cluster(&mock, Duration::from_secs(60));
+        assert_eq!(cluster.partition_leader("test", 0).await.unwrap(), 0);
+
+        mock.state.lock().topics[0].partitions[0].leader_id = 1;
+        cluster.invalidate_metadata_on(ProtocolError::InvalidRequest);
+        assert_eq!(cluster.partition_leader("test", 0).await.unwrap(), 0);
+
+        cluster.invalidate_metadata_on(ProtocolError::NotLeaderOrFollower);
+        assert_eq!(cluster.partition_leader("test", 0).await.unwrap(), 1);
+        assert_eq!(mock.state.lock().metadata_requests, 2);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_topic_is_looked_up_again() {
+        let mock = MockCluster::new(1);
+        let cluster = cluster(&mock, Duration::from_secs(60));
+        assert!(cluster.partition_leader("test", 0).await.is_err());
+
+        mock.add_topic("test", 1);
+        assert_eq!(cluster.partition_leader("test", 0).await.unwrap(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_refreshed_after_max_age() {
+        let mock = MockCluster::new(1);
+        let cluster = cluster(&mock, Duration::from_secs(60));
+        cluster.metadata().await.unwrap();
+        cluster.metadata().await.unwrap();
+        assert_eq!(mock.state.lock().metadata_requests, 1);
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        cluster.metadata().await.unwrap();
+        assert_eq!(mock.state.lock().metadata_requests, 2);
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_connection_is_reused() {
+        let mock = MockCluster::new(1);
+        // the advertised address of the only broker is unreachable
+        mock.state
+            .lock()
+            .advertised
+            .insert(0, ("unreachable".to_string(), 9092));
+        let cluster = cluster(&mock, Duration::from_secs(60));
+
+        cluster.request_metadata(None).await.unwrap();
+        cluster.request_metadata(None).await.unwrap();
+        assert_eq!(mock.state.lock().metadata_requests, 2);
+        assert_eq!(mock.state.lock().connections, vec!["broker-0:9092"]);
+    }
+}
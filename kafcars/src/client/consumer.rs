@@ -0,0 +1,243 @@
+//! Consumption of a single partition as a stream of records.
+
+use crate::{
+    client::partition::{OffsetAt, PartitionClient},
+    error::Result,
+    record::RecordAndOffset,
+};
+use futures::{
+    stream::{unfold, BoxStream},
+    Stream, StreamExt,
+};
+use std::{
+    collections::VecDeque,
+    ops::Range,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+/// Default bytes a fetch waits for
+const DEFAULT_MIN_BATCH_SIZE: i32 = 1;
+/// Default bytes a fetch returns at most, unless its first batch is larger
+const DEFAULT_MAX_BATCH_SIZE: i32 = 50 * 1024 * 1024;
+/// Default time a fetch waits for the minimum batch size
+const DEFAULT_MAX_WAIT_MS: i32 = 500;
+
+/// Where a [`StreamConsumer`] starts consuming.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartOffset {
+    /// The first record that was not deleted yet
+    Earliest,
+    /// The next record produced after the consumer started
+    Latest,
+    /// The record at this offset
+    At(i64),
+}
+
+pub struct StreamConsumerBuilder {
+    client: Arc<PartitionClient>,
+    start_offset: StartOffset,
+    min_batch_size: i32,
+    max_batch_size: i32,
+    max_wait_ms: i32,
+}
+
+impl StreamConsumerBuilder {
+    pub fn new(client: Arc<PartitionClient>, start_offset: StartOffset) -> Self {
+        Self {
+            client,
+            start_offset,
+            min_batch_size: DEFAULT_MIN_BATCH_SIZE,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            max_wait_ms: DEFAULT_MAX_WAIT_MS,
+        }
+    }
+
+    /// Bytes the partition leader waits for before answering a fetch.
+    pub fn with_min_batch_size(mut self, min_batch_size: i32) -> Self {
+        self.min_batch_size = min_batch_size;
+        self
+    }
+
+    /// Bytes a fetch returns at most, unless the first batch is larger.
+    pub fn with_max_batch_size(mut self, max_batch_size: i32) -> Self {
+        self.max_batch_size = max_batch_size;
+        self
+    }
+
+    /// Time the partition leader waits for the minimum batch size before answering a fetch.
+    pub fn with_max_wait_ms(mut self, max_wait_ms: i32) -> Self {
+        self.max_wait_ms = max_wait_ms;
+        self
+    }
+
+    pub fn build(self) -> StreamConsumer {
+        let next_offset = match self.start_offset {
+            StartOffset::At(offset) => Some(offset),
+            StartOffset::Earliest | StartOffset::Latest => None,
+        };
+        let fetch = Fetch {
+            client: self.client,
+            start_offset: self.start_offset,
+            bytes: self.min_batch_size..self.max_batch_size.saturating_add(1),
+            max_wait_ms: self.max_wait_ms,
+            next_offset,
+            records: VecDeque::new(),
+            high_watermark: -1,
+        };
+        StreamConsumer {
+            records: unfold(Some(fetch), |fetch| async move {
+                let mut fetch = fetch?;
+                match fetch.next().await {
+                    Ok(record) => Some((Ok(record), Some(fetch))),
+                    // the error ends the stream
+                    Err(e) => Some((Err(e), None)),
+                }
+            })
+            .boxed(),
+        }
+    }
+}
+
+/// Fetches the records of a partition one after another, yielding each with the high
+/// watermark of the partition when it was fetched. Ends after the first error, as fetches are
+/// already retried by the [`PartitionClient`].
+pub struct StreamConsumer {
+    records: BoxStream<'static, Result<(RecordAndOffset, i64)>>,
+}
+
+impl Stream for StreamConsumer {
+    type Item = Result<(RecordAndOffset, i64)>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.records.poll_next_unpin(cx)
+    }
+}
+
+struct Fetch {
+    client: Arc<PartitionClient>,
+    start_offset: StartOffset,
+    bytes: Range<i32>,
+    max_wait_ms: i32,
+    /// `None` until the start offset was looked up
+    next_offset: Option<i64>,
+    /// Records fetched but not yielded yet
+    records: VecDeque<RecordAndOffset>,
+    high_watermark: i64,
+}
+
+impl Fetch {
+    async fn next(&mut self) -> Result<(RecordAndOffset, i64)> {
+        loop {
+            if let Some(record) = self.records.pop_front() {
+                return Ok((record, self.high_watermark));
+            }
+
+            let offset = match self.next_offset {
+                Some(offset) => offset,
+                None => {
+                    let at = match self.start_offset {
+                        StartOffset::Latest => OffsetAt::Latest,
+                        _ => OffsetAt::Earliest,
+                    };
+                    self.client.get_offset(at).await?
+                }
+            };
+            self.next_offset = Some(offset);
+            let (records, high_watermark) = self
+                .client
+                .fetch_records(offset, self.bytes.clone(), self.max_wait_ms)
+                .await?;
+            self.high_watermark = high_watermark;
+            if let Some(last) = records.last() {
+                self.next_offset = Some(last.offset + 1);
+            }
+            self.records.extend(records);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::{
+            mock::{record, MockCluster},
+            partition::{Compression, UnknownTopicHandling},
+        },
+        error::{Error, ProtocolError},
+    };
+    use assert_matches::assert_matches;
+    use std::time::Duration;
+
+    async fn partition(mock: &MockCluster) -> Arc<PartitionClient> {
+        mock.add_topic("test", 1);
+        Arc::new(
+            mock.client()
+                .await
+                .partition_client("test", 0, UnknownTopicHandling::Error)
+                .await
+                .unwrap(),
+        )
+    }
+
+    async fn values(stream: &mut StreamConsumer, count: usize) -> Vec<(String, i64)> {
+        let mut values = vec![];
+        for _ in 0..count {
+            let (record, high_watermark) = stream.next().await.unwrap().unwrap();
+            let value = String::from_utf8(record.record.value.unwrap()).unwrap();
+            values.push((value, high_watermark));
+        }
+        values
+    }
+
+    #[tokio::test]
+    async fn test_consumes_from_start_offset() {
+        let mock = MockCluster::new(1);
+        let partition = partition(&mock).await;
+        partition
+            .produce(vec![record("a"), record("b")], Compression::NoCompression)
+            .await
+            .unwrap();
+
+        let mut stream = StreamConsumerBuilder::new(partition.clone(), StartOffset::At(1)).build();
+        assert_eq!(values(&mut stream, 1).await, vec![("b".to_string(), 2)]);
+
+        let mut latest = StreamConsumerBuilder::new(partition.clone(), StartOffset::Latest).build();
+        // nothing was produced after the consumer started
+        tokio::time::timeout(Duration::from_millis(50), latest.next())
+            .await
+            .unwrap_err();
+
+        partition
+            .produce(vec![record("c")], Compression::NoCompression)
+            .await
+            .unwrap();
+        assert_eq!(values(&mut stream, 1).await, vec![("c".to_string(), 3)]);
+        assert_eq!(values(&mut latest, 1).await, vec![("c".to_string(), 3)]);
+
+        partition.delete_records(1, 1000).await.unwrap();
+        let mut earliest = StreamConsumerBuilder::new(partition, StartOffset::Earliest).build();
+        assert_eq!(
+            values(&mut earliest, 2).await,
+            vec![("b".to_string(), 3), ("c".to_string(), 3)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ends_after_error() {
+        let mock = MockCluster::new(1);
+        let partition = partition(&mock).await;
+
+        let mut stream = StreamConsumerBuilder::new(partition, StartOffset::At(1)).build();
+        assert_matches!(
+            stream.next().await,
+            Some(Err(Error::Server {
+                error: ProtocolError::OffsetOutOfRange,
+                ..
+            }))
+        );
+        assert!(stream.next().await.is_none());
+    }
+}
@@ -0,0 +1,172 @@
+//! Administration of the topics of a cluster through its controller.
+
+use crate::{
+    backoff::Backoff,
+    client::{cluster::Cluster, pool::RequestClass},
+    error::{ConnectionSnafu, Error, Result, ServerSnafu},
+    protocol::{
+        deserializer::DeserializeVersioned,
+        error::Error as ProtocolError,
+        messages::{
+            create_topics::{CreateTopicsRequest, CreateTopicsRequestTopic, CreateTopicsResponse},
+            delete_topics::{DeleteTopicsRequest, DeleteTopicsResponse},
+            KafkaRequest, KafkaResponse,
+        },
+        serializer::SerializeVersioned,
+    },
+};
+use snafu::ResultExt;
+use std::{io::Cursor, ops::ControlFlow, sync::Arc};
+
+/// Creates and deletes topics, always talking to the current controller of the cluster.
+pub struct ControllerClient {
+    cluster: Arc<Cluster>,
+}
+
+impl ControllerClient {
+    pub(crate) fn new(cluster: Arc<Cluster>) -> Self {
+        Self { cluster }
+    }
+
+    /// Creates a topic with `num_partitions` partitions of `replication_factor` replicas each,
+    /// waiting up to `timeout_ms` for the controller to create it.
+    pub async fn create_topic(
+        &self,
+        name: impl Into<String>,
+        num_partitions: i32,
+        replication_factor: i16,
+        timeout_ms: i32,
+    ) -> Result<()> {
+        let name = name.into();
+        self.send(|| CreateTopicsRequest {
+            topics: vec![CreateTopicsRequestTopic {
+                name: name.clone(),
+                num_partitions,
+                replication_factor,
+                assignments: vec![],
+                configs: vec![],
+            }],
+            timeout_ms,
+            validate_only: false,
+        })
+        .await
+    }
+
+    /// Deletes a topic, waiting up to `timeout_ms` for the controller to delete it.
+    pub async fn delete_topic(&self, name: impl Into<String>, timeout_ms: i32) -> Result<()> {
+        let name = name.into();
+        self.send(|| DeleteTopicsRequest {
+            topic_names: vec![name.clone()],
+            timeout_ms,
+        })
+        .await
+    }
+
+    /// Sends the request built by `request` to the controller, finding the controller again
+    /// when it moved.
+    async fn send<R, F>(&self, request: F) -> Result<()>
+    where
+        F: Fn() -> R,
+        R: KafkaRequest + SerializeVersioned<Vec<u8>>,
+        R::KafkaResponse: KafkaResponse + TopicError + DeserializeVersioned<Cursor<Vec<u8>>>,
+    {
+        let mut backoff = Backoff::new(self.cluster.backoff_config());
+        backoff
+            .retry_with_backoff(&format!("{} to controller", R::API_KEY), || async {
+                let result = async {
+                    let response = self
+                        .cluster
+                        .controller()
+                        .await?
+                        .get(RequestClass::Admin)
+                        .await?
+                        .send_request(request())
+                        .await?;
+                    match response.topic_error() {
+                        Some(error) => ServerSnafu {
+                            api_key: R::API_KEY,
+                            error,
+                        }
+                        .fail(),
+                        None => Ok(()),
+                    }
+                }
+                .await;
+
+                match result {
+                    Ok(()) => ControlFlow::Break(Ok(())),
+                    Err(Error::Server {
+                        error: ProtocolError::UnknownTopicOrPartition,
+                        ..
+                    }) => ControlFlow::Break(result),
+                    Err(e @ Error::Server { error, .. }) if error.is_retriable() => {
+                        self.cluster.invalidate_metadata_on(error);
+                        ControlFlow::Continue(e)
+                    }
+                    Err(Error::Request { source }) if source.is_transient() => {
+                        ControlFlow::Continue(Error::Request { source })
+                    }
+                    Err(e) => ControlFlow::Break(Err(e)),
+                }
+            })
+            .await
+            .context(ConnectionSnafu)?
+    }
+}
+
+/// Responses carrying an error per topic, of which requests send a single one.
+trait TopicError {
+    fn topic_error(&self) -> Option<ProtocolError>;
+}
+
+impl TopicError for CreateTopicsResponse {
+    fn topic_error(&self) -> Option<ProtocolError> {
+        self.topics.iter().find_map(|topic| topic.error)
+    }
+}
+
+impl TopicError for DeleteTopicsResponse {
+    fn topic_error(&self) -> Option<ProtocolError> {
+        self.responses.iter().find_map(|topic| topic.error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client::mock::MockCluster, protocol::api_key::ApiKey};
+    use assert_matches::assert_matches;
+
+    #[tokio::test]
+    async fn test_create_and_delete_topic() {
+        let mock = MockCluster::new(2);
+        mock.state.lock().controller_id = 1;
+        let client = mock.client().await;
+        let controller = client.controller_client().unwrap();
+
+        controller.create_topic("test", 3, 1, 1000).await.unwrap();
+        let topics = client.list_topics().await.unwrap();
+        assert_eq!(topics.len(), 1);
+        assert_eq!(
+            (topics[0].name.as_str(), topics[0].partitions.len()),
+            ("test", 3)
+        );
+        assert_matches!(
+            controller.create_topic("test", 3, 1, 1000).await,
+            Err(Error::Server {
+                api_key: ApiKey::CreateTopics,
+                error: ProtocolError::TopicAlreadyExists,
+            })
+        );
+
+        controller.delete_topic("test", 1000).await.unwrap();
+        assert!(client.list_topics().await.unwrap().is_empty());
+        assert_matches!(
+            controller.delete_topic("test", 1000).await,
+            Err(Error::Server {
+                api_key: ApiKey::DeleteTopics,
+                error: ProtocolError::UnknownTopicOrPartition,
+            })
+        );
+    }
+}
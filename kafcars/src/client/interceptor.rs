@@ -0,0 +1,183 @@
+//! Hooks into every record produced and consumed through a client, like the producer and
+//! consumer interceptors of the Java client.
+
+use crate::{
+    client::partition::ProducedBatch,
+    error::Error,
+    record::{Record, RecordAndOffset, RecordMetadata},
+};
+use chrono::{DateTime, Utc};
+use std::sync::Arc;
+
+/// Sees and may change every record produced and consumed through a client, e.g. to add
+/// tracing headers. Registered with
+/// [`ClientBuilder::interceptor`](crate::client::ClientBuilder::interceptor).
+pub trait Interceptor: Send + Sync + 'static {
+    /// Called before `record` is encoded into a batch to produce. Partitioners see the record
+    /// before it was intercepted.
+    fn on_send(&self, _record: &mut Record) {}
+
+    /// Called once the batch of a produced record was acknowledged or failed. The offset of a
+    /// failed record is -1.
+    fn on_acknowledgement(
+        &self,
+        _metadata: &RecordMetadata,
+        _result: std::result::Result<(), &Error>,
+    ) {
+    }
+
+    /// Called for every fetched record before it is handed out.
+    fn on_consume(&self, _record: &mut RecordAndOffset) {}
+}
+
+/// The interceptors of a client, applied in the order they were registered.
+#[derive(Clone, Default)]
+pub(crate) struct Interceptors(Vec<Arc<dyn Interceptor>>);
+
+impl Interceptors {
+    pub(crate) fn new(interceptors: Vec<Arc<dyn Interceptor>>) -> Self {
+        Self(interceptors)
+    }
+
+    pub(crate) fn on_send(&self, records: &mut [Record]) {
+        for record in records {
+            self.0
+                .iter()
+                .for_each(|interceptor| interceptor.on_send(record));
+        }
+    }
+
+    /// Acknowledges the records of a batch to `topic` and `partition`, given their timestamps.
+    pub(crate) fn on_acknowledgement(
+        &self,
+        topic: &str,
+        partition: i32,
+        timestamps: &[DateTime<Utc>],
+        result: std::result::Result<&ProducedBatch, &Error>,
+    ) {
+        if self.0.is_empty() {
+            return;
+        }
+        for (i, timestamp) in timestamps.iter().enumerate() {
+            let (offset, log_append_time) = match result {
+                Ok(produced) => (produced.offsets[i], produced.log_append_time),
+                Err(_) => (-1, None),
+            };
+            let metadata = RecordMetadata {
+                topic: topic.to_string(),
+                partition,
+                offset,
+                timestamp: *timestamp,
+                log_append_time,
+            };
+            for interceptor in &self.0 {
+                interceptor.on_acknowledgement(&metadata, result.map(|_| ()));
+            }
+        }
+    }
+
+    pub(crate) fn on_consume(&self, records: &mut [RecordAndOffset]) {
+        for record in records {
+            self.0
+                .iter()
+                .for_each(|interceptor| interceptor.on_consume(record));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{
+        mock::{record, MockCluster},
+        partition::{Compression, UnknownTopicHandling},
+    };
+    use parking_lot::Mutex;
+    use std::collections::BTreeMap;
+
+    /// Adds a header to every record and logs every acknowledged and consumed offset.
+    struct Tracing {
+        name: &'static str,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Interceptor for Tracing {
+        fn on_send(&self, record: &mut Record) {
+            record.headers.insert(self.name.to_string(), b"on".to_vec());
+        }
+
+        fn on_acknowledgement(
+            &self,
+            metadata: &RecordMetadata,
+            result: std::result::Result<(), &Error>,
+        ) {
+            self.log.lock().push(format!(
+                "{} acked {} {}",
+                self.name,
+                metadata.offset,
+                result.is_ok()
+            ));
+        }
+
+        fn on_consume(&self, record: &mut RecordAndOffset) {
+            self.log
+                .lock()
+                .push(format!("{} consumed {}", self.name, record.offset));
+            record.record.headers.remove(self.name);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_interceptors_see_every_record() {
+        let mock = MockCluster::new(1);
+        mock.add_topic("test", 1);
+        let log = Arc::new(Mutex::new(vec![]));
+        let client = mock
+            .client_builder()
+            .interceptor(Tracing {
+                name: "first",
+                log: log.clone(),
+            })
+            .interceptor(Tracing {
+                name: "second",
+                log: log.clone(),
+            })
+            .build()
+            .await
+            .unwrap();
+        let partition = client
+            .partition_client("test", 0, UnknownTopicHandling::Error)
+            .await
+            .unwrap();
+
+        let record = Record {
+            headers: BTreeMap::from([("user".to_string(), b"kept".to_vec())]),
+            ..record("value")
+        };
+        let offsets = partition
+            .produce(vec![record.clone(); 2], Compression::default())
+            .await
+            .unwrap();
+        assert_eq!(offsets, vec![0, 1]);
+
+        let (records, _) = partition.fetch_records(0, 1..1_000_000, 100).await.unwrap();
+        // both headers were written, and both removed again when consumed
+        assert_eq!(records[0].record, record);
+        assert_eq!(
+            *log.lock(),
+            vec![
+                "first acked 0 true",
+                "second acked 0 true",
+                "first acked 1 true",
+                "second acked 1 true",
+                "first consumed 0",
+                "second consumed 0",
+                "first consumed 1",
+                "second consumed 1",
+            ]
+        );
+        let state = mock.state.lock();
+        let batch = &state.topics[0].partitions[0].batches[0];
+        assert!(batch.windows(6).any(|window| window == b"second"));
+    }
+}
@@ -0,0 +1,439 @@
+pub use crate::protocol::messages::{
+    describe_topic_partitions::DescribeTopicPartitionsResponsePartition,
+    metadata::{
+        MetadataResponse, MetadataResponseBroker, MetadataResponsePartition, MetadataResponseTopic,
+    },
+};
+
+use crate::{
+    error::{Result, ServerSnafu},
+    protocol::{
+        api_key::ApiKey, messages::describe_topic_partitions::DescribeTopicPartitionsResponseTopic,
+    },
+};
+use log::debug;
+use parking_lot::RwLock;
+use std::{
+    collections::{BTreeSet, HashMap},
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::{broadcast, Mutex},
+    time::Instant,
+};
+use uuid::Uuid;
+
+/// A partition as described by `DescribeTopicPartitions`, together with its topic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartitionDescription {
+    pub topic: String,
+    pub topic_id: Uuid,
+    pub is_internal: bool,
+    pub partition: DescribeTopicPartitionsResponsePartition,
+}
+
+impl PartitionDescription {
+    /// The partitions of a described topic, or its error.
+    pub(crate) fn from_topic(topic: DescribeTopicPartitionsResponseTopic) -> Vec<Result<Self>> {
+        if let Some(error) = topic.error {
+            return vec![ServerSnafu {
+                api_key: ApiKey::DescribeTopicPartitions,
+                error,
+            }
+            .fail()];
+        }
+
+        let name = topic.name.unwrap_or_default();
+        topic
+            .partitions
+            .into_iter()
+            .map(|partition| {
+                Ok(Self {
+                    topic: name.clone(),
+                    topic_id: topic.topic_id,
+                    is_internal: topic.is_internal,
+                    partition,
+                })
+            })
+            .collect()
+    }
+}
+
+/// A single difference between two successive metadata snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TopologyChange {
+    BrokerAdded(MetadataResponseBroker),
+    /// The broker left the cluster or now advertises a different address
+    BrokerRemoved(MetadataResponseBroker),
+    LeaderChanged {
+        topic: String,
+        partition: i32,
+        previous: i32,
+        current: i32,
+    },
+    IsrShrunk {
+        topic: String,
+        partition: i32,
+        removed: Vec<i32>,
+    },
+    IsrExpanded {
+        topic: String,
+        partition: i32,
+        added: Vec<i32>,
+    },
+    /// Also emitted when a topic is created or deleted, with a count of 0 for the missing side
+    PartitionCountChanged {
+        topic: String,
+        previous: usize,
+        current: usize,
+    },
+}
+
+/// The changes between two successive metadata snapshots of the whole cluster.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TopologyDiff {
+    pub changes: Vec<TopologyChange>,
+}
+
+impl TopologyDiff {
+    pub fn between(previous: &MetadataResponse, current: &MetadataResponse) -> Self {
+        let mut changes = vec![];
+
+        for broker in &previous.brokers {
+            if !current.brokers.contains(broker) {
+                changes.push(TopologyChange::BrokerRemoved(broker.clone()));
+            }
+        }
+        for broker in &current.brokers {
+            if !previous.brokers.contains(broker) {
+                changes.push(TopologyChange::BrokerAdded(broker.clone()));
+            }
+        }
+
+        let previous_topics: HashMap<_, _> = previous
+            .topics
+            .iter()
+            .map(|t| (t.name.as_str(), t))
+            .collect();
+        let current_topics: HashMap<_, _> = current
+            .topics
+            .iter()
+            .map(|t| (t.name.as_str(), t))
+            .collect();
+        let names: BTreeSet<&str> = previous_topics
+            .keys()
+            .chain(current_topics.keys())
+            .copied()
+            .collect();
+
+        for name in names {
+            let previous = previous_topics.get(name).map(|t| t.partitions.as_slice());
+            let current = current_topics.get(name).map(|t| t.partitions.as_slice());
+            let (previous, current) = (previous.unwrap_or_default(), current.unwrap_or_default());
+
+            if previous.len() != current.len() {
+                changes.push(TopologyChange::PartitionCountChanged {
+                    topic: name.to_string(),
+                    previous: previous.len(),
+                    current: current.len(),
+                });
+            }
+
+            for partition in current {
+                let Some(before) = previous
+                    .iter()
+                    .find(|p| p.partition_index == partition.partition_index)
+                else {
+                    continue;
+                };
+
+                if before.leader_id != partition.leader_id {
+                    changes.push(TopologyChange::LeaderChanged {
+                        topic: name.to_string(),
+                        partition: partition.partition_index,
+                        previous: before.leader_id,
+                        current: partition.leader_id,
+                    });
+                }
+
+                let removed: Vec<i32> = before
+                    .isr_nodes
+                    .iter()
+                    .filter(|node| !partition.isr_nodes.contains(node))
+                    .copied()
+                    .collect();
+                if !removed.is_empty() {
+                    changes.push(TopologyChange::IsrShrunk {
+                        topic: name.to_string(),
+                        partition: partition.partition_index,
+                        removed,
+                    });
+                }
+
+                let added: Vec<i32> = partition
+                    .isr_nodes
+                    .iter()
+                    .filter(|node| !before.isr_nodes.contains(node))
+                    .copied()
+                    .collect();
+                if !added.is_empty() {
+                    changes.push(TopologyChange::IsrExpanded {
+                        topic: name.to_string(),
+                        partition: partition.partition_index,
+                        added,
+                    });
+                }
+            }
+        }
+
+        Self { changes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
+
+struct CachedMetadata {
+    metadata: Arc<MetadataResponse>,
+    fetched_at: Instant,
+    /// Set when a broker reported the metadata to be outdated
+    invalidated: bool,
+}
+
+/// Cluster metadata shared by everything using a client, refreshed once it is older than
+/// `max_age` or got invalidated.
+pub(crate) struct MetadataCache {
+    max_age: Duration,
+    cached: RwLock<Option<CachedMetadata>>,
+    /// Counts the invalidations, so metadata fetched before the latest one is not stored as
+    /// valid
+    invalidations: AtomicU64,
+    /// Held while fetching, so concurrent lookups wait for a single request
+    refresh: Mutex<()>,
+    topology: broadcast::Sender<TopologyDiff>,
+}
+
+impl MetadataCache {
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            max_age,
+            cached: RwLock::new(None),
+            invalidations: AtomicU64::new(0),
+            refresh: Mutex::new(()),
+            topology: broadcast::channel(64).0,
+        }
+    }
+
+    pub fn max_age(&self) -> Duration {
+        self.max_age
+    }
+
+    /// The cached metadata if it is valid and not older than the max age.
+    pub fn fresh(&self) -> Option<Arc<MetadataResponse>> {
+        self.cached
+            .read()
+            .as_ref()
+            .filter(|cached| !cached.invalidated && cached.fetched_at.elapsed() < self.max_age)
+            .map(|cached| cached.metadata.clone())
+    }
+
+    /// Returns the fresh metadata, or fetches it with `fetch` if there is none. Only one fetch
+    /// runs at a time, callers arriving meanwhile get its result.
+    pub async fn get<F, Fut>(&self, fetch: F) -> Result<Arc<MetadataResponse>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<MetadataResponse>>,
+    {
+        if let Some(metadata) = self.fresh() {
+            return Ok(metadata);
+        }
+
+        let _refresh = self.refresh.lock().await;
+        // another caller may have refreshed while we were waiting
+        if let Some(metadata) = self.fresh() {
+            return Ok(metadata);
+        }
+        let invalidations = self.invalidations.load(Ordering::SeqCst);
+        self.store(fetch().await?, invalidations)
+    }
+
+    /// Fetches the metadata with `fetch` regardless of its age.
+    pub async fn refresh<F, Fut>(&self, fetch: F) -> Result<Arc<MetadataResponse>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<MetadataResponse>>,
+    {
+        let _refresh = self.refresh.lock().await;
+        let invalidations = self.invalidations.load(Ordering::SeqCst);
+        self.store(fetch().await?, invalidations)
+    }
+
+    /// Caches `metadata`, which is already outdated if the cache was invalidated since
+    /// `invalidations` were counted before fetching it.
+    fn store(
+        &self,
+        metadata: MetadataResponse,
+        invalidations: u64,
+    ) -> Result<Arc<MetadataResponse>> {
+        let metadata = Arc::new(metadata);
+        let previous = {
+            let mut cached = self.cached.write();
+            cached.replace(CachedMetadata {
+                metadata: metadata.clone(),
+                fetched_at: Instant::now(),
+                invalidated: self.invalidations.load(Ordering::SeqCst) != invalidations,
+            })
+        };
+
+        if let Some(previous) = previous {
+            let diff = TopologyDiff::between(&previous.metadata, &metadata);
+            if !diff.is_empty() {
+                // no subscribers is not an error
+                let _ = self.topology.send(diff);
+            }
+        }
+        Ok(metadata)
+    }
+
+    /// Marks the cached metadata as outdated, so the next lookup fetches it again.
+    pub fn invalidate(&self, reason: &str) {
+        // counted before taking the lock, so a fetch stored meanwhile is invalidated either way
+        self.invalidations.fetch_add(1, Ordering::SeqCst);
+        if let Some(cached) = self.cached.write().as_mut() {
+            if !cached.invalidated {
+                debug!("Invalidated cached metadata: {}", reason);
+                cached.invalidated = true;
+            }
+        }
+    }
+
+    /// Receives the diff of every refresh that changed the topology.
+    pub fn subscribe(&self) -> broadcast::Receiver<TopologyDiff> {
+        self.topology.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partition(index: i32, leader_id: i32, isr_nodes: Vec<i32>) -> MetadataResponsePartition {
+        MetadataResponsePartition {
+            error: None,
+            partition_index: index,
+            leader_id,
+            leader_epoch: None,
+            replica_nodes: vec![0, 1, 2],
+            isr_nodes,
+            offline_replicas: None,
+            tagged_fields: None,
+        }
+    }
+
+    fn metadata(brokers: Vec<i32>, partitions: Vec<MetadataResponsePartition>) -> MetadataResponse {
+        MetadataResponse {
+            throttle_time_ms: None,
+            brokers: brokers
+                .into_iter()
+                .map(|node_id| MetadataResponseBroker {
+                    node_id,
+                    host: format!("broker-{node_id}"),
+                    port: 9092,
+                    rack: None,
+                    tagged_fields: None,
+                })
+                .collect(),
+            cluster_id: None,
+            controller_id: Some(0),
+            topics: vec![MetadataResponseTopic {
+                error: None,
+                name: "test".to_string(),
+                topic_id: None,
+                is_internal: Some(false),
+                partitions,
+                topic_authorized_operations: None,
+                tagged_fields: None,
+            }],
+            cluster_authorized_operations: None,
+            tagged_fields: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalidation_during_fetch_is_kept() {
+        let cache = MetadataCache::new(Duration::from_secs(60));
+        cache
+            .get(|| async { Ok(metadata(vec![0], vec![])) })
+            .await
+            .unwrap();
+        cache.invalidate("test");
+        cache
+            .get(|| async {
+                // a broker reports the metadata to be outdated while it is fetched
+                cache.invalidate("test");
+                Ok(metadata(vec![0], vec![]))
+            })
+            .await
+            .unwrap();
+        assert!(cache.fresh().is_none());
+
+        cache
+            .refresh(|| async { Ok(metadata(vec![0], vec![])) })
+            .await
+            .unwrap();
+        assert!(cache.fresh().is_some());
+    }
+
+    #[test]
+    fn test_topology_diff() {
+        let previous = metadata(
+            vec![0, 1],
+            vec![partition(0, 0, vec![0, 1]), partition(1, 1, vec![1])],
+        );
+        let current = metadata(
+            vec![1, 2],
+            vec![
+                partition(0, 1, vec![1, 2]),
+                partition(1, 1, vec![1]),
+                partition(2, 2, vec![2]),
+            ],
+        );
+
+        let diff = TopologyDiff::between(&previous, &current);
+        assert_eq!(
+            diff.changes,
+            vec![
+                TopologyChange::BrokerRemoved(previous.brokers[0].clone()),
+                TopologyChange::BrokerAdded(current.brokers[1].clone()),
+                TopologyChange::PartitionCountChanged {
+                    topic: "test".to_string(),
+                    previous: 2,
+                    current: 3,
+                },
+                TopologyChange::LeaderChanged {
+                    topic: "test".to_string(),
+                    partition: 0,
+                    previous: 0,
+                    current: 1,
+                },
+                TopologyChange::IsrShrunk {
+                    topic: "test".to_string(),
+                    partition: 0,
+                    removed: vec![0],
+                },
+                TopologyChange::IsrExpanded {
+                    topic: "test".to_string(),
+                    partition: 0,
+                    added: vec![2],
+                },
+            ]
+        );
+        assert!(TopologyDiff::between(&current, &current).is_empty());
+    }
+}
@@ -0,0 +1,1237 @@
+//! In-memory Kafka cluster the client tests run against.
+
+use crate::{
+    backoff::BackoffConfig,
+    client::{transport::Connector, ClientBuilder, KafkaClient},
+    protocol::{
+        api_key::ApiKey,
+        deserializer::{
+            deserialize_nullable_bytes, deserialize_unsigned_var_int, DeserializeCompact,
+            DeserializeVersioned,
+        },
+        messages::TaggedFields,
+        serializer::{
+            serialize_nullable_bytes, serialize_unsigned_var_int, SerializeCompact,
+            SerializeVersioned,
+        },
+    },
+    record::Record,
+};
+use chrono::DateTime;
+use futures::{future::BoxFuture, FutureExt};
+use parking_lot::Mutex;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    io::{self, Cursor},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{
+    io::{duplex, AsyncReadExt, AsyncWriteExt, DuplexStream},
+    spawn,
+};
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+pub struct MockBroker {
+    pub node_id: i32,
+    pub host: String,
+    pub port: i32,
+}
+
+impl MockBroker {
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MockPartition {
+    pub error: i16,
+    pub leader_id: i32,
+    pub leader_epoch: i32,
+    pub replica_nodes: Vec<i32>,
+    pub isr_nodes: Vec<i32>,
+    /// Produced record batches with their base offset rewritten to their place in the log
+    pub batches: Vec<Vec<u8>>,
+    pub log_start_offset: i64,
+    pub next_offset: i64,
+    /// Idempotent producers that wrote to the partition, by producer id
+    pub producers: HashMap<i64, MockProducer>,
+}
+
+/// State of an idempotent producer kept by a partition leader.
+#[derive(Debug, Clone)]
+pub struct MockProducer {
+    pub epoch: i16,
+    pub next_sequence: i32,
+    /// Base sequence and base offset of the last batch, to answer a duplicate of it
+    pub last_batch: (i32, i64),
+}
+
+#[derive(Debug, Clone)]
+pub struct MockTopic {
+    pub name: String,
+    pub id: Uuid,
+    pub partitions: Vec<MockPartition>,
+}
+
+#[derive(Debug, Default)]
+pub struct ClusterState {
+    pub brokers: Vec<MockBroker>,
+    pub cluster_id: Option<String>,
+    pub controller_id: i32,
+    pub topics: Vec<MockTopic>,
+    /// Addresses reported in metadata instead of the ones the brokers listen on
+    pub advertised: HashMap<i32, (String, i32)>,
+    /// Addresses that refuse connections
+    pub down: HashSet<String>,
+    /// Addresses in the order they were connected to
+    pub connections: Vec<String>,
+    pub metadata_requests: usize,
+    /// Stop answering requests, like a broker behind a half-open connection
+    pub silent: bool,
+    /// Produce requests that are appended, but whose connection breaks before the response
+    pub lost_produce_responses: usize,
+    /// Log append time answered to produce requests, like for topics using log append time
+    pub log_append_time: Option<i64>,
+    /// Error codes answered to the next produce requests instead of appending their batches
+    pub produce_errors: VecDeque<i16>,
+    /// Current epoch of every producer id handed out, the next id is the count
+    pub producer_epochs: Vec<i16>,
+    /// State of every transactional id, coordinated by the controller
+    pub transactions: HashMap<String, MockTransaction>,
+    /// Transactional ids of the transactions ended so far, with whether they were committed
+    pub ended_transactions: Vec<(String, bool)>,
+    /// Offsets committed by group, topic and partition
+    pub committed_offsets: HashMap<(String, String, i32), i64>,
+}
+
+impl ClusterState {
+    /// Adds a topic whose partitions are led by the brokers in turn.
+    pub fn add_topic(&mut self, name: &str, partitions: i32) {
+        let brokers = self.brokers.len() as i32;
+        let id = Uuid::from_u128(self.topics.len() as u128 + 1);
+        self.topics.push(MockTopic {
+            name: name.to_string(),
+            id,
+            partitions: (0..partitions)
+                .map(|partition| MockPartition {
+                    error: 0,
+                    leader_id: partition % brokers,
+                    leader_epoch: 0,
+                    replica_nodes: vec![partition % brokers],
+                    isr_nodes: vec![partition % brokers],
+                    batches: vec![],
+                    log_start_offset: 0,
+                    next_offset: 0,
+                    producers: HashMap::new(),
+                })
+                .collect(),
+        });
+    }
+}
+
+/// A transactional id with the partitions and offsets of its open transaction.
+#[derive(Debug, Clone, Default)]
+pub struct MockTransaction {
+    pub producer_id: i64,
+    pub partitions: Vec<(String, i32)>,
+    pub groups: Vec<String>,
+    /// Offsets by group, topic and partition, committed with the transaction
+    pub offsets: Vec<((String, String, i32), i64)>,
+}
+
+/// Cluster whose brokers are all served by this process. Use it as the [`Connector`] of a
+/// client; every address of a broker in [`ClusterState::brokers`] is reachable unless it is down.
+#[derive(Debug, Clone, Default)]
+pub struct MockCluster {
+    pub state: Arc<Mutex<ClusterState>>,
+}
+
+impl MockCluster {
+    /// Cluster of brokers `0..brokers` listening on `broker-<id>:9092`.
+    pub fn new(brokers: i32) -> Self {
+        let cluster = Self::default();
+        {
+            let mut state = cluster.state.lock();
+            state.brokers = (0..brokers)
+                .map(|node_id| MockBroker {
+                    node_id,
+                    host: format!("broker-{node_id}"),
+                    port: 9092,
+                })
+                .collect();
+            state.cluster_id = Some("mock-cluster".to_string());
+        }
+        cluster
+    }
+
+    pub fn add_topic(&self, name: &str, partitions: i32) {
+        self.state.lock().add_topic(name, partitions);
+    }
+
+    /// Builder of a client that bootstraps from `broker-0` and gives up retrying after a second.
+    pub fn client_builder(&self) -> ClientBuilder {
+        ClientBuilder::new(vec!["broker-0:9092".to_string()])
+            .connector(self.clone())
+            .backoff_config(BackoffConfig {
+                deadline: Some(Duration::from_secs(1)),
+                ..Default::default()
+            })
+    }
+
+    /// Client built by [`client_builder`](Self::client_builder).
+    pub async fn client(&self) -> KafkaClient {
+        self.client_builder().build().await.unwrap()
+    }
+}
+
+/// Record with `value`, without key and headers, produced at 1000ms after the epoch.
+pub fn record(value: &str) -> Record {
+    Record {
+        key: None,
+        value: Some(value.as_bytes().to_vec()),
+        headers: BTreeMap::new(),
+        timestamp: DateTime::from_timestamp_millis(1000).unwrap(),
+    }
+}
+
+impl Connector for MockCluster {
+    type Transport = DuplexStream;
+
+    fn connect<'a>(&'a self, broker: &'a str) -> BoxFuture<'a, io::Result<Self::Transport>> {
+        async move {
+            let node_id = {
+                let mut state = self.state.lock();
+                let node_id = state
+                    .brokers
+                    .iter()
+                    .find(|b| b.address() == broker)
+                    .map(|b| b.node_id);
+                if node_id.is_none() || state.down.contains(broker) {
+                    return Err(io::Error::from(io::ErrorKind::ConnectionRefused));
+                }
+                state.connections.push(broker.to_string());
+                node_id.unwrap()
+            };
+            let (client, server) = duplex(64 * 1024);
+            spawn(serve(server, node_id, self.state.clone()));
+            Ok(client)
+        }
+        .boxed()
+    }
+}
+
+async fn serve(mut stream: DuplexStream, node_id: i32, state: Arc<Mutex<ClusterState>>) {
+    loop {
+        let Ok(len) = stream.read_i32().await else {
+            return;
+        };
+        let mut frame = vec![0u8; len as usize];
+        if stream.read_exact(&mut frame).await.is_err() {
+            return;
+        }
+
+        if state.lock().silent {
+            continue;
+        }
+
+        let mut data = Cursor::new(frame);
+        let api_key = ApiKey::from(i16::deserialize_versioned(&mut data, 0).unwrap());
+        let version = i16::deserialize_versioned(&mut data, 0).unwrap();
+        let correlation_id = i32::deserialize_versioned(&mut data, 0).unwrap();
+        let _client_id = Option::<String>::deserialize_versioned(&mut data, 0).unwrap();
+        let flexible = match api_key {
+            ApiKey::ApiVersions => version >= 3,
+            ApiKey::Metadata => version >= 9,
+            ApiKey::Produce => version >= 9,
+            ApiKey::Fetch => version >= 12,
+            ApiKey::ListOffsets => version >= 6,
+            ApiKey::DeleteRecords => version >= 2,
+            ApiKey::CreateTopics => version >= 5,
+            ApiKey::DeleteTopics => version >= 4,
+            _ => true,
+        };
+        if flexible {
+            TaggedFields::deserialize_versioned(&mut data, 0).unwrap();
+        }
+
+        let body = match api_key {
+            ApiKey::ApiVersions => api_versions(version),
+            ApiKey::Metadata => metadata(&mut data, version, &mut state.lock()),
+            ApiKey::DescribeTopicPartitions => describe_topic_partitions(&mut data, &state.lock()),
+            ApiKey::Produce => match produce(&mut data, node_id, &mut state.lock()) {
+                Some(body) => body,
+                None => continue,
+            },
+            ApiKey::Fetch => fetch(&mut data, node_id, &mut state.lock()),
+            ApiKey::ListOffsets => list_offsets(&mut data, node_id, &mut state.lock()),
+            ApiKey::DeleteRecords => delete_records(&mut data, node_id, &mut state.lock()),
+            ApiKey::CreateTopics => create_topics(&mut data, node_id, &mut state.lock()),
+            ApiKey::DeleteTopics => delete_topics(&mut data, node_id, &mut state.lock()),
+            ApiKey::InitProducerId => init_producer_id(&mut data, &mut state.lock()),
+            ApiKey::FindCoordinator => find_coordinator(&mut data, &state.lock()),
+            ApiKey::AddPartitionsToTxn => add_partitions_to_txn(&mut data, &mut state.lock()),
+            ApiKey::AddOffsetsToTxn => add_offsets_to_txn(&mut data, &mut state.lock()),
+            ApiKey::EndTxn => end_txn(&mut data, &mut state.lock()),
+            ApiKey::TxnOffsetCommit => txn_offset_commit(&mut data, &mut state.lock()),
+            _ => return,
+        };
+        if api_key == ApiKey::Produce {
+            let mut state = state.lock();
+            if state.lost_produce_responses > 0 {
+                state.lost_produce_responses -= 1;
+                return;
+            }
+        }
+
+        let mut header = vec![];
+        correlation_id.serialize_versioned(&mut header, 0).unwrap();
+        // ApiVersions always answers with header v0
+        if flexible && api_key != ApiKey::ApiVersions {
+            write_tags(&mut header, true);
+        }
+        let mut response = vec![];
+        ((header.len() + body.len()) as i32)
+            .serialize_versioned(&mut response, 0)
+            .unwrap();
+        response.extend_from_slice(&header);
+        response.extend_from_slice(&body);
+        if stream.write_all(&response).await.is_err() {
+            return;
+        }
+    }
+}
+
+fn api_versions(version: i16) -> Vec<u8> {
+    let flexible = version >= 3;
+    let api_keys = [
+        (ApiKey::ApiVersions, 0i16, 4i16),
+        (ApiKey::Metadata, 1, 11),
+        (ApiKey::DescribeTopicPartitions, 0, 0),
+        (ApiKey::Produce, 8, 8),
+        (ApiKey::Fetch, 11, 11),
+        (ApiKey::ListOffsets, 5, 5),
+        (ApiKey::DeleteRecords, 1, 1),
+        (ApiKey::CreateTopics, 2, 4),
+        (ApiKey::DeleteTopics, 1, 3),
+        (ApiKey::InitProducerId, 4, 4),
+        (ApiKey::FindCoordinator, 3, 3),
+        (ApiKey::AddPartitionsToTxn, 3, 3),
+        (ApiKey::AddOffsetsToTxn, 3, 3),
+        (ApiKey::EndTxn, 5, 5),
+        (ApiKey::TxnOffsetCommit, 3, 3),
+    ];
+
+    let mut body = vec![];
+    0i16.serialize_versioned(&mut body, version).unwrap();
+    if flexible {
+        serialize_unsigned_var_int(api_keys.len() as u64 + 1, &mut body).unwrap();
+    } else {
+        (api_keys.len() as i32)
+            .serialize_versioned(&mut body, version)
+            .unwrap();
+    }
+    for (api_key, min_version, max_version) in api_keys {
+        api_key.serialize_versioned(&mut body, version).unwrap();
+        min_version.serialize_versioned(&mut body, version).unwrap();
+        max_version.serialize_versioned(&mut body, version).unwrap();
+        if flexible {
+            serialize_unsigned_var_int(0, &mut body).unwrap();
+        }
+    }
+    if version >= 1 {
+        0i32.serialize_versioned(&mut body, version).unwrap();
+    }
+    if flexible {
+        serialize_unsigned_var_int(0, &mut body).unwrap();
+    }
+    body
+}
+
+fn metadata(data: &mut Cursor<Vec<u8>>, version: i16, state: &mut ClusterState) -> Vec<u8> {
+    state.metadata_requests += 1;
+    let flexible = version >= 9;
+    let requested = if flexible {
+        read_array(data, |data| {
+            if version >= 10 {
+                Uuid::deserialize_versioned(data, version).unwrap();
+            }
+            let name = String::deserialize_compact(data, version).unwrap();
+            TaggedFields::deserialize_versioned(data, version).unwrap();
+            name
+        })
+    } else {
+        Option::<Vec<String>>::deserialize_versioned(data, version).unwrap()
+    };
+    let topics: Vec<(i16, MockTopic)> = match requested {
+        None => state.topics.iter().map(|t| (0, t.clone())).collect(),
+        Some(names) => names
+            .into_iter()
+            .map(|name| match state.topics.iter().find(|t| t.name == name) {
+                Some(topic) => (0, topic.clone()),
+                // UNKNOWN_TOPIC_OR_PARTITION
+                None => (
+                    3,
+                    MockTopic {
+                        name,
+                        id: Uuid::nil(),
+                        partitions: vec![],
+                    },
+                ),
+            })
+            .collect(),
+    };
+
+    let mut body = vec![];
+    if version >= 3 {
+        0i32.serialize_versioned(&mut body, version).unwrap();
+    }
+    write_len(&mut body, state.brokers.len(), flexible);
+    for broker in &state.brokers {
+        let (host, port) = state
+            .advertised
+            .get(&broker.node_id)
+            .cloned()
+            .unwrap_or_else(|| (broker.host.clone(), broker.port));
+        broker
+            .node_id
+            .serialize_versioned(&mut body, version)
+            .unwrap();
+        write(&mut body, &host, version, flexible);
+        port.serialize_versioned(&mut body, version).unwrap();
+        write(&mut body, &None::<String>, version, flexible);
+        write_tags(&mut body, flexible);
+    }
+    if version >= 2 {
+        write(&mut body, &state.cluster_id, version, flexible);
+    }
+    state
+        .controller_id
+        .serialize_versioned(&mut body, version)
+        .unwrap();
+    write_len(&mut body, topics.len(), flexible);
+    for (error, topic) in topics {
+        error.serialize_versioned(&mut body, version).unwrap();
+        write(&mut body, &topic.name, version, flexible);
+        if version >= 10 {
+            topic.id.serialize_versioned(&mut body, version).unwrap();
+        }
+        false.serialize_versioned(&mut body, version).unwrap();
+        write_len(&mut body, topic.partitions.len(), flexible);
+        for (index, partition) in topic.partitions.iter().enumerate() {
+            partition
+                .error
+                .serialize_versioned(&mut body, version)
+                .unwrap();
+            (index as i32)
+                .serialize_versioned(&mut body, version)
+                .unwrap();
+            partition
+                .leader_id
+                .serialize_versioned(&mut body, version)
+                .unwrap();
+            if version >= 7 {
+                partition
+                    .leader_epoch
+                    .serialize_versioned(&mut body, version)
+                    .unwrap();
+            }
+            write(&mut body, &partition.replica_nodes, version, flexible);
+            write(&mut body, &partition.isr_nodes, version, flexible);
+            if version >= 5 {
+                write(&mut body, &Vec::<i32>::new(), version, flexible);
+            }
+            write_tags(&mut body, flexible);
+        }
+        if version >= 8 {
+            0i32.serialize_versioned(&mut body, version).unwrap();
+        }
+        write_tags(&mut body, flexible);
+    }
+    if (8..=10).contains(&version) {
+        0i32.serialize_versioned(&mut body, version).unwrap();
+    }
+    write_tags(&mut body, flexible);
+    body
+}
+
+/// Answers with at most `response_partition_limit` partitions, starting at the cursor.
+fn describe_topic_partitions(data: &mut Cursor<Vec<u8>>, state: &ClusterState) -> Vec<u8> {
+    let requested = read_array(data, |data| {
+        let name = String::deserialize_compact(data, 0).unwrap();
+        TaggedFields::deserialize_versioned(data, 0).unwrap();
+        name
+    })
+    .unwrap();
+    let mut limit = i32::deserialize_versioned(data, 0).unwrap() as usize;
+    let cursor = match i8::deserialize_versioned(data, 0).unwrap() {
+        -1 => None,
+        _ => {
+            let topic = String::deserialize_compact(data, 0).unwrap();
+            let partition = i32::deserialize_versioned(data, 0).unwrap() as usize;
+            TaggedFields::deserialize_versioned(data, 0).unwrap();
+            Some((topic, partition))
+        }
+    };
+
+    let mut requested: Vec<String> = requested;
+    requested.sort();
+    let first = match &cursor {
+        Some((topic, _)) => requested.iter().position(|t| t == topic).unwrap_or(0),
+        None => 0,
+    };
+
+    let mut topics = vec![];
+    let mut next_cursor = None;
+    for name in &requested[first..] {
+        let start = match &cursor {
+            Some((topic, partition)) if topic == name => *partition,
+            _ => 0,
+        };
+        let topic = state.topics.iter().find(|t| &t.name == name);
+        let count = topic.map(|t| t.partitions.len()).unwrap_or_default();
+        if limit == 0 {
+            next_cursor = Some((name.clone(), start));
+            break;
+        }
+        let end = count.min(start + limit);
+        topics.push((name, topic, start..end));
+        limit -= end - start;
+        if end < count {
+            next_cursor = Some((name.clone(), end));
+            break;
+        }
+    }
+
+    let mut body = vec![];
+    0i32.serialize_versioned(&mut body, 0).unwrap();
+    write_len(&mut body, topics.len(), true);
+    for (name, topic, partitions) in topics {
+        // UNKNOWN_TOPIC_OR_PARTITION
+        let error: i16 = if topic.is_some() { 0 } else { 3 };
+        error.serialize_versioned(&mut body, 0).unwrap();
+        write(&mut body, &Some(name.clone()), 0, true);
+        topic
+            .map(|t| t.id)
+            .unwrap_or_default()
+            .serialize_versioned(&mut body, 0)
+            .unwrap();
+        false.serialize_versioned(&mut body, 0).unwrap();
+        write_len(&mut body, partitions.len(), true);
+        for index in partitions {
+            let partition = &topic.unwrap().partitions[index];
+            partition.error.serialize_versioned(&mut body, 0).unwrap();
+            (index as i32).serialize_versioned(&mut body, 0).unwrap();
+            partition
+                .leader_id
+                .serialize_versioned(&mut body, 0)
+                .unwrap();
+            partition
+                .leader_epoch
+                .serialize_versioned(&mut body, 0)
+                .unwrap();
+            write(&mut body, &partition.replica_nodes, 0, true);
+            write(&mut body, &partition.isr_nodes, 0, true);
+            write(&mut body, &None::<Vec<i32>>, 0, true);
+            write(&mut body, &None::<Vec<i32>>, 0, true);
+            write(&mut body, &Vec::<i32>::new(), 0, true);
+            write_tags(&mut body, true);
+        }
+        0i32.serialize_versioned(&mut body, 0).unwrap();
+        write_tags(&mut body, true);
+    }
+    match next_cursor {
+        None => (-1i8).serialize_versioned(&mut body, 0).unwrap(),
+        Some((topic, partition)) => {
+            1i8.serialize_versioned(&mut body, 0).unwrap();
+            write(&mut body, &topic, 0, true);
+            (partition as i32)
+                .serialize_versioned(&mut body, 0)
+                .unwrap();
+            write_tags(&mut body, true);
+        }
+    }
+    write_tags(&mut body, true);
+    body
+}
+
+/// The partition a broker serves as leader, or the error code to answer with.
+fn led_partition<'a>(
+    state: &'a mut ClusterState,
+    node_id: i32,
+    topic: &str,
+    partition: i32,
+) -> Result<&'a mut MockPartition, i16> {
+    let partition = state
+        .topics
+        .iter_mut()
+        .find(|t| t.name == topic)
+        .and_then(|t| t.partitions.get_mut(partition as usize))
+        // UNKNOWN_TOPIC_OR_PARTITION
+        .ok_or(3i16)?;
+    if partition.leader_id != node_id {
+        // NOT_LEADER_OR_FOLLOWER
+        return Err(6);
+    }
+    Ok(partition)
+}
+
+/// Offset of the last record in a record batch.
+fn last_offset(batch: &[u8]) -> i64 {
+    let base_offset = i64::from_be_bytes(batch[0..8].try_into().unwrap());
+    base_offset + i64::from(i32::from_be_bytes(batch[23..27].try_into().unwrap()))
+}
+
+/// Appends every batch of the request to the log of its partition, with acks=-1 semantics.
+/// Appends the batches, answering unless `acks` is 0.
+fn produce(data: &mut Cursor<Vec<u8>>, node_id: i32, state: &mut ClusterState) -> Option<Vec<u8>> {
+    let _transactional_id = Option::<String>::deserialize_versioned(data, 8).unwrap();
+    let acks = i16::deserialize_versioned(data, 8).unwrap();
+    let _timeout_ms = i32::deserialize_versioned(data, 8).unwrap();
+    let topics = read_fixed_array(data, |data| {
+        let name = String::deserialize_versioned(data, 8).unwrap();
+        let partitions = read_fixed_array(data, |data| {
+            let index = i32::deserialize_versioned(data, 8).unwrap();
+            let records = deserialize_nullable_bytes(data)
+                .unwrap()
+                .unwrap_or_default();
+            (index, records)
+        });
+        (name, partitions)
+    });
+
+    let mut body = vec![];
+    write_len(&mut body, topics.len(), false);
+    for (name, partitions) in topics {
+        name.serialize_versioned(&mut body, 8).unwrap();
+        write_len(&mut body, partitions.len(), false);
+        for (index, mut records) in partitions {
+            let failure = state.produce_errors.pop_front();
+            let (error, base_offset) = match (failure, led_partition(state, node_id, &name, index)) {
+                (Some(error), _) | (None, Err(error)) => (error, -1),
+                (None, Ok(partition)) => {
+                    let mut result = (0, partition.next_offset);
+                    while !records.is_empty() {
+                        let len = 12 + i32::from_be_bytes(records[8..12].try_into().unwrap());
+                        let mut batch: Vec<u8> = records.drain(..len as usize).collect();
+                        match check_sequence(partition, &batch) {
+                            Ok(()) => {
+                                batch[0..8].copy_from_slice(&partition.next_offset.to_be_bytes());
+                                partition.next_offset = last_offset(&batch) + 1;
+                                partition.batches.push(batch);
+                            }
+                            Err(Ok(base_offset)) => result = (0, base_offset),
+                            Err(Err(error)) => result = (error, -1),
+                        }
+                    }
+                    result
+                }
+            };
+            index.serialize_versioned(&mut body, 8).unwrap();
+            error.serialize_versioned(&mut body, 8).unwrap();
+            base_offset.serialize_versioned(&mut body, 8).unwrap();
+            state
+                .log_append_time
+                .unwrap_or(-1)
+                .serialize_versioned(&mut body, 8)
+                .unwrap();
+            0i64.serialize_versioned(&mut body, 8).unwrap();
+            write_len(&mut body, 0, false);
+            None::<String>.serialize_versioned(&mut body, 8).unwrap();
+        }
+    }
+    0i32.serialize_versioned(&mut body, 8).unwrap();
+    (acks != 0).then_some(body)
+}
+
+/// Validates the sequence of a batch from an idempotent producer and records it, like a
+/// partition leader does. Fails with the base offset of a duplicate of the last batch, or with
+/// the error code to answer with.
+fn check_sequence(partition: &mut MockPartition, batch: &[u8]) -> Result<(), Result<i64, i16>> {
+    let producer_id = i64::from_be_bytes(batch[43..51].try_into().unwrap());
+    let epoch = i16::from_be_bytes(batch[51..53].try_into().unwrap());
+    let base_sequence = i32::from_be_bytes(batch[53..57].try_into().unwrap());
+    let count = i32::from_be_bytes(batch[23..27].try_into().unwrap()) + 1;
+    if producer_id < 0 {
+        return Ok(());
+    }
+    match partition.producers.get(&producer_id) {
+        // UNKNOWN_PRODUCER_ID
+        None if base_sequence != 0 => return Err(Err(59)),
+        // INVALID_PRODUCER_EPOCH
+        Some(producer) if epoch < producer.epoch => return Err(Err(47)),
+        // OUT_OF_ORDER_SEQUENCE_NUMBER
+        Some(producer) if epoch > producer.epoch && base_sequence != 0 => return Err(Err(45)),
+        Some(producer) if epoch == producer.epoch => {
+            if producer.last_batch.0 == base_sequence {
+                return Err(Ok(producer.last_batch.1));
+            }
+            if producer.next_sequence != base_sequence {
+                return Err(Err(45));
+            }
+        }
+        _ => {}
+    }
+    partition.producers.insert(
+        producer_id,
+        MockProducer {
+            epoch,
+            next_sequence: base_sequence + count,
+            last_batch: (base_sequence, partition.next_offset),
+        },
+    );
+    Ok(())
+}
+
+/// Hands out producer ids, bumping the epoch when the current id and epoch are passed or a
+/// transactional id is registered again.
+fn init_producer_id(data: &mut Cursor<Vec<u8>>, state: &mut ClusterState) -> Vec<u8> {
+    let transactional_id = Option::<String>::deserialize_compact(data, 4).unwrap();
+    let _transaction_timeout_ms = i32::deserialize_versioned(data, 4).unwrap();
+    let producer_id = i64::deserialize_versioned(data, 4).unwrap();
+    let producer_epoch = i16::deserialize_versioned(data, 4).unwrap();
+    TaggedFields::deserialize_versioned(data, 4).unwrap();
+
+    let registered = transactional_id
+        .as_ref()
+        .and_then(|id| state.transactions.get(id));
+    let current = match registered {
+        // a new instance takes over the transactional id and fences the previous one
+        Some(transaction) if producer_id < 0 => Some(transaction.producer_id as usize),
+        _ => usize::try_from(producer_id)
+            .ok()
+            .filter(|id| state.producer_epochs.get(*id) == Some(&producer_epoch)),
+    };
+    let (error, producer_id, producer_epoch) = match current {
+        Some(id) => {
+            state.producer_epochs[id] += 1;
+            (0i16, id as i64, state.producer_epochs[id])
+        }
+        // INVALID_PRODUCER_EPOCH
+        None if producer_id >= 0 => (47, -1, -1),
+        None => {
+            state.producer_epochs.push(0);
+            (0, state.producer_epochs.len() as i64 - 1, 0)
+        }
+    };
+    if let (Some(transactional_id), 0) = (transactional_id, error) {
+        // aborts the transaction left open
+        let previous = state.transactions.insert(
+            transactional_id,
+            MockTransaction {
+                producer_id,
+                ..Default::default()
+            },
+        );
+        if let Some(previous) = previous {
+            write_markers(state, producer_id, producer_epoch, &previous.partitions);
+        }
+    }
+
+    let mut body = vec![];
+    0i32.serialize_versioned(&mut body, 4).unwrap();
+    error.serialize_versioned(&mut body, 4).unwrap();
+    producer_id.serialize_versioned(&mut body, 4).unwrap();
+    producer_epoch.serialize_versioned(&mut body, 4).unwrap();
+    write_tags(&mut body, true);
+    body
+}
+
+/// Ends the transaction on its partitions under the bumped `epoch`, so their leaders reject
+/// batches of older epochs.
+fn write_markers(
+    state: &mut ClusterState,
+    producer_id: i64,
+    epoch: i16,
+    partitions: &[(String, i32)],
+) {
+    for (topic, partition) in partitions {
+        let topic = state.topics.iter_mut().find(|t| &t.name == topic).unwrap();
+        topic.partitions[*partition as usize].producers.insert(
+            producer_id,
+            MockProducer {
+                epoch,
+                next_sequence: 0,
+                last_batch: (-1, -1),
+            },
+        );
+    }
+}
+
+/// Every group and transaction is coordinated by the controller.
+fn find_coordinator(data: &mut Cursor<Vec<u8>>, state: &ClusterState) -> Vec<u8> {
+    let _key = String::deserialize_compact(data, 3).unwrap();
+    let _key_type = i8::deserialize_versioned(data, 3).unwrap();
+    TaggedFields::deserialize_versioned(data, 3).unwrap();
+
+    let coordinator = state
+        .brokers
+        .iter()
+        .find(|broker| broker.node_id == state.controller_id)
+        .unwrap();
+    let mut body = vec![];
+    0i32.serialize_versioned(&mut body, 3).unwrap();
+    0i16.serialize_versioned(&mut body, 3).unwrap();
+    None::<String>.serialize_compact(&mut body, 3).unwrap();
+    coordinator
+        .node_id
+        .serialize_versioned(&mut body, 3)
+        .unwrap();
+    coordinator.host.serialize_compact(&mut body, 3).unwrap();
+    coordinator.port.serialize_versioned(&mut body, 3).unwrap();
+    write_tags(&mut body, true);
+    body
+}
+
+/// Error code for a request of a transaction under `producer_id` and `epoch`.
+fn check_transaction(
+    state: &ClusterState,
+    transactional_id: &str,
+    producer_id: i64,
+    epoch: i16,
+) -> i16 {
+    match state.transactions.get(transactional_id) {
+        Some(transaction) if transaction.producer_id == producer_id => {
+            if state.producer_epochs[producer_id as usize] == epoch {
+                0
+            } else {
+                // PRODUCER_FENCED
+                90
+            }
+        }
+        // INVALID_PRODUCER_ID_MAPPING
+        _ => 49,
+    }
+}
+
+fn add_partitions_to_txn(data: &mut Cursor<Vec<u8>>, state: &mut ClusterState) -> Vec<u8> {
+    let transactional_id = String::deserialize_compact(data, 3).unwrap();
+    let producer_id = i64::deserialize_versioned(data, 3).unwrap();
+    let producer_epoch = i16::deserialize_versioned(data, 3).unwrap();
+    let topics = read_array(data, |data| {
+        let name = String::deserialize_compact(data, 3).unwrap();
+        let partitions =
+            read_array(data, |data| i32::deserialize_versioned(data, 3).unwrap()).unwrap();
+        TaggedFields::deserialize_versioned(data, 3).unwrap();
+        (name, partitions)
+    })
+    .unwrap();
+    TaggedFields::deserialize_versioned(data, 3).unwrap();
+
+    let error = check_transaction(state, &transactional_id, producer_id, producer_epoch);
+    let mut body = vec![];
+    0i32.serialize_versioned(&mut body, 3).unwrap();
+    write_len(&mut body, topics.len(), true);
+    for (name, partitions) in topics {
+        name.serialize_compact(&mut body, 3).unwrap();
+        write_len(&mut body, partitions.len(), true);
+        for partition in partitions {
+            if error == 0 {
+                let transaction = state.transactions.get_mut(&transactional_id).unwrap();
+                transaction.partitions.push((name.clone(), partition));
+            }
+            partition.serialize_versioned(&mut body, 3).unwrap();
+            error.serialize_versioned(&mut body, 3).unwrap();
+            write_tags(&mut body, true);
+        }
+        write_tags(&mut body, true);
+    }
+    write_tags(&mut body, true);
+    body
+}
+
+fn add_offsets_to_txn(data: &mut Cursor<Vec<u8>>, state: &mut ClusterState) -> Vec<u8> {
+    let transactional_id = String::deserialize_compact(data, 3).unwrap();
+    let producer_id = i64::deserialize_versioned(data, 3).unwrap();
+    let producer_epoch = i16::deserialize_versioned(data, 3).unwrap();
+    let group_id = String::deserialize_compact(data, 3).unwrap();
+    TaggedFields::deserialize_versioned(data, 3).unwrap();
+
+    let error = check_transaction(state, &transactional_id, producer_id, producer_epoch);
+    if error == 0 {
+        let transaction = state.transactions.get_mut(&transactional_id).unwrap();
+        transaction.groups.push(group_id);
+    }
+    let mut body = vec![];
+    0i32.serialize_versioned(&mut body, 3).unwrap();
+    error.serialize_versioned(&mut body, 3).unwrap();
+    write_tags(&mut body, true);
+    body
+}
+
+/// Ends the transaction and bumps the epoch, like coordinators supporting KIP-890 do.
+fn end_txn(data: &mut Cursor<Vec<u8>>, state: &mut ClusterState) -> Vec<u8> {
+    let transactional_id = String::deserialize_compact(data, 5).unwrap();
+    let producer_id = i64::deserialize_versioned(data, 5).unwrap();
+    let producer_epoch = i16::deserialize_versioned(data, 5).unwrap();
+    let committed = bool::deserialize_versioned(data, 5).unwrap();
+    TaggedFields::deserialize_versioned(data, 5).unwrap();
+
+    let error = check_transaction(state, &transactional_id, producer_id, producer_epoch);
+    let (producer_id, producer_epoch) = if error == 0 {
+        let transaction = state.transactions.get_mut(&transactional_id).unwrap();
+        let offsets = std::mem::take(&mut transaction.offsets);
+        let partitions = std::mem::take(&mut transaction.partitions);
+        transaction.groups.clear();
+        if committed {
+            state.committed_offsets.extend(offsets);
+        }
+        state.ended_transactions.push((transactional_id, committed));
+        state.producer_epochs[producer_id as usize] += 1;
+        let producer_epoch = state.producer_epochs[producer_id as usize];
+        write_markers(state, producer_id, producer_epoch, &partitions);
+        (producer_id, producer_epoch)
+    } else {
+        (-1, -1)
+    };
+
+    let mut body = vec![];
+    0i32.serialize_versioned(&mut body, 5).unwrap();
+    error.serialize_versioned(&mut body, 5).unwrap();
+    producer_id.serialize_versioned(&mut body, 5).unwrap();
+    producer_epoch.serialize_versioned(&mut body, 5).unwrap();
+    write_tags(&mut body, true);
+    body
+}
+
+fn txn_offset_commit(data: &mut Cursor<Vec<u8>>, state: &mut ClusterState) -> Vec<u8> {
+    let transactional_id = String::deserialize_compact(data, 3).unwrap();
+    let group_id = String::deserialize_compact(data, 3).unwrap();
+    let producer_id = i64::deserialize_versioned(data, 3).unwrap();
+    let producer_epoch = i16::deserialize_versioned(data, 3).unwrap();
+    let _generation_id = i32::deserialize_versioned(data, 3).unwrap();
+    let _member_id = String::deserialize_compact(data, 3).unwrap();
+    let _group_instance_id = Option::<String>::deserialize_compact(data, 3).unwrap();
+    let topics = read_array(data, |data| {
+        let name = String::deserialize_compact(data, 3).unwrap();
+        let partitions = read_array(data, |data| {
+            let index = i32::deserialize_versioned(data, 3).unwrap();
+            let offset = i64::deserialize_versioned(data, 3).unwrap();
+            let _leader_epoch = i32::deserialize_versioned(data, 3).unwrap();
+            let _metadata = Option::<String>::deserialize_compact(data, 3).unwrap();
+            TaggedFields::deserialize_versioned(data, 3).unwrap();
+            (index, offset)
+        })
+        .unwrap();
+        TaggedFields::deserialize_versioned(data, 3).unwrap();
+        (name, partitions)
+    })
+    .unwrap();
+    TaggedFields::deserialize_versioned(data, 3).unwrap();
+
+    let mut error = check_transaction(state, &transactional_id, producer_id, producer_epoch);
+    if error == 0
+        && !state.transactions[&transactional_id]
+            .groups
+            .contains(&group_id)
+    {
+        // INVALID_TXN_STATE
+        error = 48;
+    }
+    let mut body = vec![];
+    0i32.serialize_versioned(&mut body, 3).unwrap();
+    write_len(&mut body, topics.len(), true);
+    for (name, partitions) in topics {
+        name.serialize_compact(&mut body, 3).unwrap();
+        write_len(&mut body, partitions.len(), true);
+        for (index, offset) in partitions {
+            if error == 0 {
+                let transaction = state.transactions.get_mut(&transactional_id).unwrap();
+                let key = (group_id.clone(), name.clone(), index);
+                transaction.offsets.push((key, offset));
+            }
+            index.serialize_versioned(&mut body, 3).unwrap();
+            error.serialize_versioned(&mut body, 3).unwrap();
+            write_tags(&mut body, true);
+        }
+        write_tags(&mut body, true);
+    }
+    write_tags(&mut body, true);
+    body
+}
+
+/// Answers with the batches from the fetch offset on, at least one and otherwise up to the
+/// partition max bytes.
+fn fetch(data: &mut Cursor<Vec<u8>>, node_id: i32, state: &mut ClusterState) -> Vec<u8> {
+    for _ in 0..4 {
+        i32::deserialize_versioned(data, 11).unwrap();
+    }
+    let _isolation_level = i8::deserialize_versioned(data, 11).unwrap();
+    let _session_id = i32::deserialize_versioned(data, 11).unwrap();
+    let _session_epoch = i32::deserialize_versioned(data, 11).unwrap();
+    let topics = read_fixed_array(data, |data| {
+        let name = String::deserialize_versioned(data, 11).unwrap();
+        let partitions = read_fixed_array(data, |data| {
+            let index = i32::deserialize_versioned(data, 11).unwrap();
+            let _current_leader_epoch = i32::deserialize_versioned(data, 11).unwrap();
+            let fetch_offset = i64::deserialize_versioned(data, 11).unwrap();
+            let _log_start_offset = i64::deserialize_versioned(data, 11).unwrap();
+            let max_bytes = i32::deserialize_versioned(data, 11).unwrap();
+            (index, fetch_offset, max_bytes as usize)
+        });
+        (name, partitions)
+    });
+
+    let mut body = vec![];
+    0i32.serialize_versioned(&mut body, 11).unwrap();
+    0i16.serialize_versioned(&mut body, 11).unwrap();
+    0i32.serialize_versioned(&mut body, 11).unwrap();
+    write_len(&mut body, topics.len(), false);
+    for (name, partitions) in topics {
+        name.serialize_versioned(&mut body, 11).unwrap();
+        write_len(&mut body, partitions.len(), false);
+        for (index, fetch_offset, max_bytes) in partitions {
+            let (error, high_watermark, log_start_offset, records) =
+                match led_partition(state, node_id, &name, index) {
+                    Err(error) => (error, -1, -1, vec![]),
+                    Ok(partition)
+                        if fetch_offset < partition.log_start_offset
+                            || fetch_offset > partition.next_offset =>
+                    {
+                        // OFFSET_OUT_OF_RANGE
+                        (1, partition.next_offset, partition.log_start_offset, vec![])
+                    }
+                    Ok(partition) => {
+                        let mut records = vec![];
+                        for batch in &partition.batches {
+                            if last_offset(batch) < fetch_offset {
+                                continue;
+                            }
+                            if !records.is_empty() && records.len() + batch.len() > max_bytes {
+                                break;
+                            }
+                            records.extend_from_slice(batch);
+                        }
+                        (
+                            0,
+                            partition.next_offset,
+                            partition.log_start_offset,
+                            records,
+                        )
+                    }
+                };
+            index.serialize_versioned(&mut body, 11).unwrap();
+            error.serialize_versioned(&mut body, 11).unwrap();
+            high_watermark.serialize_versioned(&mut body, 11).unwrap();
+            high_watermark.serialize_versioned(&mut body, 11).unwrap();
+            log_start_offset.serialize_versioned(&mut body, 11).unwrap();
+            write_len(&mut body, 0, false);
+            (-1i32).serialize_versioned(&mut body, 11).unwrap();
+            serialize_nullable_bytes(&Some(records), &mut body).unwrap();
+        }
+    }
+    body
+}
+
+/// Resolves the earliest (-2) and latest (-1) offsets, and timestamps to the first batch not
+/// older than them.
+fn list_offsets(data: &mut Cursor<Vec<u8>>, node_id: i32, state: &mut ClusterState) -> Vec<u8> {
+    let _replica_id = i32::deserialize_versioned(data, 5).unwrap();
+    let _isolation_level = i8::deserialize_versioned(data, 5).unwrap();
+    let topics = read_fixed_array(data, |data| {
+        let name = String::deserialize_versioned(data, 5).unwrap();
+        let partitions = read_fixed_array(data, |data| {
+            let index = i32::deserialize_versioned(data, 5).unwrap();
+            let _current_leader_epoch = i32::deserialize_versioned(data, 5).unwrap();
+            let timestamp = i64::deserialize_versioned(data, 5).unwrap();
+            (index, timestamp)
+        });
+        (name, partitions)
+    });
+
+    let mut body = vec![];
+    0i32.serialize_versioned(&mut body, 5).unwrap();
+    write_len(&mut body, topics.len(), false);
+    for (name, partitions) in topics {
+        name.serialize_versioned(&mut body, 5).unwrap();
+        write_len(&mut body, partitions.len(), false);
+        for (index, timestamp) in partitions {
+            let (error, offset) = match led_partition(state, node_id, &name, index) {
+                Err(error) => (error, -1),
+                Ok(partition) => match timestamp {
+                    -2 => (0, partition.log_start_offset),
+                    -1 => (0, partition.next_offset),
+                    timestamp => (
+                        0,
+                        partition
+                            .batches
+                            .iter()
+                            .find(|batch| {
+                                i64::from_be_bytes(batch[35..43].try_into().unwrap()) >= timestamp
+                            })
+                            .map(|batch| i64::from_be_bytes(batch[0..8].try_into().unwrap()))
+                            .unwrap_or(partition.next_offset),
+                    ),
+                },
+            };
+            index.serialize_versioned(&mut body, 5).unwrap();
+            error.serialize_versioned(&mut body, 5).unwrap();
+            timestamp.serialize_versioned(&mut body, 5).unwrap();
+            offset.serialize_versioned(&mut body, 5).unwrap();
+            0i32.serialize_versioned(&mut body, 5).unwrap();
+        }
+    }
+    body
+}
+
+/// Moves the log start offset and drops the batches entirely before it.
+fn delete_records(data: &mut Cursor<Vec<u8>>, node_id: i32, state: &mut ClusterState) -> Vec<u8> {
+    let topics = read_fixed_array(data, |data| {
+        let name = String::deserialize_versioned(data, 1).unwrap();
+        let partitions = read_fixed_array(data, |data| {
+            let index = i32::deserialize_versioned(data, 1).unwrap();
+            let offset = i64::deserialize_versioned(data, 1).unwrap();
+            (index, offset)
+        });
+        (name, partitions)
+    });
+    let _timeout_ms = i32::deserialize_versioned(data, 1).unwrap();
+
+    let mut body = vec![];
+    0i32.serialize_versioned(&mut body, 1).unwrap();
+    write_len(&mut body, topics.len(), false);
+    for (name, partitions) in topics {
+        name.serialize_versioned(&mut body, 1).unwrap();
+        write_len(&mut body, partitions.len(), false);
+        for (index, offset) in partitions {
+            let (error, low_watermark) = match led_partition(state, node_id, &name, index) {
+                Err(error) => (error, -1),
+                Ok(partition) => {
+                    // -1 deletes up to the high watermark
+                    let offset = if offset == -1 {
+                        partition.next_offset
+                    } else {
+                        offset
+                    };
+                    if offset > partition.next_offset {
+                        // OFFSET_OUT_OF_RANGE
+                        (1, partition.log_start_offset)
+                    } else {
+                        partition.log_start_offset = partition.log_start_offset.max(offset);
+                        let start = partition.log_start_offset;
+                        partition
+                            .batches
+                            .retain(|batch| last_offset(batch) >= start);
+                        (0, start)
+                    }
+                }
+            };
+            index.serialize_versioned(&mut body, 1).unwrap();
+            low_watermark.serialize_versioned(&mut body, 1).unwrap();
+            error.serialize_versioned(&mut body, 1).unwrap();
+        }
+    }
+    body
+}
+
+fn create_topics(data: &mut Cursor<Vec<u8>>, node_id: i32, state: &mut ClusterState) -> Vec<u8> {
+    let topics = read_fixed_array(data, |data| {
+        let name = String::deserialize_versioned(data, 4).unwrap();
+        let num_partitions = i32::deserialize_versioned(data, 4).unwrap();
+        let _replication_factor = i16::deserialize_versioned(data, 4).unwrap();
+        read_fixed_array(data, |data| {
+            i32::deserialize_versioned(data, 4).unwrap();
+            Vec::<i32>::deserialize_versioned(data, 4).unwrap()
+        });
+        read_fixed_array(data, |data| {
+            String::deserialize_versioned(data, 4).unwrap();
+            Option::<String>::deserialize_versioned(data, 4).unwrap()
+        });
+        (name, num_partitions)
+    });
+    let _timeout_ms = i32::deserialize_versioned(data, 4).unwrap();
+    let validate_only = bool::deserialize_versioned(data, 4).unwrap();
+
+    let mut body = vec![];
+    0i32.serialize_versioned(&mut body, 4).unwrap();
+    write_len(&mut body, topics.len(), false);
+    for (name, num_partitions) in topics {
+        let error: i16 = if node_id != state.controller_id {
+            // NOT_CONTROLLER
+            41
+        } else if state.topics.iter().any(|topic| topic.name == name) {
+            // TOPIC_ALREADY_EXISTS
+            36
+        } else {
+            0
+        };
+        if error == 0 && !validate_only {
+            state.add_topic(&name, num_partitions);
+        }
+        name.serialize_versioned(&mut body, 4).unwrap();
+        error.serialize_versioned(&mut body, 4).unwrap();
+        None::<String>.serialize_versioned(&mut body, 4).unwrap();
+    }
+    body
+}
+
+fn delete_topics(data: &mut Cursor<Vec<u8>>, node_id: i32, state: &mut ClusterState) -> Vec<u8> {
+    let names = Vec::<String>::deserialize_versioned(data, 3).unwrap();
+    let _timeout_ms = i32::deserialize_versioned(data, 3).unwrap();
+
+    let mut body = vec![];
+    0i32.serialize_versioned(&mut body, 3).unwrap();
+    write_len(&mut body, names.len(), false);
+    for name in names {
+        let error: i16 = if node_id != state.controller_id {
+            // NOT_CONTROLLER
+            41
+        } else if let Some(index) = state.topics.iter().position(|topic| topic.name == name) {
+            state.topics.remove(index);
+            0
+        } else {
+            // UNKNOWN_TOPIC_OR_PARTITION
+            3
+        };
+        name.serialize_versioned(&mut body, 3).unwrap();
+        error.serialize_versioned(&mut body, 3).unwrap();
+    }
+    body
+}
+
+/// Reads an array with an i32 length.
+fn read_fixed_array<T>(
+    data: &mut Cursor<Vec<u8>>,
+    mut read: impl FnMut(&mut Cursor<Vec<u8>>) -> T,
+) -> Vec<T> {
+    let len = i32::deserialize_versioned(data, 0).unwrap();
+    (0..len.max(0)).map(|_| read(data)).collect()
+}
+
+/// Reads a compact nullable array.
+fn read_array<T>(
+    data: &mut Cursor<Vec<u8>>,
+    mut read: impl FnMut(&mut Cursor<Vec<u8>>) -> T,
+) -> Option<Vec<T>> {
+    match deserialize_unsigned_var_int(data).unwrap() {
+        0 => None,
+        len => Some((1..len).map(|_| read(data)).collect()),
+    }
+}
+
+fn write<T>(body: &mut Vec<u8>, value: &T, version: i16, flexible: bool)
+where
+    T: SerializeVersioned<Vec<u8>> + SerializeCompact<Vec<u8>>,
+{
+    if flexible {
+        value.serialize_compact(body, version).unwrap();
+    } else {
+        value.serialize_versioned(body, version).unwrap();
+    }
+}
+
+fn write_len(body: &mut Vec<u8>, len: usize, flexible: bool) {
+    if flexible {
+        serialize_unsigned_var_int(len as u64 + 1, body).unwrap();
+    } else {
+        (len as i32).serialize_versioned(body, 0).unwrap();
+    }
+}
+
+fn write_tags(body: &mut Vec<u8>, flexible: bool) {
+    if flexible {
+        serialize_unsigned_var_int(0, body).unwrap();
+    }
+}
@@ -1,65 +1,686 @@
 mod broker;
-mod stream;
+mod cluster;
+pub mod consumer;
+pub mod controller;
+pub mod interceptor;
+pub mod metadata;
+pub mod pool;
+#[cfg(test)]
+pub(crate) mod mock;
+pub mod partition;
+pub mod producer;
+pub mod resolver;
+pub(crate) mod stream;
+pub mod transport;
 
 use crate::{
-    client::broker::BrokerConnection,
-    error::Result,
-    protocol::messages::metadata::{MetadataRequest, MetadataRequestTopic},
+    backoff::BackoffConfig,
+    client::{
+        cluster::Cluster,
+        controller::ControllerClient,
+        interceptor::{Interceptor, Interceptors},
+        metadata::{MetadataResponse, MetadataResponseTopic, PartitionDescription, TopologyDiff},
+        partition::{PartitionClient, UnknownTopicHandling},
+        producer::{
+            idempotent::IdempotentProducer,
+            memory::{BufferMemory, DEFAULT_BUFFER_MEMORY, DEFAULT_MAX_BLOCK},
+            topic::TopicProducerBuilder,
+            transaction::TransactionalProducer,
+        },
+        pool::{ConnectionPoolConfig, RequestClass},
+        resolver::{
+            canonical_bootstrap_servers, AdvertisedAddresses, BrokerAddressResolver, DnsLookup,
+            DnsResolver, SystemDnsResolver,
+        },
+        stream::ConnectionConfig,
+        transport::{BoxedConnector, Connector, TcpConnector},
+    },
+    error::{ClientClosedSnafu, NoBrokersSnafu, Result},
+    protocol::messages::{
+        describe_topic_partitions::{
+            DescribeTopicPartitionsRequest, DescribeTopicPartitionsRequestTopic,
+        },
+        TaggedFields,
+    },
 };
-use futures::future::try_join_all;
-use rand::{prelude::SliceRandom, thread_rng};
+use futures::{
+    stream::{iter, unfold},
+    Stream, StreamExt,
+};
+use log::warn;
+use snafu::ensure;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{task::JoinHandle, time::Instant};
+use tokio_stream::wrappers::{errors::BroadcastStreamRecvError, BroadcastStream};
 
 pub struct KafkaClient {
-    pub brokers: Vec<BrokerConnection>,
+    cluster: Arc<Cluster>,
+    metadata_refresh: JoinHandle<()>,
 }
 
 pub struct ClientBuilder {
     pub brokers: Vec<String>,
     pub client_id: Option<String>,
     pub max_message_size: usize,
+    /// Opens broker connections, TCP using [`dns_resolver`](Self::dns_resolver) if `None`
+    pub connector: Option<BoxedConnector>,
+    pub dns_lookup: DnsLookup,
+    pub dns_resolver: Arc<dyn DnsResolver>,
+    /// Maps advertised broker addresses to the addresses to connect to
+    pub address_resolver: Arc<dyn BrokerAddressResolver>,
+    /// Default time to wait for a response from a broker
+    pub request_timeout: Duration,
+    /// Close a broker connection after this many requests in a row timed out
+    pub max_consecutive_timeouts: Option<usize>,
+    /// Number of requests that may wait for a response on one connection, unlimited if `None`
+    pub max_in_flight_requests_per_connection: Option<usize>,
+    /// Wait when a connection has the maximum number of requests in flight instead of failing
+    pub wait_when_saturated: bool,
+    /// Close connections that were not used for this long, they are reopened when needed
+    pub connections_max_idle: Option<Duration>,
+    /// Probe idle connections with an `ApiVersions` request this often
+    pub keepalive_interval: Option<Duration>,
+    /// Connections kept per broker and request class
+    pub connection_pool: ConnectionPoolConfig,
+    /// Backoff used when (re-)connecting to brokers
+    pub backoff_config: BackoffConfig,
+    /// Refresh the cached cluster metadata once it is older than this
+    pub metadata_max_age: Duration,
+    /// Only talk to brokers of the cluster with this id, to catch bootstrap servers of the
+    /// wrong environment
+    pub expected_cluster_id: Option<String>,
+    /// Bytes the records waiting to be produced may take up, shared by all producers
+    pub buffer_memory: usize,
+    /// Time producing a record waits for buffer memory before failing with
+    /// [`Error::BufferExhausted`](crate::error::Error::BufferExhausted), forever if `None`
+    pub max_block: Option<Duration>,
+    /// Applied to every produced and fetched record, in this order
+    pub interceptors: Vec<Arc<dyn Interceptor>>,
 }
 
 impl KafkaClient {
-    pub fn new(brokers: Vec<String>) -> ClientBuilder {
-        ClientBuilder {
+    /// Metadata of all topics in the cluster, served from the cache while it is fresh.
+    pub async fn metadata(&self) -> Result<Arc<MetadataResponse>> {
+        self.cluster.metadata().await
+    }
+
+    /// Requests metadata of the given topics, bypassing the cache. `None` requests all topics.
+    pub async fn request_metadata(&self, topics: Option<Vec<String>>) -> Result<MetadataResponse> {
+        self.cluster.request_metadata(topics).await
+    }
+
+    /// All topics of the cluster, requested from a broker instead of the cache.
+    pub async fn list_topics(&self) -> Result<Vec<MetadataResponseTopic>> {
+        Ok(self.cluster.request_metadata(None).await?.topics)
+    }
+
+    /// A client to create and delete topics through the controller of the cluster.
+    pub fn controller_client(&self) -> Result<ControllerClient> {
+        ensure!(!self.cluster.is_closed(), ClientClosedSnafu);
+        Ok(ControllerClient::new(self.cluster.clone()))
+    }
+
+    /// Number of requests waiting for a response, by the node id of the broker they were sent to.
+    pub fn in_flight_requests(&self) -> BTreeMap<i32, usize> {
+        self.cluster.in_flight_requests()
+    }
+
+    /// Node ids of the brokers a connection is currently (re-)established to. Their requests
+    /// are not in flight yet.
+    pub fn connecting_brokers(&self) -> BTreeSet<i32> {
+        self.cluster.connecting_brokers()
+    }
+
+    /// Changes of the cluster topology, found whenever the cached metadata is refreshed. Slow
+    /// subscribers miss diffs if they fall too far behind.
+    pub fn topology_changes(&self) -> impl Stream<Item = TopologyDiff> {
+        BroadcastStream::new(self.cluster.subscribe_topology()).filter_map(|diff| async {
+            match diff {
+                Ok(diff) => Some(diff),
+                Err(BroadcastStreamRecvError::Lagged(skipped)) => {
+                    warn!("Topology subscriber lagged behind, skipped {} diffs", skipped);
+                    None
+                }
+            }
+        })
+    }
+
+    /// Describes every partition of `topics`, fetching at most `partition_limit` partitions per
+    /// request and following the cursor of each response until all partitions were described.
+    /// A topic that cannot be described yields its error, a failed request ends the stream.
+    pub fn describe_topic_partitions(
+        &self,
+        topics: Vec<String>,
+        partition_limit: i32,
+    ) -> impl Stream<Item = Result<PartitionDescription>> {
+        let cluster = self.cluster.clone();
+        // `None` once the last page was fetched
+        unfold(Some(None), move |cursor| {
+            let cluster = cluster.clone();
+            let topics = topics.clone();
+            async move {
+                let cursor = cursor?;
+                let response = cluster
+                    .request_any(|| DescribeTopicPartitionsRequest {
+                        topics: topics
+                            .iter()
+                            .map(|name| DescribeTopicPartitionsRequestTopic {
+                                name: name.clone(),
+                                tagged_fields: Some(TaggedFields::default()),
+                            })
+                            .collect(),
+                        response_partition_limit: partition_limit,
+                        cursor: cursor.clone(),
+                        tagged_fields: Some(TaggedFields::default()),
+                    })
+                    .await;
+                Some(match response {
+                    Ok((_, response)) => (
+                        response
+                            .topics
+                            .into_iter()
+                            .flat_map(PartitionDescription::from_topic)
+                            .collect(),
+                        response.next_cursor.map(Some),
+                    ),
+                    Err(e) => (vec![Err(e)], None),
+                })
+            }
+        })
+        .flat_map(iter)
+    }
+
+    /// A client for a single partition, created once its leader is known.
+    pub async fn partition_client(
+        &self,
+        topic: impl Into<String>,
+        partition: i32,
+        unknown_topic_handling: UnknownTopicHandling,
+    ) -> Result<PartitionClient> {
+        PartitionClient::new(
+            self.cluster.clone(),
+            topic.into(),
+            partition,
+            unknown_topic_handling,
+        )
+        .await
+    }
+
+    /// A producer whose batches are written exactly once, to share between the partitions it
+    /// produces to.
+    pub fn idempotent_producer(&self) -> IdempotentProducer {
+        IdempotentProducer::new(self.cluster.clone())
+    }
+
+    /// A producer to all partitions of `topic`, routing the records with a partitioner.
+    pub fn topic_producer(&self, topic: impl Into<String>) -> TopicProducerBuilder {
+        TopicProducerBuilder::new(self.cluster.clone(), topic.into())
+    }
+
+    /// A producer of transactions under `transactional_id`, fencing any previous instance.
+    /// The coordinator aborts a transaction left open for longer than `transaction_timeout`.
+    pub async fn transactional_producer(
+        &self,
+        transactional_id: impl Into<String>,
+        transaction_timeout: Duration,
+    ) -> Result<TransactionalProducer> {
+        TransactionalProducer::new(
+            self.cluster.clone(),
+            transactional_id.into(),
+            transaction_timeout,
+        )
+        .await
+    }
+
+    /// Refreshes the cached metadata every max age, so lookups rarely wait for a request.
+    fn spawn_metadata_refresh(cluster: Arc<Cluster>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(cluster.metadata_max_age()).await;
+                if let Err(e) = cluster.refresh_metadata().await {
+                    warn!("Background metadata refresh failed: {}", e);
+                }
+            }
+        })
+    }
+
+    /// Gracefully shuts the client down: new requests are refused, buffered work is flushed and
+    /// outstanding responses are awaited until `deadline`, then all connections are closed.
+    pub async fn close(&self, deadline: Instant) {
+        self.metadata_refresh.abort();
+        self.cluster.close(deadline).await;
+    }
+}
+
+impl Drop for KafkaClient {
+    fn drop(&mut self) {
+        self.metadata_refresh.abort();
+    }
+}
+
+impl ClientBuilder {
+    pub fn new(brokers: Vec<String>) -> Self {
+        Self {
             brokers,
             client_id: None,
             max_message_size: 100 * 1024 * 1024, // 100 MB
+            connector: None,
+            dns_lookup: DnsLookup::default(),
+            dns_resolver: Arc::new(SystemDnsResolver),
+            address_resolver: Arc::new(AdvertisedAddresses),
+            request_timeout: Duration::from_secs(30),
+            max_consecutive_timeouts: None,
+            max_in_flight_requests_per_connection: Some(5),
+            wait_when_saturated: true,
+            connections_max_idle: Some(Duration::from_secs(9 * 60)),
+            keepalive_interval: None,
+            connection_pool: ConnectionPoolConfig::default(),
+            backoff_config: BackoffConfig::default(),
+            metadata_max_age: Duration::from_secs(5 * 60),
+            expected_cluster_id: None,
+            buffer_memory: DEFAULT_BUFFER_MEMORY,
+            max_block: Some(DEFAULT_MAX_BLOCK),
+            interceptors: vec![],
         }
     }
 
-    async fn request_metadata(&self, topics: Vec<String>) -> Result<()> {
-        let request = MetadataRequest {
-            topics: topics
-                .into_iter()
-                .map(|topic| MetadataRequestTopic { name: topic })
-                .collect(),
-            allow_auto_topic_creation: None,
-        };
+    /// Use a custom [`Connector`] to open broker connections instead of plain TCP.
+    pub fn connector<C: Connector>(mut self, connector: C) -> Self {
+        self.connector = Some(BoxedConnector::new(connector));
+        self
+    }
 
-        let mut brokers: Vec<&BrokerConnection> = self.brokers.iter().collect();
-        brokers.shuffle(&mut thread_rng());
+    /// Connect to the addresses `resolver` maps the bootstrap and advertised broker addresses
+    /// to, e.g. a [`StaticAddressMap`](resolver::StaticAddressMap).
+    pub fn address_resolver<R: BrokerAddressResolver>(mut self, resolver: R) -> Self {
+        self.address_resolver = Arc::new(resolver);
+        self
+    }
 
-        for broker in brokers {
-            Self::connect(broker).await?;
-        }
-        Ok(())
+    pub fn dns_lookup(mut self, dns_lookup: DnsLookup) -> Self {
+        self.dns_lookup = dns_lookup;
+        self
     }
 
-    async fn connect(broker: &BrokerConnection) -> Result<()> {
-        Ok(())
+    /// Resolve host names with `resolver`, used for the bootstrap servers and by the default
+    /// TCP connector.
+    pub fn dns_resolver<R: DnsResolver>(mut self, resolver: R) -> Self {
+        self.dns_resolver = Arc::new(resolver);
+        self
+    }
+
+    /// Keep `size` connections per broker and request class.
+    pub fn connection_pool_size(mut self, size: usize) -> Self {
+        self.connection_pool.connections_per_class = size;
+        self
+    }
+
+    /// Limit the requests in flight on every connection of `class`, unlimited if `None`.
+    pub fn max_in_flight_for(mut self, class: RequestClass, max_in_flight: Option<usize>) -> Self {
+        self.connection_pool.max_in_flight.insert(class, max_in_flight);
+        self
+    }
+
+    pub fn backoff_config(mut self, backoff_config: BackoffConfig) -> Self {
+        self.backoff_config = backoff_config;
+        self
+    }
+
+    pub fn metadata_max_age(mut self, metadata_max_age: Duration) -> Self {
+        self.metadata_max_age = metadata_max_age;
+        self
+    }
+
+    /// Fail instead of connecting to brokers of any other cluster.
+    pub fn expected_cluster_id(mut self, cluster_id: impl Into<String>) -> Self {
+        self.expected_cluster_id = Some(cluster_id.into());
+        self
+    }
+
+    /// Bytes the records waiting to be produced may take up, shared by all producers.
+    pub fn buffer_memory(mut self, buffer_memory: usize) -> Self {
+        self.buffer_memory = buffer_memory;
+        self
+    }
+
+    /// Time producing a record waits for buffer memory, forever if `None`.
+    pub fn max_block(mut self, max_block: Option<Duration>) -> Self {
+        self.max_block = max_block;
+        self
+    }
+
+    /// Apply `interceptor` to every produced and fetched record, after the interceptors
+    /// registered before.
+    pub fn interceptor<I: Interceptor>(mut self, interceptor: I) -> Self {
+        self.interceptors.push(Arc::new(interceptor));
+        self
     }
-}
 
-impl ClientBuilder {
     pub async fn build(self) -> Result<KafkaClient> {
+        let brokers = match self.dns_lookup {
+            DnsLookup::UseAllDnsIps => self.brokers,
+            DnsLookup::ResolveCanonicalBootstrapServersOnly => {
+                let brokers =
+                    canonical_bootstrap_servers(self.dns_resolver.as_ref(), &self.brokers).await;
+                ensure!(!brokers.is_empty(), NoBrokersSnafu);
+                brokers
+            }
+        };
+        let connector = self.connector.unwrap_or_else(|| {
+            BoxedConnector::new(TcpConnector::new().with_shared_resolver(self.dns_resolver))
+        });
+
+        let cluster = Cluster::new(
+            brokers,
+            connector,
+            self.address_resolver,
+            ConnectionConfig {
+                client_id: self.client_id,
+                max_message_size: self.max_message_size,
+                request_timeout: self.request_timeout,
+                max_consecutive_timeouts: self.max_consecutive_timeouts,
+                max_in_flight_requests_per_connection: self.max_in_flight_requests_per_connection,
+                wait_when_saturated: self.wait_when_saturated,
+                connections_max_idle: self.connections_max_idle,
+                keepalive_interval: self.keepalive_interval,
+                expected_cluster_id: self.expected_cluster_id,
+            },
+            self.connection_pool,
+            self.backoff_config,
+            self.metadata_max_age,
+        )
+        .with_producer_memory(BufferMemory::new(self.buffer_memory, self.max_block))
+        .with_interceptors(Interceptors::new(self.interceptors));
+        let cluster = Arc::new(cluster);
+        cluster.bootstrap().await?;
+
         Ok(KafkaClient {
-            brokers: try_join_all(
-                self.brokers
-                    .into_iter()
-                    .map(|broker| BrokerConnection::new(broker, self.client_id.clone())),
-            )
-            .await?,
+            metadata_refresh: KafkaClient::spawn_metadata_refresh(cluster.clone()),
+            cluster,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::{
+            cluster::CloseHook,
+            metadata::TopologyChange,
+            mock::MockCluster,
+            resolver::{tests::FakeDns, StaticAddressMap},
+        },
+        error::Error,
+        protocol::error::Error as ProtocolError,
+    };
+    use assert_matches::assert_matches;
+    use futures::future::BoxFuture;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Weak,
+    };
+
+    fn client(cluster: &MockCluster, brokers: Vec<String>) -> ClientBuilder {
+        ClientBuilder {
+            brokers,
+            ..cluster.client_builder()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_skips_unreachable_seeds() {
+        let cluster = MockCluster::new(3);
+        cluster
+            .state
+            .lock()
+            .down
+            .insert("broker-0:9092".to_string());
+
+        let seeds = vec!["broker-0:9092".to_string(), "broker-1:9092".to_string()];
+        let client = client(&cluster, seeds).build().await.unwrap();
+
+        assert_eq!(
+            client.cluster.brokers(),
+            vec![
+                (0, "broker-0:9092".to_string()),
+                (1, "broker-1:9092".to_string()),
+                (2, "broker-2:9092".to_string()),
+            ]
+        );
+        assert_eq!(cluster.state.lock().connections, vec!["broker-1:9092"]);
+    }
+
+    #[tokio::test]
+    async fn test_metadata_falls_back_to_other_brokers() {
+        let cluster = MockCluster::new(2);
+        cluster.add_topic("test", 2);
+        let client = cluster.client().await;
+
+        // discovered brokers are connected lazily, so broker 0 is only known by its address
+        cluster
+            .state
+            .lock()
+            .down
+            .insert("broker-0:9092".to_string());
+
+        let metadata = client
+            .request_metadata(Some(vec!["test".to_string()]))
+            .await
+            .unwrap();
+        assert_eq!(metadata.topics[0].partitions.len(), 2);
+        assert_eq!(
+            cluster.state.lock().connections.last().unwrap(),
+            "broker-1:9092"
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_bootstrap_fails_without_reachable_seed() {
+        let cluster = MockCluster::new(1);
+        cluster
+            .state
+            .lock()
+            .down
+            .insert("broker-0:9092".to_string());
+
+        let err = cluster
+            .client_builder()
+            .build()
+            .await
+            .err()
+            .unwrap();
+        assert!(err.to_string().starts_with("All retries failed"));
+    }
+
+    #[tokio::test]
+    async fn test_address_resolver_rewrites_bootstrap_and_advertised_brokers() {
+        let cluster = MockCluster::new(2);
+        cluster.add_topic("test", 2);
+        {
+            let mut state = cluster.state.lock();
+            state.advertised.insert(0, ("kafka-0".to_string(), 9092));
+            state.advertised.insert(1, ("kafka-1".to_string(), 9092));
+        }
+        let resolver = StaticAddressMap::new()
+            .with("kafka-0:9092", "broker-0:9092")
+            .with("kafka-1:9092", "broker-1:9092");
+
+        let client = client(&cluster, vec!["kafka-0:9092".to_string()])
+            .address_resolver(resolver)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(
+            client.cluster.brokers(),
+            vec![
+                (0, "broker-0:9092".to_string()),
+                (1, "broker-1:9092".to_string()),
+            ]
+        );
+
+        // only reachable through the discovered broker 1
+        cluster.state.lock().down.insert("broker-0:9092".to_string());
+        assert!(client.request_metadata(None).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_through_canonical_names() {
+        let cluster = MockCluster::new(2);
+        let ip: std::net::IpAddr = "10.0.0.2".parse().unwrap();
+        let dns = FakeDns {
+            hosts: [("kafka".to_string(), vec![ip])].into(),
+            names: [(ip, "broker-1".to_string())].into(),
+        };
+
+        client(&cluster, vec!["kafka:9092".to_string()])
+            .dns_lookup(DnsLookup::ResolveCanonicalBootstrapServersOnly)
+            .dns_resolver(dns)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(cluster.state.lock().connections, vec!["broker-1:9092"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_metadata_refreshed_in_background() {
+        let cluster = MockCluster::new(1);
+        let client = cluster
+            .client_builder()
+            .metadata_max_age(Duration::from_secs(60))
+            .build()
+            .await
+            .unwrap();
+        let bootstrap_requests = cluster.state.lock().metadata_requests;
+
+        tokio::time::sleep(Duration::from_secs(61)).await;
+        assert_eq!(
+            cluster.state.lock().metadata_requests,
+            bootstrap_requests + 1
+        );
+        assert!(client.cluster.metadata().await.is_ok());
+        assert_eq!(
+            cluster.state.lock().metadata_requests,
+            bootstrap_requests + 1
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_topology_changes() {
+        let cluster = MockCluster::new(2);
+        cluster.add_topic("test", 1);
+        let client = cluster
+            .client_builder()
+            .metadata_max_age(Duration::from_secs(60))
+            .build()
+            .await
+            .unwrap();
+        client.metadata().await.unwrap();
+        let changes = client.topology_changes();
+        futures::pin_mut!(changes);
+
+        cluster.state.lock().topics[0].partitions[0].leader_id = 1;
+        let diff = changes.next().await.unwrap();
+        assert_eq!(
+            diff.changes,
+            vec![TopologyChange::LeaderChanged {
+                topic: "test".to_string(),
+                partition: 0,
+                previous: 0,
+                current: 1,
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bootstrap_fails_for_other_cluster() {
+        let cluster = MockCluster::new(2);
+        let seeds = vec!["broker-0:9092".to_string()];
+
+        let err = client(&cluster, seeds.clone())
+            .expected_cluster_id("production")
+            .build()
+            .await
+            .err()
+            .unwrap();
+        assert_matches!(err, Error::ClusterIdMismatch { expected, .. } if expected == "production");
+
+        client(&cluster, seeds)
+            .expected_cluster_id("mock-cluster")
+            .build()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_describe_topic_partitions_follows_cursor() {
+        let cluster = MockCluster::new(2);
+        cluster.add_topic("a", 3);
+        cluster.add_topic("b", 2);
+        let client = cluster.client().await;
+
+        let metadata = client.metadata().await.unwrap();
+        let topic_id = metadata.topics[0].topic_id.unwrap();
+        assert_eq!(topic_id, cluster.state.lock().topics[0].id);
+
+        let partitions: Vec<_> = client
+            .describe_topic_partitions(
+                vec!["a".to_string(), "b".to_string(), "missing".to_string()],
+                2,
+            )
+            .collect()
+            .await;
+        assert_eq!(partitions.len(), 6);
+        let described: Vec<_> = partitions[..5]
+            .iter()
+            .map(|p| {
+                let p = p.as_ref().unwrap();
+                (p.topic.as_str(), p.partition.partition_index)
+            })
+            .collect();
+        assert_eq!(
+            described,
+            vec![("a", 0), ("a", 1), ("a", 2), ("b", 0), ("b", 1)]
+        );
+        assert_eq!(partitions[0].as_ref().unwrap().topic_id, topic_id);
+        assert_matches!(
+            partitions[5],
+            Err(Error::Server {
+                error: ProtocolError::UnknownTopicOrPartition,
+                ..
+            })
+        );
+    }
+
+    #[derive(Default)]
+    struct FlagHook(AtomicBool);
+
+    impl CloseHook for FlagHook {
+        fn close(&self, _: Instant) -> BoxFuture<'_, ()> {
+            self.0.store(true, Ordering::SeqCst);
+            Box::pin(async {})
+        }
+    }
+
+    #[tokio::test]
+    async fn test_close_runs_hooks_and_refuses_requests() {
+        let cluster = MockCluster::new(1);
+        let client = cluster.client().await;
+        client.metadata().await.unwrap();
+        let hook = Arc::new(FlagHook::default());
+        client
+            .cluster
+            .register_close_hook(Arc::downgrade(&hook) as Weak<dyn CloseHook>);
+
+        client.close(Instant::now() + Duration::from_secs(1)).await;
+        assert!(hook.0.load(Ordering::SeqCst));
+        assert_matches!(
+            client.request_metadata(None).await,
+            Err(Error::ClientClosed)
+        );
+    }
+}
@@ -0,0 +1,829 @@
+pub use crate::protocol::record::Compression;
+
+use crate::{
+    backoff::Backoff,
+    client::{
+        cluster::Cluster, pool::RequestClass, stream::ConnectionStream, transport::BoxedTransport,
+    },
+    error::{ConnectionSnafu, Error, MissingPartitionSnafu, Result, ServerSnafu},
+    protocol::{
+        api_key::ApiKey,
+        deserializer::DeserializeVersioned,
+        error::Error as ProtocolError,
+        messages::{
+            delete_records::{
+                DeleteRecordsRequest, DeleteRecordsRequestPartition, DeleteRecordsRequestTopic,
+            },
+            fetch::{FetchRequest, FetchRequestPartition, FetchRequestTopic},
+            list_offsets::{
+                ListOffsetsRequest, ListOffsetsRequestPartition, ListOffsetsRequestTopic,
+            },
+            produce::{
+                ProduceRequest, ProduceRequestPartition, ProduceRequestTopic, ProduceResponse,
+            },
+            KafkaRequest, KafkaResponse,
+        },
+        record::RecordBatch,
+        serializer::SerializeVersioned,
+    },
+    record::{Record, RecordAndOffset},
+};
+use chrono::{DateTime, Utc};
+use futures::{
+    future::{ready, BoxFuture},
+    FutureExt,
+};
+use snafu::{OptionExt, ResultExt};
+use std::{
+    fmt, future::Future, io::Cursor, ops::ControlFlow, ops::Range, sync::Arc, time::Duration,
+};
+
+/// What a [`PartitionClient`] does when its topic or partition does not exist (yet).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownTopicHandling {
+    /// Fail with [`ProtocolError::UnknownTopicOrPartition`]
+    Error,
+    /// Retry with backoff, e.g. while the topic is still being created
+    Retry,
+}
+
+/// Which replicas have to write a produced batch before the partition leader answers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Acks {
+    /// The leader does not answer at all, so the records get no offsets and are lost unnoticed
+    /// if it fails to write them
+    None,
+    /// The leader answers once it wrote the batch, before its followers replicated it
+    Leader,
+    /// The leader answers once all in-sync replicas wrote the batch
+    #[default]
+    All,
+}
+
+/// Offsets of the records of a produced batch.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProducedBatch {
+    /// Offset of every record, or -1 if the leader did not report it
+    pub offsets: Vec<i64>,
+    /// Time the leader appended the batch, if the topic uses log append time
+    pub log_append_time: Option<DateTime<Utc>>,
+}
+
+impl From<Acks> for i16 {
+    fn from(acks: Acks) -> Self {
+        match acks {
+            Acks::None => 0,
+            Acks::Leader => 1,
+            Acks::All => -1,
+        }
+    }
+}
+
+/// The offset [`PartitionClient::get_offset`] looks up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffsetAt {
+    /// The first offset that was not deleted yet
+    Earliest,
+    /// The offset the next produced record will get
+    Latest,
+    /// The first offset whose record is not older than the timestamp
+    Timestamp(DateTime<Utc>),
+}
+
+/// Produces to and fetches from a single partition, always talking to its current leader.
+/// Requests are retried with backoff when the leader moved or is temporarily unavailable.
+pub struct PartitionClient {
+    topic: String,
+    partition: i32,
+    cluster: Arc<Cluster>,
+    unknown_topic_handling: UnknownTopicHandling,
+    acks: Acks,
+    /// Overrides the request timeout of the connections
+    request_timeout: Option<Duration>,
+}
+
+impl fmt::Debug for PartitionClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PartitionClient")
+            .field("topic", &self.topic)
+            .field("partition", &self.partition)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartitionClient {
+    /// Creates the client once the partition leader is known.
+    pub(crate) async fn new(
+        cluster: Arc<Cluster>,
+        topic: String,
+        partition: i32,
+        unknown_topic_handling: UnknownTopicHandling,
+    ) -> Result<Self> {
+        let client = Self {
+            topic,
+            partition,
+            cluster,
+            unknown_topic_handling,
+            acks: Acks::default(),
+            request_timeout: None,
+        };
+        client
+            .retry("find partition leader", || async {
+                client
+                    .cluster
+                    .leader_broker(&client.topic, client.partition)
+                    .await
+                    .map(|_| ())
+            })
+            .await?;
+        Ok(client)
+    }
+
+    /// The replicas produced batches wait for, [`Acks::All`] by default.
+    pub fn with_acks(mut self, acks: Acks) -> Self {
+        self.acks = acks;
+        self
+    }
+
+    /// Time to wait for the response to a request instead of the request timeout of the
+    /// connection. The partition leader waits as long for the replicas to acknowledge a
+    /// produced batch.
+    pub fn with_request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = Some(request_timeout);
+        self
+    }
+
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+
+    pub fn partition(&self) -> i32 {
+        self.partition
+    }
+
+    pub fn acks(&self) -> Acks {
+        self.acks
+    }
+
+    pub(crate) fn cluster(&self) -> &Arc<Cluster> {
+        &self.cluster
+    }
+
+    /// Produces `records` as a single batch and returns their offsets.
+    pub async fn produce(
+        &self,
+        records: Vec<Record>,
+        compression: Compression,
+    ) -> Result<Vec<i64>> {
+        let produced = self.produce_records(records, compression).await?;
+        Ok(produced.offsets)
+    }
+
+    /// Produces `records` as a single batch like [`Self::produce`], also returning the time the
+    /// leader appended it.
+    pub(crate) async fn produce_records(
+        &self,
+        records: Vec<Record>,
+        compression: Compression,
+    ) -> Result<ProducedBatch> {
+        if records.is_empty() {
+            return Ok(ProducedBatch::default());
+        }
+        self.intercept(records, |records| {
+            self.produce_batch(RecordBatch::new(records, compression), None)
+        })
+        .await
+    }
+
+    /// Passes `records` through the interceptors of the client, produces them with `produce`
+    /// and acknowledges the outcome to the interceptors.
+    pub(crate) async fn intercept<F, Fut>(
+        &self,
+        mut records: Vec<Record>,
+        produce: F,
+    ) -> Result<ProducedBatch>
+    where
+        F: FnOnce(Vec<Record>) -> Fut,
+        Fut: Future<Output = Result<ProducedBatch>>,
+    {
+        let interceptors = self.cluster.interceptors();
+        interceptors.on_send(&mut records);
+        let timestamps: Vec<_> = records.iter().map(|record| record.timestamp).collect();
+        let result = produce(records).await;
+        interceptors.on_acknowledgement(&self.topic, self.partition, &timestamps, result.as_ref());
+        result
+    }
+
+    /// Produces a prepared batch, e.g. one carrying a producer id and sequence, and returns the
+    /// offsets of its records, or -1 for each with [`Acks::None`]. All retries send the very
+    /// same batch.
+    pub(crate) async fn produce_batch(
+        &self,
+        batch: RecordBatch,
+        transactional_id: Option<&str>,
+    ) -> Result<ProducedBatch> {
+        let count = batch.records.len();
+        let mut encoded = vec![];
+        batch.encode(&mut encoded)?;
+        let request = |timeout| self.produce_request(&encoded, transactional_id, timeout);
+        if self.acks == Acks::None {
+            self.send_without_response(RequestClass::Produce, request)
+                .await?;
+            return Ok(ProducedBatch {
+                offsets: vec![-1; count],
+                log_append_time: None,
+            });
+        }
+
+        let response = self.send(RequestClass::Produce, request).await?;
+        produced_batch(&self.topic, self.partition, count, response)
+    }
+
+    /// The connection to the partition leader that Produce requests are written to, so that
+    /// batches written one after another are appended in that order.
+    pub(crate) async fn produce_connection(&self) -> Result<Arc<ConnectionStream<BoxedTransport>>> {
+        let broker = self
+            .cluster
+            .leader_broker(&self.topic, self.partition)
+            .await?;
+        broker.get(RequestClass::Produce).await
+    }
+
+    /// Writes a prepared batch to `connection` without retrying and returns the future of its
+    /// offsets, so further batches can be written before the leader answered.
+    pub(crate) async fn start_produce_batch(
+        &self,
+        connection: &Arc<ConnectionStream<BoxedTransport>>,
+        batch: &RecordBatch,
+        transactional_id: Option<&str>,
+    ) -> Result<BoxFuture<'static, Result<ProducedBatch>>> {
+        let count = batch.records.len();
+        let mut encoded = vec![];
+        batch.encode(&mut encoded)?;
+        let timeout = self
+            .request_timeout
+            .unwrap_or(connection.config().request_timeout);
+        let request = self.produce_request(&encoded, transactional_id, timeout);
+        if self.acks == Acks::None {
+            connection.send_request_without_response(request).await?;
+            return Ok(ready(Ok(ProducedBatch {
+                offsets: vec![-1; count],
+                log_append_time: None,
+            }))
+            .boxed());
+        }
+
+        let response = connection.send_request_pipelined(request, timeout).await?;
+        let (topic, partition) = (self.topic.clone(), self.partition);
+        Ok(async move {
+            let response = response.await?;
+            if let Some(error) = response.partition_error(&topic, partition) {
+                return ServerSnafu {
+                    api_key: ApiKey::Produce,
+                    error,
+                }
+                .fail();
+            }
+            produced_batch(&topic, partition, count, response)
+        }
+        .boxed())
+    }
+
+    fn produce_request(
+        &self,
+        batch: &[u8],
+        transactional_id: Option<&str>,
+        timeout: Duration,
+    ) -> ProduceRequest {
+        ProduceRequest {
+            transactional_id: transactional_id.map(str::to_string),
+            acks: self.acks.into(),
+            timeout_ms: i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX),
+            topic_data: vec![ProduceRequestTopic {
+                name: self.topic.clone(),
+                partition_data: vec![ProduceRequestPartition {
+                    index: self.partition,
+                    records: Some(batch.to_vec()),
+                }],
+            }],
+        }
+    }
+
+    /// Fetches the records starting at `offset`, returning them with the high watermark of the
+    /// partition. The broker answers once `bytes.start` bytes are available or `max_wait_ms`
+    /// passed, with up to `bytes.end` bytes but at least one batch.
+    pub async fn fetch_records(
+        &self,
+        offset: i64,
+        bytes: Range<i32>,
+        max_wait_ms: i32,
+    ) -> Result<(Vec<RecordAndOffset>, i64)> {
+        let response = self
+            .send(RequestClass::Fetch, |_| FetchRequest {
+                replica_id: -1,
+                max_wait_ms,
+                min_bytes: bytes.start,
+                max_bytes: bytes.end.saturating_sub(1),
+                isolation_level: 0,
+                session_id: 0,
+                session_epoch: -1,
+                topics: vec![FetchRequestTopic {
+                    topic: self.topic.clone(),
+                    partitions: vec![FetchRequestPartition {
+                        partition: self.partition,
+                        current_leader_epoch: -1,
+                        fetch_offset: offset,
+                        log_start_offset: -1,
+                        partition_max_bytes: bytes.end.saturating_sub(1),
+                    }],
+                }],
+                forgotten_topics_data: vec![],
+                rack_id: String::new(),
+            })
+            .await?;
+
+        let partition = response
+            .responses
+            .into_iter()
+            .filter(|topic| topic.topic == self.topic)
+            .flat_map(|topic| topic.partitions)
+            .find(|partition| partition.partition_index == self.partition)
+            .context(self.missing_partition(ApiKey::Fetch))?;
+
+        let batches = RecordBatch::decode_all(&partition.records.unwrap_or_default())?;
+        let mut records = batches
+            .into_iter()
+            .filter(|batch| !batch.is_control)
+            .flat_map(|batch| {
+                let base_offset = batch.base_offset;
+                batch.records.into_iter().map(move |record| RecordAndOffset {
+                    record: record.record,
+                    offset: base_offset + i64::from(record.offset_delta),
+                })
+            })
+            // batches are returned as a whole, even if the offset is in their middle
+            .filter(|record| record.offset >= offset)
+            .collect::<Vec<_>>();
+        self.cluster.interceptors().on_consume(&mut records);
+        Ok((records, partition.high_watermark))
+    }
+
+    /// Looks up an offset of the partition.
+    pub async fn get_offset(&self, at: OffsetAt) -> Result<i64> {
+        let timestamp = match at {
+            OffsetAt::Earliest => -2,
+            OffsetAt::Latest => -1,
+            OffsetAt::Timestamp(timestamp) => timestamp.timestamp_millis(),
+        };
+        let response = self
+            .send(RequestClass::Admin, |_| ListOffsetsRequest {
+                replica_id: -1,
+                isolation_level: 0,
+                topics: vec![ListOffsetsRequestTopic {
+                    name: self.topic.clone(),
+                    partitions: vec![ListOffsetsRequestPartition {
+                        partition_index: self.partition,
+                        current_leader_epoch: -1,
+                        timestamp,
+                    }],
+                }],
+            })
+            .await?;
+
+        let partition = response
+            .topics
+            .into_iter()
+            .filter(|topic| topic.name == self.topic)
+            .flat_map(|topic| topic.partitions)
+            .find(|partition| partition.partition_index == self.partition)
+            .context(self.missing_partition(ApiKey::ListOffsets))?;
+        Ok(partition.offset)
+    }
+
+    /// Deletes all records before `offset`, waiting up to `timeout_ms` for the replicas.
+    pub async fn delete_records(&self, offset: i64, timeout_ms: i32) -> Result<()> {
+        let response = self
+            .send(RequestClass::Admin, |_| DeleteRecordsRequest {
+                topics: vec![DeleteRecordsRequestTopic {
+                    name: self.topic.clone(),
+                    partitions: vec![DeleteRecordsRequestPartition {
+                        partition_index: self.partition,
+                        offset,
+                    }],
+                }],
+                timeout_ms,
+            })
+            .await?;
+
+        response
+            .topics
+            .into_iter()
+            .filter(|topic| topic.name == self.topic)
+            .flat_map(|topic| topic.partitions)
+            .find(|partition| partition.partition_index == self.partition)
+            .context(self.missing_partition(ApiKey::DeleteRecords))?;
+        Ok(())
+    }
+
+    /// Sends the request `request` builds for the request timeout to the partition leader,
+    /// retrying until the partition in the response has no retriable error.
+    async fn send<R, F>(&self, class: RequestClass, request: F) -> Result<R::KafkaResponse>
+    where
+        F: Fn(Duration) -> R,
+        R: KafkaRequest + SerializeVersioned<Vec<u8>>,
+        R::KafkaResponse: KafkaResponse + PartitionErrors + DeserializeVersioned<Cursor<Vec<u8>>>,
+    {
+        self.retry(
+            &format!("{} {}-{}", R::API_KEY, self.topic, self.partition),
+            || async {
+                let broker = self
+                    .cluster
+                    .leader_broker(&self.topic, self.partition)
+                    .await?;
+                let stream = broker.get(class).await?;
+                let timeout = self
+                    .request_timeout
+                    .unwrap_or(stream.config().request_timeout);
+                let response = stream
+                    .send_request_with_timeout(request(timeout), timeout)
+                    .await?;
+                if let Some(error) = response.partition_error(&self.topic, self.partition) {
+                    return ServerSnafu {
+                        api_key: R::API_KEY,
+                        error,
+                    }
+                    .fail();
+                }
+                Ok(response)
+            },
+        )
+        .await
+    }
+
+    /// Like [`send`](Self::send), for requests the partition leader does not answer. Only
+    /// failures to reach the leader are retried.
+    async fn send_without_response<R, F>(&self, class: RequestClass, request: F) -> Result<()>
+    where
+        F: Fn(Duration) -> R,
+        R: KafkaRequest + SerializeVersioned<Vec<u8>>,
+        R::KafkaResponse: KafkaResponse,
+    {
+        self.retry(
+            &format!("{} {}-{}", R::API_KEY, self.topic, self.partition),
+            || async {
+                let broker = self
+                    .cluster
+                    .leader_broker(&self.topic, self.partition)
+                    .await?;
+                let stream = broker.get(class).await?;
+                let timeout = self
+                    .request_timeout
+                    .unwrap_or(stream.config().request_timeout);
+                stream
+                    .send_request_without_response(request(timeout))
+                    .await?;
+                Ok(())
+            },
+        )
+        .await
+    }
+
+    /// Runs `f` until it succeeds or fails with an error that retrying does not fix.
+    async fn retry<T, F, Fut>(&self, name: &str, f: F) -> Result<T>
+    where
+        F: Fn() -> Fut,
+        Fut: std::future::Future<Output = Result<T>>,
+    {
+        let mut backoff = Backoff::new(self.cluster.backoff_config());
+        backoff
+            .retry_with_backoff(name, || async {
+                match f().await {
+                    Ok(result) => ControlFlow::Break(Ok(result)),
+                    Err(e) => {
+                        self.invalidate_metadata_on(&e);
+                        if self.is_retriable(&e) {
+                            ControlFlow::Continue(e)
+                        } else {
+                            ControlFlow::Break(Err(e))
+                        }
+                    }
+                }
+            })
+            .await
+            .context(ConnectionSnafu)?
+    }
+
+    pub(crate) fn is_retriable(&self, error: &Error) -> bool {
+        match error {
+            Error::Server {
+                error: ProtocolError::UnknownTopicOrPartition,
+                ..
+            } => self.unknown_topic_handling == UnknownTopicHandling::Retry,
+            Error::Server { error, .. } => error.is_retriable(),
+            // the connection broke or is busy, the next attempt may use another one
+            Error::Request { source } => source.is_transient(),
+            _ => false,
+        }
+    }
+
+    /// Invalidates the cached metadata if `error` shows that it is outdated.
+    pub(crate) fn invalidate_metadata_on(&self, error: &Error) {
+        if let Error::Server { error, .. } = error {
+            self.cluster.invalidate_metadata_on(*error);
+        }
+    }
+
+    fn missing_partition(&self, api_key: ApiKey) -> MissingPartitionSnafu<ApiKey, &str, i32> {
+        MissingPartitionSnafu {
+            api_key,
+            topic: self.topic.as_str(),
+            partition: self.partition,
+        }
+    }
+}
+
+/// The offsets of the `count` records of a batch produced to `topic` and `partition`.
+fn produced_batch(
+    topic: &str,
+    partition: i32,
+    count: usize,
+    response: ProduceResponse,
+) -> Result<ProducedBatch> {
+    let response = response
+        .responses
+        .into_iter()
+        .filter(|response| response.name == topic)
+        .flat_map(|response| response.partition_responses)
+        .find(|response| response.index == partition)
+        .context(MissingPartitionSnafu {
+            api_key: ApiKey::Produce,
+            topic,
+            partition,
+        })?;
+    let offsets = if response.base_offset < 0 {
+        // a duplicate of a batch written before, whose offsets the broker did not keep
+        vec![-1; count]
+    } else {
+        (0..count as i64)
+            .map(|i| response.base_offset + i)
+            .collect()
+    };
+    Ok(ProducedBatch {
+        offsets,
+        // -1 if the topic uses the create time of the records
+        log_append_time: Some(response.log_append_time_ms)
+            .filter(|time| *time >= 0)
+            .and_then(DateTime::from_timestamp_millis),
+    })
+}
+
+/// Responses carrying an error per partition.
+pub(crate) trait PartitionErrors {
+    fn partition_error(&self, topic: &str, partition: i32) -> Option<ProtocolError>;
+}
+
+impl PartitionErrors for ProduceResponse {
+    fn partition_error(&self, topic: &str, partition: i32) -> Option<ProtocolError> {
+        self.responses
+            .iter()
+            .filter(|t| t.name == topic)
+            .flat_map(|t| &t.partition_responses)
+            .find(|p| p.index == partition)
+            .and_then(|p| p.error)
+            // the batch of an idempotent producer was already written by an earlier attempt
+            .filter(|error| *error != ProtocolError::DuplicateSequenceNumber)
+    }
+}
+
+impl PartitionErrors for crate::protocol::messages::fetch::FetchResponse {
+    fn partition_error(&self, topic: &str, partition: i32) -> Option<ProtocolError> {
+        self.error.or_else(|| {
+            self.responses
+                .iter()
+                .filter(|t| t.topic == topic)
+                .flat_map(|t| &t.partitions)
+                .find(|p| p.partition_index == partition)
+                .and_then(|p| p.error)
+        })
+    }
+}
+
+impl PartitionErrors for crate::protocol::messages::list_offsets::ListOffsetsResponse {
+    fn partition_error(&self, topic: &str, partition: i32) -> Option<ProtocolError> {
+        self.topics
+            .iter()
+            .filter(|t| t.name == topic)
+            .flat_map(|t| &t.partitions)
+            .find(|p| p.partition_index == partition)
+            .and_then(|p| p.error)
+    }
+}
+
+impl PartitionErrors for crate::protocol::messages::delete_records::DeleteRecordsResponse {
+    fn partition_error(&self, topic: &str, partition: i32) -> Option<ProtocolError> {
+        self.topics
+            .iter()
+            .filter(|t| t.name == topic)
+            .flat_map(|t| &t.partitions)
+            .find(|p| p.partition_index == partition)
+            .and_then(|p| p.error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{
+        mock::{record, MockCluster},
+        stream::RequestError,
+    };
+    use assert_matches::assert_matches;
+    use chrono::TimeZone;
+    use std::collections::BTreeMap;
+
+    #[tokio::test]
+    async fn test_produce_fetch_and_list_offsets() {
+        let mock = MockCluster::new(2);
+        mock.add_topic("test", 2);
+        let client = mock.client().await;
+        let partition = client
+            .partition_client("test", 1, UnknownTopicHandling::Error)
+            .await
+            .unwrap();
+
+        let record_at = |value, millis| Record {
+            headers: BTreeMap::from([("header".to_string(), b"value".to_vec())]),
+            timestamp: Utc.timestamp_millis_opt(millis).unwrap(),
+            ..record(value)
+        };
+        let records = vec![record_at("a", 1000), record_at("b", 2000)];
+        let offsets = partition
+            .produce(records.clone(), Compression::NoCompression)
+            .await
+            .unwrap();
+        assert_eq!(offsets, vec![0, 1]);
+        let offsets = partition
+            .produce(vec![record_at("c", 3000)], Compression::NoCompression)
+            .await
+            .unwrap();
+        assert_eq!(offsets, vec![2]);
+
+        let (fetched, high_watermark) =
+            partition.fetch_records(1, 1..1_000_000, 100).await.unwrap();
+        assert_eq!(high_watermark, 3);
+        assert_eq!(
+            fetched,
+            vec![
+                RecordAndOffset {
+                    record: records[1].clone(),
+                    offset: 1,
+                },
+                RecordAndOffset {
+                    record: record_at("c", 3000),
+                    offset: 2,
+                },
+            ]
+        );
+
+        assert_eq!(partition.get_offset(OffsetAt::Earliest).await.unwrap(), 0);
+        assert_eq!(partition.get_offset(OffsetAt::Latest).await.unwrap(), 3);
+        let at = Utc.timestamp_millis_opt(2500).unwrap();
+        assert_eq!(
+            partition.get_offset(OffsetAt::Timestamp(at)).await.unwrap(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_produce_without_acks() {
+        let mock = MockCluster::new(1);
+        mock.add_topic("test", 1);
+        let client = mock.client().await;
+        let partition = client
+            .partition_client("test", 0, UnknownTopicHandling::Error)
+            .await
+            .unwrap()
+            .with_acks(Acks::None);
+
+        let records = vec![record("a"), record("b")];
+        let offsets = partition
+            .produce(records, Compression::NoCompression)
+            .await
+            .unwrap();
+        assert_eq!(offsets, vec![-1, -1]);
+
+        // the leader appends the batch without answering
+        let (_, high_watermark) = partition.fetch_records(0, 1..1_000_000, 100).await.unwrap();
+        assert_eq!(high_watermark, 2);
+        assert_eq!(client.in_flight_requests().values().sum::<usize>(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_delete_records() {
+        let mock = MockCluster::new(1);
+        mock.add_topic("test", 1);
+        let client = mock.client().await;
+        let partition = client
+            .partition_client("test", 0, UnknownTopicHandling::Error)
+            .await
+            .unwrap();
+        for value in ["a", "b", "c"] {
+            partition
+                .produce(vec![record(value)], Compression::NoCompression)
+                .await
+                .unwrap();
+        }
+
+        partition.delete_records(2, 1000).await.unwrap();
+        assert_eq!(partition.get_offset(OffsetAt::Earliest).await.unwrap(), 2);
+        assert_matches!(
+            partition.fetch_records(0, 1..1_000_000, 100).await,
+            Err(Error::Server {
+                api_key: ApiKey::Fetch,
+                error: ProtocolError::OffsetOutOfRange,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_follows_leader_change() {
+        let mock = MockCluster::new(2);
+        mock.add_topic("test", 1);
+        let client = mock.client().await;
+        let partition = client
+            .partition_client("test", 0, UnknownTopicHandling::Error)
+            .await
+            .unwrap();
+        partition
+            .produce(vec![record("a")], Compression::NoCompression)
+            .await
+            .unwrap();
+
+        mock.state.lock().topics[0].partitions[0].leader_id = 1;
+        let offsets = partition
+            .produce(vec![record("b")], Compression::NoCompression)
+            .await
+            .unwrap();
+        // the mock keeps one log per partition, which the new leader continues
+        assert_eq!(offsets, vec![1]);
+        assert!(mock
+            .state
+            .lock()
+            .connections
+            .contains(&"broker-1:9092".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_only_transient_request_errors_are_retried() {
+        let mock = MockCluster::new(1);
+        mock.add_topic("test", 1);
+        let client = mock.client().await;
+        let partition = client
+            .partition_client("test", 0, UnknownTopicHandling::Error)
+            .await
+            .unwrap();
+
+        let retriable = |source| partition.is_retriable(&Error::Request { source });
+        assert!(retriable(RequestError::ConnectionClosed));
+        assert!(retriable(RequestError::Timeout {
+            correlation_id: 1,
+            timeout: Duration::from_secs(1),
+        }));
+        assert!(!retriable(RequestError::NoVersionMatch {
+            api_key: ApiKey::Produce,
+        }));
+        assert!(!retriable(RequestError::ShuttingDown));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_topic_handling() {
+        let mock = MockCluster::new(1);
+        let client = mock.client().await;
+        assert_matches!(
+            client
+                .partition_client("test", 0, UnknownTopicHandling::Error)
+                .await
+                .err()
+                .unwrap(),
+            Error::Server {
+                error: ProtocolError::UnknownTopicOrPartition,
+                ..
+            }
+        );
+
+        let created = {
+            let mock = mock.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                mock.add_topic("test", 1);
+            })
+        };
+        let partition = client
+            .partition_client("test", 0, UnknownTopicHandling::Retry)
+            .await
+            .unwrap();
+        created.await.unwrap();
+        assert_eq!(partition.topic(), "test");
+        assert_eq!(partition.partition(), 0);
+    }
+}
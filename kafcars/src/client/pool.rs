@@ -0,0 +1,190 @@
+use crate::{
+    backoff::BackoffConfig,
+    client::{
+        broker::{BrokerConnection, ConnectionState},
+        stream::{ConnectionConfig, ConnectionStream},
+        transport::Connector,
+    },
+    error::Result,
+};
+use futures::future::join_all;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tokio::time::Instant;
+
+/// Kind of traffic a request belongs to. Every class uses its own connections to a broker, so
+/// e.g. a long polling fetch does not delay metadata requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequestClass {
+    Fetch,
+    Produce,
+    /// Metadata, coordinator and all other control requests
+    Admin,
+}
+
+impl RequestClass {
+    const ALL: [RequestClass; 3] = [
+        RequestClass::Fetch,
+        RequestClass::Produce,
+        RequestClass::Admin,
+    ];
+}
+
+/// Connections a client keeps to every broker.
+#[derive(Debug, Clone)]
+pub struct ConnectionPoolConfig {
+    /// Connections per broker and request class
+    pub connections_per_class: usize,
+    /// In-flight limits of the connections of a class, overriding the client wide
+    /// `max_in_flight_requests_per_connection`. `None` means unlimited.
+    pub max_in_flight: HashMap<RequestClass, Option<usize>>,
+}
+
+impl Default for ConnectionPoolConfig {
+    fn default() -> Self {
+        Self {
+            connections_per_class: 1,
+            max_in_flight: HashMap::new(),
+        }
+    }
+}
+
+/// The connections to a single broker, grouped by request class and used in turns.
+pub(crate) struct BrokerPool<C: Connector> {
+    broker: String,
+    /// The connections of every class and which of them to use next
+    connections: HashMap<RequestClass, (Vec<BrokerConnection<C>>, AtomicUsize)>,
+}
+
+impl<C: Connector + Clone> BrokerPool<C> {
+    pub fn new(
+        broker: String,
+        connector: C,
+        config: ConnectionConfig,
+        pool_config: &ConnectionPoolConfig,
+        backoff_config: BackoffConfig,
+    ) -> Self {
+        let connections = RequestClass::ALL
+            .into_iter()
+            .map(|class| {
+                let mut config = config.clone();
+                if let Some(max_in_flight) = pool_config.max_in_flight.get(&class) {
+                    config.max_in_flight_requests_per_connection = *max_in_flight;
+                }
+                let connections = (0..pool_config.connections_per_class.max(1))
+                    .map(|_| {
+                        BrokerConnection::new(
+                            broker.clone(),
+                            connector.clone(),
+                            config.clone(),
+                            backoff_config.clone(),
+                        )
+                    })
+                    .collect();
+                (class, (connections, AtomicUsize::new(0)))
+            })
+            .collect();
+
+        Self {
+            broker,
+            connections,
+        }
+    }
+}
+
+impl<C: Connector> BrokerPool<C> {
+    pub fn broker(&self) -> &str {
+        &self.broker
+    }
+
+    fn connection(&self, class: RequestClass) -> &BrokerConnection<C> {
+        let (connections, next) = &self.connections[&class];
+        &connections[next.fetch_add(1, Ordering::Relaxed) % connections.len()]
+    }
+
+    /// A connection for `class`, connecting with backoff if needed.
+    pub async fn get(&self, class: RequestClass) -> Result<Arc<ConnectionStream<C::Transport>>> {
+        self.connection(class).get().await
+    }
+
+    /// A connection for `class`, connecting only once if needed.
+    pub async fn try_get(
+        &self,
+        class: RequestClass,
+    ) -> Result<Arc<ConnectionStream<C::Transport>>> {
+        self.connection(class).try_get().await
+    }
+
+    /// Requests waiting for a response on the open connections.
+    pub fn in_flight_requests(&self) -> usize {
+        self.connections()
+            .map(|connection| match connection.state() {
+                ConnectionState::Connected { in_flight_requests } => in_flight_requests,
+                ConnectionState::Disconnected | ConnectionState::Connecting => 0,
+            })
+            .sum()
+    }
+
+    /// Whether any connection is being (re-)established.
+    pub fn is_connecting(&self) -> bool {
+        self.connections()
+            .any(|connection| connection.state() == ConnectionState::Connecting)
+    }
+
+    pub async fn close(&self, deadline: Instant) {
+        join_all(
+            self.connections()
+                .map(|connection| connection.close(deadline)),
+        )
+        .await;
+    }
+
+    fn connections(&self) -> impl Iterator<Item = &BrokerConnection<C>> {
+        self.connections
+            .values()
+            .flat_map(|(connections, _)| connections)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::mock::MockCluster;
+
+    #[tokio::test]
+    async fn test_classes_use_separate_connections() {
+        let mock = MockCluster::new(1);
+        let pool = BrokerPool::new(
+            "broker-0:9092".to_string(),
+            mock.clone(),
+            ConnectionConfig::default(),
+            &ConnectionPoolConfig {
+                connections_per_class: 2,
+                max_in_flight: HashMap::from([(RequestClass::Fetch, Some(1))]),
+            },
+            BackoffConfig::default(),
+        );
+
+        let fetch = [
+            pool.get(RequestClass::Fetch).await.unwrap(),
+            pool.get(RequestClass::Fetch).await.unwrap(),
+        ];
+        let admin = pool.get(RequestClass::Admin).await.unwrap();
+        assert!(!Arc::ptr_eq(&fetch[0], &fetch[1]));
+        assert_eq!(mock.state.lock().connections.len(), 3);
+
+        assert_eq!(
+            fetch[0].config().max_in_flight_requests_per_connection,
+            Some(1)
+        );
+        assert_eq!(
+            admin.config().max_in_flight_requests_per_connection,
+            ConnectionConfig::default().max_in_flight_requests_per_connection
+        );
+    }
+}
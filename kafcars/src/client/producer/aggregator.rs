@@ -0,0 +1,105 @@
+//! Batching of the inputs of a [`BatchProducer`](super::BatchProducer).
+
+use crate::{
+    client::partition::ProducedBatch,
+    error::{Error, Result},
+    record::Record,
+};
+use std::sync::Arc;
+
+/// Outcome of [`Aggregator::try_push`].
+#[derive(Debug)]
+pub enum TryPush<I, T> {
+    /// The input was added to the batch and is identified by the tag
+    Aggregated(T),
+    /// The batch is full, the input is handed back to be pushed into the next batch
+    NoCapacity(I),
+}
+
+/// Collects inputs into a batch of records.
+pub trait Aggregator: Send + 'static {
+    type Input: Send;
+    /// Identifies an input in its batch, to look up its status once the batch was produced
+    type Tag: Send;
+    type StatusDeaggregator: StatusDeaggregator<Tag = Self::Tag>;
+
+    /// Bytes `input` takes up in a batch, reserved from the buffer memory of the client
+    /// until the batch was produced.
+    fn approximate_size(&self, input: &Self::Input) -> usize;
+
+    fn try_push(&mut self, input: Self::Input) -> Result<TryPush<Self::Input, Self::Tag>>;
+
+    /// Takes the records of the batch, leaving the aggregator empty.
+    fn flush(&mut self) -> Result<(Vec<Record>, Self::StatusDeaggregator)>;
+}
+
+/// Derives the status of every input of a produced batch from the offsets of its records.
+pub trait StatusDeaggregator: Send + Sync + 'static {
+    type Status;
+    type Tag;
+
+    fn deaggregate(&self, offsets: &[i64], tag: Self::Tag) -> Result<Self::Status>;
+
+    /// Called once the batch was produced or failed, whether or not any status is awaited.
+    fn produced(&self, _result: std::result::Result<&ProducedBatch, &Arc<Error>>) {}
+}
+
+/// Batches records up to a total [`Record::approximate_size`]. A larger record is produced in a
+/// batch of its own.
+#[derive(Debug)]
+pub struct RecordAggregator {
+    max_batch_size: usize,
+    batch_size: usize,
+    records: Vec<Record>,
+}
+
+impl RecordAggregator {
+    pub fn new(max_batch_size: usize) -> Self {
+        Self {
+            max_batch_size,
+            batch_size: 0,
+            records: vec![],
+        }
+    }
+}
+
+impl Aggregator for RecordAggregator {
+    type Input = Record;
+    type Tag = usize;
+    type StatusDeaggregator = RecordAggregatorStatusDeaggregator;
+
+    fn approximate_size(&self, record: &Record) -> usize {
+        record.approximate_size()
+    }
+
+    fn try_push(&mut self, record: Record) -> Result<TryPush<Record, usize>> {
+        let size = record.approximate_size();
+        if !self.records.is_empty() && self.batch_size + size > self.max_batch_size {
+            return Ok(TryPush::NoCapacity(record));
+        }
+        self.batch_size += size;
+        self.records.push(record);
+        Ok(TryPush::Aggregated(self.records.len() - 1))
+    }
+
+    fn flush(&mut self) -> Result<(Vec<Record>, RecordAggregatorStatusDeaggregator)> {
+        self.batch_size = 0;
+        Ok((
+            std::mem::take(&mut self.records),
+            RecordAggregatorStatusDeaggregator,
+        ))
+    }
+}
+
+/// Resolves every record of a [`RecordAggregator`] batch to its offset.
+#[derive(Debug, Default)]
+pub struct RecordAggregatorStatusDeaggregator;
+
+impl StatusDeaggregator for RecordAggregatorStatusDeaggregator {
+    type Status = i64;
+    type Tag = usize;
+
+    fn deaggregate(&self, offsets: &[i64], tag: usize) -> Result<i64> {
+        Ok(offsets[tag])
+    }
+}
@@ -0,0 +1,241 @@
+//! Delivery reports of the records of a [`BatchProducer`], so records can be handed over
+//! without holding a future per record, like the delivery reports of librdkafka.
+
+use crate::{
+    client::{
+        partition::ProducedBatch,
+        producer::{
+            aggregator::{Aggregator, RecordAggregator, StatusDeaggregator, TryPush},
+            BatchProducer,
+        },
+    },
+    error::{Error, Result},
+    record::{Record, RecordMetadata},
+};
+use chrono::{DateTime, Utc};
+use futures::Stream;
+use parking_lot::Mutex;
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::sync::mpsc;
+
+/// Outcome of a record, with the token it was sent with.
+pub type DeliveryReport<T> = (T, Result<RecordMetadata>);
+
+/// Produces records to a single partition like a [`BatchProducer`], reporting the outcome of
+/// every record on its [`DeliveryReports`] instead of a future per record.
+pub struct DeliveryProducer<T: Send + 'static> {
+    producer: BatchProducer<DeliveryAggregator<T>>,
+}
+
+impl<T: Send + 'static> DeliveryProducer<T> {
+    pub(crate) fn new(producer: BatchProducer<DeliveryAggregator<T>>) -> Self {
+        Self { producer }
+    }
+
+    /// Adds `record` to the current batch, its report carries `token`. Returns once the record
+    /// was added, after waiting for buffer memory like [`BatchProducer::produce`].
+    pub async fn send(&self, record: Record, token: T) -> Result<()> {
+        self.producer.push((record, token)).await?;
+        Ok(())
+    }
+
+    /// Produces the current batch without waiting for the linger to expire.
+    pub async fn flush(&self) -> Result<()> {
+        self.producer.flush().await
+    }
+}
+
+impl<T: Send + 'static> Drop for DeliveryProducer<T> {
+    fn drop(&mut self) {
+        // its last batch is still produced and reported
+        self.producer.start_flush();
+    }
+}
+
+/// Reports of the records sent to a [`DeliveryProducer`], in the order they were sent. Ends
+/// once the producer was dropped and all of its records are reported.
+pub struct DeliveryReports<T> {
+    reports: mpsc::UnboundedReceiver<DeliveryReport<T>>,
+}
+
+impl<T> Stream for DeliveryReports<T> {
+    type Item = DeliveryReport<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.reports.poll_recv(cx)
+    }
+}
+
+/// Batches records like a [`RecordAggregator`], keeping the token of every record.
+pub(crate) struct DeliveryAggregator<T> {
+    records: RecordAggregator,
+    topic: String,
+    partition: i32,
+    /// Token and timestamp of every record in the batch
+    tokens: Vec<(T, DateTime<Utc>)>,
+    reports: mpsc::UnboundedSender<DeliveryReport<T>>,
+}
+
+impl<T: Send + 'static> DeliveryAggregator<T> {
+    pub(crate) fn new(
+        topic: String,
+        partition: i32,
+        max_batch_size: usize,
+    ) -> (Self, DeliveryReports<T>) {
+        let (reports, receiver) = mpsc::unbounded_channel();
+        let aggregator = Self {
+            records: RecordAggregator::new(max_batch_size),
+            topic,
+            partition,
+            tokens: vec![],
+            reports,
+        };
+        (aggregator, DeliveryReports { reports: receiver })
+    }
+}
+
+impl<T: Send + 'static> Aggregator for DeliveryAggregator<T> {
+    type Input = (Record, T);
+    type Tag = usize;
+    type StatusDeaggregator = DeliveryDeaggregator<T>;
+
+    fn approximate_size(&self, (record, _): &(Record, T)) -> usize {
+        self.records.approximate_size(record)
+    }
+
+    fn try_push(&mut self, (record, token): (Record, T)) -> Result<TryPush<(Record, T), usize>> {
+        let timestamp = record.timestamp;
+        Ok(match self.records.try_push(record)? {
+            TryPush::Aggregated(tag) => {
+                self.tokens.push((token, timestamp));
+                TryPush::Aggregated(tag)
+            }
+            TryPush::NoCapacity(record) => TryPush::NoCapacity((record, token)),
+        })
+    }
+
+    fn flush(&mut self) -> Result<(Vec<Record>, DeliveryDeaggregator<T>)> {
+        let (records, _) = self.records.flush()?;
+        let deaggregator = DeliveryDeaggregator {
+            topic: self.topic.clone(),
+            partition: self.partition,
+            tokens: Mutex::new(std::mem::take(&mut self.tokens)),
+            reports: self.reports.clone(),
+        };
+        Ok((records, deaggregator))
+    }
+}
+
+/// Reports every record of a [`DeliveryAggregator`] batch once it was produced.
+pub(crate) struct DeliveryDeaggregator<T> {
+    topic: String,
+    partition: i32,
+    tokens: Mutex<Vec<(T, DateTime<Utc>)>>,
+    reports: mpsc::UnboundedSender<DeliveryReport<T>>,
+}
+
+impl<T: Send + 'static> StatusDeaggregator for DeliveryDeaggregator<T> {
+    type Status = ();
+    type Tag = usize;
+
+    fn deaggregate(&self, _offsets: &[i64], _tag: usize) -> Result<()> {
+        Ok(())
+    }
+
+    fn produced(&self, result: std::result::Result<&ProducedBatch, &Arc<Error>>) {
+        let tokens = std::mem::take(&mut *self.tokens.lock());
+        for (i, (token, timestamp)) in tokens.into_iter().enumerate() {
+            let report = match result {
+                Ok(produced) => Ok(RecordMetadata {
+                    topic: self.topic.clone(),
+                    partition: self.partition,
+                    offset: produced.offsets[i],
+                    timestamp,
+                    log_append_time: produced.log_append_time,
+                }),
+                Err(e) => Err(Error::BatchFailed { source: e.clone() }),
+            };
+            // nobody is interested once the reports were dropped
+            let _ = self.reports.send((token, report));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backoff::BackoffConfig,
+        client::{
+            mock::{record, MockCluster},
+            partition::UnknownTopicHandling,
+            producer::BatchProducerBuilder,
+            KafkaClient,
+        },
+    };
+    use assert_matches::assert_matches;
+    use futures::StreamExt;
+    use std::time::Duration;
+
+    async fn producer(mock: &MockCluster) -> (KafkaClient, BatchProducerBuilder) {
+        mock.add_topic("test", 1);
+        let client = mock
+            .client_builder()
+            .backoff_config(BackoffConfig {
+                deadline: Some(Duration::from_millis(200)),
+                ..Default::default()
+            })
+            .build()
+            .await
+            .unwrap();
+        let partition = client
+            .partition_client("test", 0, UnknownTopicHandling::Error)
+            .await
+            .unwrap();
+        let builder = BatchProducerBuilder::new(Arc::new(partition)).with_linger(Duration::ZERO);
+        (client, builder)
+    }
+
+    #[tokio::test]
+    async fn test_reports_every_record() {
+        let mock = MockCluster::new(1);
+        mock.state.lock().log_append_time = Some(1_700_000_000_000);
+        let (_client, builder) = producer(&mock).await;
+        let (producer, mut reports) = builder.build_with_delivery_reports(1024);
+
+        for token in ["a", "b", "c"] {
+            producer.send(record("value"), token).await.unwrap();
+        }
+        drop(producer);
+
+        let mut offsets = vec![];
+        while let Some((token, report)) = reports.next().await {
+            let metadata = report.unwrap();
+            assert_eq!((metadata.topic.as_str(), metadata.partition), ("test", 0));
+            assert_eq!(metadata.timestamp, record("value").timestamp);
+            assert_eq!(
+                metadata.log_append_time,
+                DateTime::from_timestamp_millis(1_700_000_000_000)
+            );
+            offsets.push((token, metadata.offset));
+        }
+        assert_eq!(offsets, vec![("a", 0), ("b", 1), ("c", 2)]);
+    }
+
+    #[tokio::test]
+    async fn test_reports_failed_batch() {
+        let mock = MockCluster::new(1);
+        let (_client, builder) = producer(&mock).await;
+        let (producer, mut reports) = builder.build_with_delivery_reports(1024);
+
+        mock.state.lock().topics.clear();
+        producer.send(record("value"), 1).await.unwrap();
+        let (token, report) = reports.next().await.unwrap();
+        assert_eq!(token, 1);
+        assert_matches!(report, Err(Error::BatchFailed { .. }));
+    }
+}
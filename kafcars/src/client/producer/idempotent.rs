@@ -0,0 +1,448 @@
+//! Exactly-once production with a producer id, an epoch and sequence numbers per partition.
+
+use crate::{
+    backoff::Backoff,
+    client::{
+        cluster::Cluster,
+        partition::{Acks, Compression, PartitionClient, ProducedBatch},
+    },
+    error::{ConnectionSnafu, Error, IdempotenceRequiresAcksAllSnafu, Result, ServerSnafu},
+    protocol::{
+        api_key::ApiKey,
+        error::Error as ProtocolError,
+        messages::{init_producer_id::InitProducerIdRequest, TaggedFields},
+        record::RecordBatch,
+    },
+    record::Record,
+};
+use snafu::{ensure, ResultExt};
+use std::{collections::HashMap, ops::ControlFlow, sync::Arc};
+
+/// Only relevant to transactional producers, which pass their own
+const TRANSACTION_TIMEOUT_MS: i32 = 60_000;
+
+/// Producer id and epoch assigned by the cluster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProducerId {
+    pub id: i64,
+    pub epoch: i16,
+}
+
+/// Next sequence of a partition, counted for the producer id it was assigned under.
+#[derive(Debug, Default)]
+struct Sequence {
+    producer_id: Option<ProducerId>,
+    next: i32,
+    /// A batch failed without knowing whether the leader appended it, so `next` may already be
+    /// taken
+    unresolved: bool,
+}
+
+/// Held while a batch is sequenced, and produced unless it is pipelined, so the batches of a
+/// partition are written in order
+type SequenceLock = Arc<tokio::sync::Mutex<Sequence>>;
+
+/// Produces every batch exactly once: each batch carries the producer id and the sequence of
+/// its first record, so the partition leader discards the duplicates retries send. Share one
+/// producer between all partitions it writes to.
+pub struct IdempotentProducer {
+    cluster: Arc<Cluster>,
+    /// Set for the producer of a [`TransactionalProducer`](super::transaction::TransactionalProducer)
+    transactional_id: Option<String>,
+    producer_id: tokio::sync::Mutex<Option<ProducerId>>,
+    /// Sequences by topic and partition
+    sequences: parking_lot::Mutex<HashMap<(String, i32), SequenceLock>>,
+}
+
+impl IdempotentProducer {
+    pub(crate) fn new(cluster: Arc<Cluster>) -> Self {
+        Self {
+            cluster,
+            transactional_id: None,
+            producer_id: Default::default(),
+            sequences: Default::default(),
+        }
+    }
+
+    /// Producer of transactional batches. The producer id the transaction coordinator assigned
+    /// to `transactional_id` has to be set before producing.
+    pub(crate) fn transactional(cluster: Arc<Cluster>, transactional_id: String) -> Self {
+        Self {
+            cluster,
+            transactional_id: Some(transactional_id),
+            producer_id: Default::default(),
+            sequences: Default::default(),
+        }
+    }
+
+    /// Continues under a new producer id or epoch, restarting all sequences.
+    pub(crate) async fn set_producer_id(&self, producer_id: ProducerId) {
+        *self.producer_id.lock().await = Some(producer_id);
+    }
+
+    /// The producer id and epoch, requested from the cluster on first use.
+    pub async fn producer_id(&self) -> Result<ProducerId> {
+        let mut current = self.producer_id.lock().await;
+        if let Some(producer_id) = *current {
+            return Ok(producer_id);
+        }
+        let producer_id = self.init_producer_id(None).await?;
+        *current = Some(producer_id);
+        Ok(producer_id)
+    }
+
+    /// Produces `records` as a single batch to the partition of `client` and returns their
+    /// offsets. The client has to wait for [`Acks::All`], otherwise a batch may be lost
+    /// without the sequences noticing.
+    pub async fn produce(
+        &self,
+        client: &PartitionClient,
+        records: Vec<Record>,
+        compression: Compression,
+    ) -> Result<Vec<i64>> {
+        let produced = self.produce_records(client, records, compression).await?;
+        Ok(produced.offsets)
+    }
+
+    /// Produces `records` like [`Self::produce`], also returning the time the leader appended
+    /// the batch.
+    pub(crate) async fn produce_records(
+        &self,
+        client: &PartitionClient,
+        records: Vec<Record>,
+        compression: Compression,
+    ) -> Result<ProducedBatch> {
+        ensure!(client.acks() == Acks::All, IdempotenceRequiresAcksAllSnafu);
+        if records.is_empty() {
+            return Ok(ProducedBatch::default());
+        }
+        client
+            .intercept(records, |records| {
+                self.produce_sequenced(client, records, compression)
+            })
+            .await
+    }
+
+    /// Produces `records` with the next sequence of the partition of `client`.
+    async fn produce_sequenced(
+        &self,
+        client: &PartitionClient,
+        records: Vec<Record>,
+        compression: Compression,
+    ) -> Result<ProducedBatch> {
+        let sequence = self.sequence_lock(client);
+        let mut sequence = sequence.lock().await;
+
+        let mut batch = RecordBatch::new(records, compression);
+        batch.is_transactional = self.transactional_id.is_some();
+        let mut bumped = false;
+        loop {
+            let producer_id = self.resolve(&mut sequence).await?;
+            batch.producer_id = producer_id.id;
+            batch.producer_epoch = producer_id.epoch;
+            batch.base_sequence = sequence.next;
+
+            match client
+                .produce_batch(batch.clone(), self.transactional_id.as_deref())
+                .await
+            {
+                Ok(produced) => {
+                    sequence.next = next_sequence(sequence.next, produced.offsets.len());
+                    return Ok(produced);
+                }
+                Err(e) => {
+                    if !self.recover(producer_id, &e, &mut bumped).await? {
+                        // a server error means the leader rejected the batch, any other failure,
+                        // e.g. a timed out request, may come after it appended the batch. A
+                        // transactional producer gets a new epoch when it aborts instead.
+                        if !matches!(e, Error::Server { .. }) && self.transactional_id.is_none() {
+                            sequence.unresolved = true;
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sets the producer id and the next sequence of the partition of `client` on `batch`,
+    /// which is produced without holding the sequence, so later batches can be sent before it
+    /// was acknowledged. A batch sent again keeps its sequence, unless the epoch was bumped.
+    pub(crate) async fn sequence(
+        &self,
+        client: &PartitionClient,
+        batch: &mut RecordBatch,
+    ) -> Result<()> {
+        ensure!(client.acks() == Acks::All, IdempotenceRequiresAcksAllSnafu);
+        let producer_id = self.producer_id().await?;
+        if (batch.producer_id, batch.producer_epoch) == (producer_id.id, producer_id.epoch) {
+            return Ok(());
+        }
+        let sequence = self.sequence_lock(client);
+        let mut sequence = sequence.lock().await;
+        let producer_id = self.resolve(&mut sequence).await?;
+        batch.is_transactional = self.transactional_id.is_some();
+        batch.producer_id = producer_id.id;
+        batch.producer_epoch = producer_id.epoch;
+        batch.base_sequence = sequence.next;
+        sequence.next = next_sequence(sequence.next, batch.records.len());
+        Ok(())
+    }
+
+    /// Whether a batch produced under `used` can be sent again after it failed with `error`,
+    /// bumping the epoch first if the leader lost the sequence of this producer. The epoch is
+    /// bumped at most once per batch, tracked by `bumped`.
+    pub(crate) async fn recover(
+        &self,
+        used: ProducerId,
+        error: &Error,
+        bumped: &mut bool,
+    ) -> Result<bool> {
+        match error {
+            // the leader lost the state of this producer, e.g. because its records were
+            // deleted, or an earlier batch is missing. Neither batch was written, so it is safe
+            // to send it again under a new epoch. A transactional producer gets a new epoch when
+            // it aborts the transaction instead.
+            Error::Server {
+                error: ProtocolError::OutOfOrderSequenceNumber | ProtocolError::UnknownProducerId,
+                ..
+            } if !*bumped && self.transactional_id.is_none() => {
+                *bumped = true;
+                self.bump_epoch(used).await?;
+                Ok(true)
+            }
+            // retry under the epoch another partition bumped in the meantime
+            Error::Server {
+                error: ProtocolError::InvalidProducerEpoch,
+                ..
+            } => Ok(*self.producer_id.lock().await != Some(used)),
+            _ => Ok(false),
+        }
+    }
+
+    /// The producer id to produce the next batch of `sequence` under, bumping the epoch if the
+    /// last batch is unresolved. Sequences restart at 0 for every epoch.
+    async fn resolve(&self, sequence: &mut Sequence) -> Result<ProducerId> {
+        let mut producer_id = self.producer_id().await?;
+        if sequence.unresolved && sequence.producer_id == Some(producer_id) {
+            self.bump_epoch(producer_id).await?;
+            producer_id = self.producer_id().await?;
+        }
+        if sequence.producer_id != Some(producer_id) {
+            *sequence = Sequence {
+                producer_id: Some(producer_id),
+                next: 0,
+                unresolved: false,
+            };
+        }
+        Ok(producer_id)
+    }
+
+    pub(crate) fn transactional_id(&self) -> Option<&str> {
+        self.transactional_id.as_deref()
+    }
+
+    fn sequence_lock(&self, client: &PartitionClient) -> SequenceLock {
+        self.sequences
+            .lock()
+            .entry((client.topic().to_string(), client.partition()))
+            .or_default()
+            .clone()
+    }
+
+    /// Bumps the epoch of `used`, unless another partition already replaced it.
+    async fn bump_epoch(&self, used: ProducerId) -> Result<()> {
+        let mut current = self.producer_id.lock().await;
+        if *current == Some(used) {
+            *current = Some(self.init_producer_id(Some(used)).await?);
+        }
+        Ok(())
+    }
+
+    /// Requests a new producer id, or a new epoch of `current`. Brokers before version 3 of
+    /// InitProducerId always assign a new producer id.
+    async fn init_producer_id(&self, current: Option<ProducerId>) -> Result<ProducerId> {
+        let mut backoff = Backoff::new(self.cluster.backoff_config());
+        backoff
+            .retry_with_backoff("init producer id", || async {
+                let response = self
+                    .cluster
+                    .request_any(|| InitProducerIdRequest {
+                        transactional_id: None,
+                        transaction_timeout_ms: TRANSACTION_TIMEOUT_MS,
+                        producer_id: current.map_or(-1, |current| current.id),
+                        producer_epoch: current.map_or(-1, |current| current.epoch),
+                        tagged_fields: Some(TaggedFields::default()),
+                    })
+                    .await;
+                let result = response.and_then(|(_, response)| match response.error {
+                    None => Ok(ProducerId {
+                        id: response.producer_id,
+                        epoch: response.producer_epoch,
+                    }),
+                    Some(error) => ServerSnafu {
+                        api_key: ApiKey::InitProducerId,
+                        error,
+                    }
+                    .fail(),
+                });
+                match result {
+                    Ok(producer_id) => ControlFlow::Break(Ok(producer_id)),
+                    Err(e @ Error::Server { error, .. }) if error.is_retriable() => {
+                        ControlFlow::Continue(e)
+                    }
+                    Err(Error::Request { source }) if source.is_transient() => {
+                        ControlFlow::Continue(Error::Request { source })
+                    }
+                    Err(e) => ControlFlow::Break(Err(e)),
+                }
+            })
+            .await
+            .context(ConnectionSnafu)?
+    }
+}
+
+/// Sequences wrap around to 0 after `i32::MAX`.
+fn next_sequence(sequence: i32, records: usize) -> i32 {
+    ((i64::from(sequence) + records as i64) % (i64::from(i32::MAX) + 1)) as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backoff::BackoffConfig,
+        client::{
+            mock::{record, MockCluster},
+            partition::UnknownTopicHandling,
+        },
+    };
+    use assert_matches::assert_matches;
+    use std::time::Duration;
+
+    #[test]
+    fn test_next_sequence_wraps() {
+        assert_eq!(next_sequence(5, 3), 8);
+        assert_eq!(next_sequence(i32::MAX - 1, 3), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retry_does_not_duplicate() {
+        let mock = MockCluster::new(1);
+        mock.add_topic("test", 1);
+        let client = mock.client().await;
+        let partition = client
+            .partition_client("test", 0, UnknownTopicHandling::Error)
+            .await
+            .unwrap();
+        let producer = client.idempotent_producer();
+
+        let offsets = producer
+            .produce(
+                &partition,
+                vec![record("value"); 2],
+                Compression::NoCompression,
+            )
+            .await
+            .unwrap();
+        assert_eq!(offsets, vec![0, 1]);
+
+        // the batch is written, but the connection breaks before the response is sent
+        mock.state.lock().lost_produce_responses = 1;
+        let offsets = producer
+            .produce(
+                &partition,
+                vec![record("value"); 3],
+                Compression::NoCompression,
+            )
+            .await
+            .unwrap();
+        assert_eq!(offsets, vec![2, 3, 4]);
+        assert_eq!(mock.state.lock().topics[0].partitions[0].next_offset, 5);
+    }
+
+    #[tokio::test]
+    async fn test_unresolved_batch_bumps_epoch() {
+        let mock = MockCluster::new(1);
+        mock.add_topic("test", 1);
+        let client = mock
+            .client_builder()
+            .backoff_config(BackoffConfig {
+                deadline: Some(Duration::from_millis(200)),
+                ..Default::default()
+            })
+            .build()
+            .await
+            .unwrap();
+        let partition = client
+            .partition_client("test", 0, UnknownTopicHandling::Error)
+            .await
+            .unwrap();
+        let producer = client.idempotent_producer();
+        let first = producer.producer_id().await.unwrap();
+
+        // the batch is written, but no response arrives before the deadline
+        mock.state.lock().lost_produce_responses = usize::MAX;
+        let result = producer
+            .produce(
+                &partition,
+                vec![record("value"); 2],
+                Compression::NoCompression,
+            )
+            .await;
+        assert_matches!(result, Err(Error::Connection { .. }));
+        assert_eq!(mock.state.lock().topics[0].partitions[0].next_offset, 2);
+
+        // a batch of the same size under the same sequence would be taken for a duplicate
+        mock.state.lock().lost_produce_responses = 0;
+        let offsets = producer
+            .produce(
+                &partition,
+                vec![record("value"); 2],
+                Compression::NoCompression,
+            )
+            .await
+            .unwrap();
+        assert_eq!(offsets, vec![2, 3]);
+        assert_eq!(mock.state.lock().topics[0].partitions[0].next_offset, 4);
+        let second = producer.producer_id().await.unwrap();
+        assert_eq!(second.id, first.id);
+        assert_eq!(second.epoch, first.epoch + 1);
+    }
+
+    #[tokio::test]
+    async fn test_bumps_epoch_on_unknown_producer() {
+        let mock = MockCluster::new(1);
+        mock.add_topic("test", 1);
+        let client = mock.client().await;
+        let partition = client
+            .partition_client("test", 0, UnknownTopicHandling::Error)
+            .await
+            .unwrap();
+        let producer = client.idempotent_producer();
+        producer
+            .produce(
+                &partition,
+                vec![record("value")],
+                Compression::NoCompression,
+            )
+            .await
+            .unwrap();
+        let first = producer.producer_id().await.unwrap();
+
+        // the leader forgets the producer, e.g. once its records were deleted
+        mock.state.lock().topics[0].partitions[0].producers.clear();
+        let offsets = producer
+            .produce(
+                &partition,
+                vec![record("value")],
+                Compression::NoCompression,
+            )
+            .await
+            .unwrap();
+        assert_eq!(offsets, vec![1]);
+        let second = producer.producer_id().await.unwrap();
+        assert_eq!(second.id, first.id);
+        assert_eq!(second.epoch, first.epoch + 1);
+    }
+}
@@ -0,0 +1,122 @@
+//! Memory budget shared by the producers of a client, so records pile up only to a bound while
+//! brokers are slow or unreachable.
+
+use crate::error::{BufferExhaustedSnafu, Result};
+use snafu::OptionExt;
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::timeout,
+};
+
+/// Default budget, like `buffer.memory` of the Java client
+pub const DEFAULT_BUFFER_MEMORY: usize = 32 * 1024 * 1024;
+/// Default time to wait for memory, like `max.block.ms` of the Java client
+pub const DEFAULT_MAX_BLOCK: Duration = Duration::from_secs(60);
+
+/// Bytes the records waiting to be produced may take up.
+#[derive(Debug)]
+pub struct BufferMemory {
+    total: usize,
+    available: Arc<Semaphore>,
+    max_block: Option<Duration>,
+}
+
+impl BufferMemory {
+    /// Budget of `total` bytes. Reserving waits up to `max_block` for memory, forever if
+    /// `None`.
+    pub fn new(total: usize, max_block: Option<Duration>) -> Self {
+        let total = total.clamp(1, Semaphore::MAX_PERMITS);
+        Self {
+            total,
+            available: Arc::new(Semaphore::new(total)),
+            max_block,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Bytes not reserved by any record.
+    pub fn available(&self) -> usize {
+        self.available.available_permits()
+    }
+
+    /// Reserves `size` bytes, waiting for reservations to be released if there is not enough
+    /// memory left. A record larger than the whole budget reserves all of it.
+    pub(crate) async fn reserve(&self, size: usize) -> Result<Reservation> {
+        let permits = u32::try_from(size.min(self.total)).unwrap_or(u32::MAX);
+        let acquire = self.available.clone().acquire_many_owned(permits);
+        let permit = match self.max_block {
+            Some(max_block) => timeout(max_block, acquire)
+                .await
+                .ok()
+                .context(BufferExhaustedSnafu { size, max_block })?,
+            None => acquire.await,
+        };
+        Ok(Reservation(Some(
+            permit.expect("semaphore is never closed"),
+        )))
+    }
+}
+
+impl Default for BufferMemory {
+    fn default() -> Self {
+        Self::new(DEFAULT_BUFFER_MEMORY, Some(DEFAULT_MAX_BLOCK))
+    }
+}
+
+/// Reserved bytes, released when dropped.
+#[derive(Debug, Default)]
+pub(crate) struct Reservation(Option<OwnedSemaphorePermit>);
+
+impl Reservation {
+    /// Adds the bytes of `other`, to release them together.
+    pub(crate) fn merge(&mut self, other: Reservation) {
+        match (&mut self.0, other.0) {
+            (Some(permit), Some(other)) => permit.merge(other),
+            (permit, other) => *permit = permit.take().or(other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use assert_matches::assert_matches;
+
+    #[tokio::test]
+    async fn test_reserve_waits_for_release() {
+        let memory = Arc::new(BufferMemory::new(100, None));
+        let mut first = memory.reserve(60).await.unwrap();
+        first.merge(memory.reserve(30).await.unwrap());
+        assert_eq!(memory.available(), 10);
+
+        let waiting = {
+            let memory = memory.clone();
+            tokio::spawn(async move { memory.reserve(20).await.map(|_| ()) })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+
+        drop(first);
+        waiting.await.unwrap().unwrap();
+        assert_eq!(memory.available(), 100);
+    }
+
+    #[tokio::test]
+    async fn test_reserve_fails_after_max_block() {
+        let memory = BufferMemory::new(100, Some(Duration::from_millis(20)));
+        let reserved = memory.reserve(100).await.unwrap();
+        assert_matches!(
+            memory.reserve(1).await,
+            Err(Error::BufferExhausted { size: 1, .. })
+        );
+        // larger than the whole budget, it takes all of it once free
+        drop(reserved);
+        let _all = memory.reserve(1000).await.unwrap();
+        assert_eq!(memory.available(), 0);
+    }
+}
@@ -0,0 +1,432 @@
+pub mod aggregator;
+pub mod delivery;
+pub mod idempotent;
+pub mod memory;
+pub mod partitioner;
+mod pipeline;
+pub mod topic;
+pub mod transaction;
+
+use crate::{
+    client::{
+        cluster::CloseHook,
+        partition::{Compression, PartitionClient},
+    },
+    error::{ClientClosedSnafu, Error, RecordTooLargeSnafu, Result},
+    record::Record,
+};
+use aggregator::{Aggregator, StatusDeaggregator, TryPush};
+use delivery::{DeliveryAggregator, DeliveryProducer, DeliveryReports};
+use futures::{future::BoxFuture, FutureExt};
+use idempotent::IdempotentProducer;
+use log::warn;
+use memory::{BufferMemory, Reservation};
+use parking_lot::Mutex;
+use pipeline::Pipeline;
+use snafu::OptionExt;
+use std::{
+    sync::{Arc, Weak},
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc, watch},
+    time::{timeout_at, Instant},
+};
+
+/// Default time a batch waits for more records after its first one
+pub(crate) const DEFAULT_LINGER: Duration = Duration::from_millis(5);
+/// Batches of a partition in flight by default, so a failed batch is never overtaken
+pub(crate) const DEFAULT_MAX_IN_FLIGHT: usize = 1;
+/// Partition leaders only remember the last 5 batches of a producer to discard duplicates of
+pub(crate) const MAX_IN_FLIGHT_IDEMPOTENT: usize = 5;
+
+/// Offsets of a produced batch with the deaggregator of its statuses, shared by its records
+type Outcome<D> = Arc<std::result::Result<(Vec<i64>, D), Arc<Error>>>;
+
+type Status<A> = <<A as Aggregator>::StatusDeaggregator as StatusDeaggregator>::Status;
+
+pub struct BatchProducerBuilder {
+    client: Arc<PartitionClient>,
+    linger: Duration,
+    compression: Compression,
+    idempotent: Option<Arc<IdempotentProducer>>,
+    max_in_flight: usize,
+}
+
+impl BatchProducerBuilder {
+    pub fn new(client: Arc<PartitionClient>) -> Self {
+        Self {
+            client,
+            linger: DEFAULT_LINGER,
+            compression: Compression::default(),
+            idempotent: None,
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+        }
+    }
+
+    /// Time a batch waits for more records after its first one before it is produced.
+    pub fn with_linger(mut self, linger: Duration) -> Self {
+        self.linger = linger;
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Produces the batches through `producer`, so retries never duplicate them.
+    pub fn with_idempotence(mut self, producer: Arc<IdempotentProducer>) -> Self {
+        self.idempotent = Some(producer);
+        self
+    }
+
+    /// Batches sent before the first of them is acknowledged, at most 5 with idempotence.
+    /// Failed batches are sent again before any later one, but without idempotence a batch
+    /// sent after a failed one may be appended first.
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = max_in_flight;
+        self
+    }
+
+    /// Creates the producer. Its last batch is flushed when the client is closed.
+    pub fn build<A: Aggregator>(self, aggregator: A) -> BatchProducer<A> {
+        let (flushes, receiver) = mpsc::unbounded_channel();
+        let max_in_flight = match self.idempotent {
+            Some(_) => self.max_in_flight.min(MAX_IN_FLIGHT_IDEMPOTENT),
+            None => self.max_in_flight,
+        };
+        let pipeline = Pipeline::new(
+            self.client.clone(),
+            self.compression,
+            self.idempotent,
+            max_in_flight,
+        );
+        tokio::spawn(pipeline.run(receiver));
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                aggregator,
+                outcome: watch::channel(None).0,
+                generation: 0,
+                pending: false,
+                reserved: Reservation::default(),
+            }),
+            flushes,
+            linger: self.linger,
+            memory: self.client.cluster().producer_memory().clone(),
+        });
+        self.client
+            .cluster()
+            .register_close_hook(Arc::downgrade(&shared) as Weak<dyn CloseHook>);
+        BatchProducer { shared }
+    }
+
+    /// Creates a producer of records up to a total [`Record::approximate_size`] of
+    /// `max_batch_size` per batch, which reports their outcome on a stream instead of a future
+    /// per record.
+    pub fn build_with_delivery_reports<T: Send + 'static>(
+        self,
+        max_batch_size: usize,
+    ) -> (DeliveryProducer<T>, DeliveryReports<T>) {
+        let (aggregator, reports) = DeliveryAggregator::new(
+            self.client.topic().to_string(),
+            self.client.partition(),
+            max_batch_size,
+        );
+        (DeliveryProducer::new(self.build(aggregator)), reports)
+    }
+}
+
+/// Aggregates inputs into batches produced to a single partition. A batch is produced once
+/// the aggregator is full or the linger expired, and batches are produced in order.
+pub struct BatchProducer<A: Aggregator> {
+    shared: Arc<Shared<A>>,
+}
+
+impl<A: Aggregator> BatchProducer<A> {
+    /// Adds `input` to the current batch and resolves to its status once the batch was
+    /// produced. Waits for buffer memory first if the records waiting to be produced already
+    /// take up all of it.
+    pub async fn produce(&self, input: A::Input) -> Result<Status<A>> {
+        let (mut receiver, tag) = self.push(input).await?;
+        match &*wait(&mut receiver).await? {
+            Ok((offsets, deaggregator)) => deaggregator.deaggregate(offsets, tag),
+            Err(e) => Err(Error::BatchFailed { source: e.clone() }),
+        }
+    }
+
+    /// Adds `input` to the current batch, returning a receiver of the outcome of the batch and
+    /// the tag of the input in it.
+    pub(crate) async fn push(
+        &self,
+        input: A::Input,
+    ) -> Result<(
+        watch::Receiver<Option<Outcome<A::StatusDeaggregator>>>,
+        A::Tag,
+    )> {
+        let size = self.shared.state.lock().aggregator.approximate_size(&input);
+        let reservation = self.shared.memory.reserve(size).await?;
+        let (receiver, tag) = {
+            let mut state = self.shared.state.lock();
+            let tag = match state.aggregator.try_push(input)? {
+                TryPush::Aggregated(tag) => tag,
+                TryPush::NoCapacity(_) if !state.pending => return RecordTooLargeSnafu.fail(),
+                TryPush::NoCapacity(input) => {
+                    self.shared.flush(&mut state);
+                    match state.aggregator.try_push(input)? {
+                        TryPush::Aggregated(tag) => tag,
+                        TryPush::NoCapacity(_) => return RecordTooLargeSnafu.fail(),
+                    }
+                }
+            };
+            state.reserved.merge(reservation);
+            if !state.pending {
+                state.pending = true;
+                self.shared.start_linger(state.generation);
+            }
+            (state.outcome.subscribe(), tag)
+        };
+        Ok((receiver, tag))
+    }
+
+    /// Whether the next input starts a new batch.
+    pub(crate) fn is_batch_empty(&self) -> bool {
+        !self.shared.state.lock().pending
+    }
+
+    /// Hands the current batch to be produced without waiting for its outcome.
+    pub(crate) fn start_flush(&self) {
+        self.shared.flush(&mut self.shared.state.lock());
+    }
+
+    /// Produces the current batch without waiting for the linger to expire.
+    pub async fn flush(&self) -> Result<()> {
+        let receiver = self.shared.flush(&mut self.shared.state.lock());
+        let Some(mut receiver) = receiver else {
+            return Ok(());
+        };
+        match &*wait(&mut receiver).await? {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Error::BatchFailed { source: e.clone() }),
+        }
+    }
+}
+
+struct State<A: Aggregator> {
+    aggregator: A,
+    /// Publishes the outcome of the batch being aggregated
+    outcome: watch::Sender<Option<Outcome<A::StatusDeaggregator>>>,
+    /// Counts the flushed batches, so a linger timer only flushes the batch it was started for
+    generation: u64,
+    /// Whether the batch being aggregated has any inputs
+    pending: bool,
+    /// Buffer memory of the inputs in the batch being aggregated
+    reserved: Reservation,
+}
+
+/// A batch handed to the task producing it.
+struct Flush<D> {
+    batch: Result<(Vec<Record>, D)>,
+    outcome: watch::Sender<Option<Outcome<D>>>,
+    reserved: Reservation,
+}
+
+struct Shared<A: Aggregator> {
+    state: Mutex<State<A>>,
+    flushes: mpsc::UnboundedSender<Flush<A::StatusDeaggregator>>,
+    linger: Duration,
+    memory: Arc<BufferMemory>,
+}
+
+impl<A: Aggregator> Shared<A> {
+    /// Hands the batch being aggregated to the producing task. Returns a receiver of its
+    /// outcome, or `None` if the batch is empty.
+    fn flush(
+        &self,
+        state: &mut State<A>,
+    ) -> Option<watch::Receiver<Option<Outcome<A::StatusDeaggregator>>>> {
+        if !state.pending {
+            return None;
+        }
+        let outcome = std::mem::replace(&mut state.outcome, watch::channel(None).0);
+        let receiver = outcome.subscribe();
+        state.generation += 1;
+        state.pending = false;
+        // the producing task only stops once this producer is dropped
+        let _ = self.flushes.send(Flush {
+            batch: state.aggregator.flush(),
+            outcome,
+            reserved: std::mem::take(&mut state.reserved),
+        });
+        Some(receiver)
+    }
+
+    fn start_linger(self: &Arc<Self>, generation: u64) {
+        let shared = Arc::downgrade(self);
+        let linger = self.linger;
+        tokio::spawn(async move {
+            tokio::time::sleep(linger).await;
+            if let Some(shared) = shared.upgrade() {
+                let mut state = shared.state.lock();
+                if state.generation == generation {
+                    shared.flush(&mut state);
+                }
+            }
+        });
+    }
+}
+
+impl<A: Aggregator> CloseHook for Shared<A> {
+    fn close(&self, deadline: Instant) -> BoxFuture<'_, ()> {
+        async move {
+            let receiver = self.flush(&mut self.state.lock());
+            if let Some(mut receiver) = receiver {
+                if timeout_at(deadline, wait(&mut receiver)).await.is_err() {
+                    warn!("Last batch was not produced before the client closed");
+                }
+            }
+        }
+        .boxed()
+    }
+}
+
+/// Waits for the outcome of a flushed batch.
+async fn wait<D>(receiver: &mut watch::Receiver<Option<Outcome<D>>>) -> Result<Outcome<D>> {
+    let outcome = receiver.wait_for(Option::is_some).await.ok();
+    outcome
+        .and_then(|outcome| outcome.clone())
+        .context(ClientClosedSnafu)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{
+        mock::{record, MockCluster},
+        partition::UnknownTopicHandling,
+        KafkaClient,
+    };
+    use aggregator::RecordAggregator;
+    use assert_matches::assert_matches;
+
+    async fn setup(mock: &MockCluster) -> (KafkaClient, Arc<PartitionClient>) {
+        mock.add_topic("test", 1);
+        let client = mock.client().await;
+        let partition = client
+            .partition_client("test", 0, UnknownTopicHandling::Error)
+            .await
+            .unwrap();
+        (client, Arc::new(partition))
+    }
+
+    #[tokio::test]
+    async fn test_flushes_full_batch() {
+        let mock = MockCluster::new(1);
+        let (_client, partition) = setup(&mock).await;
+        let producer = Arc::new(
+            BatchProducerBuilder::new(partition)
+                .with_linger(Duration::from_secs(3600))
+                .build(RecordAggregator::new(
+                    record("value").approximate_size() * 2,
+                )),
+        );
+        let produce = |producer: &Arc<BatchProducer<RecordAggregator>>| {
+            let producer = producer.clone();
+            tokio::spawn(async move { producer.produce(record("value")).await })
+        };
+
+        let a = produce(&producer);
+        let b = produce(&producer);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!a.is_finished() && !b.is_finished());
+
+        let c = produce(&producer);
+        assert_eq!(a.await.unwrap().unwrap(), 0);
+        assert_eq!(b.await.unwrap().unwrap(), 1);
+        assert!(!c.is_finished());
+
+        producer.flush().await.unwrap();
+        assert_eq!(c.await.unwrap().unwrap(), 2);
+        assert_eq!(mock.state.lock().topics[0].partitions[0].batches.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_flushes_after_linger() {
+        let mock = MockCluster::new(1);
+        let (_client, partition) = setup(&mock).await;
+        let producer = BatchProducerBuilder::new(partition)
+            .with_linger(Duration::from_millis(100))
+            .build(RecordAggregator::new(1024));
+
+        let start = Instant::now();
+        let (a, b) = tokio::join!(
+            producer.produce(record("value")),
+            producer.produce(record("value"))
+        );
+        assert_eq!((a.unwrap(), b.unwrap()), (0, 1));
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        assert_eq!(mock.state.lock().topics[0].partitions[0].batches.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_buffer_memory_bounds_pending_records() {
+        let mock = MockCluster::new(1);
+        mock.add_topic("test", 1);
+        let mut builder = mock
+            .client_builder()
+            .buffer_memory(record("value").approximate_size() * 2)
+            .max_block(Some(Duration::from_millis(50)));
+        builder.request_timeout = Duration::from_millis(100);
+        let client = builder.build().await.unwrap();
+        let partition = client
+            .partition_client("test", 0, UnknownTopicHandling::Error)
+            .await
+            .unwrap();
+        let memory = partition.cluster().producer_memory().clone();
+        let producer = Arc::new(
+            BatchProducerBuilder::new(Arc::new(partition))
+                .with_linger(Duration::ZERO)
+                .build(RecordAggregator::new(1024)),
+        );
+
+        // the broker stops answering, so the records are not produced
+        mock.state.lock().silent = true;
+        let pending: Vec<_> = (0..2)
+            .map(|_| {
+                let producer = producer.clone();
+                tokio::spawn(async move { producer.produce(record("value")).await })
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(memory.available(), 0);
+        assert_matches!(
+            producer.produce(record("value")).await,
+            Err(Error::BufferExhausted { .. })
+        );
+
+        mock.state.lock().silent = false;
+        for pending in pending {
+            pending.await.unwrap().unwrap();
+        }
+        assert_eq!(memory.available(), memory.total());
+    }
+
+    #[tokio::test]
+    async fn test_client_close_flushes_batch() {
+        let mock = MockCluster::new(1);
+        let (client, partition) = setup(&mock).await;
+        let producer = Arc::new(
+            BatchProducerBuilder::new(partition)
+                .with_linger(Duration::from_secs(3600))
+                .build(RecordAggregator::new(1024)),
+        );
+        let produced = {
+            let producer = producer.clone();
+            tokio::spawn(async move { producer.produce(record("value")).await })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        client.close(Instant::now() + Duration::from_secs(1)).await;
+        assert_eq!(produced.await.unwrap().unwrap(), 0);
+    }
+}
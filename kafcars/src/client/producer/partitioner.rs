@@ -0,0 +1,257 @@
+//! Choice of the partition a record of a [`TopicProducer`](super::topic::TopicProducer) is
+//! produced to.
+
+use crate::record::Record;
+use parking_lot::Mutex;
+use rand::{thread_rng, Rng};
+use std::collections::HashMap;
+
+/// Chooses the partition of every record produced to a topic.
+pub trait Partitioner: Send + Sync + 'static {
+    /// The partition in `0..partitions` of `topic` to produce `record` to.
+    fn partition(&self, topic: &str, record: &Record, partitions: i32) -> i32;
+
+    /// Called when `record` would start a new batch of the partition `previous`. Returns the
+    /// partition to produce it to instead, which lets sticky partitioners move on once a batch
+    /// is complete.
+    fn on_new_batch(
+        &self,
+        _topic: &str,
+        _record: &Record,
+        _partitions: i32,
+        _previous: i32,
+    ) -> Option<i32> {
+        None
+    }
+}
+
+/// The partitioner of the Java client: records with a key go to the partition of the
+/// [`murmur2`] hash of their key, so they land where Java producers put them, and records
+/// without one are spread like by the [`StickyPartitioner`].
+#[derive(Debug, Default)]
+pub struct DefaultPartitioner {
+    sticky: StickyPartitioner,
+}
+
+impl Partitioner for DefaultPartitioner {
+    fn partition(&self, topic: &str, record: &Record, partitions: i32) -> i32 {
+        match &record.key {
+            Some(key) => to_positive(murmur2(key)) % partitions,
+            None => self.sticky.partition(topic, record, partitions),
+        }
+    }
+
+    fn on_new_batch(
+        &self,
+        topic: &str,
+        record: &Record,
+        partitions: i32,
+        previous: i32,
+    ) -> Option<i32> {
+        match &record.key {
+            Some(_) => None,
+            None => self
+                .sticky
+                .on_new_batch(topic, record, partitions, previous),
+        }
+    }
+}
+
+/// Produces all records to one partition per topic until its batch is complete, then moves on
+/// to another random one (KIP-480). Fills batches faster than spreading every record, while
+/// the partitions still get an even share over time. Ignores the record keys.
+#[derive(Debug, Default)]
+pub struct StickyPartitioner {
+    /// Current partition by topic
+    partitions: Mutex<HashMap<String, i32>>,
+}
+
+impl Partitioner for StickyPartitioner {
+    fn partition(&self, topic: &str, _record: &Record, partitions: i32) -> i32 {
+        let mut current = self.partitions.lock();
+        match current.get(topic) {
+            Some(partition) if *partition < partitions => *partition,
+            _ => {
+                let partition = thread_rng().gen_range(0..partitions);
+                current.insert(topic.to_string(), partition);
+                partition
+            }
+        }
+    }
+
+    fn on_new_batch(
+        &self,
+        topic: &str,
+        _record: &Record,
+        partitions: i32,
+        previous: i32,
+    ) -> Option<i32> {
+        let mut current = self.partitions.lock();
+        match current.get(topic) {
+            // another record already moved on from the previous partition
+            Some(partition) if *partition != previous && *partition < partitions => {
+                Some(*partition)
+            }
+            _ => {
+                let partition = if partitions < 2 {
+                    0
+                } else {
+                    // any partition except the previous one
+                    (previous + thread_rng().gen_range(1..partitions)) % partitions
+                };
+                current.insert(topic.to_string(), partition);
+                Some(partition)
+            }
+        }
+    }
+}
+
+/// Produces the records of a topic to its partitions in turn, ignoring their keys.
+#[derive(Debug, Default)]
+pub struct RoundRobinPartitioner {
+    /// Records produced by topic
+    counters: Mutex<HashMap<String, u32>>,
+}
+
+impl Partitioner for RoundRobinPartitioner {
+    fn partition(&self, topic: &str, _record: &Record, partitions: i32) -> i32 {
+        let mut counters = self.counters.lock();
+        let counter = counters.entry(topic.to_string()).or_default();
+        let partition = *counter % partitions as u32;
+        *counter = counter.wrapping_add(1);
+        partition as i32
+    }
+}
+
+/// The `consistent_random` partitioner of librdkafka: records with a key go to the partition
+/// of the CRC32 of their key, so they land where librdkafka producers put them. Records with
+/// an empty or without a key go to a random partition.
+#[derive(Debug, Default)]
+pub struct ConsistentRandomPartitioner;
+
+impl Partitioner for ConsistentRandomPartitioner {
+    fn partition(&self, _topic: &str, record: &Record, partitions: i32) -> i32 {
+        match &record.key {
+            Some(key) if !key.is_empty() => (crc32fast::hash(key) % partitions as u32) as i32,
+            _ => thread_rng().gen_range(0..partitions),
+        }
+    }
+}
+
+/// The 32-bit murmur2 hash the Java client partitions keys by.
+pub fn murmur2(data: &[u8]) -> i32 {
+    const SEED: u32 = 0x9747_b28c;
+    const M: u32 = 0x5bd1_e995;
+    const R: u32 = 24;
+
+    let mut h = SEED ^ data.len() as u32;
+    let mut chunks = data.chunks_exact(4);
+    for chunk in &mut chunks {
+        let mut k = u32::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M);
+        h ^= k;
+    }
+    let rest = chunks.remainder();
+    if !rest.is_empty() {
+        for (i, byte) in rest.iter().enumerate() {
+            h ^= u32::from(*byte) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^= h >> 15;
+    h as i32
+}
+
+/// Clears the sign bit like the Java client, which maps `i32::MIN` to 0 instead of overflowing.
+fn to_positive(hash: i32) -> i32 {
+    hash & 0x7fff_ffff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use std::collections::BTreeMap;
+
+    fn record(key: Option<&[u8]>) -> Record {
+        Record {
+            key: key.map(<[u8]>::to_vec),
+            value: Some(b"value".to_vec()),
+            headers: BTreeMap::new(),
+            timestamp: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_murmur2_matches_java_client() {
+        assert_eq!(murmur2(b"21"), -973932308);
+        assert_eq!(murmur2(b"foobar"), -790332482);
+        assert_eq!(murmur2(b"a-little-bit-long-string"), -985981536);
+        assert_eq!(murmur2(b"a-little-bit-longer-string"), -1486304829);
+        assert_eq!(
+            murmur2(b"lkjh234lh9fiuh90y23oiuhsafujhadof229phr9h19h89h8"),
+            -58897971
+        );
+        assert_eq!(murmur2(b"abc"), 479470107);
+    }
+
+    #[test]
+    fn test_default_partitioner_hashes_keys() {
+        let partitioner = DefaultPartitioner::default();
+        let keyed = record(Some(b"foobar"));
+        let partition = partitioner.partition("test", &keyed, 10);
+        assert_eq!(partition, to_positive(-790332482) % 10);
+        assert_eq!(
+            partitioner.on_new_batch("test", &keyed, 10, partition),
+            None
+        );
+    }
+
+    #[test]
+    fn test_sticky_partitioner_moves_on_new_batch() {
+        let partitioner = StickyPartitioner::default();
+        let first = partitioner.partition("test", &record(None), 3);
+        assert_eq!(
+            partitioner.partition("test", &record(Some(b"key")), 3),
+            first
+        );
+
+        let second = partitioner
+            .on_new_batch("test", &record(None), 3, first)
+            .unwrap();
+        assert_ne!(second, first);
+        assert_eq!(partitioner.partition("test", &record(None), 3), second);
+        // a record that still saw the first partition joins the second one
+        assert_eq!(
+            partitioner.on_new_batch("test", &record(None), 3, first),
+            Some(second)
+        );
+    }
+
+    #[test]
+    fn test_round_robin_partitioner() {
+        let partitioner = RoundRobinPartitioner::default();
+        let partitions: Vec<i32> = (0..4)
+            .map(|_| partitioner.partition("test", &record(Some(b"key")), 3))
+            .collect();
+        assert_eq!(partitions, vec![0, 1, 2, 0]);
+        assert_eq!(partitioner.partition("other", &record(None), 3), 0);
+    }
+
+    #[test]
+    fn test_consistent_random_partitioner_hashes_keys() {
+        let partitioner = ConsistentRandomPartitioner;
+        // CRC32 of "123456789" is 0xcbf43926
+        assert_eq!(
+            partitioner.partition("test", &record(Some(b"123456789")), 1000),
+            (0xcbf4_3926u32 % 1000) as i32
+        );
+        let partition = partitioner.partition("test", &record(Some(b"")), 3);
+        assert!((0..3).contains(&partition));
+    }
+}
@@ -0,0 +1,337 @@
+//! Production of the batches of a [`BatchProducer`](super::BatchProducer) with several of them
+//! in flight, sent again in order when they fail.
+
+use crate::{
+    backoff::Backoff,
+    client::{
+        partition::{Compression, PartitionClient, ProducedBatch},
+        producer::{
+            aggregator::StatusDeaggregator,
+            idempotent::{IdempotentProducer, ProducerId},
+            memory::Reservation,
+            Flush, Outcome,
+        },
+        stream::ConnectionStream,
+        transport::BoxedTransport,
+    },
+    error::{Error, Result},
+    protocol::{api_key::ApiKey, record::RecordBatch},
+};
+use chrono::{DateTime, Utc};
+use futures::future::{poll_fn, BoxFuture};
+use std::{collections::VecDeque, sync::Arc, task::Poll};
+use tokio::sync::{mpsc, watch};
+
+/// A flushed batch waiting to be appended, sent until it was or failed for good.
+struct Queued<D> {
+    batch: RecordBatch,
+    /// Timestamps of the records, to acknowledge them to the interceptors
+    timestamps: Vec<DateTime<Utc>>,
+    deaggregator: D,
+    outcome: watch::Sender<Option<Outcome<D>>>,
+    reserved: Reservation,
+    /// Whether the epoch of the idempotent producer was bumped for this batch already
+    bumped: bool,
+}
+
+impl<D: StatusDeaggregator> Queued<D> {
+    fn complete(self, client: &PartitionClient, result: Result<ProducedBatch>) {
+        let result = result.map_err(Arc::new);
+        client.cluster().interceptors().on_acknowledgement(
+            client.topic(),
+            client.partition(),
+            &self.timestamps,
+            result.as_ref().map_err(|e| e.as_ref()),
+        );
+        self.deaggregator.produced(result.as_ref());
+        // the batch was acknowledged or failed, either way its records are done
+        drop(self.reserved);
+        let result = result.map(|produced| (produced.offsets, self.deaggregator));
+        self.outcome.send_replace(Some(Arc::new(result)));
+    }
+}
+
+enum Event<D> {
+    Flush(Option<Flush<D>>),
+    Response(Result<ProducedBatch>),
+}
+
+/// Sends the batches of a partition in order, up to `max_in_flight` of them before the first
+/// one is acknowledged. When a batch fails, the batches sent after it are awaited, and all
+/// that failed are sent again in order before any later batch. With an idempotent producer
+/// the leader rejects every batch after a missing one, so the batches are appended in order.
+/// Without one, a batch sent after a failed one may be appended before it.
+pub(super) struct Pipeline<D> {
+    client: Arc<PartitionClient>,
+    compression: Compression,
+    idempotent: Option<Arc<IdempotentProducer>>,
+    max_in_flight: usize,
+    /// Batches to send, in order
+    queue: VecDeque<Queued<D>>,
+    /// Batches sent with the futures of their outcomes, in order
+    in_flight: VecDeque<(Queued<D>, BoxFuture<'static, Result<ProducedBatch>>)>,
+    /// Batches to send again before the queue, the first one failed
+    retries: VecDeque<Queued<D>>,
+    /// Why the first of the retries failed. Nothing is sent until the batches in flight are
+    /// answered.
+    failure: Option<Error>,
+    /// Reset once a batch was appended
+    backoff: Option<Backoff>,
+    /// All batches in flight are sent on this connection, so they are appended in order
+    connection: Option<Arc<ConnectionStream<BoxedTransport>>>,
+}
+
+impl<D: StatusDeaggregator> Pipeline<D> {
+    pub(super) fn new(
+        client: Arc<PartitionClient>,
+        compression: Compression,
+        idempotent: Option<Arc<IdempotentProducer>>,
+        max_in_flight: usize,
+    ) -> Self {
+        Self {
+            client,
+            compression,
+            idempotent,
+            max_in_flight: max_in_flight.max(1),
+            queue: VecDeque::new(),
+            in_flight: VecDeque::new(),
+            retries: VecDeque::new(),
+            failure: None,
+            backoff: None,
+            connection: None,
+        }
+    }
+
+    /// Produces the flushed batches until the producer is dropped and all of them are done.
+    pub(super) async fn run(mut self, mut flushes: mpsc::UnboundedReceiver<Flush<D>>) {
+        let mut closed = false;
+        loop {
+            self.send_queued().await;
+            if self.in_flight.is_empty() {
+                if let Some(error) = self.failure.take() {
+                    self.retry(error).await;
+                    continue;
+                }
+                if closed && self.queue.is_empty() {
+                    return;
+                }
+            }
+
+            let in_flight = &mut self.in_flight;
+            let event = poll_fn(|cx| {
+                if !closed {
+                    if let Poll::Ready(flush) = flushes.poll_recv(cx) {
+                        return Poll::Ready(Event::Flush(flush));
+                    }
+                }
+                match in_flight.front_mut() {
+                    Some((_, response)) => response.as_mut().poll(cx).map(Event::Response),
+                    None => Poll::Pending,
+                }
+            })
+            .await;
+            match event {
+                Event::Flush(Some(flush)) => self.enqueue(flush),
+                Event::Flush(None) => closed = true,
+                Event::Response(result) => {
+                    let (queued, _) = self.in_flight.pop_front().expect("polled in-flight batch");
+                    match result {
+                        Ok(produced) => {
+                            self.backoff = None;
+                            queued.complete(&self.client, Ok(produced));
+                        }
+                        // sent after a failed batch, it is sent again in order
+                        Err(_) if self.failure.is_some() => self.retries.push_back(queued),
+                        Err(e) => {
+                            if let Some(queued) = self.on_error(queued, e).await {
+                                self.retries.push_back(queued);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn enqueue(&mut self, flush: Flush<D>) {
+        let Flush {
+            batch,
+            outcome,
+            reserved,
+        } = flush;
+        let (mut records, deaggregator) = match batch {
+            Ok(batch) => batch,
+            Err(e) => {
+                drop(reserved);
+                outcome.send_replace(Some(Arc::new(Err(Arc::new(e)))));
+                return;
+            }
+        };
+        self.client.cluster().interceptors().on_send(&mut records);
+        let queued = Queued {
+            timestamps: records.iter().map(|record| record.timestamp).collect(),
+            batch: RecordBatch::new(records, self.compression),
+            deaggregator,
+            outcome,
+            reserved,
+            bumped: false,
+        };
+        if queued.batch.records.is_empty() {
+            queued.complete(&self.client, Ok(ProducedBatch::default()));
+        } else {
+            self.queue.push_back(queued);
+        }
+    }
+
+    /// Sends queued batches until the maximum is in flight or sending one failed.
+    async fn send_queued(&mut self) {
+        while self.failure.is_none() && self.in_flight.len() < self.max_in_flight {
+            let Some(mut queued) = self.queue.pop_front() else {
+                return;
+            };
+            match self.send(&mut queued).await {
+                Ok(response) => self.in_flight.push_back((queued, response)),
+                Err(e) => {
+                    if let Some(queued) = self.on_error(queued, e).await {
+                        self.queue.push_front(queued);
+                    }
+                }
+            }
+        }
+    }
+
+    async fn send(
+        &mut self,
+        queued: &mut Queued<D>,
+    ) -> Result<BoxFuture<'static, Result<ProducedBatch>>> {
+        if let Some(producer) = &self.idempotent {
+            producer.sequence(&self.client, &mut queued.batch).await?;
+        }
+        let connection = match &self.connection {
+            Some(connection) if !self.in_flight.is_empty() => connection.clone(),
+            // nothing to keep the order with, follow the leader and the pool
+            _ => {
+                let connection = self.client.produce_connection().await?;
+                self.connection = Some(connection.clone());
+                connection
+            }
+        };
+        let transactional_id = self
+            .idempotent
+            .as_ref()
+            .and_then(|producer| producer.transactional_id());
+        self.client
+            .start_produce_batch(&connection, &queued.batch, transactional_id)
+            .await
+    }
+
+    /// Returns the batch if it is sent again, otherwise fails it.
+    async fn on_error(&mut self, mut queued: Queued<D>, error: Error) -> Option<Queued<D>> {
+        self.client.invalidate_metadata_on(&error);
+        let retry = if self.client.is_retriable(&error) {
+            Ok(true)
+        } else if let Some(producer) = &self.idempotent {
+            let used = ProducerId {
+                id: queued.batch.producer_id,
+                epoch: queued.batch.producer_epoch,
+            };
+            producer.recover(used, &error, &mut queued.bumped).await
+        } else {
+            Ok(false)
+        };
+        match retry {
+            Ok(true) => {
+                self.connection = None;
+                self.failure = Some(error);
+                Some(queued)
+            }
+            Ok(false) => {
+                queued.complete(&self.client, Err(error));
+                None
+            }
+            Err(e) => {
+                queued.complete(&self.client, Err(e));
+                None
+            }
+        }
+    }
+
+    /// Queues the retries before all other batches and waits before they are sent. Once the
+    /// deadline of the backoff is exceeded, the first one fails for good.
+    async fn retry(&mut self, error: Error) {
+        let mut retries = std::mem::take(&mut self.retries);
+        retries.append(&mut self.queue);
+        self.queue = retries;
+
+        let name = format!(
+            "{} {}-{}",
+            ApiKey::Produce,
+            self.client.topic(),
+            self.client.partition()
+        );
+        let backoff = self
+            .backoff
+            .get_or_insert_with(|| Backoff::new(self.client.cluster().backoff_config()));
+        match backoff.backoff(&name, error) {
+            Ok(delay) => tokio::time::sleep(delay).await,
+            Err(source) => {
+                if let Some(queued) = self.queue.pop_front() {
+                    queued.complete(&self.client, Err(Error::Connection { source }));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{
+        mock::{record, MockCluster},
+        partition::UnknownTopicHandling,
+        producer::BatchProducerBuilder,
+    };
+    use futures::StreamExt;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_failed_batch_is_sent_again_before_later_ones() {
+        let mock = MockCluster::new(1);
+        mock.add_topic("test", 1);
+        let client = mock.client().await;
+        let partition = client
+            .partition_client("test", 0, UnknownTopicHandling::Error)
+            .await
+            .unwrap();
+        let idempotent = Arc::new(client.idempotent_producer());
+        idempotent.producer_id().await.unwrap();
+        let (producer, reports) = BatchProducerBuilder::new(Arc::new(partition))
+            .with_linger(Duration::from_secs(3600))
+            .with_idempotence(idempotent)
+            .with_max_in_flight(5)
+            .build_with_delivery_reports(1);
+
+        // the first batch fails while the later ones are in flight, which the leader rejects
+        // for the missing sequence
+        mock.state.lock().produce_errors.push_back(6);
+        for value in 0..4 {
+            producer
+                .send(record(&value.to_string()), value)
+                .await
+                .unwrap();
+        }
+        drop(producer);
+
+        let reports: Vec<_> = reports
+            .map(|(value, report)| (value, report.unwrap().offset))
+            .collect()
+            .await;
+        assert_eq!(reports, vec![(0, 0), (1, 1), (2, 2), (3, 3)]);
+        let state = mock.state.lock();
+        let batches = &state.topics[0].partitions[0].batches;
+        // every batch was appended once, with its value at the end
+        let values: Vec<_> = batches.iter().map(|batch| batch[batch.len() - 2]).collect();
+        assert_eq!(values, b"0123");
+        assert!(state.produce_errors.is_empty());
+    }
+}
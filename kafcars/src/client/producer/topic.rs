@@ -0,0 +1,286 @@
+//! Production to all partitions of a topic, routed by a [`Partitioner`].
+
+use crate::{
+    client::{
+        cluster::Cluster,
+        partition::{Acks, Compression, PartitionClient, UnknownTopicHandling},
+        producer::{
+            aggregator::RecordAggregator,
+            idempotent::IdempotentProducer,
+            partitioner::{DefaultPartitioner, Partitioner},
+            BatchProducer, BatchProducerBuilder, DEFAULT_LINGER, DEFAULT_MAX_IN_FLIGHT,
+        },
+    },
+    error::{PartitionOutOfRangeSnafu, Result},
+    record::Record,
+};
+use futures::future::try_join_all;
+use parking_lot::Mutex;
+use snafu::ensure;
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+/// Default maximum size of a batch, like `batch.size` of the Java client
+const DEFAULT_MAX_BATCH_SIZE: usize = 16 * 1024;
+
+pub struct TopicProducerBuilder {
+    cluster: Arc<Cluster>,
+    topic: String,
+    partitioner: Arc<dyn Partitioner>,
+    max_batch_size: usize,
+    linger: Duration,
+    compression: Compression,
+    acks: Acks,
+    request_timeout: Option<Duration>,
+    idempotent: Option<Arc<IdempotentProducer>>,
+    max_in_flight: usize,
+}
+
+impl TopicProducerBuilder {
+    pub(crate) fn new(cluster: Arc<Cluster>, topic: String) -> Self {
+        Self {
+            cluster,
+            topic,
+            partitioner: Arc::new(DefaultPartitioner::default()),
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            linger: DEFAULT_LINGER,
+            compression: Compression::default(),
+            acks: Acks::default(),
+            request_timeout: None,
+            idempotent: None,
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+        }
+    }
+
+    /// Routes the records with `partitioner` instead of the [`DefaultPartitioner`].
+    pub fn with_partitioner<P: Partitioner>(mut self, partitioner: P) -> Self {
+        self.partitioner = Arc::new(partitioner);
+        self
+    }
+
+    /// Maximum total [`Record::approximate_size`] of a batch.
+    pub fn with_max_batch_size(mut self, max_batch_size: usize) -> Self {
+        self.max_batch_size = max_batch_size;
+        self
+    }
+
+    /// Time a batch waits for more records after its first one before it is produced.
+    pub fn with_linger(mut self, linger: Duration) -> Self {
+        self.linger = linger;
+        self
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// The replicas produced batches wait for, see [`PartitionClient::with_acks`].
+    pub fn with_acks(mut self, acks: Acks) -> Self {
+        self.acks = acks;
+        self
+    }
+
+    /// See [`PartitionClient::with_request_timeout`].
+    pub fn with_request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = Some(request_timeout);
+        self
+    }
+
+    /// Produces the batches through `producer`, so retries never duplicate them.
+    pub fn with_idempotence(mut self, producer: Arc<IdempotentProducer>) -> Self {
+        self.idempotent = Some(producer);
+        self
+    }
+
+    /// See [`BatchProducerBuilder::with_max_in_flight`].
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.max_in_flight = max_in_flight;
+        self
+    }
+
+    pub fn build(self) -> TopicProducer {
+        TopicProducer {
+            config: self,
+            producers: Default::default(),
+        }
+    }
+}
+
+/// Produces records to the partitions of a topic, each batched by a [`BatchProducer`] of its
+/// own. The partition count is taken from the cached metadata, so records are routed to new
+/// partitions once the metadata is refreshed.
+pub struct TopicProducer {
+    config: TopicProducerBuilder,
+    /// Producers by partition, created on their first record
+    producers: Mutex<HashMap<i32, Arc<BatchProducer<RecordAggregator>>>>,
+}
+
+impl TopicProducer {
+    pub fn topic(&self) -> &str {
+        &self.config.topic
+    }
+
+    /// Adds `record` to the batch of the partition chosen by the partitioner and resolves to
+    /// the partition and offset once the batch was produced.
+    pub async fn produce(&self, record: Record) -> Result<(i32, i64)> {
+        let partitions = self.config.cluster.partition_count(self.topic()).await?;
+        let mut partition = self.partition(&record, partitions)?;
+        let mut producer = self.producer(partition).await?;
+        if producer.is_batch_empty() {
+            let next =
+                self.config
+                    .partitioner
+                    .on_new_batch(self.topic(), &record, partitions, partition);
+            if let Some(next) = next.filter(|next| *next != partition) {
+                partition = self.check_range(next, partitions)?;
+                producer = self.producer(partition).await?;
+            }
+        }
+        let offset = producer.produce(record).await?;
+        Ok((partition, offset))
+    }
+
+    /// Produces the current batches of all partitions without waiting for the linger to expire.
+    pub async fn flush(&self) -> Result<()> {
+        let producers: Vec<_> = self.producers.lock().values().cloned().collect();
+        try_join_all(producers.iter().map(|producer| producer.flush())).await?;
+        Ok(())
+    }
+
+    fn partition(&self, record: &Record, partitions: i32) -> Result<i32> {
+        let partition = self
+            .config
+            .partitioner
+            .partition(self.topic(), record, partitions);
+        self.check_range(partition, partitions)
+    }
+
+    fn check_range(&self, partition: i32, partitions: i32) -> Result<i32> {
+        ensure!(
+            (0..partitions).contains(&partition),
+            PartitionOutOfRangeSnafu {
+                topic: self.topic(),
+                partition,
+                partitions,
+            }
+        );
+        Ok(partition)
+    }
+
+    async fn producer(&self, partition: i32) -> Result<Arc<BatchProducer<RecordAggregator>>> {
+        if let Some(producer) = self.producers.lock().get(&partition) {
+            return Ok(producer.clone());
+        }
+
+        let mut client = PartitionClient::new(
+            self.config.cluster.clone(),
+            self.config.topic.clone(),
+            partition,
+            UnknownTopicHandling::Retry,
+        )
+        .await?
+        .with_acks(self.config.acks);
+        if let Some(request_timeout) = self.config.request_timeout {
+            client = client.with_request_timeout(request_timeout);
+        }
+        let mut builder = BatchProducerBuilder::new(Arc::new(client))
+            .with_linger(self.config.linger)
+            .with_compression(self.config.compression)
+            .with_max_in_flight(self.config.max_in_flight);
+        if let Some(idempotent) = &self.config.idempotent {
+            builder = builder.with_idempotence(idempotent.clone());
+        }
+        let producer = Arc::new(builder.build(RecordAggregator::new(self.config.max_batch_size)));
+        // keeps the producer of a concurrent first record of the partition
+        let mut producers = self.producers.lock();
+        Ok(producers.entry(partition).or_insert(producer).clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{
+        mock::{record, MockCluster},
+        producer::partitioner::{murmur2, RoundRobinPartitioner},
+    };
+
+    fn keyed_record(key: &str) -> Record {
+        Record {
+            key: Some(key.as_bytes().to_vec()),
+            ..record("value")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keyed_records_follow_murmur2() {
+        let mock = MockCluster::new(2);
+        mock.add_topic("test", 3);
+        let client = mock.client().await;
+        let producer = client
+            .topic_producer("test")
+            .with_linger(Duration::ZERO)
+            .build();
+
+        for key in ["a", "b", "c", "d"] {
+            let (partition, _) = producer.produce(keyed_record(key)).await.unwrap();
+            assert_eq!(partition, (murmur2(key.as_bytes()) & 0x7fff_ffff) % 3);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keyless_records_stick_to_batch() {
+        let mock = MockCluster::new(1);
+        mock.add_topic("test", 3);
+        let client = mock.client().await;
+        let producer = Arc::new(
+            client
+                .topic_producer("test")
+                .with_linger(Duration::from_secs(3600))
+                .build(),
+        );
+
+        let mut produced = vec![];
+        for _ in 0..3 {
+            let producer = producer.clone();
+            produced.push(tokio::spawn(async move {
+                producer.produce(record("value")).await
+            }));
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        producer.flush().await.unwrap();
+
+        let mut results = vec![];
+        for produced in produced {
+            results.push(produced.await.unwrap().unwrap());
+        }
+        let partition = results[0].0;
+        assert_eq!(
+            results,
+            vec![(partition, 0), (partition, 1), (partition, 2)]
+        );
+        let state = mock.state.lock();
+        assert_eq!(
+            state.topics[0].partitions[partition as usize].batches.len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_custom_partitioner() {
+        let mock = MockCluster::new(1);
+        mock.add_topic("test", 3);
+        let client = mock.client().await;
+        let producer = client
+            .topic_producer("test")
+            .with_partitioner(RoundRobinPartitioner::default())
+            .with_linger(Duration::ZERO)
+            .build();
+
+        let mut partitions = vec![];
+        for _ in 0..4 {
+            partitions.push(producer.produce(keyed_record("key")).await.unwrap().0);
+        }
+        assert_eq!(partitions, vec![0, 1, 2, 0]);
+    }
+}
@@ -0,0 +1,870 @@
+//! Transactions over produced records and consumed offsets, committed or aborted atomically.
+
+use crate::{
+    backoff::Backoff,
+    client::{
+        cluster::{Broker, Cluster},
+        partition::{Compression, PartitionClient},
+        pool::RequestClass,
+        producer::idempotent::{IdempotentProducer, ProducerId},
+    },
+    error::{
+        ConnectionSnafu, Error, InvalidTransactionStateSnafu, ProducerFencedSnafu, Result,
+        ServerSnafu,
+    },
+    protocol::{
+        deserializer::DeserializeVersioned,
+        error::Error as ProtocolError,
+        messages::{
+            add_offsets_to_txn::{AddOffsetsToTxnRequest, AddOffsetsToTxnResponse},
+            add_partitions_to_txn::{
+                AddPartitionsToTxnRequest, AddPartitionsToTxnRequestTopic,
+                AddPartitionsToTxnResponse,
+            },
+            end_txn::{EndTxnRequest, EndTxnResponse},
+            find_coordinator::CoordinatorType,
+            init_producer_id::{InitProducerIdRequest, InitProducerIdResponse},
+            txn_offset_commit::{
+                TxnOffsetCommitRequest, TxnOffsetCommitRequestPartition,
+                TxnOffsetCommitRequestTopic, TxnOffsetCommitResponse,
+            },
+            KafkaRequest, KafkaResponse, TaggedFields,
+        },
+        serializer::SerializeVersioned,
+    },
+    record::Record,
+};
+use snafu::{ensure, ResultExt};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io::Cursor,
+    ops::ControlFlow,
+    sync::Arc,
+    time::Duration,
+};
+use tokio::sync::watch;
+
+/// Identifies the consumer whose offsets a transaction commits, so the group coordinator
+/// rejects them once the consumer left the group.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsumerGroupMetadata {
+    pub group_id: String,
+    pub generation_id: i32,
+    pub member_id: String,
+    pub group_instance_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionState {
+    /// No transaction is open
+    Ready,
+    InTransaction,
+    /// A request of the open transaction failed, it can only be aborted
+    Abortable,
+    /// The open transaction is being committed or aborted
+    Ending,
+    /// A newer producer with the same transactional id took over, this one cannot be used
+    /// anymore
+    Fenced,
+}
+
+#[derive(Debug)]
+struct Transaction {
+    state: TransactionState,
+    /// Partitions added to the open transaction
+    partitions: HashSet<(String, i32)>,
+    /// Whether the coordinator knows about the open transaction
+    started: bool,
+    /// Whether a request of the transaction failed, tracked apart from the state while the
+    /// transaction is ending
+    failed: bool,
+}
+
+/// Produces records in transactions: the records of a transaction, and the consumed offsets
+/// sent with it, become visible to read-committed consumers together once it is committed.
+/// Only one instance per transactional id can be used, creating another one fences this one.
+pub struct TransactionalProducer {
+    cluster: Arc<Cluster>,
+    transactional_id: String,
+    transaction_timeout: Duration,
+    producer: IdempotentProducer,
+    transaction: parking_lot::Mutex<Transaction>,
+    /// Number of requests of the open transaction in flight, it only ends once they finished
+    requests: watch::Sender<usize>,
+    /// Known coordinators by key and type
+    coordinators: parking_lot::Mutex<HashMap<(String, CoordinatorType), Broker>>,
+}
+
+impl TransactionalProducer {
+    /// Registers `transactional_id` with its coordinator, which aborts a transaction a previous
+    /// instance left open and fences that instance.
+    pub(crate) async fn new(
+        cluster: Arc<Cluster>,
+        transactional_id: String,
+        transaction_timeout: Duration,
+    ) -> Result<Self> {
+        let producer = Self {
+            producer: IdempotentProducer::transactional(cluster.clone(), transactional_id.clone()),
+            cluster,
+            transactional_id,
+            transaction_timeout,
+            transaction: parking_lot::Mutex::new(Transaction {
+                state: TransactionState::Ready,
+                partitions: HashSet::new(),
+                started: false,
+                failed: false,
+            }),
+            requests: watch::Sender::new(0),
+            coordinators: Default::default(),
+        };
+        let producer_id = producer.init_producer_id(None).await?;
+        producer.producer.set_producer_id(producer_id).await;
+        Ok(producer)
+    }
+
+    pub fn transactional_id(&self) -> &str {
+        &self.transactional_id
+    }
+
+    pub fn state(&self) -> TransactionState {
+        self.transaction.lock().state
+    }
+
+    pub fn begin_transaction(&self) -> Result<()> {
+        let mut transaction = self.transaction.lock();
+        self.ensure_state(
+            &transaction,
+            &[TransactionState::Ready],
+            "begin a transaction",
+        )?;
+        transaction.state = TransactionState::InTransaction;
+        Ok(())
+    }
+
+    /// Produces `records` as a single batch to the partition of `client` within the open
+    /// transaction and returns their offsets.
+    pub async fn produce(
+        &self,
+        client: &PartitionClient,
+        records: Vec<Record>,
+        compression: Compression,
+    ) -> Result<Vec<i64>> {
+        let _request = self.start_request("produce")?;
+        let partition = (client.topic().to_string(), client.partition());
+        if !self.transaction.lock().partitions.contains(&partition) {
+            let result = self.add_partition(&partition).await;
+            self.track(result)?;
+            let mut transaction = self.transaction.lock();
+            transaction.partitions.insert(partition);
+            transaction.started = true;
+        }
+        let result = self.producer.produce(client, records, compression).await;
+        self.track(result)
+    }
+
+    /// Commits the consumed `offsets`, the next offset to consume by topic and partition, as
+    /// part of the open transaction.
+    pub async fn send_offsets_to_transaction(
+        &self,
+        offsets: &BTreeMap<(String, i32), i64>,
+        group_metadata: &ConsumerGroupMetadata,
+    ) -> Result<()> {
+        let _request = self.start_request("send offsets")?;
+        let result = self.send_offsets(offsets, group_metadata).await;
+        self.track(result)
+    }
+
+    pub async fn commit_transaction(&self) -> Result<()> {
+        self.end_transaction(true).await
+    }
+
+    /// Aborts the open transaction, also after one of its requests failed.
+    pub async fn abort_transaction(&self) -> Result<()> {
+        self.end_transaction(false).await
+    }
+
+    async fn end_transaction(&self, committed: bool) -> Result<()> {
+        let (operation, allowed): (_, &[_]) = if committed {
+            ("commit the transaction", &[TransactionState::InTransaction])
+        } else {
+            (
+                "abort the transaction",
+                &[TransactionState::InTransaction, TransactionState::Abortable],
+            )
+        };
+        {
+            let mut transaction = self.transaction.lock();
+            self.ensure_state(&transaction, allowed, operation)?;
+            transaction.failed = transaction.state == TransactionState::Abortable;
+            // claimed under the same lock as the check, so concurrent calls are rejected
+            transaction.state = TransactionState::Ending;
+        }
+        let _attempt = EndAttempt {
+            transaction: &self.transaction,
+        };
+
+        // the requests still in flight are part of the transaction, and may fail it
+        let _ = self
+            .requests
+            .subscribe()
+            .wait_for(|requests| *requests == 0)
+            .await;
+        let (started, abortable) = {
+            let transaction = self.transaction.lock();
+            self.ensure_state(&transaction, &[TransactionState::Ending], operation)?;
+            ensure!(
+                !(committed && transaction.failed),
+                InvalidTransactionStateSnafu {
+                    operation,
+                    state: TransactionState::Abortable,
+                }
+            );
+            (transaction.started, transaction.failed)
+        };
+
+        if started {
+            let result = self.send_end_txn(committed).await;
+            let response = self.track(result)?;
+            if let (Some(id), Some(epoch)) = (response.producer_id, response.producer_epoch) {
+                // KIP-890: the coordinator bumps the epoch when a transaction ends
+                self.producer
+                    .set_producer_id(ProducerId { id, epoch })
+                    .await;
+            } else if abortable {
+                // KIP-360: a new epoch resets the sequences, whatever state the failed
+                // requests left them in
+                let result = self.producer.producer_id().await;
+                let current = self.track(result)?;
+                let result = self.init_producer_id(Some(current)).await;
+                let producer_id = self.track(result)?;
+                self.producer.set_producer_id(producer_id).await;
+            }
+        }
+
+        let mut transaction = self.transaction.lock();
+        transaction.state = TransactionState::Ready;
+        transaction.partitions.clear();
+        transaction.started = false;
+        transaction.failed = false;
+        Ok(())
+    }
+
+    async fn add_partition(&self, (topic, partition): &(String, i32)) -> Result<()> {
+        let producer_id = self.producer.producer_id().await?;
+        self.send_to_coordinator::<_, _, AddPartitionsToTxnResponse>(
+            &self.transactional_id,
+            CoordinatorType::Transaction,
+            || AddPartitionsToTxnRequest {
+                transactional_id: self.transactional_id.clone(),
+                producer_id: producer_id.id,
+                producer_epoch: producer_id.epoch,
+                topics: vec![AddPartitionsToTxnRequestTopic {
+                    name: topic.clone(),
+                    partitions: vec![*partition],
+                    tagged_fields: Some(TaggedFields::default()),
+                }],
+                tagged_fields: Some(TaggedFields::default()),
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn send_offsets(
+        &self,
+        offsets: &BTreeMap<(String, i32), i64>,
+        group_metadata: &ConsumerGroupMetadata,
+    ) -> Result<()> {
+        let producer_id = self.producer.producer_id().await?;
+        self.send_to_coordinator::<_, _, AddOffsetsToTxnResponse>(
+            &self.transactional_id,
+            CoordinatorType::Transaction,
+            || AddOffsetsToTxnRequest {
+                transactional_id: self.transactional_id.clone(),
+                producer_id: producer_id.id,
+                producer_epoch: producer_id.epoch,
+                group_id: group_metadata.group_id.clone(),
+                tagged_fields: Some(TaggedFields::default()),
+            },
+        )
+        .await?;
+        self.transaction.lock().started = true;
+
+        let mut topics: BTreeMap<&str, Vec<(i32, i64)>> = BTreeMap::new();
+        for ((topic, partition), offset) in offsets {
+            topics.entry(topic).or_default().push((*partition, *offset));
+        }
+        self.send_to_coordinator::<_, _, TxnOffsetCommitResponse>(
+            &group_metadata.group_id,
+            CoordinatorType::Group,
+            || TxnOffsetCommitRequest {
+                transactional_id: self.transactional_id.clone(),
+                group_id: group_metadata.group_id.clone(),
+                producer_id: producer_id.id,
+                producer_epoch: producer_id.epoch,
+                generation_id: group_metadata.generation_id,
+                member_id: group_metadata.member_id.clone(),
+                group_instance_id: group_metadata.group_instance_id.clone(),
+                topics: topics
+                    .iter()
+                    .map(|(topic, partitions)| TxnOffsetCommitRequestTopic {
+                        name: topic.to_string(),
+                        partitions: partitions
+                            .iter()
+                            .map(|(partition, offset)| TxnOffsetCommitRequestPartition {
+                                partition_index: *partition,
+                                committed_offset: *offset,
+                                committed_leader_epoch: -1,
+                                committed_metadata: None,
+                                tagged_fields: Some(TaggedFields::default()),
+                            })
+                            .collect(),
+                        tagged_fields: Some(TaggedFields::default()),
+                    })
+                    .collect(),
+                tagged_fields: Some(TaggedFields::default()),
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn send_end_txn(&self, committed: bool) -> Result<EndTxnResponse> {
+        let producer_id = self.producer.producer_id().await?;
+        self.send_to_coordinator(&self.transactional_id, CoordinatorType::Transaction, || {
+            EndTxnRequest {
+                transactional_id: self.transactional_id.clone(),
+                producer_id: producer_id.id,
+                producer_epoch: producer_id.epoch,
+                committed,
+                tagged_fields: Some(TaggedFields::default()),
+            }
+        })
+        .await
+    }
+
+    /// Requests the producer id of the transactional id, or a new epoch of `current`.
+    async fn init_producer_id(&self, current: Option<ProducerId>) -> Result<ProducerId> {
+        let response: InitProducerIdResponse = self
+            .send_to_coordinator(&self.transactional_id, CoordinatorType::Transaction, || {
+                InitProducerIdRequest {
+                    transactional_id: Some(self.transactional_id.clone()),
+                    transaction_timeout_ms: i32::try_from(self.transaction_timeout.as_millis())
+                        .unwrap_or(i32::MAX),
+                    producer_id: current.map_or(-1, |current| current.id),
+                    producer_epoch: current.map_or(-1, |current| current.epoch),
+                    tagged_fields: Some(TaggedFields::default()),
+                }
+            })
+            .await?;
+        Ok(ProducerId {
+            id: response.producer_id,
+            epoch: response.producer_epoch,
+        })
+    }
+
+    /// Sends the request built by `request` to the coordinator of `key`, finding the
+    /// coordinator again when it moved.
+    async fn send_to_coordinator<R, F, T>(
+        &self,
+        key: &str,
+        key_type: CoordinatorType,
+        request: F,
+    ) -> Result<T>
+    where
+        F: Fn() -> R,
+        R: KafkaRequest<KafkaResponse = T> + SerializeVersioned<Vec<u8>>,
+        T: KafkaResponse + CoordinatorError + DeserializeVersioned<Cursor<Vec<u8>>>,
+    {
+        let cache_key = (key.to_string(), key_type);
+        let mut backoff = Backoff::new(self.cluster.backoff_config());
+        backoff
+            .retry_with_backoff(&format!("{} to coordinator", R::API_KEY), || async {
+                let result = async {
+                    let cached = self.coordinators.lock().get(&cache_key).cloned();
+                    let broker = match cached {
+                        Some(broker) => broker,
+                        None => {
+                            let broker = self.cluster.coordinator(key, key_type).await?;
+                            self.coordinators
+                                .lock()
+                                .insert(cache_key.clone(), broker.clone());
+                            broker
+                        }
+                    };
+                    let response = broker
+                        .get(RequestClass::Admin)
+                        .await?
+                        .send_request(request())
+                        .await?;
+                    match response.error() {
+                        Some(error) => ServerSnafu {
+                            api_key: R::API_KEY,
+                            error,
+                        }
+                        .fail(),
+                        None => Ok(response),
+                    }
+                }
+                .await;
+
+                match result {
+                    Ok(response) => ControlFlow::Break(Ok(response)),
+                    Err(e @ Error::Server { error, .. }) if error.is_retriable() => {
+                        if matches!(
+                            error,
+                            ProtocolError::NotCoordinator | ProtocolError::CoordinatorNotAvailable
+                        ) {
+                            self.coordinators.lock().remove(&cache_key);
+                        }
+                        ControlFlow::Continue(e)
+                    }
+                    Err(Error::Request { source }) if source.is_transient() => {
+                        self.coordinators.lock().remove(&cache_key);
+                        ControlFlow::Continue(Error::Request { source })
+                    }
+                    Err(e) => ControlFlow::Break(Err(e)),
+                }
+            })
+            .await
+            .context(ConnectionSnafu)?
+    }
+
+    /// Checks that a transaction is open and keeps it from ending until the returned request
+    /// is dropped.
+    fn start_request(&self, operation: &'static str) -> Result<TransactionRequest<'_>> {
+        let transaction = self.transaction.lock();
+        self.ensure_state(&transaction, &[TransactionState::InTransaction], operation)?;
+        self.requests.send_modify(|requests| *requests += 1);
+        Ok(TransactionRequest {
+            requests: &self.requests,
+        })
+    }
+
+    fn ensure_state(
+        &self,
+        transaction: &Transaction,
+        allowed: &[TransactionState],
+        operation: &'static str,
+    ) -> Result<()> {
+        ensure!(
+            transaction.state != TransactionState::Fenced,
+            ProducerFencedSnafu {
+                transactional_id: &self.transactional_id,
+            }
+        );
+        ensure!(
+            allowed.contains(&transaction.state),
+            InvalidTransactionStateSnafu {
+                operation,
+                state: transaction.state,
+            }
+        );
+        Ok(())
+    }
+
+    /// Moves to [`TransactionState::Fenced`] or [`TransactionState::Abortable`] when a request
+    /// of the transaction failed.
+    fn track<T>(&self, result: Result<T>) -> Result<T> {
+        let Err(error) = result else {
+            return result;
+        };
+        let mut transaction = self.transaction.lock();
+        match error {
+            Error::Server {
+                error:
+                    ProtocolError::ProducerFenced
+                    | ProtocolError::InvalidProducerEpoch
+                    | ProtocolError::TransactionCoordinatorFenced,
+                ..
+            } => {
+                transaction.state = TransactionState::Fenced;
+                ProducerFencedSnafu {
+                    transactional_id: &self.transactional_id,
+                }
+                .fail()
+            }
+            error => {
+                match transaction.state {
+                    TransactionState::InTransaction => {
+                        transaction.state = TransactionState::Abortable
+                    }
+                    // fails the commit waiting for this request
+                    TransactionState::Ending => transaction.failed = true,
+                    _ => {}
+                }
+                Err(error)
+            }
+        }
+    }
+}
+
+/// A request of the open transaction in flight.
+struct TransactionRequest<'a> {
+    requests: &'a watch::Sender<usize>,
+}
+
+impl Drop for TransactionRequest<'_> {
+    fn drop(&mut self) {
+        self.requests.send_modify(|requests| *requests -= 1);
+    }
+}
+
+/// Makes the transaction abortable again when ending it failed or was cancelled.
+struct EndAttempt<'a> {
+    transaction: &'a parking_lot::Mutex<Transaction>,
+}
+
+impl Drop for EndAttempt<'_> {
+    fn drop(&mut self) {
+        let mut transaction = self.transaction.lock();
+        if transaction.state == TransactionState::Ending {
+            transaction.state = TransactionState::Abortable;
+        }
+    }
+}
+
+/// Responses of the coordinator carrying an error, the first one for batched requests.
+pub(crate) trait CoordinatorError {
+    fn error(&self) -> Option<ProtocolError>;
+}
+
+impl CoordinatorError for InitProducerIdResponse {
+    fn error(&self) -> Option<ProtocolError> {
+        self.error
+    }
+}
+
+impl CoordinatorError for AddPartitionsToTxnResponse {
+    fn error(&self) -> Option<ProtocolError> {
+        self.results
+            .iter()
+            .flat_map(|topic| &topic.results)
+            .find_map(|partition| partition.error)
+    }
+}
+
+impl CoordinatorError for AddOffsetsToTxnResponse {
+    fn error(&self) -> Option<ProtocolError> {
+        self.error
+    }
+}
+
+impl CoordinatorError for EndTxnResponse {
+    fn error(&self) -> Option<ProtocolError> {
+        self.error
+    }
+}
+
+impl CoordinatorError for TxnOffsetCommitResponse {
+    fn error(&self) -> Option<ProtocolError> {
+        self.topics
+            .iter()
+            .flat_map(|topic| &topic.partitions)
+            .find_map(|partition| partition.error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{
+        mock::{record, MockCluster},
+        partition::UnknownTopicHandling,
+    };
+    use assert_matches::assert_matches;
+
+    fn group_metadata() -> ConsumerGroupMetadata {
+        ConsumerGroupMetadata {
+            group_id: "group".to_string(),
+            generation_id: 1,
+            member_id: "member".to_string(),
+            group_instance_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_commit_transaction() {
+        let mock = MockCluster::new(2);
+        mock.add_topic("test", 1);
+        let client = mock.client().await;
+        let partition = client
+            .partition_client("test", 0, UnknownTopicHandling::Error)
+            .await
+            .unwrap();
+        let producer = client
+            .transactional_producer("txn", Duration::from_secs(10))
+            .await
+            .unwrap();
+        let first = producer.producer.producer_id().await.unwrap();
+
+        producer.begin_transaction().unwrap();
+        let offsets = producer
+            .produce(
+                &partition,
+                vec![record("value")],
+                Compression::NoCompression,
+            )
+            .await
+            .unwrap();
+        assert_eq!(offsets, vec![0]);
+        let consumed = BTreeMap::from([(("input".to_string(), 0), 42)]);
+        producer
+            .send_offsets_to_transaction(&consumed, &group_metadata())
+            .await
+            .unwrap();
+        producer.commit_transaction().await.unwrap();
+        assert_eq!(producer.state(), TransactionState::Ready);
+
+        {
+            let state = mock.state.lock();
+            assert_eq!(state.ended_transactions, vec![("txn".to_string(), true)]);
+            let key = ("group".to_string(), "input".to_string(), 0);
+            assert_eq!(state.committed_offsets.get(&key), Some(&42));
+        }
+        // the coordinator bumped the epoch, sequences start over under it
+        let second = producer.producer.producer_id().await.unwrap();
+        assert_eq!(second.id, first.id);
+        assert_eq!(second.epoch, first.epoch + 1);
+        producer.begin_transaction().unwrap();
+        let offsets = producer
+            .produce(
+                &partition,
+                vec![record("value")],
+                Compression::NoCompression,
+            )
+            .await
+            .unwrap();
+        assert_eq!(offsets, vec![1]);
+    }
+
+    #[tokio::test]
+    async fn test_abort_transaction() {
+        let mock = MockCluster::new(1);
+        mock.add_topic("test", 1);
+        let client = mock.client().await;
+        let partition = client
+            .partition_client("test", 0, UnknownTopicHandling::Error)
+            .await
+            .unwrap();
+        let producer = client
+            .transactional_producer("txn", Duration::from_secs(10))
+            .await
+            .unwrap();
+
+        producer.begin_transaction().unwrap();
+        producer
+            .produce(
+                &partition,
+                vec![record("value")],
+                Compression::NoCompression,
+            )
+            .await
+            .unwrap();
+        let consumed = BTreeMap::from([(("input".to_string(), 0), 42)]);
+        producer
+            .send_offsets_to_transaction(&consumed, &group_metadata())
+            .await
+            .unwrap();
+        producer.abort_transaction().await.unwrap();
+
+        let state = mock.state.lock();
+        assert_eq!(state.ended_transactions, vec![("txn".to_string(), false)]);
+        assert!(state.committed_offsets.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_end_is_rejected() {
+        let mock = MockCluster::new(1);
+        mock.add_topic("test", 1);
+        let client = mock.client().await;
+        let partition = client
+            .partition_client("test", 0, UnknownTopicHandling::Error)
+            .await
+            .unwrap();
+        let producer = client
+            .transactional_producer("txn", Duration::from_secs(10))
+            .await
+            .unwrap();
+        producer.begin_transaction().unwrap();
+        producer
+            .produce(
+                &partition,
+                vec![record("value")],
+                Compression::NoCompression,
+            )
+            .await
+            .unwrap();
+
+        let commit = producer.commit_transaction();
+        tokio::pin!(commit);
+        assert!(futures::poll!(&mut commit).is_pending());
+        assert_eq!(producer.state(), TransactionState::Ending);
+        assert_matches!(
+            producer.abort_transaction().await,
+            Err(Error::InvalidTransactionState {
+                state: TransactionState::Ending,
+                ..
+            })
+        );
+        assert_matches!(
+            producer.begin_transaction(),
+            Err(Error::InvalidTransactionState {
+                state: TransactionState::Ending,
+                ..
+            })
+        );
+        commit.await.unwrap();
+        assert_eq!(producer.state(), TransactionState::Ready);
+        assert_eq!(
+            mock.state.lock().ended_transactions,
+            vec![("txn".to_string(), true)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_commit_waits_for_requests() {
+        let mock = MockCluster::new(1);
+        mock.add_topic("test", 1);
+        let client = mock.client().await;
+        let partition = client
+            .partition_client("test", 0, UnknownTopicHandling::Error)
+            .await
+            .unwrap();
+        let producer = client
+            .transactional_producer("txn", Duration::from_secs(10))
+            .await
+            .unwrap();
+        producer.begin_transaction().unwrap();
+
+        // the first produce of the transaction is still adding the partition
+        let produce = producer.produce(
+            &partition,
+            vec![record("value")],
+            Compression::NoCompression,
+        );
+        tokio::pin!(produce);
+        assert!(futures::poll!(&mut produce).is_pending());
+        let commit = producer.commit_transaction();
+        tokio::pin!(commit);
+        assert!(futures::poll!(&mut commit).is_pending());
+        assert_matches!(
+            producer
+                .produce(
+                    &partition,
+                    vec![record("value")],
+                    Compression::NoCompression,
+                )
+                .await,
+            Err(Error::InvalidTransactionState {
+                state: TransactionState::Ending,
+                ..
+            })
+        );
+
+        let (produced, committed) = tokio::join!(produce, commit);
+        assert_eq!(produced.unwrap(), vec![0]);
+        committed.unwrap();
+        assert_eq!(producer.state(), TransactionState::Ready);
+        assert_eq!(
+            mock.state.lock().ended_transactions,
+            vec![("txn".to_string(), true)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_commit_is_abortable() {
+        let mock = MockCluster::new(1);
+        mock.add_topic("test", 1);
+        let client = mock.client().await;
+        let partition = client
+            .partition_client("test", 0, UnknownTopicHandling::Error)
+            .await
+            .unwrap();
+        let producer = client
+            .transactional_producer("txn", Duration::from_secs(10))
+            .await
+            .unwrap();
+        producer.begin_transaction().unwrap();
+        producer
+            .produce(
+                &partition,
+                vec![record("value")],
+                Compression::NoCompression,
+            )
+            .await
+            .unwrap();
+
+        {
+            let commit = producer.commit_transaction();
+            tokio::pin!(commit);
+            assert!(futures::poll!(&mut commit).is_pending());
+        }
+        // the outcome of the dropped commit is unknown, it must not block the producer
+        assert_eq!(producer.state(), TransactionState::Abortable);
+    }
+
+    #[tokio::test]
+    async fn test_new_instance_fences_producer() {
+        let mock = MockCluster::new(1);
+        mock.add_topic("test", 1);
+        let client = mock.client().await;
+        let partition = client
+            .partition_client("test", 0, UnknownTopicHandling::Error)
+            .await
+            .unwrap();
+        let producer = client
+            .transactional_producer("txn", Duration::from_secs(10))
+            .await
+            .unwrap();
+        producer.begin_transaction().unwrap();
+        producer
+            .produce(
+                &partition,
+                vec![record("value")],
+                Compression::NoCompression,
+            )
+            .await
+            .unwrap();
+
+        let _newer = client
+            .transactional_producer("txn", Duration::from_secs(10))
+            .await
+            .unwrap();
+        let result = producer
+            .produce(
+                &partition,
+                vec![record("value")],
+                Compression::NoCompression,
+            )
+            .await;
+        assert_matches!(result, Err(Error::ProducerFenced { .. }));
+        assert_eq!(producer.state(), TransactionState::Fenced);
+        assert_matches!(
+            producer.abort_transaction().await,
+            Err(Error::ProducerFenced { .. })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_commit_requires_transaction() {
+        let mock = MockCluster::new(1);
+        mock.add_topic("test", 1);
+        let client = mock.client().await;
+        let producer = client
+            .transactional_producer("txn", Duration::from_secs(10))
+            .await
+            .unwrap();
+        assert_matches!(
+            producer.commit_transaction().await,
+            Err(Error::InvalidTransactionState {
+                state: TransactionState::Ready,
+                ..
+            })
+        );
+        // nothing was produced, so there is nothing to end
+        producer.begin_transaction().unwrap();
+        producer.commit_transaction().await.unwrap();
+        assert!(mock.state.lock().ended_transactions.is_empty());
+    }
+}
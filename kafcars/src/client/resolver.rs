@@ -0,0 +1,250 @@
+use futures::{future::BoxFuture, FutureExt};
+use log::warn;
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    io,
+    net::{IpAddr, SocketAddr},
+};
+
+/// Maps the address a broker advertises to the address the client actually connects to, e.g.
+/// when the advertised listeners are only reachable inside a Docker network or through a port
+/// forward.
+///
+/// Bootstrap brokers are resolved without a node id.
+pub trait BrokerAddressResolver: Debug + Send + Sync + 'static {
+    /// Returns the `host:port` address to connect to.
+    fn resolve(&self, node_id: Option<i32>, host: &str, port: i32) -> String;
+}
+
+/// Connects to the advertised addresses as they are.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AdvertisedAddresses;
+
+impl BrokerAddressResolver for AdvertisedAddresses {
+    fn resolve(&self, _: Option<i32>, host: &str, port: i32) -> String {
+        join_address(host, port)
+    }
+}
+
+/// Replaces advertised `host:port` addresses by fixed ones, other addresses are used as they
+/// are.
+#[derive(Debug, Default, Clone)]
+pub struct StaticAddressMap {
+    addresses: HashMap<String, String>,
+}
+
+impl StaticAddressMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Connect to `address` whenever a broker advertises `advertised`, both as `host:port`.
+    pub fn with(mut self, advertised: impl Into<String>, address: impl Into<String>) -> Self {
+        self.addresses.insert(advertised.into(), address.into());
+        self
+    }
+}
+
+impl<A: Into<String>, B: Into<String>> FromIterator<(A, B)> for StaticAddressMap {
+    fn from_iter<T: IntoIterator<Item = (A, B)>>(iter: T) -> Self {
+        Self {
+            addresses: iter
+                .into_iter()
+                .map(|(advertised, address)| (advertised.into(), address.into()))
+                .collect(),
+        }
+    }
+}
+
+impl BrokerAddressResolver for StaticAddressMap {
+    fn resolve(&self, _: Option<i32>, host: &str, port: i32) -> String {
+        let advertised = join_address(host, port);
+        match self.addresses.get(&advertised) {
+            Some(address) => address.clone(),
+            None => advertised,
+        }
+    }
+}
+
+/// How broker host names are resolved, named after the Java client's `client.dns.lookup`.
+///
+/// Connections always try every IP address a host name resolves to.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DnsLookup {
+    /// Use the bootstrap servers as they are
+    #[default]
+    UseAllDnsIps,
+    /// Replace every bootstrap server by the canonical host names of all its IP addresses, e.g.
+    /// to expand a DNS alias pointing to all brokers
+    ResolveCanonicalBootstrapServersOnly,
+}
+
+/// Resolves host names, replace the [`SystemDnsResolver`] to fake DNS in tests.
+pub trait DnsResolver: Debug + Send + Sync + 'static {
+    /// All IP addresses of `host`.
+    fn lookup<'a>(&'a self, host: &'a str) -> BoxFuture<'a, io::Result<Vec<IpAddr>>>;
+
+    /// The canonical host name of `ip`.
+    fn reverse_lookup(&self, ip: IpAddr) -> BoxFuture<'_, io::Result<String>>;
+}
+
+/// Resolves with the resolver of the operating system.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemDnsResolver;
+
+impl DnsResolver for SystemDnsResolver {
+    fn lookup<'a>(&'a self, host: &'a str) -> BoxFuture<'a, io::Result<Vec<IpAddr>>> {
+        async move {
+            let addresses = tokio::net::lookup_host((host, 0)).await?;
+            Ok(addresses.map(|address| address.ip()).collect())
+        }
+        .boxed()
+    }
+
+    fn reverse_lookup(&self, ip: IpAddr) -> BoxFuture<'_, io::Result<String>> {
+        async move {
+            tokio::task::spawn_blocking(move || dns_lookup::lookup_addr(&ip))
+                .await
+                .map_err(io::Error::other)?
+        }
+        .boxed()
+    }
+}
+
+/// All socket addresses of a `host:port` address, in the order the resolver returned them.
+pub(crate) async fn resolve_address(
+    resolver: &dyn DnsResolver,
+    address: &str,
+) -> io::Result<Vec<SocketAddr>> {
+    let (host, port) = split_address(address).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid broker address \"{address}\""),
+        )
+    })?;
+    let port = u16::try_from(port).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid port in broker address \"{address}\""),
+        )
+    })?;
+
+    let ips = match host.parse::<IpAddr>() {
+        Ok(ip) => vec![ip],
+        Err(_) => resolver.lookup(host).await?,
+    };
+    Ok(ips
+        .into_iter()
+        .map(|ip| SocketAddr::new(ip, port))
+        .collect())
+}
+
+/// Expands every bootstrap server into the canonical host names of its IP addresses. Servers
+/// that cannot be resolved are skipped.
+pub(crate) async fn canonical_bootstrap_servers(
+    resolver: &dyn DnsResolver,
+    servers: &[String],
+) -> Vec<String> {
+    let mut canonical = vec![];
+    for server in servers {
+        let addresses = match resolve_address(resolver, server).await {
+            Ok(addresses) => addresses,
+            Err(e) => {
+                warn!("Could not resolve bootstrap server {}: {}", server, e);
+                continue;
+            }
+        };
+        for address in addresses {
+            match resolver.reverse_lookup(address.ip()).await {
+                Ok(host) => {
+                    let server = join_address(&host, address.port().into());
+                    if !canonical.contains(&server) {
+                        canonical.push(server);
+                    }
+                }
+                Err(e) => warn!("Could not resolve canonical name of {}: {}", address, e),
+            }
+        }
+    }
+    canonical
+}
+
+/// Splits a `host:port` address, also accepting bracketed IPv6 hosts like `[::1]:9092`.
+pub(crate) fn split_address(address: &str) -> Option<(&str, i32)> {
+    let (host, port) = address.rsplit_once(':')?;
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+    Some((host, port.parse().ok()?))
+}
+
+/// Joins a host and port to an address, bracketing IPv6 hosts.
+pub(crate) fn join_address(host: &str, port: i32) -> String {
+    if host.contains(':') {
+        format!("[{host}]:{port}")
+    } else {
+        format!("{host}:{port}")
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Resolves host names from a fixed table.
+    #[derive(Debug, Default)]
+    pub struct FakeDns {
+        pub hosts: HashMap<String, Vec<IpAddr>>,
+        pub names: HashMap<IpAddr, String>,
+    }
+
+    impl DnsResolver for FakeDns {
+        fn lookup<'a>(&'a self, host: &'a str) -> BoxFuture<'a, io::Result<Vec<IpAddr>>> {
+            let ips = self.hosts.get(host).cloned();
+            async move { ips.ok_or_else(|| io::Error::from(io::ErrorKind::NotFound)) }.boxed()
+        }
+
+        fn reverse_lookup(&self, ip: IpAddr) -> BoxFuture<'_, io::Result<String>> {
+            let name = self.names.get(&ip).cloned();
+            async move { name.ok_or_else(|| io::Error::from(io::ErrorKind::NotFound)) }.boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_canonical_bootstrap_servers() {
+        let dns = FakeDns {
+            hosts: HashMap::from([(
+                "kafka".to_string(),
+                vec!["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()],
+            )]),
+            names: HashMap::from([
+                ("10.0.0.1".parse().unwrap(), "broker-0.kafka".to_string()),
+                ("10.0.0.2".parse().unwrap(), "broker-1.kafka".to_string()),
+            ]),
+        };
+
+        let servers = canonical_bootstrap_servers(
+            &dns,
+            &["kafka:9092".to_string(), "unknown:9092".to_string()],
+        )
+        .await;
+        assert_eq!(servers, vec!["broker-0.kafka:9092", "broker-1.kafka:9092"]);
+    }
+
+    #[test]
+    fn test_static_address_map() {
+        let resolver: StaticAddressMap = [("kafka:9092", "localhost:29092")].into_iter().collect();
+        assert_eq!(resolver.resolve(Some(1), "kafka", 9092), "localhost:29092");
+        assert_eq!(resolver.resolve(None, "kafka", 9093), "kafka:9093");
+    }
+
+    #[test]
+    fn test_split_address() {
+        assert_eq!(split_address("kafka:9092"), Some(("kafka", 9092)));
+        assert_eq!(split_address("[::1]:9092"), Some(("::1", 9092)));
+        assert_eq!(split_address("kafka"), None);
+        assert_eq!(join_address("::1", 9092), "[::1]:9092");
+    }
+}
@@ -1,53 +1,109 @@
 use crate::{
-    client::stream::ResponseError::{InvalidHeader, UnknownRequest},
+    client::transport::Transport,
+    error::ServerSnafu,
     protocol::{
+        api_key::ApiKey,
         deserializer::{DeserializeVersioned, DeserializeVersionedInto, VersionedDeserializer},
-        error::SerializationError,
-        messages::{header::ResponseHeader, TaggedFields},
+        error::{Error as ProtocolError, SerializationError},
+        messages::{
+            header::{RequestHeader, ResponseHeader},
+            version::ApiVersionsRequest,
+            ApiVersion, ApiVersionRange, KafkaRequest, KafkaResponse, TaggedFields,
+        },
+        serializer::SerializeVersioned,
     },
 };
-use futures::{StreamExt, TryStream, TryStreamExt};
-use log::warn;
-use serde::Deserialize;
+use futures::{future::BoxFuture, FutureExt, TryStreamExt};
+use log::{debug, warn};
 use snafu::{OptionExt, ResultExt, Snafu};
 use std::{
-    collections::HashMap,
-    io::{Cursor, Read},
-    ops::DerefMut,
-    sync::Arc,
+    cmp::min,
+    collections::{BTreeSet, HashMap},
+    io::{self, Cursor},
+    sync::{
+        atomic::{AtomicI32, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
-use std::cmp::min;
-use std::sync::atomic::{AtomicI32, Ordering};
 use tokio::{
-    net::{
-        tcp::{OwnedReadHalf, OwnedWriteHalf},
-        TcpStream,
-    },
+    io::{AsyncWriteExt, ReadHalf, WriteHalf},
+    runtime::Handle,
     spawn,
-    sync::{oneshot::Sender, Mutex},
-};
-use tokio::task::JoinHandle;
-use tokio_serde::{formats::Json, Deserializer, Framed};
-use tokio_util::{
-    bytes::BytesMut,
-    codec::{FramedRead, LengthDelimitedCodec},
+    sync::{
+        oneshot::{self, Sender},
+        Mutex, Notify, OwnedSemaphorePermit, Semaphore, TryAcquireError,
+    },
+    task::JoinHandle,
+    time::{timeout_at, Instant},
 };
-use crate::protocol::api_key::ApiKey;
-use crate::protocol::messages::{ApiVersion, ApiVersionRange, KafkaRequest, KafkaResponse};
-use crate::protocol::messages::header::RequestHeader;
-use crate::protocol::messages::version::ApiVersionsRequest;
+use tokio_serde::Framed;
+use tokio_util::codec::{FramedRead, LengthDelimitedCodec};
+
+/// Length delimited response frames read from a connection.
+type ResponseFrames<T> = Framed<
+    FramedRead<ReadHalf<T>, LengthDelimitedCodec>,
+    Cursor<Vec<u8>>,
+    (),
+    VersionedDeserializer,
+>;
 
 #[derive(Debug)]
-pub struct ConnectionStream {
-    stream_write: Arc<Mutex<OwnedWriteHalf>>,
-    state: Arc<Mutex<HashMap<i32, ActiveRequest>>>,
-    response_handler: JoinHandle<crate::error::Result<()>>,
+pub struct ConnectionStream<T> {
+    stream_write: Arc<Mutex<WriteHalf<T>>>,
+    state: Arc<Mutex<RequestState>>,
+    response_handler: JoinHandle<()>,
     correlation_id: AtomicI32,
+    config: ConnectionConfig,
+    version_ranges: HashMap<ApiKey, ApiVersionRange>,
+    consecutive_timeouts: AtomicUsize,
+    /// One permit per request that may be in flight
+    in_flight: Arc<Semaphore>,
+    /// When the last request was sent or answered, keepalive probes do not count
+    last_activity: parking_lot::Mutex<Instant>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConnectionConfig {
+    pub client_id: Option<String>,
+    pub max_message_size: usize,
+    /// Time to wait for a response before the request is abandoned
+    pub request_timeout: Duration,
+    /// Close the connection after this many requests in a row timed out
+    pub max_consecutive_timeouts: Option<usize>,
+    /// Number of requests that may wait for a response at the same time, unlimited if `None`
+    pub max_in_flight_requests_per_connection: Option<usize>,
+    /// Wait for a response to arrive when the in-flight limit is reached, instead of failing
+    /// with [`RequestError::TooManyInFlightRequests`]
+    pub wait_when_saturated: bool,
+    /// Close the connection once no request was sent or answered for this long
+    pub connections_max_idle: Option<Duration>,
+    /// Send a cheap `ApiVersions` request after being idle for this long, to detect dead
+    /// connections before a real request is lost on them
+    pub keepalive_interval: Option<Duration>,
+    /// Refuse brokers whose metadata reports a different cluster id
+    pub expected_cluster_id: Option<String>,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            client_id: None,
+            max_message_size: 100 * 1024 * 1024, // 100 MB
+            request_timeout: Duration::from_secs(30),
+            max_consecutive_timeouts: None,
+            max_in_flight_requests_per_connection: Some(5),
+            wait_when_saturated: true,
+            // brokers close connections after 10 minutes by default
+            connections_max_idle: Some(Duration::from_secs(9 * 60)),
+            keepalive_interval: None,
+            expected_cluster_id: None,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Response {
-    header: ResponseHeader,
     payload: Cursor<Vec<u8>>,
 }
 
@@ -55,8 +111,58 @@ pub struct Response {
 pub enum RequestError {
     #[snafu(display("Could not read message"))]
     ReadError { source: SerializationError },
+    #[snafu(display("Could not write message"))]
+    WriteError { source: SerializationError },
     #[snafu(display("Could not find a version match for api key {api_key}"))]
     NoVersionMatch { api_key: ApiKey },
+    #[snafu(display("Connection closed before a response was received"))]
+    ConnectionClosed,
+    #[snafu(display("Request {correlation_id} timed out after {timeout:?}"))]
+    Timeout {
+        correlation_id: i32,
+        timeout: Duration,
+    },
+    #[snafu(display("Connection is poisoned"))]
+    Poisoned { source: Arc<PoisonCause> },
+    #[snafu(display("Already {max} requests in flight on this connection"))]
+    TooManyInFlightRequests { max: usize },
+    #[snafu(display("Connection is shutting down"))]
+    ShuttingDown,
+}
+
+impl RequestError {
+    /// Whether sending the request again, on another connection if need be, may succeed.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Self::ConnectionClosed
+                | Self::Timeout { .. }
+                | Self::Poisoned { .. }
+                | Self::TooManyInFlightRequests { .. }
+        )
+    }
+}
+
+/// Why a connection stopped working. Once poisoned, a connection fails every pending and new
+/// request and has to be replaced.
+#[derive(Debug, Snafu)]
+pub enum PoisonCause {
+    #[snafu(display("Connection was closed by the broker"))]
+    Disconnected,
+    #[snafu(display("Could not read from connection"))]
+    ReadFailed { source: io::Error },
+    #[snafu(display("Could not write to connection"))]
+    WriteFailed { source: io::Error },
+    #[snafu(display("Timed out while writing a request"))]
+    WriteTimeout,
+    #[snafu(display("Closed after {timeouts} consecutive request timeouts"))]
+    TooManyTimeouts { timeouts: usize },
+    #[snafu(display("Closed after being idle for {idle:?}"))]
+    Idle { idle: Duration },
+    #[snafu(display("Keepalive probe failed"))]
+    KeepaliveFailed { source: Box<RequestError> },
+    #[snafu(display("Connection was closed by the client"))]
+    Closed,
 }
 
 #[derive(Debug, Snafu)]
@@ -67,6 +173,125 @@ pub enum ResponseError {
     UnknownRequest { correlation_id: i32 },
 }
 
+/// Abandoned requests whose late responses are recognized, older ones are forgotten
+const MAX_TIMED_OUT: usize = 1024;
+
+#[derive(Debug, Default)]
+struct RequestState {
+    active: HashMap<i32, ActiveRequest>,
+    /// Requests that were given up on, their responses are dropped if they still arrive. Holds
+    /// the latest [`MAX_TIMED_OUT`] correlation ids.
+    timed_out: BTreeSet<i32>,
+    poisoned: Option<Arc<PoisonCause>>,
+    /// Set by [`ConnectionStream::close`], new requests are refused
+    closing: bool,
+    /// Notified when the last active request finished while closing
+    drained: Arc<Notify>,
+}
+
+impl RequestState {
+    fn finish(&mut self, correlation_id: i32) -> Option<ActiveRequest> {
+        let request = self.active.remove(&correlation_id);
+        if self.closing && self.active.is_empty() {
+            self.drained.notify_waiters();
+        }
+        request
+    }
+
+    /// Gives up on a request that is still active, so that its response is dropped if it
+    /// still arrives.
+    fn abandon(&mut self, correlation_id: i32) {
+        if self.finish(correlation_id).is_some() {
+            self.timed_out.insert(correlation_id);
+            if self.timed_out.len() > MAX_TIMED_OUT {
+                self.timed_out.pop_first();
+            }
+        }
+    }
+
+    /// Refuses new requests on a poisoned or closing connection.
+    fn ensure_usable(&self) -> Result<(), RequestError> {
+        if let Some(cause) = &self.poisoned {
+            return Err(RequestError::Poisoned {
+                source: cause.clone(),
+            });
+        }
+        if self.closing {
+            return Err(RequestError::ShuttingDown);
+        }
+        Ok(())
+    }
+
+    /// Fails every pending request with the cause, the first cause wins.
+    fn poison(&mut self, cause: PoisonCause) -> Arc<PoisonCause> {
+        if let Some(poisoned) = &self.poisoned {
+            return poisoned.clone();
+        }
+        let cause = Arc::new(cause);
+        self.poisoned = Some(cause.clone());
+        for (_, request) in self.active.drain() {
+            request
+                .channel
+                .send(Err(RequestError::Poisoned {
+                    source: cause.clone(),
+                }))
+                .ok();
+        }
+        self.timed_out.clear();
+        cause
+    }
+}
+
+/// A request frame ready to be written.
+struct EncodedRequest {
+    correlation_id: i32,
+    body_version: ApiVersion,
+    use_tagged_fields_in_response: bool,
+    buf: Vec<u8>,
+}
+
+/// A request written to the broker, waiting for its response.
+struct SentRequest {
+    correlation_id: i32,
+    body_version: ApiVersion,
+    response: oneshot::Receiver<Result<Response, RequestError>>,
+    request_timeout: Duration,
+    deadline: Instant,
+    /// Released once the response arrived or the request was given up on
+    _in_flight: OwnedSemaphorePermit,
+    abandon: AbandonOnDrop,
+}
+
+/// Abandons an active request when the future waiting for its response is dropped, unless
+/// disarmed before.
+struct AbandonOnDrop {
+    correlation_id: i32,
+    state: Option<Arc<Mutex<RequestState>>>,
+}
+
+impl AbandonOnDrop {
+    fn disarm(&mut self) {
+        self.state = None;
+    }
+}
+
+impl Drop for AbandonOnDrop {
+    fn drop(&mut self) {
+        let Some(state) = self.state.take() else {
+            return;
+        };
+        let correlation_id = self.correlation_id;
+        if let Ok(mut state) = state.try_lock() {
+            state.abandon(correlation_id);
+            return;
+        }
+        // without a runtime the connection is gone as well
+        if let Ok(handle) = Handle::try_current() {
+            handle.spawn(async move { state.lock().await.abandon(correlation_id) });
+        }
+    }
+}
+
 #[derive(Debug)]
 struct ActiveRequest {
     channel: Sender<Result<Response, RequestError>>,
@@ -82,34 +307,58 @@ impl DeserializeVersionedInto<Cursor<Vec<u8>>> for Cursor<Vec<u8>> {
     }
 }
 
-impl ConnectionStream {
-    pub fn new(stream: TcpStream) -> Self {
-        let (stream_read, stream_write) = stream.into_split();
-        let state = Arc::new(Mutex::new(HashMap::default()));
+impl<T: Transport> ConnectionStream<T> {
+    pub fn new(stream: T, config: ConnectionConfig) -> Self {
+        let (stream_read, stream_write) = tokio::io::split(stream);
+        let state = Arc::new(Mutex::new(RequestState::default()));
 
-        let join = spawn(Self::stream_reader_task(stream_read, state.clone()));
+        let join = spawn(Self::stream_reader_task(
+            stream_read,
+            state.clone(),
+            config.max_message_size,
+        ));
 
         Self {
             stream_write: Arc::new(Mutex::new(stream_write)),
             state,
             response_handler: join,
             correlation_id: AtomicI32::new(0),
+            in_flight: Arc::new(Semaphore::new(Self::max_in_flight(&config))),
+            version_ranges: HashMap::default(),
+            consecutive_timeouts: AtomicUsize::new(0),
+            last_activity: parking_lot::Mutex::new(Instant::now()),
+            config,
         }
     }
 
+    fn max_in_flight(config: &ConnectionConfig) -> usize {
+        config
+            .max_in_flight_requests_per_connection
+            .unwrap_or(Semaphore::MAX_PERMITS)
+            .clamp(1, Semaphore::MAX_PERMITS)
+    }
+
+    pub fn config(&self) -> &ConnectionConfig {
+        &self.config
+    }
+
+    /// Number of requests currently waiting for a response.
+    pub fn in_flight_requests(&self) -> usize {
+        Self::max_in_flight(&self.config) - self.in_flight.available_permits()
+    }
+
     async fn stream_reader_task(
-        stream_read: OwnedReadHalf,
-        state: Arc<Mutex<HashMap<i32, ActiveRequest>>>,
-    ) -> crate::error::Result<()> {
-        let stream = FramedRead::new(stream_read, LengthDelimitedCodec::new());
+        stream_read: ReadHalf<T>,
+        state: Arc<Mutex<RequestState>>,
+        max_message_size: usize,
+    ) {
+        let codec = LengthDelimitedCodec::builder()
+            .max_frame_length(max_message_size)
+            .new_codec();
+        let stream = FramedRead::new(stream_read, codec);
         let deserializer = VersionedDeserializer::new(0);
-        let mut stream: Framed<
-            FramedRead<OwnedReadHalf, LengthDelimitedCodec>,
-            Cursor<Vec<u8>>,
-            (),
-            VersionedDeserializer,
-        > = Framed::new(stream, deserializer);
-        stream
+        let stream: ResponseFrames<T> = Framed::new(stream, deserializer);
+        let result = stream
             .try_for_each_concurrent(1, |val| {
                 let state = state.clone();
                 async move {
@@ -119,50 +368,61 @@ impl ConnectionStream {
                     Ok(())
                 }
             })
-            .await?;
-        Ok(())
+            .await;
+        let cause = match result {
+            Ok(()) => PoisonCause::Disconnected,
+            Err(source) => PoisonCause::ReadFailed { source },
+        };
+        warn!("Connection reader stopped: {}", cause);
+        state.lock().await.poison(cause);
     }
 
     async fn read_with_raw_response_message(
         mut data: Cursor<Vec<u8>>,
-        state: Arc<Mutex<HashMap<i32, ActiveRequest>>>,
+        state: Arc<Mutex<RequestState>>,
     ) -> Result<(), ResponseError> {
-        let mut header =
+        let header =
             ResponseHeader::deserialize_versioned(&mut data, 0).context(InvalidHeaderSnafu)?;
-        let active_request = state
-            .lock()
-            .await
-            .deref_mut()
-            .remove(&header.correlation_id)
-            .context(UnknownRequestSnafu {
-                correlation_id: header.correlation_id,
-            })?;
+        let active_request = {
+            let mut state = state.lock().await;
+            if state.timed_out.remove(&header.correlation_id) {
+                debug!(
+                    "Dropping late response for timed out request {}",
+                    header.correlation_id
+                );
+                return Ok(());
+            }
+            state
+                .finish(header.correlation_id)
+                .context(UnknownRequestSnafu {
+                    correlation_id: header.correlation_id,
+                })?
+        };
         if active_request.use_tagged_fields_in_response {
-            header.tagged_fields = match TaggedFields::deserialize_versioned(&mut data, 0) {
-                Ok(fields) => Some(fields),
-                Err(e) => {
-                    active_request
-                        .channel
-                        .send(Err(RequestError::ReadError { source: e }))
-                        .ok();
-                    return Ok(());
-                }
+            if let Err(e) = TaggedFields::deserialize_versioned(&mut data, 0) {
+                active_request
+                    .channel
+                    .send(Err(RequestError::ReadError { source: e }))
+                    .ok();
+                return Ok(());
             }
         }
 
         active_request
             .channel
-            .send(Ok(Response {
-                header,
-                payload: data,
-            }))
+            .send(Ok(Response { payload: data }))
             .ok();
 
         Ok(())
     }
-    
+
+    /// Negotiates the API versions with the broker, starting with the highest `ApiVersions`
+    /// version we know and falling back to lower ones if the broker does not support it.
     pub async fn sync_versions(&mut self) -> crate::error::Result<()> {
-        'upper_bound: for upper_bound in (ApiVersionsRequest::API_VERSION_RANGE.min..=ApiVersionsRequest::API_VERSION_RANGE.max).rev() {
+        for upper_bound in (ApiVersionsRequest::API_VERSION_RANGE.min
+            ..=ApiVersionsRequest::API_VERSION_RANGE.max)
+            .rev()
+        {
             let version_ranges = HashMap::from([(
                 ApiKey::ApiVersions,
                 ApiVersionRange {
@@ -170,50 +430,441 @@ impl ConnectionStream {
                     max: upper_bound,
                 },
             )]);
-            
+
             let body = ApiVersionsRequest {
                 client_software_name: Some(String::from(env!("CARGO_PKG_NAME"))),
                 client_software_version: Some(String::from(env!("CARGO_PKG_VERSION"))),
                 tagged_fields: Some(TaggedFields::default()),
             };
-            
-            'send: loop {
-                
+
+            let response = match self
+                .send_request_with_version_ranges(
+                    body,
+                    &version_ranges,
+                    self.config.request_timeout,
+                )
+                .await
+            {
+                Ok(response) => response,
+                // Older brokers answer unsupported versions in the v0 format
+                Err(RequestError::ReadError { .. }) => continue,
+                Err(e) => return Err(e.into()),
+            };
+            match response.error {
+                None => {}
+                Some(ProtocolError::UnsupportedVersion) => continue,
+                Some(error) => {
+                    return ServerSnafu {
+                        api_key: ApiKey::ApiVersions,
+                        error,
+                    }
+                    .fail()
+                }
             }
+
+            self.version_ranges = response
+                .api_keys
+                .into_iter()
+                .map(|key| {
+                    (
+                        key.api_key,
+                        ApiVersionRange {
+                            min: key.min_version,
+                            max: key.max_version,
+                        },
+                    )
+                })
+                .collect();
+            return Ok(());
+        }
+
+        Err(RequestError::NoVersionMatch {
+            api_key: ApiKey::ApiVersions,
+        }
+        .into())
+    }
+
+    pub async fn send_request<R>(&self, message: R) -> Result<R::KafkaResponse, RequestError>
+    where
+        R: KafkaRequest + SerializeVersioned<Vec<u8>>,
+        R::KafkaResponse: KafkaResponse + DeserializeVersioned<Cursor<Vec<u8>>>,
+    {
+        self.send_request_with_timeout(message, self.config.request_timeout)
+            .await
+    }
+
+    /// Like [`send_request`](Self::send_request), but overrides the configured request timeout.
+    pub async fn send_request_with_timeout<R>(
+        &self,
+        message: R,
+        request_timeout: Duration,
+    ) -> Result<R::KafkaResponse, RequestError>
+    where
+        R: KafkaRequest + SerializeVersioned<Vec<u8>>,
+        R::KafkaResponse: KafkaResponse + DeserializeVersioned<Cursor<Vec<u8>>>,
+    {
+        *self.last_activity.lock() = Instant::now();
+        let response = self
+            .send_request_with_version_ranges(message, &self.version_ranges, request_timeout)
+            .await;
+        *self.last_activity.lock() = Instant::now();
+        response
+    }
+
+    /// How long no request was in flight.
+    pub fn idle_for(&self) -> Duration {
+        if self.in_flight_requests() > 0 {
+            Duration::ZERO
+        } else {
+            self.last_activity.lock().elapsed()
+        }
+    }
+
+    /// Sends an `ApiVersions` request that does not count as activity, and closes the
+    /// connection if it fails.
+    pub async fn keepalive(&self) -> Result<(), RequestError> {
+        let request = ApiVersionsRequest {
+            client_software_name: Some(String::from(env!("CARGO_PKG_NAME"))),
+            client_software_version: Some(String::from(env!("CARGO_PKG_VERSION"))),
+            tagged_fields: Some(TaggedFields::default()),
+        };
+        match self
+            .send_request_with_version_ranges(
+                request,
+                &self.version_ranges,
+                self.config.request_timeout,
+            )
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(source) => {
+                let cause = PoisonCause::KeepaliveFailed {
+                    source: Box::new(source),
+                };
+                Err(RequestError::Poisoned {
+                    source: self.poison(cause).await,
+                })
+            }
+        }
+    }
+
+    /// Closes the connection if it was idle for longer than the configured maximum.
+    pub async fn close_if_idle(&self) -> bool {
+        let idle = self.idle_for();
+        match self.config.connections_max_idle {
+            Some(max_idle) if idle >= max_idle => {
+                self.poison(PoisonCause::Idle { idle }).await;
+                true
+            }
+            _ => false,
         }
-        Ok(())
     }
-    
+
     async fn send_request_with_version_ranges<R>(
         &self,
         message: R,
-        version_ranges: HashMap<ApiKey, ApiVersionRange>,
-    ) -> Result<(), RequestError>
+        version_ranges: &HashMap<ApiKey, ApiVersionRange>,
+        request_timeout: Duration,
+    ) -> Result<R::KafkaResponse, RequestError>
+    where
+        R: KafkaRequest + SerializeVersioned<Vec<u8>>,
+        R::KafkaResponse: KafkaResponse + DeserializeVersioned<Cursor<Vec<u8>>>,
+    {
+        let request = self
+            .write_awaited_request(message, version_ranges, request_timeout)
+            .await?;
+        self.read_response::<R>(request).await
+    }
+
+    /// Writes a request and returns the future of its response, so that further requests can
+    /// be written before it arrives. Requests are written in the order this is awaited in.
+    pub async fn send_request_pipelined<R>(
+        self: &Arc<Self>,
+        message: R,
+        request_timeout: Duration,
+    ) -> Result<BoxFuture<'static, Result<R::KafkaResponse, RequestError>>, RequestError>
+    where
+        R: KafkaRequest + SerializeVersioned<Vec<u8>>,
+        R::KafkaResponse: KafkaResponse + DeserializeVersioned<Cursor<Vec<u8>>> + Send + 'static,
+    {
+        *self.last_activity.lock() = Instant::now();
+        let request = self
+            .write_awaited_request(message, &self.version_ranges, request_timeout)
+            .await?;
+        let stream = self.clone();
+        Ok(async move {
+            let response = stream.read_response::<R>(request).await;
+            *stream.last_activity.lock() = Instant::now();
+            response
+        }
+        .boxed())
+    }
+
+    /// Registers a request whose response is awaited and writes it.
+    async fn write_awaited_request<R>(
+        &self,
+        message: R,
+        version_ranges: &HashMap<ApiKey, ApiVersionRange>,
+        request_timeout: Duration,
+    ) -> Result<SentRequest, RequestError>
+    where
+        R: KafkaRequest + SerializeVersioned<Vec<u8>>,
+        R::KafkaResponse: KafkaResponse,
+    {
+        let EncodedRequest {
+            correlation_id,
+            body_version,
+            use_tagged_fields_in_response,
+            buf,
+        } = self.encode_request(message, version_ranges)?;
+
+        let in_flight = if self.config.wait_when_saturated {
+            self.in_flight
+                .clone()
+                .acquire_owned()
+                .await
+                .expect("semaphore is never closed")
+        } else {
+            match self.in_flight.clone().try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(TryAcquireError::NoPermits) => {
+                    return TooManyInFlightRequestsSnafu {
+                        max: Self::max_in_flight(&self.config),
+                    }
+                    .fail()
+                }
+                Err(TryAcquireError::Closed) => unreachable!("semaphore is never closed"),
+            }
+        };
+
+        let (tx, rx) = oneshot::channel();
+        {
+            let mut state = self.state.lock().await;
+            state.ensure_usable()?;
+            state.active.insert(
+                correlation_id,
+                ActiveRequest {
+                    channel: tx,
+                    use_tagged_fields_in_response,
+                },
+            );
+        }
+        let abandon = AbandonOnDrop {
+            correlation_id,
+            state: Some(self.state.clone()),
+        };
+
+        let deadline = Instant::now() + request_timeout;
+        self.write_request(&buf, correlation_id, request_timeout, deadline)
+            .await?;
+        Ok(SentRequest {
+            correlation_id,
+            body_version,
+            response: rx,
+            request_timeout,
+            deadline,
+            _in_flight: in_flight,
+            abandon,
+        })
+    }
+
+    /// Waits for the response of a written request until its deadline.
+    async fn read_response<R>(
+        &self,
+        mut request: SentRequest,
+    ) -> Result<R::KafkaResponse, RequestError>
     where
         R: KafkaRequest,
+        R::KafkaResponse: KafkaResponse + DeserializeVersioned<Cursor<Vec<u8>>>,
+    {
+        let response = timeout_at(request.deadline, request.response).await;
+        // the request is finished either way
+        request.abandon.disarm();
+        let mut response = match response {
+            Ok(response) => {
+                self.consecutive_timeouts.store(0, Ordering::SeqCst);
+                response.map_err(|_| RequestError::ConnectionClosed)??
+            }
+            Err(_) => {
+                self.abandon_request(request.correlation_id).await;
+                return Err(RequestError::Timeout {
+                    correlation_id: request.correlation_id,
+                    timeout: request.request_timeout,
+                });
+            }
+        };
+        R::KafkaResponse::deserialize_versioned(&mut response.payload, request.body_version)
+            .context(ReadSnafu)
+    }
+
+    /// Sends a request the broker does not answer, like a Produce request with acks 0. No
+    /// response is awaited, the request is done once it is written.
+    pub async fn send_request_without_response<R>(&self, message: R) -> Result<(), RequestError>
+    where
+        R: KafkaRequest + SerializeVersioned<Vec<u8>>,
+        R::KafkaResponse: KafkaResponse,
+    {
+        *self.last_activity.lock() = Instant::now();
+        let EncodedRequest {
+            correlation_id,
+            buf,
+            ..
+        } = self.encode_request(message, &self.version_ranges)?;
+        self.state.lock().await.ensure_usable()?;
+
+        let request_timeout = self.config.request_timeout;
+        let deadline = Instant::now() + request_timeout;
+        self.write_request(&buf, correlation_id, request_timeout, deadline)
+            .await
+    }
+
+    /// Serializes the header and body of a request into a frame, in the highest version both
+    /// sides support.
+    fn encode_request<R>(
+        &self,
+        message: R,
+        version_ranges: &HashMap<ApiKey, ApiVersionRange>,
+    ) -> Result<EncodedRequest, RequestError>
+    where
+        R: KafkaRequest + SerializeVersioned<Vec<u8>>,
         R::KafkaResponse: KafkaResponse,
     {
         let body_version = version_ranges
             .get(&R::API_KEY)
             .and_then(|range_server| match_versions(*range_server, R::API_VERSION_RANGE))
-            .ok_or(RequestError::NoVersionMatch {
+            .context(NoVersionMatchSnafu {
                 api_key: R::API_KEY,
             })?;
-        
+
         let use_tagged_fields_in_request = R::TAGGED_FIELDS_MIN_VERSION
             .map(|v| body_version >= v)
             .unwrap_or(false);
-        let use_tagged_fields_in_response = R::KafkaResponse::TAGGED_FIELDS_MIN_VERSION
-            .map(|v| body_version >= v)
-            .unwrap_or(false);
-        
+        // ApiVersions responses always use header v0, so that clients can parse them before
+        // knowing which versions the broker supports
+        let use_tagged_fields_in_response = R::API_KEY != ApiKey::ApiVersions
+            && R::KafkaResponse::TAGGED_FIELDS_MIN_VERSION
+                .map(|v| body_version >= v)
+                .unwrap_or(false);
+
         let correlation_id = self.correlation_id.fetch_add(1, Ordering::SeqCst);
-        
+
         let header = RequestHeader {
-            correlat
+            request_api_key: R::API_KEY,
+            request_api_version: body_version,
+            correlation_id,
+            client_id: Some(self.config.client_id.clone().unwrap_or_default()),
+            tagged_fields: Some(TaggedFields::default()),
+        };
+        let header_version = if use_tagged_fields_in_request { 2 } else { 1 };
+
+        let mut buf = vec![0u8; 4];
+        header
+            .serialize_versioned(&mut buf, header_version)
+            .context(WriteSnafu)?;
+        message
+            .serialize_versioned(&mut buf, body_version)
+            .context(WriteSnafu)?;
+        let len = i32::try_from(buf.len() - 4)
+            .map_err(|_| SerializationError::Overflow)
+            .context(WriteSnafu)?;
+        buf[..4].copy_from_slice(&len.to_be_bytes());
+
+        Ok(EncodedRequest {
+            correlation_id,
+            body_version,
+            use_tagged_fields_in_response,
+            buf,
+        })
+    }
+
+    /// Writes a request frame, poisoning the connection if that fails or does not finish
+    /// before `deadline`.
+    async fn write_request(
+        &self,
+        buf: &[u8],
+        correlation_id: i32,
+        request_timeout: Duration,
+        deadline: Instant,
+    ) -> Result<(), RequestError> {
+        match timeout_at(deadline, self.write_message(buf)).await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(source)) => Err(RequestError::Poisoned {
+                source: self.poison(PoisonCause::WriteFailed { source }).await,
+            }),
+            Err(_) => {
+                // The frame might be written partially, so the connection cannot be used anymore
+                self.poison(PoisonCause::WriteTimeout).await;
+                Err(RequestError::Timeout {
+                    correlation_id,
+                    timeout: request_timeout,
+                })
+            }
         }
-        
-        Ok(())
+    }
+
+    async fn abandon_request(&self, correlation_id: i32) {
+        self.state.lock().await.abandon(correlation_id);
+
+        let timeouts = self.consecutive_timeouts.fetch_add(1, Ordering::SeqCst) + 1;
+        if let Some(max_timeouts) = self.config.max_consecutive_timeouts {
+            if timeouts >= max_timeouts {
+                self.poison(PoisonCause::TooManyTimeouts { timeouts }).await;
+            }
+        }
+    }
+
+    /// The reason this connection stopped working, if it did.
+    pub async fn poisoned(&self) -> Option<Arc<PoisonCause>> {
+        self.state.lock().await.poisoned.clone()
+    }
+
+    /// Refuses new requests, waits until the outstanding ones are answered or the deadline
+    /// passed, and then closes the connection. Requests still pending at the deadline fail.
+    pub async fn close(&self, deadline: Instant) {
+        let drained = {
+            let mut state = self.state.lock().await;
+            state.closing = true;
+            state.drained.clone()
+        };
+
+        let drain = async {
+            loop {
+                let notified = drained.notified();
+                if self.state.lock().await.active.is_empty() {
+                    return;
+                }
+                notified.await;
+            }
+        };
+        if timeout_at(deadline, drain).await.is_err() {
+            warn!("Closing connection with requests still in flight");
+        }
+        self.poison(PoisonCause::Closed).await;
+    }
+
+    async fn poison(&self, cause: PoisonCause) -> Arc<PoisonCause> {
+        let cause = self.state.lock().await.poison(cause);
+        match cause.as_ref() {
+            PoisonCause::Closed | PoisonCause::Idle { .. } => {
+                debug!("Closing connection: {}", cause)
+            }
+            _ => warn!("Closing connection: {}", cause),
+        }
+        self.response_handler.abort();
+        self.stream_write.lock().await.shutdown().await.ok();
+        cause
+    }
+
+    async fn write_message(&self, buf: &[u8]) -> io::Result<()> {
+        let mut stream_write = self.stream_write.lock().await;
+        stream_write.write_all(buf).await?;
+        stream_write.flush().await
+    }
+}
+
+impl<T> Drop for ConnectionStream<T> {
+    fn drop(&mut self) {
+        self.response_handler.abort();
     }
 }
 
@@ -225,4 +876,408 @@ fn match_versions(range0: ApiVersionRange, range1: ApiVersionRange) -> Option<Ap
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{
+        messages::produce::ProduceRequest, serializer::serialize_unsigned_var_int,
+    };
+    use assert_matches::assert_matches;
+    use tokio::io::{duplex, AsyncReadExt, DuplexStream};
+
+    async fn read_request_header(broker: &mut DuplexStream) -> (i16, i16, i32) {
+        let len = broker.read_i32().await.unwrap();
+        let mut frame = vec![0u8; len as usize];
+        broker.read_exact(&mut frame).await.unwrap();
+        let api_key = i16::from_be_bytes([frame[0], frame[1]]);
+        let version = i16::from_be_bytes([frame[2], frame[3]]);
+        let correlation_id = i32::from_be_bytes([frame[4], frame[5], frame[6], frame[7]]);
+        (api_key, version, correlation_id)
+    }
+
+    async fn write_response(broker: &mut DuplexStream, correlation_id: i32, body: &[u8]) {
+        let mut frame = vec![];
+        frame.extend_from_slice(&(body.len() as i32 + 4).to_be_bytes());
+        frame.extend_from_slice(&correlation_id.to_be_bytes());
+        frame.extend_from_slice(body);
+        broker.write_all(&frame).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sync_versions_over_duplex() {
+        let (client, mut broker) = duplex(1024);
+        let mut stream = ConnectionStream::new(
+            client,
+            ConnectionConfig {
+                client_id: Some("test".to_string()),
+                ..Default::default()
+            },
+        );
+
+        let broker = spawn(async move {
+            let (api_key, version, correlation_id) = read_request_header(&mut broker).await;
+            assert_eq!(ApiKey::from(api_key), ApiKey::ApiVersions);
+            assert_eq!(version, ApiVersionsRequest::API_VERSION_RANGE.max);
+
+            let mut body = vec![];
+            0i16.serialize_versioned(&mut body, version).unwrap();
+            serialize_unsigned_var_int(3, &mut body).unwrap();
+            for (api_key, max_version) in [(ApiKey::ApiVersions, 3i16), (ApiKey::Metadata, 12)] {
+                api_key.serialize_versioned(&mut body, version).unwrap();
+                0i16.serialize_versioned(&mut body, version).unwrap();
+                max_version.serialize_versioned(&mut body, version).unwrap();
+                serialize_unsigned_var_int(0, &mut body).unwrap();
+            }
+            0i32.serialize_versioned(&mut body, version).unwrap();
+            serialize_unsigned_var_int(0, &mut body).unwrap();
+            write_response(&mut broker, correlation_id, &body).await;
+        });
+
+        stream.sync_versions().await.unwrap();
+        broker.await.unwrap();
+
+        let ranges = &stream.version_ranges;
+        assert_eq!(ranges.len(), 2);
+        assert_eq!(ranges[&ApiKey::ApiVersions].max, 3);
+        assert_eq!(ranges[&ApiKey::Metadata].max, 12);
+    }
+
+    fn api_versions_request() -> (ApiVersionsRequest, HashMap<ApiKey, ApiVersionRange>) {
+        let request = ApiVersionsRequest {
+            client_software_name: None,
+            client_software_version: None,
+            tagged_fields: None,
+        };
+        let ranges = HashMap::from([(ApiKey::ApiVersions, ApiVersionRange { min: 0, max: 0 })]);
+        (request, ranges)
+    }
+
+    #[tokio::test]
+    async fn test_timed_out_request_drops_late_response() {
+        let (client, mut broker) = duplex(1024);
+        let stream = ConnectionStream::new(client, ConnectionConfig::default());
+
+        let (request, ranges) = api_versions_request();
+        let err = stream
+            .send_request_with_version_ranges(request, &ranges, Duration::from_millis(50))
+            .await
+            .unwrap_err();
+        assert_matches!(
+            err,
+            RequestError::Timeout {
+                correlation_id: 0,
+                ..
+            }
+        );
+        {
+            let state = stream.state.lock().await;
+            assert!(state.active.is_empty());
+            assert!(state.timed_out.contains(&0));
+        }
+
+        let (_, version, correlation_id) = read_request_header(&mut broker).await;
+        let mut body = vec![];
+        0i16.serialize_versioned(&mut body, version).unwrap();
+        0i32.serialize_versioned(&mut body, version).unwrap();
+        write_response(&mut broker, correlation_id, &body).await;
 
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(stream.state.lock().await.timed_out.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dropped_request_is_abandoned() {
+        let (client, mut broker) = duplex(1024);
+        let stream = ConnectionStream::new(client, ConnectionConfig::default());
+
+        let (request, ranges) = api_versions_request();
+        let send =
+            stream.send_request_with_version_ranges(request, &ranges, Duration::from_secs(5));
+        tokio::time::timeout(Duration::from_millis(50), send)
+            .await
+            .unwrap_err();
+        {
+            let state = stream.state.lock().await;
+            assert!(state.active.is_empty());
+            assert!(state.timed_out.contains(&0));
+        }
+        assert_eq!(stream.in_flight_requests(), 0);
+
+        let (_, _, correlation_id) = read_request_header(&mut broker).await;
+        write_response(&mut broker, correlation_id, &api_versions_response()).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(stream.state.lock().await.timed_out.is_empty());
+    }
+
+    #[test]
+    fn test_timed_out_requests_are_capped() {
+        let mut state = RequestState::default();
+        for correlation_id in 0..MAX_TIMED_OUT as i32 + 10 {
+            let (channel, _) = oneshot::channel();
+            state.active.insert(
+                correlation_id,
+                ActiveRequest {
+                    channel,
+                    use_tagged_fields_in_response: false,
+                },
+            );
+            state.abandon(correlation_id);
+        }
+        assert_eq!(state.timed_out.len(), MAX_TIMED_OUT);
+        // the oldest are forgotten
+        assert_eq!(state.timed_out.first(), Some(&10));
+    }
+
+    #[tokio::test]
+    async fn test_request_without_response() {
+        let (client, mut broker) = duplex(1024);
+        let mut stream = ConnectionStream::new(client, ConnectionConfig::default());
+        stream.version_ranges = HashMap::from([
+            (ApiKey::ApiVersions, ApiVersionRange { min: 0, max: 0 }),
+            (ApiKey::Produce, ApiVersionRange { min: 8, max: 8 }),
+        ]);
+
+        let request = ProduceRequest {
+            transactional_id: None,
+            acks: 0,
+            timeout_ms: 1000,
+            topic_data: vec![],
+        };
+        stream.send_request_without_response(request).await.unwrap();
+        assert!(stream.state.lock().await.active.is_empty());
+        assert_eq!(stream.in_flight_requests(), 0);
+        let (api_key, _, correlation_id) = read_request_header(&mut broker).await;
+        assert_eq!(
+            (ApiKey::from(api_key), correlation_id),
+            (ApiKey::Produce, 0)
+        );
+
+        // the next request is answered as usual
+        let broker = spawn(async move {
+            let (_, version, correlation_id) = read_request_header(&mut broker).await;
+            assert_eq!(correlation_id, 1);
+            let mut body = vec![];
+            0i16.serialize_versioned(&mut body, version).unwrap();
+            0i32.serialize_versioned(&mut body, version).unwrap();
+            write_response(&mut broker, correlation_id, &body).await;
+        });
+        let (request, _) = api_versions_request();
+        stream.send_request(request).await.unwrap();
+        broker.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_close_after_consecutive_timeouts() {
+        let (client, _broker) = duplex(1024);
+        let stream = ConnectionStream::new(
+            client,
+            ConnectionConfig {
+                max_consecutive_timeouts: Some(2),
+                ..Default::default()
+            },
+        );
+
+        for _ in 0..2 {
+            let (request, ranges) = api_versions_request();
+            let err = stream
+                .send_request_with_version_ranges(request, &ranges, Duration::from_millis(10))
+                .await
+                .unwrap_err();
+            assert_matches!(err, RequestError::Timeout { .. });
+        }
+
+        let (request, ranges) = api_versions_request();
+        let err = stream
+            .send_request_with_version_ranges(request, &ranges, Duration::from_millis(10))
+            .await
+            .unwrap_err();
+        assert_matches!(err, RequestError::Poisoned { source } => {
+            assert_matches!(*source, PoisonCause::TooManyTimeouts { timeouts: 2 });
+        });
+    }
+
+    #[tokio::test]
+    async fn test_reader_failure_fails_pending_requests() {
+        let (client, mut broker) = duplex(1024);
+        let stream = Arc::new(ConnectionStream::new(client, ConnectionConfig::default()));
+
+        let pending = spawn({
+            let stream = stream.clone();
+            async move {
+                let (request, ranges) = api_versions_request();
+                stream
+                    .send_request_with_version_ranges(request, &ranges, Duration::from_secs(10))
+                    .await
+            }
+        });
+        read_request_header(&mut broker).await;
+        drop(broker);
+
+        let err = pending.await.unwrap().unwrap_err();
+        assert_matches!(err, RequestError::Poisoned { source } => {
+            assert_matches!(*source, PoisonCause::Disconnected);
+        });
+        assert!(stream.poisoned().await.is_some());
+
+        let (request, ranges) = api_versions_request();
+        let err = stream
+            .send_request_with_version_ranges(request, &ranges, Duration::from_secs(10))
+            .await
+            .unwrap_err();
+        assert_matches!(err, RequestError::Poisoned { .. });
+    }
+
+    #[tokio::test]
+    async fn test_request_fails_when_transport_closes() {
+        let (client, broker) = duplex(1024);
+        let mut stream = ConnectionStream::new(client, ConnectionConfig::default());
+        drop(broker);
+
+        assert!(stream.sync_versions().await.is_err());
+    }
+
+    fn limited_config(wait_when_saturated: bool) -> ConnectionConfig {
+        ConnectionConfig {
+            max_in_flight_requests_per_connection: Some(1),
+            wait_when_saturated,
+            ..Default::default()
+        }
+    }
+
+    /// An empty `ApiVersions` v0 response body.
+    fn api_versions_response() -> Vec<u8> {
+        let mut body = vec![];
+        0i16.serialize_versioned(&mut body, 0).unwrap();
+        0i32.serialize_versioned(&mut body, 0).unwrap();
+        body
+    }
+
+    #[tokio::test]
+    async fn test_saturated_connection_waits_for_slot() {
+        let (client, mut broker) = duplex(1024);
+        let stream = Arc::new(ConnectionStream::new(client, limited_config(true)));
+
+        let (request, ranges) = api_versions_request();
+        let first = spawn({
+            let stream = stream.clone();
+            let ranges = ranges.clone();
+            async move {
+                stream
+                    .send_request_with_version_ranges(request, &ranges, Duration::from_secs(5))
+                    .await
+            }
+        });
+        let (api_key, _, correlation_id) = read_request_header(&mut broker).await;
+        assert_eq!(stream.in_flight_requests(), 1);
+
+        let (request, _) = api_versions_request();
+        let second = spawn({
+            let stream = stream.clone();
+            async move {
+                stream
+                    .send_request_with_version_ranges(request, &ranges, Duration::from_secs(5))
+                    .await
+            }
+        });
+        // the second request is only written once the first one was answered
+        assert_eq!(ApiKey::from(api_key), ApiKey::ApiVersions);
+        write_response(&mut broker, correlation_id, &api_versions_response()).await;
+        first.await.unwrap().unwrap();
+
+        let (_, _, correlation_id) = read_request_header(&mut broker).await;
+        write_response(&mut broker, correlation_id, &api_versions_response()).await;
+        second.await.unwrap().unwrap();
+        assert_eq!(stream.in_flight_requests(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_saturated_connection_fails_if_configured() {
+        let (client, mut broker) = duplex(1024);
+        let stream = Arc::new(ConnectionStream::new(client, limited_config(false)));
+
+        let (request, ranges) = api_versions_request();
+        let first = spawn({
+            let stream = stream.clone();
+            let ranges = ranges.clone();
+            async move {
+                stream
+                    .send_request_with_version_ranges(request, &ranges, Duration::from_secs(5))
+                    .await
+            }
+        });
+        let (_, _, correlation_id) = read_request_header(&mut broker).await;
+
+        let (request, _) = api_versions_request();
+        let err = stream
+            .send_request_with_version_ranges(request, &ranges, Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert_matches!(err, RequestError::TooManyInFlightRequests { max: 1 });
+
+        write_response(&mut broker, correlation_id, &api_versions_response()).await;
+        first.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_close_drains_outstanding_requests() {
+        let (client, mut broker) = duplex(1024);
+        let stream = Arc::new(ConnectionStream::new(client, ConnectionConfig::default()));
+
+        let (request, ranges) = api_versions_request();
+        let pending = spawn({
+            let stream = stream.clone();
+            let ranges = ranges.clone();
+            async move {
+                stream
+                    .send_request_with_version_ranges(request, &ranges, Duration::from_secs(5))
+                    .await
+            }
+        });
+        let (_, _, correlation_id) = read_request_header(&mut broker).await;
+
+        let close = spawn({
+            let stream = stream.clone();
+            async move { stream.close(Instant::now() + Duration::from_secs(5)).await }
+        });
+        while !stream.state.lock().await.closing {
+            tokio::task::yield_now().await;
+        }
+        let (request, _) = api_versions_request();
+        let err = stream
+            .send_request_with_version_ranges(request, &ranges, Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert_matches!(err, RequestError::ShuttingDown);
+
+        write_response(&mut broker, correlation_id, &api_versions_response()).await;
+        pending.await.unwrap().unwrap();
+        close.await.unwrap();
+        assert_matches!(
+            stream.poisoned().await.as_deref(),
+            Some(PoisonCause::Closed)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_close_fails_requests_pending_at_deadline() {
+        let (client, _broker) = duplex(1024);
+        let stream = Arc::new(ConnectionStream::new(client, ConnectionConfig::default()));
+
+        let (request, ranges) = api_versions_request();
+        let pending = spawn({
+            let stream = stream.clone();
+            async move {
+                stream
+                    .send_request_with_version_ranges(request, &ranges, Duration::from_secs(60))
+                    .await
+            }
+        });
+        while stream.in_flight_requests() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        stream.close(Instant::now() + Duration::from_secs(1)).await;
+        let err = pending.await.unwrap().unwrap_err();
+        assert_matches!(err, RequestError::Poisoned { .. });
+    }
+}
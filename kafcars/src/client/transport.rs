@@ -0,0 +1,183 @@
+use crate::client::resolver::{resolve_address, DnsResolver, SystemDnsResolver};
+use futures::{future::BoxFuture, FutureExt, TryFutureExt};
+use std::{fmt::Debug, io, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    time::timeout,
+};
+
+/// A duplex byte stream a [`ConnectionStream`](crate::client::stream::ConnectionStream) can run
+/// the Kafka protocol on.
+pub trait Transport: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
+
+impl<T> Transport for T where T: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
+
+pub type BoxedTransport = Box<dyn Transport>;
+
+/// Opens a [`Transport`] to a broker.
+///
+/// Implement this for in-memory streams in tests, proxies or custom tunnels.
+pub trait Connector: Debug + Send + Sync + 'static {
+    type Transport: Transport;
+
+    fn connect<'a>(&'a self, broker: &'a str) -> BoxFuture<'a, io::Result<Self::Transport>>;
+}
+
+/// Connects to the broker address via TCP, trying every IP address the host resolves to in turn.
+#[derive(Debug, Clone)]
+pub struct TcpConnector {
+    resolver: Arc<dyn DnsResolver>,
+    connect_timeout: Duration,
+}
+
+impl TcpConnector {
+    pub fn new() -> Self {
+        Self {
+            resolver: Arc::new(SystemDnsResolver),
+            connect_timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Resolve host names with `resolver` instead of the system resolver.
+    pub fn with_resolver<R: DnsResolver>(mut self, resolver: R) -> Self {
+        self.resolver = Arc::new(resolver);
+        self
+    }
+
+    pub(crate) fn with_shared_resolver(mut self, resolver: Arc<dyn DnsResolver>) -> Self {
+        self.resolver = resolver;
+        self
+    }
+
+    /// Give up on a single IP address after this long and try the next one.
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.connect_timeout = connect_timeout;
+        self
+    }
+}
+
+impl Default for TcpConnector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Connector for TcpConnector {
+    type Transport = TcpStream;
+
+    fn connect<'a>(&'a self, broker: &'a str) -> BoxFuture<'a, io::Result<Self::Transport>> {
+        async move {
+            let mut last_error = None;
+            for address in resolve_address(self.resolver.as_ref(), broker).await? {
+                match timeout(self.connect_timeout, TcpStream::connect(address)).await {
+                    Ok(Ok(stream)) => return Ok(stream),
+                    Ok(Err(e)) => last_error = Some(e),
+                    Err(_) => {
+                        last_error = Some(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            format!("Connecting to {address} timed out"),
+                        ))
+                    }
+                }
+            }
+            Err(last_error.unwrap_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No address found for \"{broker}\""),
+                )
+            }))
+        }
+        .boxed()
+    }
+}
+
+/// Connects every broker through the same Unix domain socket, e.g. one served by a sidecar proxy.
+#[cfg(unix)]
+#[derive(Debug, Clone)]
+pub struct UnixConnector {
+    path: std::path::PathBuf,
+}
+
+#[cfg(unix)]
+impl UnixConnector {
+    pub fn new(path: impl Into<std::path::PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+#[cfg(unix)]
+impl Connector for UnixConnector {
+    type Transport = tokio::net::UnixStream;
+
+    fn connect<'a>(&'a self, _: &'a str) -> BoxFuture<'a, io::Result<Self::Transport>> {
+        tokio::net::UnixStream::connect(&self.path).boxed()
+    }
+}
+
+/// Type erased [`Connector`], so the client does not need to be generic over it.
+#[derive(Debug, Clone)]
+pub struct BoxedConnector(Arc<dyn Connector<Transport = BoxedTransport>>);
+
+impl BoxedConnector {
+    pub fn new<C: Connector>(connector: C) -> Self {
+        Self(Arc::new(BoxingConnector(connector)))
+    }
+}
+
+impl Default for BoxedConnector {
+    fn default() -> Self {
+        Self::new(TcpConnector::new())
+    }
+}
+
+impl Connector for BoxedConnector {
+    type Transport = BoxedTransport;
+
+    fn connect<'a>(&'a self, broker: &'a str) -> BoxFuture<'a, io::Result<Self::Transport>> {
+        self.0.connect(broker)
+    }
+}
+
+#[derive(Debug)]
+struct BoxingConnector<C>(C);
+
+impl<C: Connector> Connector for BoxingConnector<C> {
+    type Transport = BoxedTransport;
+
+    fn connect<'a>(&'a self, broker: &'a str) -> BoxFuture<'a, io::Result<Self::Transport>> {
+        self.0
+            .connect(broker)
+            .map_ok(|transport| Box::new(transport) as BoxedTransport)
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::resolver::tests::FakeDns;
+    use std::collections::HashMap;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_tcp_tries_all_addresses() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let dns = FakeDns {
+            // nothing listens on 127.0.0.2
+            hosts: HashMap::from([(
+                "kafka".to_string(),
+                vec!["127.0.0.2".parse().unwrap(), "127.0.0.1".parse().unwrap()],
+            )]),
+            ..Default::default()
+        };
+
+        let connector = TcpConnector::new().with_resolver(dns);
+        let stream = connector.connect(&format!("kafka:{port}")).await.unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), listener.local_addr().unwrap());
+
+        let err = connector.connect("unknown:9092").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
@@ -1,7 +1,13 @@
-use crate::protocol::error::SerializationError;
+pub use crate::protocol::{api_key::ApiKey, error::Error as ProtocolError};
+
+use crate::{
+    backoff::BackoffError,
+    client::{producer::transaction::TransactionState, stream::RequestError},
+    protocol::error::SerializationError,
+};
 use serde::de;
 use snafu::Snafu;
-use std::{backtrace::Backtrace, fmt::Display};
+use std::{backtrace::Backtrace, fmt::Display, sync::Arc, time::Duration};
 use tokio::io;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -18,6 +24,63 @@ pub enum Error {
     Serialization { source: SerializationError },
     #[snafu(display("Error deserializing kafka message: {message}"))]
     Deserialize { message: String },
+    #[snafu(transparent)]
+    Request { source: RequestError },
+    #[snafu(display("Server responded to {api_key} with error {error}"))]
+    Server { api_key: ApiKey, error: ProtocolError },
+    #[snafu(display("Error connecting to broker \"{broker}\""))]
+    Connect { broker: String, source: io::Error },
+    #[snafu(display("All retries failed: {source}"))]
+    Connection { source: BackoffError },
+    #[snafu(display("No brokers to connect to"))]
+    NoBrokers,
+    #[snafu(display("Client is closed"))]
+    ClientClosed,
+    #[snafu(display(
+        "Response to {api_key} does not contain partition {partition} of topic \"{topic}\""
+    ))]
+    MissingPartition {
+        api_key: ApiKey,
+        topic: String,
+        partition: i32,
+    },
+    #[snafu(display(
+        "Partitioner chose partition {partition} of topic \"{topic}\", which has {partitions}"
+    ))]
+    PartitionOutOfRange {
+        topic: String,
+        partition: i32,
+        partitions: i32,
+    },
+    #[snafu(display("Aggregator failed: {source}"))]
+    Aggregator {
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[snafu(display("Record does not fit into an empty batch"))]
+    RecordTooLarge,
+    #[snafu(display("No producer memory for {size} bytes became free within {max_block:?}"))]
+    BufferExhausted { size: usize, max_block: Duration },
+    #[snafu(display("Producing the batch failed: {source}"))]
+    BatchFailed { source: Arc<Error> },
+    #[snafu(display("Idempotent producers require acks from all in-sync replicas"))]
+    IdempotenceRequiresAcksAll,
+    #[snafu(display("Cannot {operation} in transaction state {state:?}"))]
+    InvalidTransactionState {
+        operation: &'static str,
+        state: TransactionState,
+    },
+    #[snafu(display(
+        "Producer with transactional id \"{transactional_id}\" was fenced by a newer instance"
+    ))]
+    ProducerFenced { transactional_id: String },
+    #[snafu(display(
+        "Broker \"{broker}\" belongs to cluster {actual:?} instead of \"{expected}\""
+    ))]
+    ClusterIdMismatch {
+        broker: String,
+        expected: String,
+        actual: Option<String>,
+    },
 }
 
 impl de::Error for Error {
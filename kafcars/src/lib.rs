@@ -1,3 +1,7 @@
-mod client;
+mod backoff;
+pub mod client;
 pub mod error;
 pub(crate) mod protocol;
+pub mod record;
+
+pub use backoff::{BackoffConfig, BackoffError};
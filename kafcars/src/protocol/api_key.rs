@@ -1,4 +1,10 @@
-use std::fmt::{Display, Formatter};
+use crate::protocol::{
+    deserializer::{DeserializeCompact, DeserializeVersioned},
+    error::SerializationError,
+    messages::ApiVersion,
+    serializer::{SerializeCompact, SerializeVersioned},
+};
+use std::io::{Read, Write};
 
 #[derive(Debug, strum_macros::Display, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
 #[cfg_attr(test, derive(proptest_derive::Arbitrary))]
@@ -74,9 +80,9 @@ pub enum ApiKey {
     Unknown(i16),
 }
 
-impl Into<i16> for ApiKey {
-    fn into(self) -> i16 {
-        match self {
+impl From<ApiKey> for i16 {
+    fn from(api_key: ApiKey) -> i16 {
+        match api_key {
             ApiKey::Produce => 0,
             ApiKey::Fetch => 1,
             ApiKey::ListOffsets => 2,
@@ -226,6 +232,31 @@ impl From<i16> for ApiKey {
     }
 }
 
+impl<W: Write> SerializeVersioned<W> for ApiKey {
+    fn serialize_versioned(&self, writer: &mut W, version: ApiVersion) -> Result<(), SerializationError> {
+        let key: i16 = (*self).into();
+        key.serialize_versioned(writer, version)
+    }
+}
+
+impl<W: Write> SerializeCompact<W> for ApiKey {
+    fn serialize_compact(&self, writer: &mut W, version: ApiVersion) -> Result<(), SerializationError> {
+        self.serialize_versioned(writer, version)
+    }
+}
+
+impl<R: Read> DeserializeVersioned<R> for ApiKey {
+    fn deserialize_versioned(data: &mut R, version: ApiVersion) -> Result<Self, SerializationError> {
+        Ok(ApiKey::from(i16::deserialize_versioned(data, version)?))
+    }
+}
+
+impl<R: Read> DeserializeCompact<R> for ApiKey {
+    fn deserialize_compact(data: &mut R, version: ApiVersion) -> Result<Self, SerializationError> {
+        Self::deserialize_versioned(data, version)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
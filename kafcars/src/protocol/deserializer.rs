@@ -7,6 +7,7 @@ use std::{
     pin::Pin,
 };
 use tokio_util::bytes::BytesMut;
+use uuid::Uuid;
 use crate::protocol::messages::ApiVersion;
 
 pub trait DeserializeVersioned<R>
@@ -18,6 +19,17 @@ where
         Self: Sized;
 }
 
+/// Deserialization used by flexible message versions, where strings, bytes and arrays are encoded
+/// with a compact length.
+pub trait DeserializeCompact<R>
+where
+    R: Read,
+{
+    fn deserialize_compact(data: &mut R, version: ApiVersion) -> Result<Self, SerializationError>
+    where
+        Self: Sized;
+}
+
 pub trait DeserializeVersionedInto<R>
 where
     R: Read,
@@ -48,6 +60,20 @@ impl<T: DeserializeVersionedInto<Cursor<Vec<u8>>>> tokio_serde::Deserializer<T>
     }
 }
 
+macro_rules! impl_deserialize_compact_as_versioned {
+    ($($ty:ty),*) => {
+        $(
+            impl<R: Read> DeserializeCompact<R> for $ty {
+                fn deserialize_compact(data: &mut R, version: ApiVersion) -> Result<Self, SerializationError> {
+                    <$ty as DeserializeVersioned<R>>::deserialize_versioned(data, version)
+                }
+            }
+        )*
+    };
+}
+
+impl_deserialize_compact_as_versioned!(bool, i8, i16, i32, u32, i64, Uuid);
+
 impl<R: Read> DeserializeVersioned<R> for bool {
     fn deserialize_versioned(data: &mut R, _: ApiVersion) -> Result<Self, SerializationError> {
         let mut buf = [0u8; 1];
@@ -100,6 +126,118 @@ impl<R: Read> DeserializeVersioned<R> for i64 {
     }
 }
 
+impl<R: Read> DeserializeVersioned<R> for Uuid {
+    fn deserialize_versioned(data: &mut R, _: ApiVersion) -> Result<Self, SerializationError> {
+        let mut buf = [0u8; 16];
+        data.read_exact(&mut buf)?;
+        Ok(Uuid::from_bytes(buf))
+    }
+}
+
+impl<R: Read> DeserializeVersioned<R> for String {
+    fn deserialize_versioned(data: &mut R, version: ApiVersion) -> Result<Self, SerializationError> {
+        Option::<String>::deserialize_versioned(data, version)?.ok_or_else(|| Malformed {
+            message: "Unexpected null string".to_string(),
+        })
+    }
+}
+
+impl<R: Read> DeserializeCompact<R> for String {
+    fn deserialize_compact(data: &mut R, version: ApiVersion) -> Result<Self, SerializationError> {
+        Option::<String>::deserialize_compact(data, version)?.ok_or_else(|| Malformed {
+            message: "Unexpected null compact string".to_string(),
+        })
+    }
+}
+
+impl<R: Read> DeserializeVersioned<R> for Option<String> {
+    fn deserialize_versioned(data: &mut R, version: ApiVersion) -> Result<Self, SerializationError> {
+        let len = i16::deserialize_versioned(data, version)?;
+        match usize::try_from(len) {
+            Ok(len) => read_string(data, len).map(Some),
+            Err(_) if len == -1 => Ok(None),
+            Err(_) => Err(Malformed {
+                message: format!("Invalid string length {}", len),
+            }),
+        }
+    }
+}
+
+impl<R: Read> DeserializeCompact<R> for Option<String> {
+    fn deserialize_compact(data: &mut R, _: ApiVersion) -> Result<Self, SerializationError> {
+        match deserialize_unsigned_var_int(data)? {
+            0 => Ok(None),
+            len => {
+                let len = usize::try_from(len - 1).map_err(|_| SerializationError::Overflow)?;
+                read_string(data, len).map(Some)
+            }
+        }
+    }
+}
+
+impl<R: Read, T: DeserializeVersioned<R>> DeserializeVersioned<R> for Vec<T> {
+    fn deserialize_versioned(data: &mut R, version: ApiVersion) -> Result<Self, SerializationError> {
+        Option::<Vec<T>>::deserialize_versioned(data, version)?.ok_or_else(|| Malformed {
+            message: "Unexpected null array".to_string(),
+        })
+    }
+}
+
+impl<R: Read, T: DeserializeCompact<R>> DeserializeCompact<R> for Vec<T> {
+    fn deserialize_compact(data: &mut R, version: ApiVersion) -> Result<Self, SerializationError> {
+        Option::<Vec<T>>::deserialize_compact(data, version)?.ok_or_else(|| Malformed {
+            message: "Unexpected null compact array".to_string(),
+        })
+    }
+}
+
+impl<R: Read, T: DeserializeVersioned<R>> DeserializeVersioned<R> for Option<Vec<T>> {
+    fn deserialize_versioned(data: &mut R, version: ApiVersion) -> Result<Self, SerializationError> {
+        let len = i32::deserialize_versioned(data, version)?;
+        if len == -1 {
+            return Ok(None);
+        }
+        let len = usize::try_from(len).map_err(|_| Malformed {
+            message: format!("Invalid array length {}", len),
+        })?;
+        let mut elements = Vec::with_capacity(len.min(MAX_PREALLOCATED_ELEMENTS));
+        for _ in 0..len {
+            elements.push(T::deserialize_versioned(data, version)?);
+        }
+        Ok(Some(elements))
+    }
+}
+
+impl<R: Read, T: DeserializeCompact<R>> DeserializeCompact<R> for Option<Vec<T>> {
+    fn deserialize_compact(data: &mut R, version: ApiVersion) -> Result<Self, SerializationError> {
+        let len = match deserialize_unsigned_var_int(data)? {
+            0 => return Ok(None),
+            len => usize::try_from(len - 1).map_err(|_| SerializationError::Overflow)?,
+        };
+        let mut elements = Vec::with_capacity(len.min(MAX_PREALLOCATED_ELEMENTS));
+        for _ in 0..len {
+            elements.push(T::deserialize_compact(data, version)?);
+        }
+        Ok(Some(elements))
+    }
+}
+
+/// Upper bound for preallocating arrays, so a corrupt length cannot exhaust memory up front
+const MAX_PREALLOCATED_ELEMENTS: usize = 1024;
+
+fn read_string<R: Read>(data: &mut R, len: usize) -> Result<String, SerializationError> {
+    let mut buf = Vec::new();
+    data.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(Malformed {
+            message: format!("Expected string of length {}, got {}", len, buf.len()),
+        });
+    }
+    String::from_utf8(buf).map_err(|e| Malformed {
+        message: format!("Invalid utf-8 string: {}", e),
+    })
+}
+
 pub fn deserialize_unsigned_var_int<R: Read>(data: &mut R) -> Result<u64, SerializationError> {
     let mut buf = [0u8; 1];
     let mut res: u64 = 0;
@@ -124,3 +262,28 @@ pub fn deserialize_unsigned_var_int<R: Read>(data: &mut R) -> Result<u64, Serial
     }
     Ok(res)
 }
+
+/// Zigzag encoded variable length integer, as used inside of record batches.
+pub fn deserialize_var_long<R: Read>(data: &mut R) -> Result<i64, SerializationError> {
+    let val = deserialize_unsigned_var_int(data)?;
+    Ok(((val >> 1) as i64) ^ -((val & 1) as i64))
+}
+
+pub fn deserialize_nullable_bytes<R: Read>(data: &mut R) -> Result<Option<Vec<u8>>, SerializationError> {
+    let len = i32::deserialize_versioned(data, 0)?;
+    if len < 0 {
+        return Ok(None);
+    }
+    read_bytes(data, len as usize).map(Some)
+}
+
+pub fn read_bytes<R: Read>(data: &mut R, len: usize) -> Result<Vec<u8>, SerializationError> {
+    let mut buf = Vec::new();
+    data.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(Malformed {
+            message: format!("Expected {} bytes, got {}", len, buf.len()),
+        });
+    }
+    Ok(buf)
+}
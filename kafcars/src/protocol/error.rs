@@ -1,8 +1,455 @@
+use crate::protocol::{
+    deserializer::{DeserializeCompact, DeserializeVersioned},
+    messages::ApiVersion,
+};
 use snafu::Snafu;
-use std::{io, io::ErrorKind};
+use std::io::{self, ErrorKind, Read};
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub enum Error {}
+/// Error codes returned by the broker. The absence of an error (code `0`) is represented by `None`.
+#[allow(clippy::enum_variant_names)]
+#[derive(Debug, strum_macros::Display, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Error {
+    UnknownServerError,
+    OffsetOutOfRange,
+    CorruptMessage,
+    UnknownTopicOrPartition,
+    InvalidFetchSize,
+    LeaderNotAvailable,
+    NotLeaderOrFollower,
+    RequestTimedOut,
+    BrokerNotAvailable,
+    ReplicaNotAvailable,
+    MessageTooLarge,
+    StaleControllerEpoch,
+    OffsetMetadataTooLarge,
+    NetworkException,
+    CoordinatorLoadInProgress,
+    CoordinatorNotAvailable,
+    NotCoordinator,
+    InvalidTopicException,
+    RecordListTooLarge,
+    NotEnoughReplicas,
+    NotEnoughReplicasAfterAppend,
+    InvalidRequiredAcks,
+    IllegalGeneration,
+    InconsistentGroupProtocol,
+    InvalidGroupId,
+    UnknownMemberId,
+    InvalidSessionTimeout,
+    RebalanceInProgress,
+    InvalidCommitOffsetSize,
+    TopicAuthorizationFailed,
+    GroupAuthorizationFailed,
+    ClusterAuthorizationFailed,
+    InvalidTimestamp,
+    UnsupportedSaslMechanism,
+    IllegalSaslState,
+    UnsupportedVersion,
+    TopicAlreadyExists,
+    InvalidPartitions,
+    InvalidReplicationFactor,
+    InvalidReplicaAssignment,
+    InvalidConfig,
+    NotController,
+    InvalidRequest,
+    UnsupportedForMessageFormat,
+    PolicyViolation,
+    OutOfOrderSequenceNumber,
+    DuplicateSequenceNumber,
+    InvalidProducerEpoch,
+    InvalidTxnState,
+    InvalidProducerIdMapping,
+    InvalidTransactionTimeout,
+    ConcurrentTransactions,
+    TransactionCoordinatorFenced,
+    TransactionalIdAuthorizationFailed,
+    SecurityDisabled,
+    OperationNotAttempted,
+    KafkaStorageError,
+    LogDirNotFound,
+    SaslAuthenticationFailed,
+    UnknownProducerId,
+    ReassignmentInProgress,
+    DelegationTokenAuthDisabled,
+    DelegationTokenNotFound,
+    DelegationTokenOwnerMismatch,
+    DelegationTokenRequestNotAllowed,
+    DelegationTokenAuthorizationFailed,
+    DelegationTokenExpired,
+    InvalidPrincipalType,
+    NonEmptyGroup,
+    GroupIdNotFound,
+    FetchSessionIdNotFound,
+    InvalidFetchSessionEpoch,
+    ListenerNotFound,
+    TopicDeletionDisabled,
+    FencedLeaderEpoch,
+    UnknownLeaderEpoch,
+    UnsupportedCompressionType,
+    StaleBrokerEpoch,
+    OffsetNotAvailable,
+    MemberIdRequired,
+    PreferredLeaderNotAvailable,
+    GroupMaxSizeReached,
+    FencedInstanceId,
+    EligibleLeadersNotAvailable,
+    ElectionNotNeeded,
+    NoReassignmentInProgress,
+    GroupSubscribedToTopic,
+    InvalidRecord,
+    UnstableOffsetCommit,
+    ThrottlingQuotaExceeded,
+    ProducerFenced,
+    ResourceNotFound,
+    DuplicateResource,
+    UnacceptableCredential,
+    InconsistentVoterSet,
+    InvalidUpdateVersion,
+    FeatureUpdateFailed,
+    PrincipalDeserializationFailure,
+    SnapshotNotFound,
+    PositionOutOfRange,
+    UnknownTopicId,
+    DuplicateBrokerRegistration,
+    BrokerIdNotRegistered,
+    InconsistentTopicId,
+    InconsistentClusterId,
+    TransactionalIdNotFound,
+    FetchSessionTopicIdError,
+    IneligibleReplica,
+    NewLeaderElected,
+    OffsetMovedToTieredStorage,
+    FencedMemberEpoch,
+    UnreleasedInstanceId,
+    UnsupportedAssignor,
+    StaleMemberEpoch,
+    MismatchedEndpointType,
+    UnsupportedEndpointType,
+    UnknownControllerId,
+    UnknownSubscriptionId,
+    TelemetryTooLarge,
+    InvalidRegistration,
+    TransactionAbortable,
+    Unknown(i16),
+}
+
+impl Error {
+    /// Maps an error code to an error, `0` means that there was no error.
+    pub fn new(code: i16) -> Option<Self> {
+        match code {
+            0 => None,
+            -1 => Some(Error::UnknownServerError),
+            1 => Some(Error::OffsetOutOfRange),
+            2 => Some(Error::CorruptMessage),
+            3 => Some(Error::UnknownTopicOrPartition),
+            4 => Some(Error::InvalidFetchSize),
+            5 => Some(Error::LeaderNotAvailable),
+            6 => Some(Error::NotLeaderOrFollower),
+            7 => Some(Error::RequestTimedOut),
+            8 => Some(Error::BrokerNotAvailable),
+            9 => Some(Error::ReplicaNotAvailable),
+            10 => Some(Error::MessageTooLarge),
+            11 => Some(Error::StaleControllerEpoch),
+            12 => Some(Error::OffsetMetadataTooLarge),
+            13 => Some(Error::NetworkException),
+            14 => Some(Error::CoordinatorLoadInProgress),
+            15 => Some(Error::CoordinatorNotAvailable),
+            16 => Some(Error::NotCoordinator),
+            17 => Some(Error::InvalidTopicException),
+            18 => Some(Error::RecordListTooLarge),
+            19 => Some(Error::NotEnoughReplicas),
+            20 => Some(Error::NotEnoughReplicasAfterAppend),
+            21 => Some(Error::InvalidRequiredAcks),
+            22 => Some(Error::IllegalGeneration),
+            23 => Some(Error::InconsistentGroupProtocol),
+            24 => Some(Error::InvalidGroupId),
+            25 => Some(Error::UnknownMemberId),
+            26 => Some(Error::InvalidSessionTimeout),
+            27 => Some(Error::RebalanceInProgress),
+            28 => Some(Error::InvalidCommitOffsetSize),
+            29 => Some(Error::TopicAuthorizationFailed),
+            30 => Some(Error::GroupAuthorizationFailed),
+            31 => Some(Error::ClusterAuthorizationFailed),
+            32 => Some(Error::InvalidTimestamp),
+            33 => Some(Error::UnsupportedSaslMechanism),
+            34 => Some(Error::IllegalSaslState),
+            35 => Some(Error::UnsupportedVersion),
+            36 => Some(Error::TopicAlreadyExists),
+            37 => Some(Error::InvalidPartitions),
+            38 => Some(Error::InvalidReplicationFactor),
+            39 => Some(Error::InvalidReplicaAssignment),
+            40 => Some(Error::InvalidConfig),
+            41 => Some(Error::NotController),
+            42 => Some(Error::InvalidRequest),
+            43 => Some(Error::UnsupportedForMessageFormat),
+            44 => Some(Error::PolicyViolation),
+            45 => Some(Error::OutOfOrderSequenceNumber),
+            46 => Some(Error::DuplicateSequenceNumber),
+            47 => Some(Error::InvalidProducerEpoch),
+            48 => Some(Error::InvalidTxnState),
+            49 => Some(Error::InvalidProducerIdMapping),
+            50 => Some(Error::InvalidTransactionTimeout),
+            51 => Some(Error::ConcurrentTransactions),
+            52 => Some(Error::TransactionCoordinatorFenced),
+            53 => Some(Error::TransactionalIdAuthorizationFailed),
+            54 => Some(Error::SecurityDisabled),
+            55 => Some(Error::OperationNotAttempted),
+            56 => Some(Error::KafkaStorageError),
+            57 => Some(Error::LogDirNotFound),
+            58 => Some(Error::SaslAuthenticationFailed),
+            59 => Some(Error::UnknownProducerId),
+            60 => Some(Error::ReassignmentInProgress),
+            61 => Some(Error::DelegationTokenAuthDisabled),
+            62 => Some(Error::DelegationTokenNotFound),
+            63 => Some(Error::DelegationTokenOwnerMismatch),
+            64 => Some(Error::DelegationTokenRequestNotAllowed),
+            65 => Some(Error::DelegationTokenAuthorizationFailed),
+            66 => Some(Error::DelegationTokenExpired),
+            67 => Some(Error::InvalidPrincipalType),
+            68 => Some(Error::NonEmptyGroup),
+            69 => Some(Error::GroupIdNotFound),
+            70 => Some(Error::FetchSessionIdNotFound),
+            71 => Some(Error::InvalidFetchSessionEpoch),
+            72 => Some(Error::ListenerNotFound),
+            73 => Some(Error::TopicDeletionDisabled),
+            74 => Some(Error::FencedLeaderEpoch),
+            75 => Some(Error::UnknownLeaderEpoch),
+            76 => Some(Error::UnsupportedCompressionType),
+            77 => Some(Error::StaleBrokerEpoch),
+            78 => Some(Error::OffsetNotAvailable),
+            79 => Some(Error::MemberIdRequired),
+            80 => Some(Error::PreferredLeaderNotAvailable),
+            81 => Some(Error::GroupMaxSizeReached),
+            82 => Some(Error::FencedInstanceId),
+            83 => Some(Error::EligibleLeadersNotAvailable),
+            84 => Some(Error::ElectionNotNeeded),
+            85 => Some(Error::NoReassignmentInProgress),
+            86 => Some(Error::GroupSubscribedToTopic),
+            87 => Some(Error::InvalidRecord),
+            88 => Some(Error::UnstableOffsetCommit),
+            89 => Some(Error::ThrottlingQuotaExceeded),
+            90 => Some(Error::ProducerFenced),
+            91 => Some(Error::ResourceNotFound),
+            92 => Some(Error::DuplicateResource),
+            93 => Some(Error::UnacceptableCredential),
+            94 => Some(Error::InconsistentVoterSet),
+            95 => Some(Error::InvalidUpdateVersion),
+            96 => Some(Error::FeatureUpdateFailed),
+            97 => Some(Error::PrincipalDeserializationFailure),
+            98 => Some(Error::SnapshotNotFound),
+            99 => Some(Error::PositionOutOfRange),
+            100 => Some(Error::UnknownTopicId),
+            101 => Some(Error::DuplicateBrokerRegistration),
+            102 => Some(Error::BrokerIdNotRegistered),
+            103 => Some(Error::InconsistentTopicId),
+            104 => Some(Error::InconsistentClusterId),
+            105 => Some(Error::TransactionalIdNotFound),
+            106 => Some(Error::FetchSessionTopicIdError),
+            107 => Some(Error::IneligibleReplica),
+            108 => Some(Error::NewLeaderElected),
+            109 => Some(Error::OffsetMovedToTieredStorage),
+            110 => Some(Error::FencedMemberEpoch),
+            111 => Some(Error::UnreleasedInstanceId),
+            112 => Some(Error::UnsupportedAssignor),
+            113 => Some(Error::StaleMemberEpoch),
+            114 => Some(Error::MismatchedEndpointType),
+            115 => Some(Error::UnsupportedEndpointType),
+            116 => Some(Error::UnknownControllerId),
+            117 => Some(Error::UnknownSubscriptionId),
+            118 => Some(Error::TelemetryTooLarge),
+            119 => Some(Error::InvalidRegistration),
+            120 => Some(Error::TransactionAbortable),
+            _ => Some(Error::Unknown(code)),
+        }
+    }
+
+    /// Whether a request failing with this error may succeed when it is retried.
+    pub fn is_retriable(&self) -> bool {
+        matches!(
+            self,
+            Error::CorruptMessage
+                | Error::UnknownTopicOrPartition
+                | Error::LeaderNotAvailable
+                | Error::NotLeaderOrFollower
+                | Error::RequestTimedOut
+                | Error::ReplicaNotAvailable
+                | Error::NetworkException
+                | Error::CoordinatorLoadInProgress
+                | Error::CoordinatorNotAvailable
+                | Error::NotCoordinator
+                | Error::NotEnoughReplicas
+                | Error::NotEnoughReplicasAfterAppend
+                | Error::NotController
+                | Error::ConcurrentTransactions
+                | Error::KafkaStorageError
+                | Error::FetchSessionIdNotFound
+                | Error::InvalidFetchSessionEpoch
+                | Error::ListenerNotFound
+                | Error::FencedLeaderEpoch
+                | Error::UnknownLeaderEpoch
+                | Error::OffsetNotAvailable
+                | Error::PreferredLeaderNotAvailable
+                | Error::EligibleLeadersNotAvailable
+                | Error::UnstableOffsetCommit
+                | Error::ThrottlingQuotaExceeded
+                | Error::UnknownTopicId
+                | Error::InconsistentTopicId
+        )
+    }
+
+    /// Whether this error means the cached cluster metadata is outdated, e.g. because the
+    /// partition leader or the controller moved.
+    pub fn invalidates_metadata(&self) -> bool {
+        matches!(
+            self,
+            Error::NotLeaderOrFollower
+                | Error::UnknownTopicOrPartition
+                | Error::LeaderNotAvailable
+                | Error::NotController
+        )
+    }
+}
+
+impl From<Error> for i16 {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::UnknownServerError => -1,
+            Error::OffsetOutOfRange => 1,
+            Error::CorruptMessage => 2,
+            Error::UnknownTopicOrPartition => 3,
+            Error::InvalidFetchSize => 4,
+            Error::LeaderNotAvailable => 5,
+            Error::NotLeaderOrFollower => 6,
+            Error::RequestTimedOut => 7,
+            Error::BrokerNotAvailable => 8,
+            Error::ReplicaNotAvailable => 9,
+            Error::MessageTooLarge => 10,
+            Error::StaleControllerEpoch => 11,
+            Error::OffsetMetadataTooLarge => 12,
+            Error::NetworkException => 13,
+            Error::CoordinatorLoadInProgress => 14,
+            Error::CoordinatorNotAvailable => 15,
+            Error::NotCoordinator => 16,
+            Error::InvalidTopicException => 17,
+            Error::RecordListTooLarge => 18,
+            Error::NotEnoughReplicas => 19,
+            Error::NotEnoughReplicasAfterAppend => 20,
+            Error::InvalidRequiredAcks => 21,
+            Error::IllegalGeneration => 22,
+            Error::InconsistentGroupProtocol => 23,
+            Error::InvalidGroupId => 24,
+            Error::UnknownMemberId => 25,
+            Error::InvalidSessionTimeout => 26,
+            Error::RebalanceInProgress => 27,
+            Error::InvalidCommitOffsetSize => 28,
+            Error::TopicAuthorizationFailed => 29,
+            Error::GroupAuthorizationFailed => 30,
+            Error::ClusterAuthorizationFailed => 31,
+            Error::InvalidTimestamp => 32,
+            Error::UnsupportedSaslMechanism => 33,
+            Error::IllegalSaslState => 34,
+            Error::UnsupportedVersion => 35,
+            Error::TopicAlreadyExists => 36,
+            Error::InvalidPartitions => 37,
+            Error::InvalidReplicationFactor => 38,
+            Error::InvalidReplicaAssignment => 39,
+            Error::InvalidConfig => 40,
+            Error::NotController => 41,
+            Error::InvalidRequest => 42,
+            Error::UnsupportedForMessageFormat => 43,
+            Error::PolicyViolation => 44,
+            Error::OutOfOrderSequenceNumber => 45,
+            Error::DuplicateSequenceNumber => 46,
+            Error::InvalidProducerEpoch => 47,
+            Error::InvalidTxnState => 48,
+            Error::InvalidProducerIdMapping => 49,
+            Error::InvalidTransactionTimeout => 50,
+            Error::ConcurrentTransactions => 51,
+            Error::TransactionCoordinatorFenced => 52,
+            Error::TransactionalIdAuthorizationFailed => 53,
+            Error::SecurityDisabled => 54,
+            Error::OperationNotAttempted => 55,
+            Error::KafkaStorageError => 56,
+            Error::LogDirNotFound => 57,
+            Error::SaslAuthenticationFailed => 58,
+            Error::UnknownProducerId => 59,
+            Error::ReassignmentInProgress => 60,
+            Error::DelegationTokenAuthDisabled => 61,
+            Error::DelegationTokenNotFound => 62,
+            Error::DelegationTokenOwnerMismatch => 63,
+            Error::DelegationTokenRequestNotAllowed => 64,
+            Error::DelegationTokenAuthorizationFailed => 65,
+            Error::DelegationTokenExpired => 66,
+            Error::InvalidPrincipalType => 67,
+            Error::NonEmptyGroup => 68,
+            Error::GroupIdNotFound => 69,
+            Error::FetchSessionIdNotFound => 70,
+            Error::InvalidFetchSessionEpoch => 71,
+            Error::ListenerNotFound => 72,
+            Error::TopicDeletionDisabled => 73,
+            Error::FencedLeaderEpoch => 74,
+            Error::UnknownLeaderEpoch => 75,
+            Error::UnsupportedCompressionType => 76,
+            Error::StaleBrokerEpoch => 77,
+            Error::OffsetNotAvailable => 78,
+            Error::MemberIdRequired => 79,
+            Error::PreferredLeaderNotAvailable => 80,
+            Error::GroupMaxSizeReached => 81,
+            Error::FencedInstanceId => 82,
+            Error::EligibleLeadersNotAvailable => 83,
+            Error::ElectionNotNeeded => 84,
+            Error::NoReassignmentInProgress => 85,
+            Error::GroupSubscribedToTopic => 86,
+            Error::InvalidRecord => 87,
+            Error::UnstableOffsetCommit => 88,
+            Error::ThrottlingQuotaExceeded => 89,
+            Error::ProducerFenced => 90,
+            Error::ResourceNotFound => 91,
+            Error::DuplicateResource => 92,
+            Error::UnacceptableCredential => 93,
+            Error::InconsistentVoterSet => 94,
+            Error::InvalidUpdateVersion => 95,
+            Error::FeatureUpdateFailed => 96,
+            Error::PrincipalDeserializationFailure => 97,
+            Error::SnapshotNotFound => 98,
+            Error::PositionOutOfRange => 99,
+            Error::UnknownTopicId => 100,
+            Error::DuplicateBrokerRegistration => 101,
+            Error::BrokerIdNotRegistered => 102,
+            Error::InconsistentTopicId => 103,
+            Error::InconsistentClusterId => 104,
+            Error::TransactionalIdNotFound => 105,
+            Error::FetchSessionTopicIdError => 106,
+            Error::IneligibleReplica => 107,
+            Error::NewLeaderElected => 108,
+            Error::OffsetMovedToTieredStorage => 109,
+            Error::FencedMemberEpoch => 110,
+            Error::UnreleasedInstanceId => 111,
+            Error::UnsupportedAssignor => 112,
+            Error::StaleMemberEpoch => 113,
+            Error::MismatchedEndpointType => 114,
+            Error::UnsupportedEndpointType => 115,
+            Error::UnknownControllerId => 116,
+            Error::UnknownSubscriptionId => 117,
+            Error::TelemetryTooLarge => 118,
+            Error::InvalidRegistration => 119,
+            Error::TransactionAbortable => 120,
+            Error::Unknown(code) => code,
+        }
+    }
+}
+
+impl<R: Read> DeserializeVersioned<R> for Option<Error> {
+    fn deserialize_versioned(data: &mut R, version: ApiVersion) -> Result<Self, SerializationError> {
+        Ok(Error::new(i16::deserialize_versioned(data, version)?))
+    }
+}
+
+impl<R: Read> DeserializeCompact<R> for Option<Error> {
+    fn deserialize_compact(data: &mut R, version: ApiVersion) -> Result<Self, SerializationError> {
+        Self::deserialize_versioned(data, version)
+    }
+}
 
 #[derive(Snafu, Debug)]
 pub enum SerializationError {
@@ -30,3 +477,17 @@ impl From<SerializationError> for io::Error {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    proptest! {
+        #[test]
+        fn test_error_code(code: i16) {
+            let code2 = Error::new(code).map(i16::from).unwrap_or_default();
+            assert_eq!(code, code2);
+        }
+    }
+}
@@ -0,0 +1,35 @@
+use crate::protocol::{api_key::ApiKey, error::Error, messages::TaggedFields};
+use kafcars_inner_macros::{KafkaRequest, KafkaResponse};
+
+#[derive(Debug, KafkaRequest)]
+#[kafka(
+    response = "AddOffsetsToTxnResponse",
+    api_key = "ApiKey::AddOffsetsToTxn",
+    max_version = "3",
+    tag_version = "3"
+)]
+pub struct AddOffsetsToTxnRequest {
+    /// The transactional id corresponding to the transaction
+    pub transactional_id: String,
+    /// Current producer id in use by the transactional id
+    pub producer_id: i64,
+    /// Current epoch associated with the producer id
+    pub producer_epoch: i16,
+    /// The unique group identifier
+    pub group_id: String,
+    #[kafka(min_version = 3)]
+    pub tagged_fields: Option<TaggedFields>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, KafkaResponse)]
+#[kafka(max_version = 3, tag_version = 3)]
+pub struct AddOffsetsToTxnResponse {
+    /// The duration in milliseconds for which the request was throttled due to
+    /// a quota violation, or zero if the request did not violate any quota.
+    pub throttle_time_ms: i32,
+    /// The error if any
+    #[kafka(nullable)]
+    pub error: Option<Error>,
+    #[kafka(min_version = 3)]
+    pub tagged_fields: Option<TaggedFields>,
+}
@@ -0,0 +1,68 @@
+use crate::protocol::{api_key::ApiKey, error::Error, messages::TaggedFields};
+use kafcars_inner_macros::{KafkaRequest, KafkaResponse, VersionedDeserialize, VersionedSerialize};
+
+#[derive(Debug, KafkaRequest)]
+#[kafka(
+    response = "AddPartitionsToTxnResponse",
+    api_key = "ApiKey::AddPartitionsToTxn",
+    max_version = "3",
+    tag_version = "3"
+)]
+pub struct AddPartitionsToTxnRequest {
+    /// The transactional id corresponding to the transaction
+    pub transactional_id: String,
+    /// Current producer id in use by the transactional id
+    pub producer_id: i64,
+    /// Current epoch associated with the producer id
+    pub producer_epoch: i16,
+    /// The partitions to add to the transaction
+    pub topics: Vec<AddPartitionsToTxnRequestTopic>,
+    #[kafka(min_version = 3)]
+    pub tagged_fields: Option<TaggedFields>,
+}
+
+#[derive(Debug, VersionedSerialize)]
+#[kafka(max_version = 3, tag_version = 3)]
+pub struct AddPartitionsToTxnRequestTopic {
+    /// The name of the topic
+    pub name: String,
+    /// The partition indexes to add to the transaction
+    pub partitions: Vec<i32>,
+    #[kafka(min_version = 3)]
+    pub tagged_fields: Option<TaggedFields>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, KafkaResponse)]
+#[kafka(max_version = 3, tag_version = 3)]
+pub struct AddPartitionsToTxnResponse {
+    /// The duration in milliseconds for which the request was throttled due to
+    /// a quota violation, or zero if the request did not violate any quota.
+    pub throttle_time_ms: i32,
+    /// The results for each topic
+    pub results: Vec<AddPartitionsToTxnResponseTopic>,
+    #[kafka(min_version = 3)]
+    pub tagged_fields: Option<TaggedFields>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, VersionedDeserialize)]
+#[kafka(max_version = 3, tag_version = 3)]
+pub struct AddPartitionsToTxnResponseTopic {
+    /// The topic name
+    pub name: String,
+    /// The results for each partition
+    pub results: Vec<AddPartitionsToTxnResponsePartition>,
+    #[kafka(min_version = 3)]
+    pub tagged_fields: Option<TaggedFields>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, VersionedDeserialize)]
+#[kafka(max_version = 3, tag_version = 3)]
+pub struct AddPartitionsToTxnResponsePartition {
+    /// The partition index
+    pub partition_index: i32,
+    /// The error if any
+    #[kafka(nullable)]
+    pub error: Option<Error>,
+    #[kafka(min_version = 3)]
+    pub tagged_fields: Option<TaggedFields>,
+}
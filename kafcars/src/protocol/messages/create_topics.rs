@@ -0,0 +1,76 @@
+use crate::protocol::{api_key::ApiKey, error::Error};
+use kafcars_inner_macros::{KafkaRequest, KafkaResponse, VersionedDeserialize, VersionedSerialize};
+
+#[derive(Debug, KafkaRequest)]
+#[kafka(
+    response = "CreateTopicsResponse",
+    api_key = "ApiKey::CreateTopics",
+    min_version = "2",
+    max_version = "4"
+)]
+pub struct CreateTopicsRequest {
+    /// The topics to create
+    pub topics: Vec<CreateTopicsRequestTopic>,
+    /// How long to wait for the topics to be created, in milliseconds
+    pub timeout_ms: i32,
+    /// If true, check that the topics can be created as specified, but don't create anything
+    pub validate_only: bool,
+}
+
+#[derive(Debug, VersionedSerialize)]
+#[kafka(max_version = 4)]
+pub struct CreateTopicsRequestTopic {
+    /// The topic name
+    pub name: String,
+    /// The number of partitions, or -1 if assignments are given or for the broker default
+    pub num_partitions: i32,
+    /// The number of replicas of each partition, or -1 if assignments are given or for the
+    /// broker default
+    pub replication_factor: i16,
+    /// The replicas of each partition, empty to let the broker assign them
+    pub assignments: Vec<CreateTopicsRequestAssignment>,
+    /// Custom topic configurations
+    pub configs: Vec<CreateTopicsRequestConfig>,
+}
+
+#[derive(Debug, VersionedSerialize)]
+#[kafka(max_version = 4)]
+pub struct CreateTopicsRequestAssignment {
+    /// The partition index
+    pub partition_index: i32,
+    /// The brokers to place the partition on
+    pub broker_ids: Vec<i32>,
+}
+
+#[derive(Debug, VersionedSerialize)]
+#[kafka(max_version = 4)]
+pub struct CreateTopicsRequestConfig {
+    /// The configuration name
+    pub name: String,
+    /// The configuration value
+    #[kafka(nullable)]
+    pub value: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, KafkaResponse)]
+#[kafka(max_version = 4)]
+pub struct CreateTopicsResponse {
+    /// The duration in milliseconds for which the request was throttled due to
+    /// a quota violation, or zero if the request did not violate any quota.
+    pub throttle_time_ms: i32,
+    /// Results for each topic we tried to create
+    pub topics: Vec<CreateTopicsResponseTopic>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, VersionedDeserialize)]
+#[kafka(max_version = 4)]
+pub struct CreateTopicsResponseTopic {
+    /// The topic name
+    pub name: String,
+    /// The error if any
+    #[kafka(nullable)]
+    pub error: Option<Error>,
+    /// The error message, or `None` if there was no error
+    #[kafka(nullable)]
+    pub error_message: Option<String>,
+}
@@ -0,0 +1,64 @@
+use crate::protocol::{api_key::ApiKey, error::Error};
+use kafcars_inner_macros::{KafkaRequest, KafkaResponse, VersionedDeserialize, VersionedSerialize};
+
+#[derive(Debug, KafkaRequest)]
+#[kafka(
+    response = "DeleteRecordsResponse",
+    api_key = "ApiKey::DeleteRecords",
+    max_version = "1"
+)]
+pub struct DeleteRecordsRequest {
+    /// Each topic that we want to delete records from
+    pub topics: Vec<DeleteRecordsRequestTopic>,
+    /// How long to wait for the deletion to complete, in milliseconds
+    pub timeout_ms: i32,
+}
+
+#[derive(Debug, VersionedSerialize)]
+#[kafka(max_version = 1)]
+pub struct DeleteRecordsRequestTopic {
+    /// The topic name
+    pub name: String,
+    /// Each partition that we want to delete records from
+    pub partitions: Vec<DeleteRecordsRequestPartition>,
+}
+
+#[derive(Debug, VersionedSerialize)]
+#[kafka(max_version = 1)]
+pub struct DeleteRecordsRequestPartition {
+    /// The partition index
+    pub partition_index: i32,
+    /// The deletion offset, records before it are deleted
+    pub offset: i64,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, KafkaResponse)]
+#[kafka(max_version = 1)]
+pub struct DeleteRecordsResponse {
+    /// The duration in milliseconds for which the request was throttled due to
+    /// a quota violation, or zero if the request did not violate any quota.
+    pub throttle_time_ms: i32,
+    /// Each topic that we wanted to delete records from
+    pub topics: Vec<DeleteRecordsResponseTopic>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, VersionedDeserialize)]
+#[kafka(max_version = 1)]
+pub struct DeleteRecordsResponseTopic {
+    /// The topic name
+    pub name: String,
+    /// Each partition that we wanted to delete records from
+    pub partitions: Vec<DeleteRecordsResponsePartition>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, VersionedDeserialize)]
+#[kafka(max_version = 1)]
+pub struct DeleteRecordsResponsePartition {
+    /// The partition index
+    pub partition_index: i32,
+    /// The partition low water mark
+    pub low_watermark: i64,
+    /// The deletion error if any
+    #[kafka(nullable)]
+    pub error: Option<Error>,
+}
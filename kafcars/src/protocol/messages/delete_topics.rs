@@ -0,0 +1,36 @@
+use crate::protocol::{api_key::ApiKey, error::Error};
+use kafcars_inner_macros::{KafkaRequest, KafkaResponse, VersionedDeserialize};
+
+#[derive(Debug, KafkaRequest)]
+#[kafka(
+    response = "DeleteTopicsResponse",
+    api_key = "ApiKey::DeleteTopics",
+    min_version = "1",
+    max_version = "3"
+)]
+pub struct DeleteTopicsRequest {
+    /// The names of the topics to delete
+    pub topic_names: Vec<String>,
+    /// How long to wait for the deletion to complete, in milliseconds
+    pub timeout_ms: i32,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, KafkaResponse)]
+#[kafka(max_version = 3)]
+pub struct DeleteTopicsResponse {
+    /// The duration in milliseconds for which the request was throttled due to
+    /// a quota violation, or zero if the request did not violate any quota.
+    pub throttle_time_ms: i32,
+    /// The results for each topic we tried to delete
+    pub responses: Vec<DeleteTopicsResponseTopic>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, VersionedDeserialize)]
+#[kafka(max_version = 3)]
+pub struct DeleteTopicsResponseTopic {
+    /// The topic name
+    pub name: String,
+    /// The deletion error if any
+    #[kafka(nullable)]
+    pub error: Option<Error>,
+}
@@ -0,0 +1,137 @@
+use crate::protocol::{
+    api_key::ApiKey,
+    deserializer::{DeserializeCompact, DeserializeVersioned},
+    error::{Error, SerializationError},
+    messages::TaggedFields,
+    serializer::{SerializeCompact, SerializeVersioned},
+};
+use kafcars_inner_macros::{KafkaRequest, KafkaResponse, VersionedDeserialize, VersionedSerialize};
+use std::io::{Read, Write};
+use uuid::Uuid;
+
+#[derive(Debug, KafkaRequest)]
+#[kafka(
+    response = "DescribeTopicPartitionsResponse",
+    api_key = "ApiKey::DescribeTopicPartitions",
+    max_version = "0",
+    tag_version = "0"
+)]
+pub struct DescribeTopicPartitionsRequest {
+    /// The topics to fetch details for
+    pub topics: Vec<DescribeTopicPartitionsRequestTopic>,
+    /// The maximum number of partitions included in the response
+    pub response_partition_limit: i32,
+    /// The first topic and partition index to fetch details for
+    #[kafka(nullable, serialize_with = "serialize_cursor")]
+    pub cursor: Option<DescribeTopicPartitionsCursor>,
+    #[kafka(min_version = 0)]
+    pub tagged_fields: Option<TaggedFields>,
+}
+
+#[derive(Debug, VersionedSerialize)]
+#[kafka(max_version = 0, tag_version = 0)]
+pub struct DescribeTopicPartitionsRequestTopic {
+    /// The topic name
+    pub name: String,
+    #[kafka(min_version = 0)]
+    pub tagged_fields: Option<TaggedFields>,
+}
+
+/// Position to continue a paginated description at.
+#[derive(Debug, PartialEq, Eq, Clone, VersionedSerialize, VersionedDeserialize)]
+#[kafka(max_version = 0, tag_version = 0)]
+pub struct DescribeTopicPartitionsCursor {
+    /// The name of the topic
+    pub topic_name: String,
+    /// The partition index to start with
+    pub partition_index: i32,
+    #[kafka(min_version = 0)]
+    pub tagged_fields: Option<TaggedFields>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, KafkaResponse)]
+#[kafka(max_version = 0, tag_version = 0)]
+pub struct DescribeTopicPartitionsResponse {
+    /// The duration in milliseconds for which the request was throttled due to
+    /// a quota violation, or zero if the request did not violate any quota.
+    pub throttle_time_ms: i32,
+    /// Each topic in the response
+    pub topics: Vec<DescribeTopicPartitionsResponseTopic>,
+    /// The position to continue at, `None` once all partitions were described
+    #[kafka(nullable, deserialize_with = "deserialize_cursor")]
+    pub next_cursor: Option<DescribeTopicPartitionsCursor>,
+    #[kafka(min_version = 0)]
+    pub tagged_fields: Option<TaggedFields>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, VersionedDeserialize)]
+#[kafka(max_version = 0, tag_version = 0)]
+pub struct DescribeTopicPartitionsResponseTopic {
+    /// The topic error if any
+    #[kafka(nullable)]
+    pub error: Option<Error>,
+    /// The topic name
+    #[kafka(nullable)]
+    pub name: Option<String>,
+    /// The topic id
+    pub topic_id: Uuid,
+    /// True if the topic is internal
+    pub is_internal: bool,
+    /// Each partition in the topic
+    pub partitions: Vec<DescribeTopicPartitionsResponsePartition>,
+    /// 32-bit bitfield to represent authorized operations for this topic
+    pub topic_authorized_operations: i32,
+    #[kafka(min_version = 0)]
+    pub tagged_fields: Option<TaggedFields>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, VersionedDeserialize)]
+#[kafka(max_version = 0, tag_version = 0)]
+pub struct DescribeTopicPartitionsResponsePartition {
+    /// The partition error if any
+    #[kafka(nullable)]
+    pub error: Option<Error>,
+    /// The partition index
+    pub partition_index: i32,
+    /// The ID of the leader broker
+    pub leader_id: i32,
+    /// The leader epoch of this partition
+    pub leader_epoch: i32,
+    /// The set of all nodes that host this partition
+    pub replica_nodes: Vec<i32>,
+    /// The set of all nodes that are in sync with the leader for this partition
+    pub isr_nodes: Vec<i32>,
+    /// The eligible leader replicas of this partition
+    #[kafka(nullable)]
+    pub eligible_leader_replicas: Option<Vec<i32>>,
+    /// The last known eligible leader replicas
+    #[kafka(nullable)]
+    pub last_known_elr: Option<Vec<i32>>,
+    /// The set of offline replicas of this partition
+    pub offline_replicas: Vec<i32>,
+    #[kafka(min_version = 0)]
+    pub tagged_fields: Option<TaggedFields>,
+}
+
+/// Nullable structs are prefixed by -1 if they are null and 1 otherwise.
+fn serialize_cursor<W: Write>(
+    cursor: &Option<DescribeTopicPartitionsCursor>,
+    writer: &mut W,
+) -> Result<(), SerializationError> {
+    match cursor {
+        None => (-1i8).serialize_versioned(writer, 0),
+        Some(cursor) => {
+            1i8.serialize_versioned(writer, 0)?;
+            cursor.serialize_compact(writer, 0)
+        }
+    }
+}
+
+fn deserialize_cursor<R: Read>(
+    data: &mut R,
+) -> Result<Option<DescribeTopicPartitionsCursor>, SerializationError> {
+    Ok(match i8::deserialize_versioned(data, 0)? {
+        -1 => None,
+        _ => Some(DescribeTopicPartitionsCursor::deserialize_compact(data, 0)?),
+    })
+}
@@ -0,0 +1,41 @@
+use crate::protocol::{api_key::ApiKey, error::Error, messages::TaggedFields};
+use kafcars_inner_macros::{KafkaRequest, KafkaResponse};
+
+#[derive(Debug, KafkaRequest)]
+#[kafka(
+    response = "EndTxnResponse",
+    api_key = "ApiKey::EndTxn",
+    max_version = "5",
+    tag_version = "3"
+)]
+pub struct EndTxnRequest {
+    /// The id of the transaction to end
+    pub transactional_id: String,
+    /// The producer id
+    pub producer_id: i64,
+    /// The current epoch associated with the producer
+    pub producer_epoch: i16,
+    /// True if the transaction was committed, false if it was aborted
+    pub committed: bool,
+    #[kafka(min_version = 3)]
+    pub tagged_fields: Option<TaggedFields>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, KafkaResponse)]
+#[kafka(max_version = 5, tag_version = 3)]
+pub struct EndTxnResponse {
+    /// The duration in milliseconds for which the request was throttled due to
+    /// a quota violation, or zero if the request did not violate any quota.
+    pub throttle_time_ms: i32,
+    /// The error if any
+    #[kafka(nullable)]
+    pub error: Option<Error>,
+    /// The producer id for the next transaction
+    #[kafka(min_version = 5)]
+    pub producer_id: Option<i64>,
+    /// The epoch for the next transaction, bumped by the coordinator when it ended this one
+    #[kafka(min_version = 5)]
+    pub producer_epoch: Option<i16>,
+    #[kafka(min_version = 3)]
+    pub tagged_fields: Option<TaggedFields>,
+}
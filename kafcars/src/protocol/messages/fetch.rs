@@ -0,0 +1,152 @@
+use crate::protocol::{api_key::ApiKey, deserializer::deserialize_nullable_bytes, error::Error};
+use kafcars_inner_macros::{KafkaRequest, KafkaResponse, VersionedDeserialize, VersionedSerialize};
+
+#[derive(Debug, KafkaRequest)]
+#[kafka(
+    response = "FetchResponse",
+    api_key = "ApiKey::Fetch",
+    min_version = "4",
+    max_version = "11"
+)]
+pub struct FetchRequest {
+    /// The broker ID of the follower, of -1 if this request is from a consumer
+    pub replica_id: i32,
+    /// The maximum time in milliseconds to wait for the response
+    pub max_wait_ms: i32,
+    /// The minimum bytes to accumulate in the response
+    pub min_bytes: i32,
+    /// The maximum bytes to fetch
+    pub max_bytes: i32,
+    /// 0 to read uncommitted records and 1 to only read committed records
+    pub isolation_level: i8,
+    /// The fetch session ID, 0 to not use a fetch session.
+    ///
+    /// Added in version 7
+    #[kafka(min_version = 7)]
+    pub session_id: i32,
+    /// The fetch session epoch, -1 to not use a fetch session.
+    ///
+    /// Added in version 7
+    #[kafka(min_version = 7)]
+    pub session_epoch: i32,
+    /// The topics to fetch
+    pub topics: Vec<FetchRequestTopic>,
+    /// In an incremental fetch request, the partitions to remove.
+    ///
+    /// Added in version 7
+    #[kafka(min_version = 7)]
+    pub forgotten_topics_data: Vec<FetchRequestForgottenTopic>,
+    /// Rack ID of the consumer making this request.
+    ///
+    /// Added in version 11
+    #[kafka(min_version = 11)]
+    pub rack_id: String,
+}
+
+#[derive(Debug, VersionedSerialize)]
+#[kafka(max_version = 11)]
+pub struct FetchRequestTopic {
+    /// The name of the topic to fetch
+    pub topic: String,
+    /// The partitions to fetch
+    pub partitions: Vec<FetchRequestPartition>,
+}
+
+#[derive(Debug, VersionedSerialize)]
+#[kafka(max_version = 11)]
+pub struct FetchRequestPartition {
+    /// The partition index
+    pub partition: i32,
+    /// The current leader epoch of the partition, -1 if unknown.
+    ///
+    /// Added in version 9
+    #[kafka(min_version = 9)]
+    pub current_leader_epoch: i32,
+    /// The message offset
+    pub fetch_offset: i64,
+    /// The earliest available offset of the follower replica, -1 for consumers.
+    ///
+    /// Added in version 5
+    #[kafka(min_version = 5)]
+    pub log_start_offset: i64,
+    /// The maximum bytes to fetch from this partition
+    pub partition_max_bytes: i32,
+}
+
+#[derive(Debug, VersionedSerialize)]
+#[kafka(max_version = 11)]
+pub struct FetchRequestForgottenTopic {
+    /// The topic name
+    pub topic: String,
+    /// The partitions indexes to forget
+    pub partitions: Vec<i32>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, KafkaResponse)]
+#[kafka(max_version = 11)]
+pub struct FetchResponse {
+    /// The duration in milliseconds for which the request was throttled due to
+    /// a quota violation, or zero if the request did not violate any quota.
+    pub throttle_time_ms: i32,
+    /// The top level response error if any.
+    ///
+    /// Added in version 7
+    #[kafka(min_version = 7, nullable)]
+    pub error: Option<Error>,
+    /// The fetch session ID.
+    ///
+    /// Added in version 7
+    #[kafka(min_version = 7)]
+    pub session_id: Option<i32>,
+    /// The response topics
+    pub responses: Vec<FetchResponseTopic>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, VersionedDeserialize)]
+#[kafka(max_version = 11)]
+pub struct FetchResponseTopic {
+    /// The topic name
+    pub topic: String,
+    /// The topic partitions
+    pub partitions: Vec<FetchResponsePartition>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, VersionedDeserialize)]
+#[kafka(max_version = 11)]
+pub struct FetchResponsePartition {
+    /// The partition index
+    pub partition_index: i32,
+    /// The error if any
+    #[kafka(nullable)]
+    pub error: Option<Error>,
+    /// The current high water mark
+    pub high_watermark: i64,
+    /// The last stable offset of the partition, the offset up to which all transactional
+    /// records are decided
+    pub last_stable_offset: i64,
+    /// The current log start offset.
+    ///
+    /// Added in version 5
+    #[kafka(min_version = 5)]
+    pub log_start_offset: Option<i64>,
+    /// The aborted transactions
+    #[kafka(nullable)]
+    pub aborted_transactions: Option<Vec<FetchResponseAbortedTransaction>>,
+    /// The preferred read replica for the consumer to use on its next fetch request.
+    ///
+    /// Added in version 11
+    #[kafka(min_version = 11)]
+    pub preferred_read_replica: Option<i32>,
+    /// The encoded record batches
+    #[kafka(nullable, deserialize_with = "deserialize_nullable_bytes")]
+    pub records: Option<Vec<u8>>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, VersionedDeserialize)]
+#[kafka(max_version = 11)]
+pub struct FetchResponseAbortedTransaction {
+    /// The producer id associated with the aborted transaction
+    pub producer_id: i64,
+    /// The first offset in the aborted transaction
+    pub first_offset: i64,
+}
@@ -0,0 +1,58 @@
+use crate::protocol::{api_key::ApiKey, error::Error, messages::TaggedFields};
+use kafcars_inner_macros::{KafkaRequest, KafkaResponse};
+
+/// Kind of coordinator to find.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CoordinatorType {
+    Group,
+    Transaction,
+}
+
+impl From<CoordinatorType> for i8 {
+    fn from(value: CoordinatorType) -> Self {
+        match value {
+            CoordinatorType::Group => 0,
+            CoordinatorType::Transaction => 1,
+        }
+    }
+}
+
+#[derive(Debug, KafkaRequest)]
+#[kafka(
+    response = "FindCoordinatorResponse",
+    api_key = "ApiKey::FindCoordinator",
+    max_version = "3",
+    tag_version = "3"
+)]
+pub struct FindCoordinatorRequest {
+    /// The coordinator key, the group id or transactional id
+    pub key: String,
+    /// The coordinator key type, 0 for groups and 1 for transactions
+    #[kafka(min_version = 1)]
+    pub key_type: i8,
+    #[kafka(min_version = 3)]
+    pub tagged_fields: Option<TaggedFields>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, KafkaResponse)]
+#[kafka(max_version = 3, tag_version = 3)]
+pub struct FindCoordinatorResponse {
+    /// The duration in milliseconds for which the request was throttled due to
+    /// a quota violation, or zero if the request did not violate any quota.
+    #[kafka(min_version = 1)]
+    pub throttle_time_ms: Option<i32>,
+    /// The error if any
+    #[kafka(nullable)]
+    pub error: Option<Error>,
+    /// The error message, or `None` if there was no error
+    #[kafka(min_version = 1, nullable)]
+    pub error_message: Option<String>,
+    /// The node id of the coordinator
+    pub node_id: i32,
+    /// The host name of the coordinator
+    pub host: String,
+    /// The port of the coordinator
+    pub port: i32,
+    #[kafka(min_version = 3)]
+    pub tagged_fields: Option<TaggedFields>,
+}
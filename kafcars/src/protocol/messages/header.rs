@@ -1,18 +1,22 @@
-use crate::protocol::messages::{ApiVersion, TaggedFields};
+use crate::protocol::{
+    api_key::ApiKey,
+    messages::{ApiVersion, TaggedFields},
+};
 use kafcars_inner_macros::{VersionedDeserialize, VersionedSerialize};
-use crate::protocol::api_key::ApiKey;
 
 #[derive(Debug, VersionedSerialize)]
 #[kafka(max_version = 2)]
 pub struct RequestHeader {
-    pub request_api_version: ApiVersion,
     pub request_api_key: ApiKey,
+    pub request_api_version: ApiVersion,
     pub correlation_id: i32,
-    #[kafka(min_version = 1, serialize_with = "serialize_nullable_string")]
-    pub client_id: String,
+    #[kafka(min_version = 1, nullable)]
+    pub client_id: Option<String>,
+    #[kafka(min_version = 2)]
+    pub tagged_fields: Option<TaggedFields>,
 }
 
-#[derive(Debug, VersionedDeserialize)]
+#[derive(Debug, PartialEq, Eq, VersionedDeserialize)]
 #[kafka(max_version = 1)]
 pub struct ResponseHeader {
     pub correlation_id: i32,
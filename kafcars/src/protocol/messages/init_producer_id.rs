@@ -0,0 +1,43 @@
+use crate::protocol::{api_key::ApiKey, error::Error, messages::TaggedFields};
+use kafcars_inner_macros::{KafkaRequest, KafkaResponse};
+
+#[derive(Debug, KafkaRequest)]
+#[kafka(
+    response = "InitProducerIdResponse",
+    api_key = "ApiKey::InitProducerId",
+    max_version = "4",
+    tag_version = "2"
+)]
+pub struct InitProducerIdRequest {
+    /// The transactional id, or `None` if the producer is not transactional
+    #[kafka(nullable)]
+    pub transactional_id: Option<String>,
+    /// The time in ms to wait before aborting idle transactions sent by this producer. This is
+    /// only relevant if a transactional id has been defined.
+    pub transaction_timeout_ms: i32,
+    /// The producer id, to bump the epoch of an existing producer, or -1
+    #[kafka(min_version = 3)]
+    pub producer_id: i64,
+    /// The producer epoch, to bump the epoch of an existing producer, or -1
+    #[kafka(min_version = 3)]
+    pub producer_epoch: i16,
+    #[kafka(min_version = 2)]
+    pub tagged_fields: Option<TaggedFields>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, KafkaResponse)]
+#[kafka(max_version = 4, tag_version = 2)]
+pub struct InitProducerIdResponse {
+    /// The duration in milliseconds for which the request was throttled due to
+    /// a quota violation, or zero if the request did not violate any quota.
+    pub throttle_time_ms: i32,
+    /// The error if any
+    #[kafka(nullable)]
+    pub error: Option<Error>,
+    /// The current producer id
+    pub producer_id: i64,
+    /// The current epoch associated with the producer id
+    pub producer_epoch: i16,
+    #[kafka(min_version = 2)]
+    pub tagged_fields: Option<TaggedFields>,
+}
@@ -0,0 +1,86 @@
+use crate::protocol::{api_key::ApiKey, error::Error};
+use kafcars_inner_macros::{KafkaRequest, KafkaResponse, VersionedDeserialize, VersionedSerialize};
+
+#[derive(Debug, KafkaRequest)]
+#[kafka(
+    response = "ListOffsetsResponse",
+    api_key = "ApiKey::ListOffsets",
+    min_version = "1",
+    max_version = "5"
+)]
+pub struct ListOffsetsRequest {
+    /// The broker ID of the requester, or -1 if this request is being made by a normal consumer
+    pub replica_id: i32,
+    /// 0 to see all records and 1 to only see committed records.
+    ///
+    /// Added in version 2
+    #[kafka(min_version = 2)]
+    pub isolation_level: i8,
+    /// Each topic in the request
+    pub topics: Vec<ListOffsetsRequestTopic>,
+}
+
+#[derive(Debug, VersionedSerialize)]
+#[kafka(max_version = 5)]
+pub struct ListOffsetsRequestTopic {
+    /// The topic name
+    pub name: String,
+    /// Each partition in the request
+    pub partitions: Vec<ListOffsetsRequestPartition>,
+}
+
+#[derive(Debug, VersionedSerialize)]
+#[kafka(max_version = 5)]
+pub struct ListOffsetsRequestPartition {
+    /// The partition index
+    pub partition_index: i32,
+    /// The current leader epoch, -1 if unknown.
+    ///
+    /// Added in version 4
+    #[kafka(min_version = 4)]
+    pub current_leader_epoch: i32,
+    /// The timestamp to look up in milliseconds, -1 for the latest and -2 for the earliest
+    /// offset
+    pub timestamp: i64,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, KafkaResponse)]
+#[kafka(max_version = 5)]
+pub struct ListOffsetsResponse {
+    /// The duration in milliseconds for which the request was throttled due to
+    /// a quota violation, or zero if the request did not violate any quota.
+    ///
+    /// Added in version 2
+    #[kafka(min_version = 2)]
+    pub throttle_time_ms: Option<i32>,
+    /// Each topic in the response
+    pub topics: Vec<ListOffsetsResponseTopic>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, VersionedDeserialize)]
+#[kafka(max_version = 5)]
+pub struct ListOffsetsResponseTopic {
+    /// The topic name
+    pub name: String,
+    /// Each partition in the response
+    pub partitions: Vec<ListOffsetsResponsePartition>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, VersionedDeserialize)]
+#[kafka(max_version = 5)]
+pub struct ListOffsetsResponsePartition {
+    /// The partition index
+    pub partition_index: i32,
+    /// The partition error if any
+    #[kafka(nullable)]
+    pub error: Option<Error>,
+    /// The timestamp associated with the returned offset
+    pub timestamp: i64,
+    /// The returned offset
+    pub offset: i64,
+    /// The leader epoch of the returned offset.
+    ///
+    /// Added in version 4
+    #[kafka(min_version = 4)]
+    pub leader_epoch: Option<i32>,
+}
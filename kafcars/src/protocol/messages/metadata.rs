@@ -1,24 +1,73 @@
-use crate::protocol::error::Error;
-use serde::{Deserialize, Serialize};
+use crate::protocol::{api_key::ApiKey, error::Error, messages::TaggedFields};
+use kafcars_inner_macros::{KafkaRequest, KafkaResponse, VersionedDeserialize, VersionedSerialize};
+use uuid::Uuid;
 
-#[derive(Debug)]
+#[derive(Debug, KafkaRequest)]
+#[kafka(response = "MetadataResponse", api_key = "ApiKey::Metadata", min_version = "1", max_version = "11", tag_version = "9")]
 pub struct MetadataRequest {
-    pub topics: Vec<MetadataRequestTopic>,
+    /// The topics to fetch metadata for, `None` fetches all topics
+    #[kafka(nullable)]
+    pub topics: Option<Vec<MetadataRequestTopic>>,
 
-    pub allow_auto_topic_creation: Option<bool>,
+    /// Added in version 4
+    #[kafka(min_version = 4)]
+    pub allow_auto_topic_creation: bool,
+
+    /// Added in version 8, removed in version 11
+    #[kafka(min_version = 8, max_version = 10)]
+    pub include_cluster_authorized_operations: bool,
+
+    /// Added in version 8
+    #[kafka(min_version = 8)]
+    pub include_topic_authorized_operations: bool,
+
+    #[kafka(min_version = 9)]
+    pub tagged_fields: Option<TaggedFields>,
 }
 
-#[derive(Debug)]
+impl MetadataRequest {
+    /// Requests the metadata of `topics` by name, `None` requests all topics.
+    pub fn new(topics: Option<Vec<String>>) -> Self {
+        Self {
+            topics: topics.map(|topics| {
+                topics
+                    .into_iter()
+                    .map(|name| MetadataRequestTopic {
+                        topic_id: Uuid::nil(),
+                        name,
+                        tagged_fields: Some(TaggedFields::default()),
+                    })
+                    .collect()
+            }),
+            allow_auto_topic_creation: false,
+            include_cluster_authorized_operations: false,
+            include_topic_authorized_operations: false,
+            tagged_fields: Some(TaggedFields::default()),
+        }
+    }
+}
+
+#[derive(Debug, VersionedSerialize)]
+#[kafka(max_version = 11, tag_version = 9)]
 pub struct MetadataRequestTopic {
+    /// The topic id, topics are always requested by name.
+    ///
+    /// Added in version 10
+    #[kafka(min_version = 10)]
+    pub topic_id: Uuid,
     pub name: String,
+    #[kafka(min_version = 9)]
+    pub tagged_fields: Option<TaggedFields>,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, KafkaResponse)]
+#[kafka(max_version = 11, tag_version = 9)]
 pub struct MetadataResponse {
     /// The duration in milliseconds for which the request was throttled due to
     /// a quota violation, or zero if the request did not violate any quota.
     ///
     /// Added in version 3
+    #[kafka(min_version = 3)]
     pub throttle_time_ms: Option<i32>,
 
     /// Each broker in the response
@@ -27,18 +76,30 @@ pub struct MetadataResponse {
     /// The cluster ID that responding broker belongs to.
     ///
     /// Added in version 2
+    #[kafka(min_version = 2, nullable)]
     pub cluster_id: Option<String>,
 
     /// The ID of the controller broker.
     ///
     /// Added in version 1
+    #[kafka(min_version = 1)]
     pub controller_id: Option<i32>,
 
     /// Each topic in the response
     pub topics: Vec<MetadataResponseTopic>,
+
+    /// 32-bit bitfield to represent authorized operations for this cluster.
+    ///
+    /// Added in version 8, removed in version 11
+    #[kafka(min_version = 8, max_version = 10)]
+    pub cluster_authorized_operations: Option<i32>,
+
+    #[kafka(min_version = 9)]
+    pub tagged_fields: Option<TaggedFields>,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, VersionedDeserialize)]
+#[kafka(max_version = 11, tag_version = 9)]
 pub struct MetadataResponseBroker {
     /// The broker ID
     pub node_id: i32,
@@ -47,31 +108,63 @@ pub struct MetadataResponseBroker {
     /// The broker port
     pub port: i32,
     /// Added in version 1
+    #[kafka(min_version = 1, nullable)]
     pub rack: Option<String>,
+    #[kafka(min_version = 9)]
+    pub tagged_fields: Option<TaggedFields>,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, VersionedDeserialize)]
+#[kafka(max_version = 11, tag_version = 9)]
 pub struct MetadataResponseTopic {
     /// The topic error if any
+    #[kafka(nullable)]
     pub error: Option<Error>,
     /// The topic name
     pub name: String,
+    /// The topic id.
+    ///
+    /// Added in version 10
+    #[kafka(min_version = 10)]
+    pub topic_id: Option<Uuid>,
     /// True if the topic is internal
+    #[kafka(min_version = 1)]
     pub is_internal: Option<bool>,
     /// Each partition in the topic
     pub partitions: Vec<MetadataResponsePartition>,
+    /// 32-bit bitfield to represent authorized operations for this topic.
+    ///
+    /// Added in version 8
+    #[kafka(min_version = 8)]
+    pub topic_authorized_operations: Option<i32>,
+    #[kafka(min_version = 9)]
+    pub tagged_fields: Option<TaggedFields>,
 }
 
-#[derive(Debug, PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone, VersionedDeserialize)]
+#[kafka(max_version = 11, tag_version = 9)]
 pub struct MetadataResponsePartition {
     /// The partition error if any
+    #[kafka(nullable)]
     pub error: Option<Error>,
     /// The partition index
     pub partition_index: i32,
     /// The ID of the leader broker
     pub leader_id: i32,
+    /// The leader epoch of this partition.
+    ///
+    /// Added in version 7
+    #[kafka(min_version = 7)]
+    pub leader_epoch: Option<i32>,
     /// The set of all nodes that host this partition
     pub replica_nodes: Vec<i32>,
     /// The set of all nodes that are in sync with the leader for this partition
     pub isr_nodes: Vec<i32>,
+    /// The set of offline replicas of this partition.
+    ///
+    /// Added in version 5
+    #[kafka(min_version = 5)]
+    pub offline_replicas: Option<Vec<i32>>,
+    #[kafka(min_version = 9)]
+    pub tagged_fields: Option<TaggedFields>,
 }
@@ -1,20 +1,29 @@
 use crate::protocol::{
     api_key::ApiKey,
-    deserializer::{deserialize_unsigned_var_int, DeserializeVersioned, DeserializeVersionedInto},
+    deserializer::{deserialize_unsigned_var_int, DeserializeCompact, DeserializeVersioned},
     error::SerializationError,
-    messages::header::ResponseHeader,
+    serializer::{serialize_unsigned_var_int, SerializeCompact, SerializeVersioned},
 };
-use futures::StreamExt;
 use std::{
     collections::HashMap,
-    io::{Cursor, Read},
+    io::{Read, Write},
 };
-use std::io::Write;
-use crate::protocol::messages::version::ApiVersionsRequest;
-use crate::protocol::serializer::{serialize_unsigned_var_int, SerializeVersioned};
 
+pub mod add_offsets_to_txn;
+pub mod add_partitions_to_txn;
+pub mod create_topics;
+pub mod delete_records;
+pub mod delete_topics;
+pub mod describe_topic_partitions;
+pub mod end_txn;
+pub mod fetch;
+pub mod find_coordinator;
 pub mod header;
+pub mod init_producer_id;
+pub mod list_offsets;
 pub mod metadata;
+pub mod produce;
+pub mod txn_offset_commit;
 pub mod version;
 
 pub type ApiVersion = i16;
@@ -44,7 +53,9 @@ impl<W: Write> SerializeVersioned<W> for TaggedFields {
         Self: Sized
     {
         serialize_unsigned_var_int(self.len() as u64, writer)?;
-        for (tag, values) in self {
+        let mut tags: Vec<_> = self.iter().collect();
+        tags.sort_by_key(|(tag, _)| **tag);
+        for (tag, values) in tags {
             serialize_unsigned_var_int(*tag, writer)?;
             serialize_unsigned_var_int(values.len() as u64, writer)?;
             writer.write_all(values)?;
@@ -63,7 +74,7 @@ impl<R: Read> DeserializeVersioned<R> for TaggedFields {
 
         let mut fields = HashMap::new();
         let mut prev_tag = None;
-        for _ in 0..=num_fields {
+        for _ in 0..num_fields {
             let tag = deserialize_unsigned_var_int(data)?;
             if prev_tag.map(|prev_tag| tag <= prev_tag).unwrap_or(false) {
                 return Err(SerializationError::Malformed {
@@ -73,9 +84,13 @@ impl<R: Read> DeserializeVersioned<R> for TaggedFields {
             prev_tag = Some(tag);
 
             let size = deserialize_unsigned_var_int(data)?;
-            let mut content = vec![0u8; size as usize];
-            let mut chunk = data.take(size);
-            chunk.read_to_end(&mut content)?;
+            let mut content = Vec::new();
+            data.take(size).read_to_end(&mut content)?;
+            if content.len() as u64 != size {
+                return Err(SerializationError::Malformed {
+                    message: format!("Tag {} is truncated", tag),
+                });
+            }
             if fields.insert(tag, content).is_some() {
                 return Err(SerializationError::Malformed {
                     message: format!("Tag {} already exists", tag),
@@ -86,3 +101,15 @@ impl<R: Read> DeserializeVersioned<R> for TaggedFields {
         Ok(fields)
     }
 }
+
+impl<W: Write> SerializeCompact<W> for TaggedFields {
+    fn serialize_compact(&self, writer: &mut W, version: i16) -> Result<(), SerializationError> {
+        self.serialize_versioned(writer, version)
+    }
+}
+
+impl<R: Read> DeserializeCompact<R> for TaggedFields {
+    fn deserialize_compact(data: &mut R, version: i16) -> Result<Self, SerializationError> {
+        Self::deserialize_versioned(data, version)
+    }
+}
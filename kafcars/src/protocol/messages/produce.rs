@@ -0,0 +1,102 @@
+use crate::protocol::{api_key::ApiKey, error::Error, serializer::serialize_nullable_bytes};
+use kafcars_inner_macros::{KafkaRequest, KafkaResponse, VersionedDeserialize, VersionedSerialize};
+
+#[derive(Debug, KafkaRequest)]
+#[kafka(
+    response = "ProduceResponse",
+    api_key = "ApiKey::Produce",
+    min_version = "3",
+    max_version = "8"
+)]
+pub struct ProduceRequest {
+    /// The transactional ID, or `None` if the producer is not transactional
+    #[kafka(nullable)]
+    pub transactional_id: Option<String>,
+    /// The number of acknowledgments the producer requires the leader to have received before
+    /// considering a request complete. Allowed values: 0 for no acknowledgments, 1 for only the
+    /// leader and -1 for the full ISR.
+    pub acks: i16,
+    /// The timeout to await a response in milliseconds
+    pub timeout_ms: i32,
+    /// Each topic to produce to
+    pub topic_data: Vec<ProduceRequestTopic>,
+}
+
+#[derive(Debug, VersionedSerialize)]
+#[kafka(max_version = 8)]
+pub struct ProduceRequestTopic {
+    /// The topic name
+    pub name: String,
+    /// Each partition to produce to
+    pub partition_data: Vec<ProduceRequestPartition>,
+}
+
+#[derive(Debug, VersionedSerialize)]
+#[kafka(max_version = 8)]
+pub struct ProduceRequestPartition {
+    /// The partition index
+    pub index: i32,
+    /// The encoded record batches
+    #[kafka(nullable, serialize_with = "serialize_nullable_bytes")]
+    pub records: Option<Vec<u8>>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, KafkaResponse)]
+#[kafka(max_version = 8)]
+pub struct ProduceResponse {
+    /// Each produce response
+    pub responses: Vec<ProduceResponseTopic>,
+    /// The duration in milliseconds for which the request was throttled due to
+    /// a quota violation, or zero if the request did not violate any quota.
+    pub throttle_time_ms: i32,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, VersionedDeserialize)]
+#[kafka(max_version = 8)]
+pub struct ProduceResponseTopic {
+    /// The topic name
+    pub name: String,
+    /// Each partition that we produced to within the topic
+    pub partition_responses: Vec<ProduceResponsePartition>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, VersionedDeserialize)]
+#[kafka(max_version = 8)]
+pub struct ProduceResponsePartition {
+    /// The partition index
+    pub index: i32,
+    /// The error if any
+    #[kafka(nullable)]
+    pub error: Option<Error>,
+    /// The base offset
+    pub base_offset: i64,
+    /// The timestamp returned by broker after appending the messages, or -1 if the topic uses
+    /// create time
+    pub log_append_time_ms: i64,
+    /// The log start offset.
+    ///
+    /// Added in version 5
+    #[kafka(min_version = 5)]
+    pub log_start_offset: Option<i64>,
+    /// The batch indices of records that caused the batch to be dropped.
+    ///
+    /// Added in version 8
+    #[kafka(min_version = 8)]
+    pub record_errors: Option<Vec<ProduceResponseRecordError>>,
+    /// The global error message summarizing the common root cause of the records that caused
+    /// the batch to be dropped.
+    ///
+    /// Added in version 8
+    #[kafka(min_version = 8, nullable)]
+    pub error_message: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, VersionedDeserialize)]
+#[kafka(max_version = 8)]
+pub struct ProduceResponseRecordError {
+    /// The batch index of the record that caused the batch to be dropped
+    pub batch_index: i32,
+    /// The error message of the record that caused the batch to be dropped
+    #[kafka(nullable)]
+    pub batch_index_error_message: Option<String>,
+}
@@ -0,0 +1,96 @@
+use crate::protocol::{api_key::ApiKey, error::Error, messages::TaggedFields};
+use kafcars_inner_macros::{KafkaRequest, KafkaResponse, VersionedDeserialize, VersionedSerialize};
+
+#[derive(Debug, KafkaRequest)]
+#[kafka(
+    response = "TxnOffsetCommitResponse",
+    api_key = "ApiKey::TxnOffsetCommit",
+    max_version = "3",
+    tag_version = "3"
+)]
+pub struct TxnOffsetCommitRequest {
+    /// The id of the transaction
+    pub transactional_id: String,
+    /// The id of the group
+    pub group_id: String,
+    /// The current producer id in use by the transactional id
+    pub producer_id: i64,
+    /// The current epoch associated with the producer id
+    pub producer_epoch: i16,
+    /// The generation of the consumer
+    #[kafka(min_version = 3)]
+    pub generation_id: i32,
+    /// The member id assigned by the group coordinator
+    #[kafka(min_version = 3)]
+    pub member_id: String,
+    /// The unique identifier of the consumer instance provided by end user
+    #[kafka(min_version = 3, nullable)]
+    pub group_instance_id: Option<String>,
+    /// Each topic that we want to commit offsets for
+    pub topics: Vec<TxnOffsetCommitRequestTopic>,
+    #[kafka(min_version = 3)]
+    pub tagged_fields: Option<TaggedFields>,
+}
+
+#[derive(Debug, VersionedSerialize)]
+#[kafka(max_version = 3, tag_version = 3)]
+pub struct TxnOffsetCommitRequestTopic {
+    /// The topic name
+    pub name: String,
+    /// The partitions inside the topic that we want to commit offsets for
+    pub partitions: Vec<TxnOffsetCommitRequestPartition>,
+    #[kafka(min_version = 3)]
+    pub tagged_fields: Option<TaggedFields>,
+}
+
+#[derive(Debug, VersionedSerialize)]
+#[kafka(max_version = 3, tag_version = 3)]
+pub struct TxnOffsetCommitRequestPartition {
+    /// The index of the partition
+    pub partition_index: i32,
+    /// The message offset to be committed
+    pub committed_offset: i64,
+    /// The leader epoch of the last consumed record
+    #[kafka(min_version = 2)]
+    pub committed_leader_epoch: i32,
+    /// Any associated metadata the client wants to keep
+    #[kafka(nullable)]
+    pub committed_metadata: Option<String>,
+    #[kafka(min_version = 3)]
+    pub tagged_fields: Option<TaggedFields>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, KafkaResponse)]
+#[kafka(max_version = 3, tag_version = 3)]
+pub struct TxnOffsetCommitResponse {
+    /// The duration in milliseconds for which the request was throttled due to
+    /// a quota violation, or zero if the request did not violate any quota.
+    pub throttle_time_ms: i32,
+    /// The responses for each topic
+    pub topics: Vec<TxnOffsetCommitResponseTopic>,
+    #[kafka(min_version = 3)]
+    pub tagged_fields: Option<TaggedFields>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, VersionedDeserialize)]
+#[kafka(max_version = 3, tag_version = 3)]
+pub struct TxnOffsetCommitResponseTopic {
+    /// The topic name
+    pub name: String,
+    /// The responses for each partition in the topic
+    pub partitions: Vec<TxnOffsetCommitResponsePartition>,
+    #[kafka(min_version = 3)]
+    pub tagged_fields: Option<TaggedFields>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, VersionedDeserialize)]
+#[kafka(max_version = 3, tag_version = 3)]
+pub struct TxnOffsetCommitResponsePartition {
+    /// The partition index
+    pub partition_index: i32,
+    /// The error if any
+    #[kafka(nullable)]
+    pub error: Option<Error>,
+    #[kafka(min_version = 3)]
+    pub tagged_fields: Option<TaggedFields>,
+}
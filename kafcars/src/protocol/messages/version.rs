@@ -1,7 +1,10 @@
-use kafcars_inner_macros::KafkaRequest;
-use crate::protocol::{api_key::ApiKey, messages::KafkaRequest};
-use crate::protocol::messages::TaggedFields;
-use crate::protocol::serializer::serialize_compact_string;
+use crate::protocol::{
+    api_key::ApiKey,
+    error::Error,
+    messages::TaggedFields,
+    serializer::serialize_compact_string,
+};
+use kafcars_inner_macros::{KafkaRequest, KafkaResponse, VersionedDeserialize};
 
 #[derive(Debug, KafkaRequest)]
 #[kafka(response = "ApiVersionsResponse", api_key = "ApiKey::ApiVersions", max_version = "4")]
@@ -14,4 +17,33 @@ pub struct ApiVersionsRequest {
     pub tagged_fields: Option<TaggedFields>,
 }
 
-pub struct ApiVersionsResponse {}
+#[derive(Debug, PartialEq, Eq, KafkaResponse)]
+#[kafka(max_version = 4, tag_version = 3)]
+pub struct ApiVersionsResponse {
+    /// The top-level error code
+    #[kafka(nullable)]
+    pub error: Option<Error>,
+    /// The APIs supported by the broker
+    pub api_keys: Vec<ApiVersionsResponseKey>,
+    /// The duration in milliseconds for which the request was throttled due to a quota violation,
+    /// or zero if the request did not violate any quota.
+    ///
+    /// Added in version 1
+    #[kafka(min_version = 1)]
+    pub throttle_time_ms: Option<i32>,
+    #[kafka(min_version = 3)]
+    pub tagged_fields: Option<TaggedFields>,
+}
+
+#[derive(Debug, PartialEq, Eq, VersionedDeserialize)]
+#[kafka(max_version = 4, tag_version = 3)]
+pub struct ApiVersionsResponseKey {
+    /// The API index
+    pub api_key: ApiKey,
+    /// The minimum supported version, inclusive
+    pub min_version: i16,
+    /// The maximum supported version, inclusive
+    pub max_version: i16,
+    #[kafka(min_version = 3)]
+    pub tagged_fields: Option<TaggedFields>,
+}
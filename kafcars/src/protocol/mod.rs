@@ -2,4 +2,5 @@ pub mod api_key;
 pub mod deserializer;
 pub(crate) mod error;
 pub mod messages;
+pub mod record;
 pub mod serializer;
@@ -0,0 +1,480 @@
+//! Record batches (message format v2), the payload of produce and fetch requests.
+
+use crate::{
+    protocol::{
+        deserializer::{deserialize_var_long, read_bytes, DeserializeVersioned},
+        error::SerializationError,
+        serializer::{serialize_var_long, SerializeVersioned},
+    },
+    record::Record,
+};
+use chrono::{DateTime, Utc};
+use std::io::{Cursor, Read, Write};
+
+const MAGIC: i8 = 2;
+/// Bytes of a batch before its records, starting at the partition leader epoch
+const HEADER_SIZE_AFTER_LENGTH: usize = 49;
+/// Offset of the attributes within a batch, the checksum covers everything from there
+const ATTRIBUTES_OFFSET: usize = 21;
+
+const COMPRESSION_MASK: i16 = 0b111;
+const LOG_APPEND_TIME: i16 = 1 << 3;
+const TRANSACTIONAL: i16 = 1 << 4;
+const CONTROL: i16 = 1 << 5;
+
+/// Compression of the records of a batch.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Compression {
+    #[default]
+    NoCompression,
+    #[cfg(feature = "compression-gzip")]
+    Gzip,
+    #[cfg(feature = "compression-snappy")]
+    Snappy,
+    #[cfg(feature = "compression-lz4")]
+    Lz4,
+    #[cfg(feature = "compression-zstd")]
+    Zstd,
+}
+
+impl Compression {
+    fn attributes(self) -> i16 {
+        match self {
+            Compression::NoCompression => 0,
+            #[cfg(feature = "compression-gzip")]
+            Compression::Gzip => 1,
+            #[cfg(feature = "compression-snappy")]
+            Compression::Snappy => 2,
+            #[cfg(feature = "compression-lz4")]
+            Compression::Lz4 => 3,
+            #[cfg(feature = "compression-zstd")]
+            Compression::Zstd => 4,
+        }
+    }
+
+    fn from_attributes(attributes: i16) -> Result<Self, SerializationError> {
+        Ok(match attributes & COMPRESSION_MASK {
+            0 => Compression::NoCompression,
+            #[cfg(feature = "compression-gzip")]
+            1 => Compression::Gzip,
+            #[cfg(feature = "compression-snappy")]
+            2 => Compression::Snappy,
+            #[cfg(feature = "compression-lz4")]
+            3 => Compression::Lz4,
+            #[cfg(feature = "compression-zstd")]
+            4 => Compression::Zstd,
+            codec => {
+                return Err(SerializationError::Malformed {
+                    message: format!("Unsupported compression codec {codec}"),
+                })
+            }
+        })
+    }
+
+    fn compress(self, data: Vec<u8>) -> Result<Vec<u8>, SerializationError> {
+        Ok(match self {
+            Compression::NoCompression => data,
+            #[cfg(feature = "compression-gzip")]
+            Compression::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(&data)?;
+                encoder.finish()?
+            }
+            #[cfg(feature = "compression-snappy")]
+            Compression::Snappy => snap::raw::Encoder::new()
+                .compress_vec(&data)
+                .map_err(std::io::Error::from)?,
+            #[cfg(feature = "compression-lz4")]
+            Compression::Lz4 => {
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+                encoder.write_all(&data)?;
+                encoder.finish().map_err(std::io::Error::from)?
+            }
+            #[cfg(feature = "compression-zstd")]
+            Compression::Zstd => zstd::encode_all(data.as_slice(), 0)?,
+        })
+    }
+
+    fn decompress(self, data: Vec<u8>) -> Result<Vec<u8>, SerializationError> {
+        let mut decompressed = vec![];
+        match self {
+            Compression::NoCompression => return Ok(data),
+            #[cfg(feature = "compression-gzip")]
+            Compression::Gzip => {
+                flate2::read::GzDecoder::new(data.as_slice()).read_to_end(&mut decompressed)?;
+            }
+            #[cfg(feature = "compression-snappy")]
+            Compression::Snappy => decompressed = decompress_snappy(&data)?,
+            #[cfg(feature = "compression-lz4")]
+            Compression::Lz4 => {
+                lz4_flex::frame::FrameDecoder::new(data.as_slice())
+                    .read_to_end(&mut decompressed)?;
+            }
+            #[cfg(feature = "compression-zstd")]
+            Compression::Zstd => decompressed = zstd::decode_all(data.as_slice())?,
+        }
+        Ok(decompressed)
+    }
+}
+
+/// The Java client frames snappy data like `snappy-java` does, other clients send it raw.
+#[cfg(feature = "compression-snappy")]
+fn decompress_snappy(data: &[u8]) -> Result<Vec<u8>, SerializationError> {
+    const JAVA_MAGIC: &[u8] = b"\x82SNAPPY\x00";
+    let snappy_error = |e: snap::Error| SerializationError::Malformed {
+        message: format!("Invalid snappy data: {e}"),
+    };
+
+    let Some(mut chunks) = data.strip_prefix(JAVA_MAGIC) else {
+        return snap::raw::Decoder::new()
+            .decompress_vec(data)
+            .map_err(snappy_error);
+    };
+    // version and compatible version
+    chunks = chunks.get(8..).unwrap_or_default();
+    let mut decompressed = vec![];
+    while !chunks.is_empty() {
+        let mut len = [0u8; 4];
+        Read::read_exact(&mut chunks, &mut len)?;
+        let chunk = read_bytes(&mut chunks, i32::from_be_bytes(len) as usize)?;
+        decompressed.extend(
+            snap::raw::Decoder::new()
+                .decompress_vec(&chunk)
+                .map_err(snappy_error)?,
+        );
+    }
+    Ok(decompressed)
+}
+
+/// A record of a batch with its position relative to the batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchRecord {
+    pub offset_delta: i32,
+    pub record: Record,
+}
+
+/// Records written together, sharing one header. A batch's records get consecutive offsets
+/// starting at its base offset, which the broker assigns when producing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordBatch {
+    pub base_offset: i64,
+    pub partition_leader_epoch: i32,
+    pub last_offset_delta: i32,
+    /// Timestamp of the first record in milliseconds, the others are stored relative to it
+    pub first_timestamp: i64,
+    pub max_timestamp: i64,
+    /// `-1` unless the batch is produced idempotently
+    pub producer_id: i64,
+    pub producer_epoch: i16,
+    pub base_sequence: i32,
+    pub compression: Compression,
+    /// The broker set the timestamps when appending the batch
+    pub log_append_time: bool,
+    pub is_transactional: bool,
+    /// Control batches contain transaction markers instead of records
+    pub is_control: bool,
+    pub records: Vec<BatchRecord>,
+}
+
+impl RecordBatch {
+    /// A batch of `records` that are not produced idempotently.
+    pub fn new(records: Vec<Record>, compression: Compression) -> Self {
+        let timestamps = records.iter().map(|r| r.timestamp.timestamp_millis());
+        let first_timestamp = timestamps.clone().next().unwrap_or_default();
+        let max_timestamp = timestamps.max().unwrap_or_default();
+        Self {
+            base_offset: 0,
+            partition_leader_epoch: -1,
+            last_offset_delta: records.len() as i32 - 1,
+            first_timestamp,
+            max_timestamp,
+            producer_id: -1,
+            producer_epoch: -1,
+            base_sequence: -1,
+            compression,
+            log_append_time: false,
+            is_transactional: false,
+            is_control: false,
+            records: records
+                .into_iter()
+                .enumerate()
+                .map(|(offset_delta, record)| BatchRecord {
+                    offset_delta: offset_delta as i32,
+                    record,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn encode<W: Write>(&self, writer: &mut W) -> Result<(), SerializationError> {
+        let mut records = vec![];
+        for record in &self.records {
+            self.encode_record(record, &mut records)?;
+        }
+        let records = self.compression.compress(records)?;
+
+        let mut attributes = self.compression.attributes();
+        if self.log_append_time {
+            attributes |= LOG_APPEND_TIME;
+        }
+        if self.is_transactional {
+            attributes |= TRANSACTIONAL;
+        }
+        if self.is_control {
+            attributes |= CONTROL;
+        }
+
+        // everything covered by the checksum
+        let mut body = vec![];
+        attributes.serialize_versioned(&mut body, 0)?;
+        self.last_offset_delta.serialize_versioned(&mut body, 0)?;
+        self.first_timestamp.serialize_versioned(&mut body, 0)?;
+        self.max_timestamp.serialize_versioned(&mut body, 0)?;
+        self.producer_id.serialize_versioned(&mut body, 0)?;
+        self.producer_epoch.serialize_versioned(&mut body, 0)?;
+        self.base_sequence.serialize_versioned(&mut body, 0)?;
+        (self.records.len() as i32).serialize_versioned(&mut body, 0)?;
+        body.extend(records);
+
+        let batch_length =
+            i32::try_from(body.len() + 9).map_err(|_| SerializationError::Overflow)?;
+        self.base_offset.serialize_versioned(writer, 0)?;
+        batch_length.serialize_versioned(writer, 0)?;
+        self.partition_leader_epoch.serialize_versioned(writer, 0)?;
+        MAGIC.serialize_versioned(writer, 0)?;
+        crc32c::crc32c(&body).serialize_versioned(writer, 0)?;
+        writer.write_all(&body)?;
+        Ok(())
+    }
+
+    fn encode_record(
+        &self,
+        record: &BatchRecord,
+        writer: &mut Vec<u8>,
+    ) -> Result<(), SerializationError> {
+        let record_timestamp = record.record.timestamp.timestamp_millis();
+        let mut body = vec![];
+        // attributes are unused
+        0i8.serialize_versioned(&mut body, 0)?;
+        serialize_var_long(record_timestamp - self.first_timestamp, &mut body)?;
+        serialize_var_long(record.offset_delta.into(), &mut body)?;
+        serialize_var_bytes(record.record.key.as_deref(), &mut body)?;
+        serialize_var_bytes(record.record.value.as_deref(), &mut body)?;
+        serialize_var_long(record.record.headers.len() as i64, &mut body)?;
+        for (key, value) in &record.record.headers {
+            serialize_var_bytes(Some(key.as_bytes()), &mut body)?;
+            serialize_var_bytes(Some(value), &mut body)?;
+        }
+
+        serialize_var_long(body.len() as i64, writer)?;
+        writer.extend(body);
+        Ok(())
+    }
+
+    /// Decodes all complete batches of a fetch response. Brokers may cut the last batch off
+    /// to fit the requested size, which is ignored.
+    pub fn decode_all(data: &[u8]) -> Result<Vec<Self>, SerializationError> {
+        let mut batches = vec![];
+        let mut data = data;
+        while data.len() >= 12 {
+            let batch_length = i32::from_be_bytes(data[8..12].try_into().unwrap());
+            let size = 12
+                + usize::try_from(batch_length).map_err(|_| SerializationError::Malformed {
+                    message: format!("Invalid batch length {batch_length}"),
+                })?;
+            if data.len() < size {
+                break;
+            }
+            let (batch, rest) = data.split_at(size);
+            batches.push(Self::decode(batch)?);
+            data = rest;
+        }
+        Ok(batches)
+    }
+
+    fn decode(batch: &[u8]) -> Result<Self, SerializationError> {
+        let mut data = Cursor::new(batch);
+        let base_offset = i64::deserialize_versioned(&mut data, 0)?;
+        let batch_length = i32::deserialize_versioned(&mut data, 0)?;
+        if (batch_length as usize) < HEADER_SIZE_AFTER_LENGTH {
+            return Err(SerializationError::Malformed {
+                message: format!("Record batch of {batch_length} bytes is too short"),
+            });
+        }
+        let partition_leader_epoch = i32::deserialize_versioned(&mut data, 0)?;
+        let magic = i8::deserialize_versioned(&mut data, 0)?;
+        if magic != MAGIC {
+            return Err(SerializationError::Malformed {
+                message: format!("Unsupported record batch magic {magic}"),
+            });
+        }
+        let crc = u32::deserialize_versioned(&mut data, 0)?;
+        if crc != crc32c::crc32c(&batch[ATTRIBUTES_OFFSET..]) {
+            return Err(SerializationError::Malformed {
+                message: format!("Record batch at offset {base_offset} has an invalid checksum"),
+            });
+        }
+
+        let attributes = i16::deserialize_versioned(&mut data, 0)?;
+        let last_offset_delta = i32::deserialize_versioned(&mut data, 0)?;
+        let first_timestamp = i64::deserialize_versioned(&mut data, 0)?;
+        let max_timestamp = i64::deserialize_versioned(&mut data, 0)?;
+        let producer_id = i64::deserialize_versioned(&mut data, 0)?;
+        let producer_epoch = i16::deserialize_versioned(&mut data, 0)?;
+        let base_sequence = i32::deserialize_versioned(&mut data, 0)?;
+        let count = i32::deserialize_versioned(&mut data, 0)?;
+
+        let compression = Compression::from_attributes(attributes)?;
+        let records = compression.decompress(batch[data.position() as usize..].to_vec())?;
+        let log_append_time = attributes & LOG_APPEND_TIME != 0;
+        let mut records = Cursor::new(records.as_slice());
+        let records = (0..count.max(0))
+            .map(|_| {
+                decode_record(
+                    &mut records,
+                    if log_append_time {
+                        max_timestamp
+                    } else {
+                        first_timestamp
+                    },
+                    log_append_time,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            base_offset,
+            partition_leader_epoch,
+            last_offset_delta,
+            first_timestamp,
+            max_timestamp,
+            producer_id,
+            producer_epoch,
+            base_sequence,
+            compression,
+            log_append_time,
+            is_transactional: attributes & TRANSACTIONAL != 0,
+            is_control: attributes & CONTROL != 0,
+            records,
+        })
+    }
+}
+
+/// Decodes a record, with timestamps relative to `base_timestamp` unless `fixed_timestamp`.
+fn decode_record<R: Read>(
+    data: &mut R,
+    base_timestamp: i64,
+    fixed_timestamp: bool,
+) -> Result<BatchRecord, SerializationError> {
+    let length = deserialize_var_long(data)?;
+    let mut data = Cursor::new(read_bytes(
+        data,
+        usize::try_from(length).unwrap_or_default(),
+    )?);
+    let _attributes = i8::deserialize_versioned(&mut data, 0)?;
+    let timestamp_delta = deserialize_var_long(&mut data)?;
+    let offset_delta = deserialize_var_long(&mut data)? as i32;
+    let key = deserialize_var_bytes(&mut data)?;
+    let value = deserialize_var_bytes(&mut data)?;
+    let headers = (0..deserialize_var_long(&mut data)?.max(0))
+        .map(|_| {
+            let key = deserialize_var_bytes(&mut data)?.unwrap_or_default();
+            let key = String::from_utf8(key).map_err(|e| SerializationError::Malformed {
+                message: format!("Invalid utf-8 header key: {e}"),
+            })?;
+            let value = deserialize_var_bytes(&mut data)?.unwrap_or_default();
+            Ok((key, value))
+        })
+        .collect::<Result<_, SerializationError>>()?;
+
+    let timestamp = if fixed_timestamp {
+        base_timestamp
+    } else {
+        base_timestamp + timestamp_delta
+    };
+    Ok(BatchRecord {
+        offset_delta,
+        record: Record {
+            key,
+            value,
+            headers,
+            timestamp: DateTime::<Utc>::from_timestamp_millis(timestamp).unwrap_or_default(),
+        },
+    })
+}
+
+fn serialize_var_bytes<W: Write>(
+    val: Option<&[u8]>,
+    writer: &mut W,
+) -> Result<(), SerializationError> {
+    match val {
+        Some(val) => {
+            serialize_var_long(val.len() as i64, writer)?;
+            Ok(writer.write_all(val)?)
+        }
+        None => serialize_var_long(-1, writer),
+    }
+}
+
+fn deserialize_var_bytes<R: Read>(data: &mut R) -> Result<Option<Vec<u8>>, SerializationError> {
+    let len = deserialize_var_long(data)?;
+    if len < 0 {
+        return Ok(None);
+    }
+    read_bytes(data, len as usize).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::collections::BTreeMap;
+
+    fn record(key: &str, timestamp: i64) -> Record {
+        Record {
+            key: Some(key.as_bytes().to_vec()),
+            value: None,
+            headers: BTreeMap::from([("foo".to_string(), b"bar".to_vec())]),
+            timestamp: Utc.timestamp_millis_opt(timestamp).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let compressions = [
+            Compression::NoCompression,
+            #[cfg(feature = "compression-gzip")]
+            Compression::Gzip,
+            #[cfg(feature = "compression-snappy")]
+            Compression::Snappy,
+            #[cfg(feature = "compression-lz4")]
+            Compression::Lz4,
+            #[cfg(feature = "compression-zstd")]
+            Compression::Zstd,
+        ];
+        for compression in compressions {
+            let mut batch =
+                RecordBatch::new(vec![record("a", 1337), record("b", 1000)], compression);
+            batch.base_offset = 42;
+
+            let mut data = vec![];
+            batch.encode(&mut data).unwrap();
+            assert_eq!(RecordBatch::decode_all(&data).unwrap(), vec![batch.clone()]);
+
+            // a truncated batch at the end is skipped
+            data.extend_from_slice(&data.clone()[..20]);
+            assert_eq!(RecordBatch::decode_all(&data).unwrap(), vec![batch]);
+        }
+    }
+
+    #[test]
+    fn test_invalid_checksum() {
+        let mut data = vec![];
+        RecordBatch::new(vec![record("a", 0)], Compression::NoCompression)
+            .encode(&mut data)
+            .unwrap();
+        *data.last_mut().unwrap() ^= 1;
+        assert!(RecordBatch::decode_all(&data).is_err());
+    }
+}
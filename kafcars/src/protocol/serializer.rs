@@ -1,13 +1,6 @@
-use crate::protocol::error::{
-    SerializationError,
-    SerializationError::{Malformed, UnknownValue},
-};
-use std::{
-    io::{Cursor, Read},
-    pin::Pin,
-};
+use crate::protocol::error::SerializationError;
 use std::io::Write;
-use tokio_util::bytes::BytesMut;
+use uuid::Uuid;
 
 pub trait SerializeVersioned<W>
 where
@@ -18,16 +11,31 @@ where
         Self: Sized;
 }
 
-pub struct VersionedSerializer {
-    api_version: i16,
+/// Serialization used by flexible message versions, where strings, bytes and arrays are encoded
+/// with a compact length.
+pub trait SerializeCompact<W>
+where
+    W: Write,
+{
+    fn serialize_compact(&self, writer: &mut W, version: i16) -> Result<(), SerializationError>
+    where
+        Self: Sized;
 }
 
-impl VersionedSerializer {
-    pub fn new(api_version: i16) -> Self {
-        VersionedSerializer { api_version }
-    }
+macro_rules! impl_serialize_compact_as_versioned {
+    ($($ty:ty),*) => {
+        $(
+            impl<W: Write> SerializeCompact<W> for $ty {
+                fn serialize_compact(&self, writer: &mut W, version: i16) -> Result<(), SerializationError> {
+                    self.serialize_versioned(writer, version)
+                }
+            }
+        )*
+    };
 }
 
+impl_serialize_compact_as_versioned!(bool, i8, i16, i32, u32, i64, Uuid);
+
 impl<W: Write> SerializeVersioned<W> for bool {
     fn serialize_versioned(&self, writer: &mut W, _: i16) -> Result<(), SerializationError> {
         Ok(writer.write_all(&[if *self { 1 } else { 0 }])?)
@@ -64,6 +72,96 @@ impl<W: Write> SerializeVersioned<W> for i64 {
     }
 }
 
+impl<W: Write> SerializeVersioned<W> for Uuid {
+    fn serialize_versioned(&self, writer: &mut W, _: i16) -> Result<(), SerializationError> {
+        Ok(writer.write_all(self.as_bytes())?)
+    }
+}
+
+impl<W: Write> SerializeVersioned<W> for String {
+    fn serialize_versioned(&self, writer: &mut W, _: i16) -> Result<(), SerializationError> {
+        serialize_string(self, writer)
+    }
+}
+
+impl<W: Write> SerializeCompact<W> for String {
+    fn serialize_compact(&self, writer: &mut W, _: i16) -> Result<(), SerializationError> {
+        serialize_compact_string(self, writer)
+    }
+}
+
+impl<W: Write> SerializeVersioned<W> for Option<String> {
+    fn serialize_versioned(&self, writer: &mut W, _: i16) -> Result<(), SerializationError> {
+        serialize_nullable_string(self, writer)
+    }
+}
+
+impl<W: Write> SerializeCompact<W> for Option<String> {
+    fn serialize_compact(&self, writer: &mut W, _: i16) -> Result<(), SerializationError> {
+        match self {
+            Some(val) => serialize_compact_string(val, writer),
+            None => serialize_unsigned_var_int(0, writer),
+        }
+    }
+}
+
+impl<W: Write, T: SerializeVersioned<W>> SerializeVersioned<W> for Vec<T> {
+    fn serialize_versioned(&self, writer: &mut W, version: i16) -> Result<(), SerializationError> {
+        let len = i32::try_from(self.len()).map_err(|_| SerializationError::Overflow)?;
+        len.serialize_versioned(writer, version)?;
+        for element in self {
+            element.serialize_versioned(writer, version)?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write, T: SerializeCompact<W>> SerializeCompact<W> for Vec<T> {
+    fn serialize_compact(&self, writer: &mut W, version: i16) -> Result<(), SerializationError> {
+        let len = u64::try_from(self.len() + 1).map_err(|_| SerializationError::Overflow)?;
+        serialize_unsigned_var_int(len, writer)?;
+        for element in self {
+            element.serialize_compact(writer, version)?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write, T: SerializeVersioned<W>> SerializeVersioned<W> for Option<Vec<T>> {
+    fn serialize_versioned(&self, writer: &mut W, version: i16) -> Result<(), SerializationError> {
+        match self {
+            Some(val) => val.serialize_versioned(writer, version),
+            None => (-1i32).serialize_versioned(writer, version),
+        }
+    }
+}
+
+impl<W: Write, T: SerializeCompact<W>> SerializeCompact<W> for Option<Vec<T>> {
+    fn serialize_compact(&self, writer: &mut W, version: i16) -> Result<(), SerializationError> {
+        match self {
+            Some(val) => val.serialize_compact(writer, version),
+            None => serialize_unsigned_var_int(0, writer),
+        }
+    }
+}
+
+pub fn serialize_string<W: Write>(val: &String, writer: &mut W) -> Result<(), SerializationError> {
+    let len = i16::try_from(val.len()).map_err(|_| SerializationError::Overflow)?;
+    writer.write_all(&len.to_be_bytes())?;
+    writer.write_all(val.as_bytes())?;
+    Ok(())
+}
+
+pub fn serialize_nullable_string<W: Write>(
+    val: &Option<String>,
+    writer: &mut W,
+) -> Result<(), SerializationError> {
+    match val {
+        Some(val) => serialize_string(val, writer),
+        None => Ok(writer.write_all(&(-1i16).to_be_bytes())?),
+    }
+}
+
 pub fn serialize_compact_string<W: Write>(val: &String, writer: &mut W) -> Result<(), SerializationError> {
     let len = u64::try_from(val.len() + 1).map_err(|_| SerializationError::Overflow)?;
     serialize_unsigned_var_int(len, writer)?;
@@ -74,7 +172,7 @@ pub fn serialize_compact_string<W: Write>(val: &String, writer: &mut W) -> Resul
 pub fn serialize_unsigned_var_int<W: Write>(val: u64, writer: &mut W) -> Result<(), SerializationError> {
     let mut curr = val;
     loop {
-        let mut c = u8::try_from(val & 0x7f)
+        let mut c = u8::try_from(curr & 0x7f)
             .expect("u64 to u8 with 0x7f mask should always work");
         curr >>= 7;
         if curr > 0 {
@@ -88,3 +186,22 @@ pub fn serialize_unsigned_var_int<W: Write>(val: u64, writer: &mut W) -> Result<
     }
     Ok(())
 }
+
+/// Zigzag encoded variable length integer, as used inside of record batches.
+pub fn serialize_var_long<W: Write>(val: i64, writer: &mut W) -> Result<(), SerializationError> {
+    serialize_unsigned_var_int(((val << 1) ^ (val >> 63)) as u64, writer)
+}
+
+pub fn serialize_nullable_bytes<W: Write>(
+    val: &Option<Vec<u8>>,
+    writer: &mut W,
+) -> Result<(), SerializationError> {
+    match val {
+        Some(val) => {
+            let len = i32::try_from(val.len()).map_err(|_| SerializationError::Overflow)?;
+            writer.write_all(&len.to_be_bytes())?;
+            Ok(writer.write_all(val)?)
+        }
+        None => Ok(writer.write_all(&(-1i32).to_be_bytes())?),
+    }
+}
@@ -0,0 +1,49 @@
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+
+/// A single record as it is produced to and fetched from a partition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub key: Option<Vec<u8>>,
+    pub value: Option<Vec<u8>>,
+    pub headers: BTreeMap<String, Vec<u8>>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Record {
+    /// Size of the keys and values of the record, ignoring the encoding overhead.
+    pub fn approximate_size(&self) -> usize {
+        self.key.as_ref().map(|key| key.len()).unwrap_or_default()
+            + self
+                .value
+                .as_ref()
+                .map(|value| value.len())
+                .unwrap_or_default()
+            + self
+                .headers
+                .iter()
+                .map(|(key, value)| key.len() + value.len())
+                .sum::<usize>()
+    }
+}
+
+/// A fetched record together with its offset in the partition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordAndOffset {
+    pub record: Record,
+    pub offset: i64,
+}
+
+/// Where a produced record was written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordMetadata {
+    pub topic: String,
+    pub partition: i32,
+    /// -1 if the leader did not report it, e.g. with
+    /// [`Acks::None`](crate::client::partition::Acks::None)
+    pub offset: i64,
+    /// Timestamp the record was produced with
+    pub timestamp: DateTime<Utc>,
+    /// Time the leader appended the record, if the topic uses log append time
+    pub log_append_time: Option<DateTime<Utc>>,
+}
@@ -2,17 +2,17 @@ use assert_matches::assert_matches;
 use chrono::{TimeZone, Utc};
 use kafcars::{
     client::{
-        error::{Error as ClientError, ProtocolError, ServerErrorResponse},
         partition::{Compression, OffsetAt, UnknownTopicHandling},
         ClientBuilder,
     },
+    error::{ApiKey, Error as ClientError, ProtocolError},
     record::{Record, RecordAndOffset},
     BackoffConfig,
 };
-use std::{collections::BTreeMap, env, str::FromStr, sync::Arc, time::Duration};
+use std::{collections::BTreeMap, str::FromStr, sync::Arc, time::Duration};
 
 mod test_helpers;
-use test_helpers::{maybe_start_logging, random_topic_name, record, BrokerImpl, TEST_TIMEOUT};
+use test_helpers::{maybe_start_logging, random_topic_name, record, TEST_TIMEOUT};
 
 #[tokio::test]
 async fn test_plain() {
@@ -25,29 +25,9 @@ async fn test_plain() {
         .unwrap();
 }
 
+#[ignore = "kafcars does not support SASL authentication yet"]
 #[tokio::test]
-async fn test_sasl() {
-    maybe_start_logging();
-    if env::var("TEST_INTEGRATION").is_err() {
-        return;
-    }
-    if env::var("KAFKA_SASL_CONNECT").is_err() {
-        eprintln!("Skipping sasl test.");
-        return;
-    }
-    let test_cfg = maybe_skip_kafka_integration!();
-    // Redpanda broker doesn't support SASL/PLAIN at this moment.
-    if test_cfg.broker_impl != BrokerImpl::Kafka {
-        return;
-    }
-    ClientBuilder::new(vec![env::var("KAFKA_SASL_CONNECT").unwrap()])
-        .sasl_config(rskafka::client::SaslConfig::Plain(
-            rskafka::client::Credentials::new("admin".to_string(), "admin-secret".to_string()),
-        ))
-        .build()
-        .await
-        .unwrap();
-}
+async fn test_sasl() {}
 
 #[tokio::test]
 async fn test_topic_crud() {
@@ -106,8 +86,8 @@ async fn test_topic_crud() {
         .await
         .unwrap_err();
     match err {
-        ClientError::ServerError {
-            protocol_error: ProtocolError::TopicAlreadyExists,
+        ClientError::Server {
+            error: ProtocolError::TopicAlreadyExists,
             ..
         } => {}
         _ => panic!("Unexpected error: {}", err),
@@ -192,97 +172,20 @@ async fn test_non_existing_partition() {
         .unwrap_err();
     assert_matches!(
         err,
-        ClientError::ServerError {
-            protocol_error: ProtocolError::UnknownTopicOrPartition,
+        ClientError::Server {
+            error: ProtocolError::UnknownTopicOrPartition,
             ..
         }
     );
 }
 
-// Disabled as currently no TLS integration tests
-#[ignore]
+#[ignore = "kafcars does not support TLS yet, a custom Connector can provide it"]
 #[tokio::test]
-#[cfg(feature = "transport-tls")]
-async fn test_tls() {
-    maybe_start_logging();
+async fn test_tls() {}
 
-    let mut root_store = rustls::RootCertStore::empty();
-
-    let file = std::fs::File::open("/tmp/cluster-ca.crt").unwrap();
-    let mut reader = std::io::BufReader::new(file);
-    match rustls_pemfile::read_one(&mut reader).unwrap().unwrap() {
-        rustls_pemfile::Item::X509Certificate(key) => {
-            root_store.add(key).unwrap();
-        }
-        _ => unreachable!(),
-    }
-
-    let file = std::fs::File::open("/tmp/ca.crt").unwrap();
-    let mut reader = std::io::BufReader::new(file);
-    let producer_root = match rustls_pemfile::read_one(&mut reader).unwrap().unwrap() {
-        rustls_pemfile::Item::X509Certificate(key) => key,
-        _ => unreachable!(),
-    };
-
-    let file = std::fs::File::open("/tmp/ca.key").unwrap();
-    let mut reader = std::io::BufReader::new(file);
-    let private_key = match rustls_pemfile::read_one(&mut reader).unwrap().unwrap() {
-        rustls_pemfile::Item::Pkcs8Key(key) => rustls::pki_types::PrivateKeyDer::Pkcs8(key),
-        _ => unreachable!(),
-    };
-
-    let config = rustls::ClientConfig::builder()
-        .with_root_certificates(root_store)
-        .with_client_auth_cert(vec![producer_root], private_key)
-        .unwrap();
-
-    let test_cfg = maybe_skip_kafka_integration!();
-    ClientBuilder::new(test_cfg.bootstrap_brokers)
-        .tls_config(Arc::new(config))
-        .build()
-        .await
-        .unwrap();
-}
-
-#[cfg(feature = "transport-socks5")]
+#[ignore = "kafcars does not support SOCKS5 proxies yet, a custom Connector can provide them"]
 #[tokio::test]
-async fn test_socks5() {
-    maybe_start_logging();
-
-    let test_cfg = maybe_skip_kafka_integration!(socks5);
-    let topic_name = random_topic_name();
-
-    let client = ClientBuilder::new(test_cfg.bootstrap_brokers)
-        .socks5_proxy(test_cfg.socks5_proxy.unwrap())
-        .build()
-        .await
-        .unwrap();
-
-    let controller_client = client.controller_client().unwrap();
-    controller_client
-        .create_topic(&topic_name, 1, 1, 5_000)
-        .await
-        .unwrap();
-
-    let partition_client = client
-        .partition_client(topic_name, 0, UnknownTopicHandling::Retry)
-        .await
-        .unwrap();
-
-    let record = record(b"");
-    partition_client
-        .produce(vec![record.clone()], Compression::NoCompression)
-        .await
-        .unwrap();
-
-    let (mut records, _watermark) = partition_client
-        .fetch_records(0, 1..10_000_001, 1_000)
-        .await
-        .unwrap();
-    assert_eq!(records.len(), 1);
-    let record2 = records.remove(0).record;
-    assert_eq!(record, record2);
-}
+async fn test_socks5() {}
 
 #[tokio::test]
 async fn test_produce_empty() {
@@ -377,10 +280,9 @@ async fn test_consume_offset_out_of_range() {
         .unwrap_err();
     assert_matches!(
         err,
-        ClientError::ServerError {
-            protocol_error: ProtocolError::OffsetOutOfRange,
-            response: Some(ServerErrorResponse::PartitionFetchState { .. }),
-            ..
+        ClientError::Server {
+            api_key: ApiKey::Fetch,
+            error: ProtocolError::OffsetOutOfRange,
         }
     );
 }
@@ -651,10 +553,9 @@ async fn test_delete_records() {
         .unwrap_err();
     assert_matches!(
         err,
-        ClientError::ServerError {
-            protocol_error: ProtocolError::OffsetOutOfRange,
-            response: Some(ServerErrorResponse::PartitionFetchState { .. }),
-            ..
+        ClientError::Server {
+            api_key: ApiKey::Fetch,
+            error: ProtocolError::OffsetOutOfRange,
         }
     );
     let err = partition_client
@@ -663,10 +564,9 @@ async fn test_delete_records() {
         .unwrap_err();
     assert_matches!(
         err,
-        ClientError::ServerError {
-            protocol_error: ProtocolError::OffsetOutOfRange,
-            response: Some(ServerErrorResponse::PartitionFetchState { .. }),
-            ..
+        ClientError::Server {
+            api_key: ApiKey::Fetch,
+            error: ProtocolError::OffsetOutOfRange,
         }
     );
 
@@ -719,14 +619,14 @@ async fn test_client_backoff_terminates() {
         });
 
     match client_builder.build().await {
-        Err(rskafka::client::error::Error::Connection(e)) => {
+        Err(e @ ClientError::Connection { .. }) => {
             // Error can be slightly different depending on the exact underlying error.
             assert!(
                 e.to_string().starts_with(concat!(
-                    "all retries failed: Retry exceeded deadline. ",
-                    "Source: error connecting to broker \"localhost:9000\""
+                    "All retries failed: Retry exceeded deadline. ",
+                    "Source: Error connecting to broker \"localhost:9000\""
                 )),
-                "expected error to start with \"all retries failed...\", actual: {}",
+                "expected error to start with \"All retries failed...\", actual: {}",
                 e
             );
         }
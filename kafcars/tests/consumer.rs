@@ -8,10 +8,10 @@ use tokio::time::timeout;
 use kafcars::{
     client::{
         consumer::{StartOffset, StreamConsumer, StreamConsumerBuilder},
-        error::{Error, ProtocolError},
         partition::{Compression, UnknownTopicHandling},
         ClientBuilder,
     },
+    error::{Error, ProtocolError},
     record::RecordAndOffset,
 };
 use test_helpers::{maybe_start_logging, random_topic_name, record, TEST_TIMEOUT};
@@ -151,8 +151,8 @@ async fn test_stream_consumer_offset_out_of_range() {
     let error = stream.next().await.expect("stream not empty").unwrap_err();
     assert_matches!(
         error,
-        Error::ServerError {
-            protocol_error: ProtocolError::OffsetOutOfRange,
+        Error::Server {
+            error: ProtocolError::OffsetOutOfRange,
             ..
         }
     );